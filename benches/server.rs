@@ -0,0 +1,142 @@
+//! Benchmarks of the server.
+//!
+//! These are run against the in-memory transport of `Simulation`,
+//! so the results do not depend on the network stack of the host.
+//!
+//! ```console
+//! $ cargo bench --features bench
+//! ```
+use bytecodec::bytes::Utf8Encoder;
+use bytecodec::null::NullDecoder;
+use bytecodec::DecodeExt;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fibers_http_server::testing::{SimConnection, Simulation};
+use fibers_http_server::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+use futures::future::ok;
+use httpcodec::{BodyDecoder, BodyEncoder, NoBodyDecoder, RequestDecoder};
+
+const REQUEST: &[u8] = b"GET /users/123/posts?limit=10 HTTP/1.1\r\n\
+    Host: localhost\r\n\
+    User-Agent: fibers_http_server-bench\r\n\
+    Accept: */*\r\n\
+    Accept-Encoding: gzip, deflate\r\n\
+    Connection: keep-alive\r\n\r\n";
+
+macro_rules! define_handler {
+    ($name:ident, $path:expr) => {
+        struct $name;
+        impl HandleRequest for $name {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = $path;
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "hello".to_owned())))
+            }
+        }
+    };
+}
+
+define_handler!(Hello, "/hello");
+define_handler!(Users, "/users");
+define_handler!(User, "/users/*");
+define_handler!(UserPosts, "/users/*/posts");
+define_handler!(UserPost, "/users/*/posts/*");
+define_handler!(Groups, "/groups");
+define_handler!(Group, "/groups/*");
+define_handler!(GroupMembers, "/groups/*/members");
+define_handler!(Assets, "/assets/**");
+
+fn simulation(all_routes: bool) -> (Simulation, SimConnection) {
+    let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+    builder.add_handler(Hello).unwrap();
+    builder.add_handler(UserPosts).unwrap();
+    if all_routes {
+        builder.add_handler(Users).unwrap();
+        builder.add_handler(User).unwrap();
+        builder.add_handler(UserPost).unwrap();
+        builder.add_handler(Groups).unwrap();
+        builder.add_handler(Group).unwrap();
+        builder.add_handler(GroupMembers).unwrap();
+        builder.add_handler(Assets).unwrap();
+    }
+    let mut sim = builder.finish_simulation(0);
+    let conn = sim.connect().unwrap();
+    (sim, conn)
+}
+
+fn roundtrip(sim: &mut Simulation, conn: &SimConnection, requests: &[u8]) -> usize {
+    conn.write(requests);
+    sim.run().unwrap();
+    let received = conn.take_received();
+    assert!(!received.is_empty());
+    received.len()
+}
+
+fn request_head_parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("request_head_parsing");
+    group.throughput(Throughput::Bytes(REQUEST.len() as u64));
+    group.bench_function("get", |b| {
+        let mut decoder = RequestDecoder::<NoBodyDecoder>::default();
+        b.iter(|| black_box(decoder.decode_from_bytes(black_box(REQUEST)).unwrap()))
+    });
+    group.finish();
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(1));
+    for &(name, all_routes) in &[("2_routes", false), ("9_routes", true)] {
+        group.bench_function(BenchmarkId::new("wildcards", name), |b| {
+            let (mut sim, conn) = simulation(all_routes);
+            b.iter(|| roundtrip(&mut sim, &conn, REQUEST))
+        });
+        group.bench_function(BenchmarkId::new("not_found", name), |b| {
+            let (mut sim, conn) = simulation(all_routes);
+            b.iter(|| roundtrip(&mut sim, &conn, b"GET /users/123/likes HTTP/1.1\r\n\r\n"))
+        });
+    }
+    group.finish();
+}
+
+fn small_response_encoding(c: &mut Criterion) {
+    let request = b"GET /hello HTTP/1.1\r\n\r\n";
+    let (mut sim, conn) = simulation(false);
+    let size = roundtrip(&mut sim, &conn, request);
+
+    let mut group = c.benchmark_group("small_response_encoding");
+    group.throughput(Throughput::Bytes(size as u64));
+    group.bench_function("hello", |b| b.iter(|| roundtrip(&mut sim, &conn, request)));
+    group.finish();
+}
+
+fn keep_alive_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("keep_alive_throughput");
+    for &pipelined in &[1, 16, 128] {
+        let requests = REQUEST.repeat(pipelined);
+        group.throughput(Throughput::Elements(pipelined as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(pipelined),
+            &requests,
+            |b, requests| {
+                let (mut sim, conn) = simulation(true);
+                b.iter(|| roundtrip(&mut sim, &conn, requests))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    request_head_parsing,
+    dispatch,
+    small_response_encoding,
+    keep_alive_throughput
+);
+criterion_main!(benches);
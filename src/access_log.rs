@@ -0,0 +1,529 @@
+use crate::observer::RequestTraffic;
+use crate::{Error, ErrorKind, Result, Route};
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A destination of access log entries (see `ServerBuilder::access_log`).
+///
+/// Unlike the logs emitted via `slog`, an entry is produced for every completed request
+/// (including the ones rejected by the server itself), so that the access logs can be
+/// shipped separately from the application logs.
+pub trait AccessLogSink: Send + Sync + 'static {
+    /// Writes the entry of a completed request.
+    ///
+    /// This is called on the threads running the server, so it should not block for long.
+    fn log(&self, entry: &AccessLogEntry);
+}
+
+/// An entry of the access log.
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub(crate) time: SystemTime,
+    pub(crate) traffic: RequestTraffic,
+    pub(crate) status: u16,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) request_id: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+impl AccessLogEntry {
+    /// Returns the time when the response was completed.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Returns the ID of the connection that the request was received on.
+    pub fn connection_id(&self) -> u64 {
+        self.traffic.connection_id()
+    }
+
+    /// Returns the address of the client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.traffic.peer_addr()
+    }
+
+    /// Returns the method of the request.
+    ///
+    /// If the head part of the request could not be decoded, this returns `None`.
+    pub fn method(&self) -> Option<&str> {
+        self.traffic.method()
+    }
+
+    /// Returns the path of the request.
+    ///
+    /// If the head part of the request could not be decoded, this returns `None`.
+    pub fn path(&self) -> Option<&str> {
+        self.traffic.path()
+    }
+
+    /// Returns the route that the request was dispatched to.
+    pub fn route(&self) -> Option<&Route> {
+        self.traffic.route()
+    }
+
+    /// Returns the status code of the response.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Returns the number of bytes of the request consumed by the server.
+    pub fn bytes_read(&self) -> u64 {
+        self.traffic.bytes_read()
+    }
+
+    /// Returns the number of bytes of the response produced by the server.
+    pub fn bytes_written(&self) -> u64 {
+        self.traffic.bytes_written()
+    }
+
+    /// Returns the time elapsed from the decoding of the request head to the completion of the response.
+    ///
+    /// If the head part of the request could not be decoded, this returns `None`.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Returns the ID of the request (i.e., the `X-Request-Id` header or the ID assigned by the server).
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Returns the value of the `User-Agent` header of the request.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+}
+
+/// A format of the lines written by `AccessLogWriter`.
+pub trait AccessLogFormat: Send + Sync + 'static {
+    /// Appends the line representing `entry` (without the trailing newline) to `buf`.
+    fn format(&self, entry: &AccessLogEntry, buf: &mut String);
+}
+
+/// The [JSON Lines] format.
+///
+/// Each line is an object like the following (the fields whose values are unknown are omitted):
+///
+/// ```json
+/// {"time":"2018-06-01T12:34:56.789Z","connection_id":3,"peer_addr":"127.0.0.1:50000","method":"GET","path":"/hello","route":"/hello","status":200,"bytes_read":78,"bytes_written":44,"duration_seconds":0.000125,"request_id":"1","user_agent":"curl/7.58.0"}
+/// ```
+///
+/// [JSON Lines]: https://jsonlines.org/
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonLines;
+impl AccessLogFormat for JsonLines {
+    fn format(&self, entry: &AccessLogEntry, buf: &mut String) {
+        buf.push_str("{\"time\":\"");
+        write_rfc3339(buf, entry.time);
+        let _ = write!(
+            buf,
+            "\",\"connection_id\":{},\"peer_addr\":\"{}\"",
+            entry.connection_id(),
+            entry.peer_addr()
+        );
+        if let Some(method) = entry.method() {
+            buf.push_str(",\"method\":");
+            write_json_string(buf, method);
+        }
+        if let Some(path) = entry.path() {
+            buf.push_str(",\"path\":");
+            write_json_string(buf, path);
+        }
+        if let Some(route) = entry.route() {
+            buf.push_str(",\"route\":");
+            write_json_string(buf, route.path());
+        }
+        let _ = write!(
+            buf,
+            ",\"status\":{},\"bytes_read\":{},\"bytes_written\":{}",
+            entry.status,
+            entry.bytes_read(),
+            entry.bytes_written()
+        );
+        if let Some(duration) = entry.duration {
+            let _ = write!(buf, ",\"duration_seconds\":{}", duration.as_secs_f64());
+        }
+        if let Some(request_id) = entry.request_id() {
+            buf.push_str(",\"request_id\":");
+            write_json_string(buf, request_id);
+        }
+        if let Some(user_agent) = entry.user_agent() {
+            buf.push_str(",\"user_agent\":");
+            write_json_string(buf, user_agent);
+        }
+        buf.push('}');
+    }
+}
+
+type RotateHookFn = dyn Fn(&Path) -> io::Result<()> + Send + Sync + 'static;
+
+/// `AccessLogSink` that writes the entries line by line to the standard output, a file or an arbitrary writer.
+///
+/// The entries are formatted by `JsonLines` unless another format is specified.
+/// Errors that occur while writing the entries are ignored.
+///
+/// The instances are cheaply cloneable and the clones share the same destination,
+/// so a clone can be kept to call `reopen` or `rotate` after the writer has been passed to
+/// `ServerBuilder::access_log`.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{AccessLogWriter, ServerBuilder};
+///
+/// # let dir = std::env::temp_dir();
+/// let path = dir.join("fibers_http_server_access_log_doctest.log");
+/// let writer = AccessLogWriter::file(&path)
+///     .unwrap()
+///     .max_file_size(64 * 1024 * 1024)
+///     .on_rotate(|path| std::fs::rename(path, path.with_extension("log.old")));
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.access_log(writer.clone());
+///
+/// // e.g., on `SIGHUP` after the file has been moved by `logrotate`
+/// writer.reopen().unwrap();
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct AccessLogWriter {
+    format: Arc<dyn AccessLogFormat>,
+    state: Arc<Mutex<WriterState>>,
+}
+impl AccessLogWriter {
+    /// Makes a new `AccessLogWriter` instance that writes the entries to the standard output.
+    pub fn stdout() -> Self {
+        Self::with_output(Output::Stdout, 0)
+    }
+
+    /// Makes a new `AccessLogWriter` instance that appends the entries to the file at `path`.
+    ///
+    /// The file is created if it does not exist.
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (file, size) = track!(open_append(&path); path)?;
+        Ok(Self::with_output(Output::File { path, file }, size))
+    }
+
+    /// Makes a new `AccessLogWriter` instance that writes the entries to `writer`.
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self::with_output(Output::Writer(Box::new(writer)), 0)
+    }
+
+    /// Sets the format of the lines.
+    ///
+    /// The default value is `JsonLines`.
+    pub fn format<F: AccessLogFormat>(mut self, format: F) -> Self {
+        self.format = Arc::new(format);
+        self
+    }
+
+    /// Makes the writer rotate the file (see `rotate`) when its size exceeds `size` bytes.
+    ///
+    /// This has no effect on the writers other than the ones made by `file`.
+    pub fn max_file_size(self, size: u64) -> Self {
+        if let Ok(mut state) = self.state.lock() {
+            state.max_file_size = Some(size);
+        }
+        self
+    }
+
+    /// Sets the function that is invoked with the path of the file when it is rotated.
+    ///
+    /// The function is expected to move the file (e.g., rename it with a timestamp, or compress it).
+    /// By default, the file is renamed by appending `.1` to its name (the previous one is overwritten).
+    pub fn on_rotate<F>(self, f: F) -> Self
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        if let Ok(mut state) = self.state.lock() {
+            state.on_rotate = Some(Arc::new(f));
+        }
+        self
+    }
+
+    /// Reopens the file (e.g., after it has been moved by an external tool like `logrotate`).
+    ///
+    /// This has no effect on the writers other than the ones made by `file`.
+    pub fn reopen(&self) -> Result<()> {
+        match self.state.lock() {
+            Err(e) => track_panic!(ErrorKind::Other, "{}", e),
+            Ok(mut state) => track!(state.reopen()),
+        }
+    }
+
+    /// Rotates the file (i.e., invokes the function set by `on_rotate` and reopens the file).
+    ///
+    /// This has no effect on the writers other than the ones made by `file`.
+    pub fn rotate(&self) -> Result<()> {
+        match self.state.lock() {
+            Err(e) => track_panic!(ErrorKind::Other, "{}", e),
+            Ok(mut state) => track!(state.rotate()),
+        }
+    }
+
+    fn with_output(output: Output, written: u64) -> Self {
+        AccessLogWriter {
+            format: Arc::new(JsonLines),
+            state: Arc::new(Mutex::new(WriterState {
+                output,
+                written,
+                max_file_size: None,
+                on_rotate: None,
+            })),
+        }
+    }
+}
+impl AccessLogSink for AccessLogWriter {
+    fn log(&self, entry: &AccessLogEntry) {
+        let mut line = String::new();
+        self.format.format(entry, &mut line);
+        line.push('\n');
+        if let Ok(mut state) = self.state.lock() {
+            let _ = state.write_line(line.as_bytes());
+        }
+    }
+}
+impl fmt::Debug for AccessLogWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AccessLogWriter {{ .. }}")
+    }
+}
+
+struct WriterState {
+    output: Output,
+    written: u64,
+    max_file_size: Option<u64>,
+    on_rotate: Option<Arc<RotateHookFn>>,
+}
+impl WriterState {
+    fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        match self.output {
+            Output::Stdout => track!(io::stdout().lock().write_all(line).map_err(Error::from))?,
+            Output::File { ref mut file, .. } => track!(file.write_all(line).map_err(Error::from))?,
+            Output::Writer(ref mut w) => {
+                track!(w.write_all(line).map_err(Error::from))?;
+                track!(w.flush().map_err(Error::from))?;
+            }
+        }
+        self.written += line.len() as u64;
+        if self.max_file_size.map_or(false, |max| self.written > max) {
+            track!(self.rotate())?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        if let Output::File { ref path, .. } = self.output {
+            match self.on_rotate {
+                Some(ref f) => track!(f(path).map_err(Error::from); path)?,
+                None => {
+                    let mut rotated = path.clone().into_os_string();
+                    rotated.push(".1");
+                    track!(fs::rename(path, rotated).map_err(Error::from); path)?;
+                }
+            }
+        }
+        track!(self.reopen())
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        if let Output::File {
+            ref path,
+            ref mut file,
+        } = self.output
+        {
+            let (reopened, size) = track!(open_append(path); path)?;
+            *file = reopened;
+            self.written = size;
+        }
+        Ok(())
+    }
+}
+
+enum Output {
+    Stdout,
+    File { path: PathBuf, file: File },
+    Writer(Box<dyn Write + Send>),
+}
+
+fn open_append(path: &Path) -> Result<(File, u64)> {
+    let file = track!(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(Error::from))?;
+    let size = track!(file.metadata().map_err(Error::from))?.len();
+    Ok((file, size))
+}
+
+#[derive(Clone)]
+pub struct SharedAccessLog(Arc<dyn AccessLogSink>);
+impl SharedAccessLog {
+    pub fn new<S: AccessLogSink>(sink: S) -> Self {
+        SharedAccessLog(Arc::new(sink))
+    }
+
+    pub fn log(&self, entry: &AccessLogEntry) {
+        self.0.log(entry);
+    }
+}
+impl fmt::Debug for SharedAccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedAccessLog(_)")
+    }
+}
+
+fn write_json_string(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+/// Writes `time` in the RFC 3339 format (in UTC with milliseconds).
+fn write_rfc3339(buf: &mut String, time: SystemTime) {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let _ = write!(
+        buf,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis()
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+
+    #[test]
+    fn json_lines_works() {
+        let mut traffic = RequestTraffic::new(3, ([127, 0, 0, 1], 50000).into());
+        traffic.method = Some("GET".to_owned());
+        traffic.path = Some("/a\"b".to_owned());
+        traffic.bytes_read = 78;
+        traffic.bytes_written = 44;
+        let entry = AccessLogEntry {
+            time: UNIX_EPOCH + Duration::from_millis(1_527_856_496_789),
+            traffic,
+            status: 404,
+            duration: Some(Duration::from_millis(250)),
+            request_id: Some("1".to_owned()),
+            user_agent: None,
+        };
+
+        let mut line = String::new();
+        JsonLines.format(&entry, &mut line);
+        assert_eq!(
+            line,
+            r#"{"time":"2018-06-01T12:34:56.789Z","connection_id":3,"peer_addr":"127.0.0.1:50000","method":"GET","path":"/a\"b","status":404,"bytes_read":78,"bytes_written":44,"duration_seconds":0.25,"request_id":"1"}"#
+        );
+    }
+
+    #[test]
+    fn rotation_works() {
+        let path = std::env::temp_dir().join(format!(
+            "fibers_http_server_access_log_test_{}.log",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let entry = AccessLogEntry {
+            time: UNIX_EPOCH,
+            traffic: RequestTraffic::new(3, ([127, 0, 0, 1], 50000).into()),
+            status: 400,
+            duration: None,
+            request_id: None,
+            user_agent: None,
+        };
+
+        let writer = track_try_unwrap!(AccessLogWriter::file(&path)).max_file_size(150);
+        writer.log(&entry);
+        assert!(fs::metadata(&path).unwrap().len() > 0);
+        writer.log(&entry);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+        let rotated = fs::read_to_string(&rotated).unwrap();
+        assert_eq!(rotated.lines().count(), 2);
+        assert!(rotated.starts_with(r#"{"time":"1970-01-01T00:00:00.000Z","#));
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log.1"));
+    }
+
+    #[test]
+    fn access_log_works() {
+        #[derive(Clone, Default)]
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+        impl Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buf = SharedBuf::default();
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.access_log(AccessLogWriter::new(buf.clone()));
+        builder.add_handler(Hello).unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        let res = fibers_global::execute(client.get("/world").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 404);
+
+        let log = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"time":""#));
+        assert!(
+            lines[0].contains(r#""method":"GET","path":"/hello","route":"/hello","status":200,"#)
+        );
+        assert!(lines[0].contains(r#""request_id":""#));
+        assert!(lines[1].contains(r#""path":"/world","status":404,"#));
+    }
+}
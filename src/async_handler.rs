@@ -0,0 +1,177 @@
+use crate::{Error, HandleRequest, Priority, Reply, Req, Res};
+use bytecodec::marker::Never;
+use futures03::compat::Future01CompatExt as _;
+use futures03::future::{FutureExt, TryFutureExt};
+use httpcodec::{BodyDecode, BodyEncode};
+use std::future::Future;
+
+/// `HandleRequestAsync` allows for handling HTTP requests by using `std::future::Future`.
+///
+/// Implementations are registered to a server via `AsyncHandler`.
+pub trait HandleRequestAsync: Sized + Send + Sync + 'static {
+    /// The method that the handler can handle.
+    const METHOD: &'static str;
+
+    /// The methods that the handler can handle.
+    ///
+    /// See the documentation of `HandleRequest::METHODS` for the details.
+    const METHODS: &'static [&'static str] = &[Self::METHOD];
+
+    /// The request path that the handler can handle.
+    ///
+    /// See the documentation of `HandleRequest::PATH` for the syntax.
+    const PATH: &'static str;
+
+    /// The priority class of the requests handled by the handler.
+    ///
+    /// See the documentation of `HandleRequest::PRIORITY` for the details.
+    const PRIORITY: Priority = Priority::Normal;
+
+    /// The query parameters that the requests handled by the handler must have.
+    ///
+    /// See the documentation of `HandleRequest::QUERY` for the details.
+    const QUERY: &'static [(&'static str, &'static str)] = &[];
+
+    /// The type of the request bodies.
+    type ReqBody: Send + 'static;
+
+    /// The type of the response bodies.
+    type ResBody: Send + 'static;
+
+    /// Request body decoder.
+    type Decoder: BodyDecode<Item = Self::ReqBody> + Send + 'static;
+
+    /// Response body encoder.
+    type Encoder: BodyEncode<Item = Self::ResBody> + Send + 'static;
+
+    /// Handles the head part of a request.
+    ///
+    /// If a `Some(..)` value is returned, the invocation of `handle_request` method will be skipped.
+    ///
+    /// The default implementation always returns `None`.
+    #[allow(unused_variables)]
+    fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
+        None
+    }
+
+    /// Handles a request.
+    fn handle_request(
+        &self,
+        req: Req<Self::ReqBody>,
+    ) -> impl Future<Output = Res<Self::ResBody>> + Send + 'static;
+
+    /// Handles an error occurred while decoding the body of a request.
+    ///
+    /// The default implementation always returns `None`
+    /// (i.e., the default error response will be returned to the HTTP client).
+    #[allow(unused_variables)]
+    fn handle_decoding_error(&self, req: Req<()>, error: &Error) -> Option<Res<Self::ResBody>> {
+        None
+    }
+}
+
+/// A `HandleRequest` implementation that drives the inner `HandleRequestAsync`.
+///
+/// The futures returned by the inner handler are converted into the futures 0.1 ones,
+/// so they are polled by the same fibers as the ordinary handlers.
+#[derive(Debug)]
+pub struct AsyncHandler<H>(H);
+impl<H: HandleRequestAsync> AsyncHandler<H> {
+    /// Makes a new `AsyncHandler` instance.
+    pub fn new(inner: H) -> Self {
+        AsyncHandler(inner)
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn inner_ref(&self) -> &H {
+        &self.0
+    }
+
+    /// Takes ownership of the handler, and returns the inner handler.
+    pub fn into_inner(self) -> H {
+        self.0
+    }
+}
+impl<H: HandleRequestAsync> HandleRequest for AsyncHandler<H> {
+    const METHOD: &'static str = H::METHOD;
+    const METHODS: &'static [&'static str] = H::METHODS;
+    const PATH: &'static str = H::PATH;
+    const PRIORITY: Priority = H::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = H::QUERY;
+
+    type ReqBody = H::ReqBody;
+    type ResBody = H::ResBody;
+    type Decoder = H::Decoder;
+    type Encoder = H::Encoder;
+    type Reply = Reply<Self::ResBody>;
+
+    fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
+        self.0.handle_request_head(req)
+    }
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let future = self.0.handle_request(req).map(Ok::<_, Never>);
+        Box::new(future.boxed().compat())
+    }
+
+    fn handle_decoding_error(&self, req: Req<()>, error: &Error) -> Option<Res<Self::ResBody>> {
+        self.0.handle_decoding_error(req, error)
+    }
+}
+
+/// Converts a `HandleRequest::Reply` into a `std::future::Future`.
+///
+/// This is useful for reusing existing `HandleRequest` implementations from `HandleRequestAsync`.
+pub fn reply_into_future<F, T>(reply: F) -> impl Future<Output = Res<T>> + Send + 'static
+where
+    F: futures::Future<Item = Res<T>, Error = Never> + Send + 'static,
+{
+    reply.compat().map(|result| result.expect("Never fails"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Status;
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::Future as _;
+    use httpcodec::{BodyDecoder, BodyEncoder, HttpVersion, Method, Request, RequestTarget};
+    use url::Url;
+
+    struct Hello;
+    impl HandleRequestAsync for Hello {
+        const METHOD: &'static str = "GET";
+        const PATH: &'static str = "/hello";
+
+        type ReqBody = ();
+        type ResBody = String;
+        type Decoder = BodyDecoder<NullDecoder>;
+        type Encoder = BodyEncoder<Utf8Encoder>;
+
+        fn handle_request(
+            &self,
+            _req: Req<Self::ReqBody>,
+        ) -> impl Future<Output = Res<Self::ResBody>> + Send + 'static {
+            let reply = futures::finished(Res::new(Status::Ok, "hello".to_owned()));
+            async { reply_into_future(reply).await }
+        }
+    }
+
+    #[test]
+    fn async_handler_works() {
+        let inner = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new("/hello").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        let base_url = Url::parse("http://localhost/").unwrap();
+        let req = track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()));
+
+        let handler = AsyncHandler::new(Hello);
+        let res = handler.handle_request(req).wait().unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), "hello");
+    }
+}
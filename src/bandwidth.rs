@@ -0,0 +1,277 @@
+use crate::clock::{Clock, SharedClock, Sleep};
+use crate::connection::Transport;
+use fibers::net::TcpStream;
+use futures::{task, Async, Future};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Configuration of the bandwidth throttling.
+///
+/// The bytes transferred through a connection are throttled by using the token bucket algorithm.
+/// Each connection has its own buckets (i.e., one for reading and one for writing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthLimit {
+    bytes_per_second: u64,
+    burst: u64,
+}
+impl BandwidthLimit {
+    /// Makes a new `BandwidthLimit` instance.
+    ///
+    /// `bytes_per_second` is the rate at which tokens are refilled,
+    /// and `burst` is the capacity of each bucket (i.e., the maximum number of bytes that can be
+    /// transferred at once).
+    ///
+    /// # Panics
+    ///
+    /// If `bytes_per_second` or `burst` is zero, this function will panic.
+    pub fn new(bytes_per_second: u64, burst: u64) -> Self {
+        assert!(bytes_per_second > 0, "bytes_per_second must be positive");
+        assert!(burst > 0, "burst must be positive");
+        BandwidthLimit {
+            bytes_per_second,
+            burst,
+        }
+    }
+
+    /// Returns the rate at which tokens are refilled.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Returns the capacity of each bucket.
+    pub fn burst(&self) -> u64 {
+        self.burst
+    }
+}
+
+/// A stream that throttles the bytes read from and written to the inner stream.
+#[derive(Debug)]
+pub struct Throttled<S> {
+    inner: S,
+    read: Throttle,
+    write: Throttle,
+}
+impl<S> Throttled<S> {
+    pub fn new(
+        inner: S,
+        read: Option<BandwidthLimit>,
+        write: Option<BandwidthLimit>,
+        clock: SharedClock,
+    ) -> Self {
+        Throttled {
+            inner,
+            read: Throttle::new(read, clock.clone()),
+            write: Throttle::new(write, clock),
+        }
+    }
+
+    /// Overrides the limits until `reset_limits` is called.
+    ///
+    /// `None` means that the default limit is used.
+    pub fn set_limits(&mut self, read: Option<BandwidthLimit>, write: Option<BandwidthLimit>) {
+        self.read.set_limit(read.or(self.read.default));
+        self.write.set_limit(write.or(self.write.default));
+    }
+
+    /// Restores the default limits.
+    pub fn reset_limits(&mut self) {
+        self.read.set_limit(self.read.default);
+        self.write.set_limit(self.write.default);
+    }
+}
+impl<S: Read> Read for Throttled<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        let n = self.read.available()?.min(buf.len());
+        let size = self.inner.read(&mut buf[..n])?;
+        self.read.consume(size);
+        Ok(size)
+    }
+}
+impl<S: Write> Write for Throttled<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let n = self.write.available()?.min(buf.len());
+        let size = self.inner.write(&buf[..n])?;
+        self.write.consume(size);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl<S: Transport> Transport for Throttled<S> {
+    /// Returns `None` while the writing is throttled, because the direct writes bypass the throttle.
+    fn tcp_stream(&mut self) -> Option<&mut TcpStream> {
+        if self.write.limit.is_some() {
+            None
+        } else {
+            self.inner.tcp_stream()
+        }
+    }
+
+    fn set_cork(&mut self, cork: bool) -> io::Result<()> {
+        self.inner.set_cork(cork)
+    }
+}
+
+#[derive(Debug)]
+struct Throttle {
+    default: Option<BandwidthLimit>,
+    limit: Option<BandwidthLimit>,
+    tokens: f64,
+    last_refill: Instant,
+    timer: Option<Sleep>,
+    clock: SharedClock,
+}
+impl Throttle {
+    fn new(limit: Option<BandwidthLimit>, clock: SharedClock) -> Self {
+        Throttle {
+            default: limit,
+            limit,
+            tokens: limit.map_or(0.0, |l| l.burst as f64),
+            last_refill: clock.now(),
+            timer: None,
+            clock,
+        }
+    }
+
+    fn set_limit(&mut self, limit: Option<BandwidthLimit>) {
+        if limit == self.limit {
+            return;
+        }
+        self.refill();
+        self.tokens = match (self.limit, limit) {
+            (Some(_), Some(l)) => self.tokens.min(l.burst as f64),
+            (_, l) => l.map_or(0.0, |l| l.burst as f64),
+        };
+        self.limit = limit;
+        self.timer = None;
+    }
+
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        if let Some(limit) = self.limit {
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            let tokens = self.tokens + elapsed * limit.bytes_per_second as f64;
+            self.tokens = tokens.min(limit.burst as f64);
+        }
+        self.last_refill = now;
+    }
+
+    /// Returns the number of bytes that can be transferred now.
+    ///
+    /// If the bucket is empty, an `io::ErrorKind::WouldBlock` error is returned
+    /// and the current task will be notified when some tokens are refilled.
+    fn available(&mut self) -> io::Result<usize> {
+        let limit = match self.limit {
+            None => return Ok(usize::MAX),
+            Some(limit) => limit,
+        };
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.timer = None;
+            return Ok(self.tokens as usize);
+        }
+
+        let wait = (1.0 - self.tokens) / limit.bytes_per_second as f64;
+        let mut timer = self.clock.sleep(Duration::from_secs_f64(wait));
+        if let Ok(Async::NotReady) = timer.poll() {
+            self.timer = Some(timer);
+        } else {
+            task::current().notify();
+        }
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "Bandwidth limit exceeded",
+        ))
+    }
+
+    fn consume(&mut self, size: usize) {
+        if self.limit.is_some() {
+            self.tokens -= size as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        HandleRequest, HandlerOptions, ManualClock, Reply, Req, Res, ServerBuilder, Status,
+    };
+    use bytecodec::bytes::BytesEncoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+
+    #[test]
+    fn throttle_works() {
+        let clock = ManualClock::new();
+        let mut throttle = Throttle::new(
+            Some(BandwidthLimit::new(1, 4)),
+            SharedClock::new(clock.clone()),
+        );
+        assert_eq!(throttle.available().ok(), Some(4));
+        throttle.consume(4);
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(throttle.available().ok(), Some(2));
+
+        throttle.set_limit(Some(BandwidthLimit::new(1024 * 1024, 10)));
+        assert!(throttle
+            .available()
+            .ok()
+            .map_or(false, |n| (1..=10).contains(&n)));
+
+        throttle.set_limit(None);
+        assert_eq!(throttle.available().ok(), Some(usize::MAX));
+    }
+
+    #[test]
+    fn bandwidth_limit_works() {
+        struct Large;
+        impl HandleRequest for Large {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/large";
+
+            type ReqBody = ();
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, vec![0; 48 * 1024])))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Large).unwrap();
+        builder.write_bandwidth_limit(BandwidthLimit::new(64 * 1024, 16 * 1024));
+        let client = builder.finish_test_client();
+
+        let start = std::time::Instant::now();
+        let res = fibers_global::execute(client.get("/large").unwrap()).unwrap();
+        assert_eq!(res.body().len(), 48 * 1024);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        // Overridden by the handler
+        let options = HandlerOptions::default()
+            .write_bandwidth_limit(BandwidthLimit::new(1024 * 1024 * 1024, 1024 * 1024));
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler_with_options(Large, options).unwrap();
+        builder.write_bandwidth_limit(BandwidthLimit::new(1024, 1024));
+        let client = builder.finish_test_client();
+
+        let start = std::time::Instant::now();
+        let res = fibers_global::execute(client.get("/large").unwrap()).unwrap();
+        assert_eq!(res.body().len(), 48 * 1024);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::thread_pool::ThreadPoolReply;
+use crate::{HandleRequest, Priority, Req, Res, Status, WithThreadPool};
+use bytecodec::marker::Never;
+use fibers::sync::oneshot;
+use fibers::Spawn;
+use futures::future::{finished, FutureResult};
+use httpcodec::{BodyDecode, BodyEncode};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The default value of `BlockingHandler::queue_capacity`.
+pub const DEFAULT_BLOCKING_QUEUE_CAPACITY: usize = 1024;
+
+/// `BlockingEndpoint` specifies the route and the body types of a `BlockingHandler`.
+///
+/// See the documentation of `HandleRequest` for the details of each item.
+pub trait BlockingEndpoint: Send + Sync + 'static {
+    /// The method that the handler can handle.
+    const METHOD: &'static str;
+
+    /// The methods that the handler can handle.
+    const METHODS: &'static [&'static str] = &[Self::METHOD];
+
+    /// The request path that the handler can handle.
+    const PATH: &'static str;
+
+    /// The priority class of the requests handled by the handler.
+    const PRIORITY: Priority = Priority::Normal;
+
+    /// The query parameters that the requests handled by the handler must have.
+    const QUERY: &'static [(&'static str, &'static str)] = &[];
+
+    /// The type of the request bodies.
+    type ReqBody: Send + 'static;
+
+    /// The type of the response bodies.
+    ///
+    /// The default value is used as the body of `500 Internal Server Error`
+    /// returned when the function panics.
+    type ResBody: Default + Send + 'static;
+
+    /// Request body decoder.
+    type Decoder: BodyDecode<Item = Self::ReqBody> + Send + 'static;
+
+    /// Response body encoder.
+    type Encoder: BodyEncode<Item = Self::ResBody> + Send + 'static;
+}
+
+/// A handler that invokes a synchronous function on a pool of worker threads.
+///
+/// This is useful for simple endpoints that perform blocking operations
+/// (e.g., file I/O and queries to databases via synchronous clients),
+/// because the fibers executing `Server` and connections must not be blocked.
+/// The function is executed by `WithThreadPool`, so the given spawner should be
+/// a dedicated pool (e.g., `ThreadPoolExecutor`) rather than the one that runs the server.
+///
+/// At most `queue_capacity` invocations can be queued or running at the same time.
+/// If the limit is reached, `503 Service Unavailable` with the default body is returned
+/// without invoking the function.
+///
+/// If the function panics, the `500 Internal Server Error` response with
+/// the default body will be returned.
+///
+/// # Examples
+///
+/// ```
+/// use bytecodec::bytes::Utf8Encoder;
+/// use bytecodec::null::NullDecoder;
+/// use fibers::{Executor, ThreadPoolExecutor};
+/// use fibers_http_server::{BlockingEndpoint, BlockingHandler, Res, ServerBuilder, Status};
+/// use httpcodec::{BodyDecoder, BodyEncoder};
+///
+/// struct Hostname;
+/// impl BlockingEndpoint for Hostname {
+///     const METHOD: &'static str = "GET";
+///     const PATH: &'static str = "/hostname";
+///
+///     type ReqBody = ();
+///     type ResBody = String;
+///     type Decoder = BodyDecoder<NullDecoder>;
+///     type Encoder = BodyEncoder<Utf8Encoder>;
+/// }
+///
+/// let pool = ThreadPoolExecutor::with_thread_count(4).unwrap();
+/// let handler = BlockingHandler::<Hostname, _>::new(
+///     |_req| {
+///         let hostname = std::fs::read_to_string("/etc/hostname").unwrap_or_default();
+///         Res::new(Status::Ok, hostname)
+///     },
+///     pool.handle(),
+/// );
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.add_handler(handler.queue_capacity(64)).unwrap();
+/// ```
+pub struct BlockingHandler<E: BlockingEndpoint, S> {
+    inner: WithThreadPool<BlockingFn<E>, S>,
+    queue_capacity: usize,
+    queue_len: Arc<AtomicUsize>,
+}
+impl<E, S> BlockingHandler<E, S>
+where
+    E: BlockingEndpoint,
+    S: Spawn + Send + 'static,
+{
+    /// Makes a new `BlockingHandler` instance that handles requests by `f` on the threads of `spawner`.
+    pub fn new<F>(f: F, spawner: S) -> Self
+    where
+        F: Fn(Req<E::ReqBody>) -> Res<E::ResBody> + Send + Sync + 'static,
+    {
+        let f = BlockingFn { f: Box::new(f) };
+        BlockingHandler {
+            inner: WithThreadPool::new(f, spawner),
+            queue_capacity: DEFAULT_BLOCKING_QUEUE_CAPACITY,
+            queue_len: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Sets the maximum number of the invocations of the function that can be queued or running.
+    ///
+    /// The default value is `DEFAULT_BLOCKING_QUEUE_CAPACITY`.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+}
+impl<E, S> HandleRequest for BlockingHandler<E, S>
+where
+    E: BlockingEndpoint,
+    S: Spawn + Send + 'static,
+{
+    const METHOD: &'static str = E::METHOD;
+    const METHODS: &'static [&'static str] = E::METHODS;
+    const PATH: &'static str = E::PATH;
+    const PRIORITY: Priority = E::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = E::QUERY;
+
+    type ReqBody = E::ReqBody;
+    type ResBody = E::ResBody;
+    type Decoder = E::Decoder;
+    type Encoder = E::Encoder;
+    type Reply = ThreadPoolReply<Self>;
+
+    fn handle_request(&self, mut req: Req<Self::ReqBody>) -> Self::Reply {
+        if self.queue_len.fetch_add(1, Ordering::SeqCst) >= self.queue_capacity {
+            self.queue_len.fetch_sub(1, Ordering::SeqCst);
+            let (tx, rx) = oneshot::channel();
+            let _ = tx.send(Res::new(Status::ServiceUnavailable, E::ResBody::default()));
+            return ThreadPoolReply(rx);
+        }
+        req.extensions_mut()
+            .insert(QueueSlot(Arc::clone(&self.queue_len)));
+        let ThreadPoolReply(rx) = self.inner.handle_request(req);
+        ThreadPoolReply(rx)
+    }
+}
+impl<E: BlockingEndpoint, S> fmt::Debug for BlockingHandler<E, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BlockingHandler {{ queue_capacity: {}, queue_len: {}, .. }}",
+            self.queue_capacity,
+            self.queue_len.load(Ordering::SeqCst)
+        )
+    }
+}
+
+/// An adapter that makes a synchronous function a `HandleRequest` to be run by `WithThreadPool`.
+struct BlockingFn<E: BlockingEndpoint> {
+    f: Box<dyn Fn(Req<E::ReqBody>) -> Res<E::ResBody> + Send + Sync + 'static>,
+}
+impl<E: BlockingEndpoint> HandleRequest for BlockingFn<E> {
+    const METHOD: &'static str = E::METHOD;
+    const METHODS: &'static [&'static str] = E::METHODS;
+    const PATH: &'static str = E::PATH;
+    const PRIORITY: Priority = E::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = E::QUERY;
+
+    type ReqBody = E::ReqBody;
+    type ResBody = E::ResBody;
+    type Decoder = E::Decoder;
+    type Encoder = E::Encoder;
+    type Reply = FutureResult<Res<Self::ResBody>, Never>;
+
+    fn handle_request(&self, mut req: Req<Self::ReqBody>) -> Self::Reply {
+        // The slot is released when the function returns (or panics)
+        let _slot = req.extensions_mut().remove::<QueueSlot>();
+        let res = panic::catch_unwind(AssertUnwindSafe(|| (self.f)(req)))
+            .unwrap_or_else(|_| Res::new(Status::InternalServerError, E::ResBody::default()));
+        finished(res)
+    }
+}
+
+/// A slot of the queue of a `BlockingHandler`, which is released when dropped.
+struct QueueSlot(Arc<AtomicUsize>);
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use fibers::{Executor, ThreadPoolExecutor};
+    use httpcodec::{BodyDecoder, BodyEncoder, HttpVersion, Method, Request, RequestTarget};
+    use std::sync::{mpsc, Mutex};
+    use std::thread::{self, ThreadId};
+    use url::Url;
+
+    struct ThreadName;
+    impl BlockingEndpoint for ThreadName {
+        const METHOD: &'static str = "GET";
+        const PATH: &'static str = "/";
+
+        type ReqBody = ();
+        type ResBody = String;
+        type Decoder = BodyDecoder<NullDecoder>;
+        type Encoder = BodyEncoder<Utf8Encoder>;
+    }
+
+    fn req(target: &str) -> Req<()> {
+        let inner = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new(target).unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        let base_url = Url::parse("http://localhost/").unwrap();
+        track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()))
+    }
+
+    #[test]
+    fn blocking_handler_works() {
+        let (pool_tx, pool_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let executor =
+                track_try_unwrap!(track_any_err!(ThreadPoolExecutor::with_thread_count(1)));
+            pool_tx.send(executor.handle()).unwrap();
+            executor.run()
+        });
+        let pool = pool_rx.recv().unwrap();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (resume_tx, resume_rx) = mpsc::channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let resume_rx = Mutex::new(resume_rx);
+        let handler = BlockingHandler::<ThreadName, _>::new(
+            move |req| {
+                if req.url().query() == Some("wait") {
+                    let _ = started_tx.lock().unwrap().send(());
+                    let _ = resume_rx.lock().unwrap().recv();
+                }
+                let id: ThreadId = thread::current().id();
+                Res::new(Status::Ok, format!("{:?}", id))
+            },
+            pool.clone(),
+        )
+        .queue_capacity(1);
+        let panicking =
+            BlockingHandler::<ThreadName, _>::new(|_req| panic!("Expected panic"), pool);
+
+        let res = fibers_global::execute(handler.handle_request(req("/"))).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_ne!(*res.body(), format!("{:?}", thread::current().id()));
+
+        let res = fibers_global::execute(panicking.handle_request(req("/"))).unwrap();
+        assert_eq!(res.status_code(), 500);
+
+        // The queue is full while the first invocation is blocked
+        let waiting = handler.handle_request(req("/?wait"));
+        started_rx.recv().unwrap();
+        let res = fibers_global::execute(handler.handle_request(req("/"))).unwrap();
+        assert_eq!(res.status_code(), 503);
+
+        resume_tx.send(()).unwrap();
+        let res = fibers_global::execute(waiting).unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        // The worker thread survives the panic, and the slot is released
+        let res = fibers_global::execute(handler.handle_request(req("/"))).unwrap();
+        assert_eq!(res.status_code(), 200);
+    }
+}
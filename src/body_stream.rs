@@ -0,0 +1,317 @@
+use crate::{Error, ErrorKind};
+use bytecodec::{self, ByteCount, Decode, Eos};
+use futures::task::{self, Task};
+use futures::{Async, Poll, Stream};
+use httpcodec::{BodyDecode, BodyDecoder, Header};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// A request body that is received chunk by chunk.
+///
+/// This is passed to the handlers that use `BodyStreamDecoder` as the request body decoder.
+/// Such handlers are invoked as soon as the head part of a request has been received,
+/// and the chunks of the body are yielded by this stream while they are being read from the socket.
+///
+/// The chunks that have not been consumed are buffered up to the capacity of the decoder.
+/// Once the buffer is filled up, the server stops reading the body until some chunks are consumed.
+///
+/// If the connection is closed before the whole body has been received,
+/// the stream terminates with an error.
+#[derive(Debug)]
+pub struct BodyStream {
+    channel: Arc<Mutex<Channel>>,
+}
+impl BodyStream {
+    fn new(capacity: usize) -> Self {
+        let channel = Channel {
+            capacity,
+            ..Channel::default()
+        };
+        BodyStream {
+            channel: Arc::new(Mutex::new(channel)),
+        }
+    }
+}
+impl Stream for BodyStream {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut channel = self.channel.lock().expect("Never fails");
+        if let Some(chunk) = channel.chunks.pop_front() {
+            channel.buffered -= chunk.len();
+            if let Some(producer) = channel.producer.take() {
+                producer.notify();
+            }
+            return Ok(Async::Ready(Some(chunk)));
+        }
+        if channel.is_eos {
+            return Ok(Async::Ready(None));
+        }
+        track_assert!(
+            !channel.is_aborted,
+            ErrorKind::Other,
+            "The request body has not been received completely"
+        );
+        channel.consumer = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+/// A request body decoder that passes the body to the handler as a `BodyStream`.
+///
+/// Unlike the other decoders, the handler using this decoder is invoked without waiting for
+/// the whole body to be received (see the documentation of `BodyStream`).
+///
+/// The default capacity is `65536` bytes.
+#[derive(Debug)]
+pub struct BodyStreamDecoder {
+    inner: BodyDecoder<ChunkSink>,
+    channel: Arc<Mutex<Channel>>,
+    stream: Option<BodyStream>,
+}
+impl BodyStreamDecoder {
+    /// Makes a new `BodyStreamDecoder` instance.
+    ///
+    /// `capacity` is the number of bytes of unconsumed chunks that can be buffered.
+    pub fn new(capacity: usize) -> Self {
+        let stream = BodyStream::new(capacity);
+        let sink = ChunkSink {
+            channel: Arc::clone(&stream.channel),
+        };
+        BodyStreamDecoder {
+            inner: BodyDecoder::new(sink),
+            channel: Arc::clone(&stream.channel),
+            stream: Some(stream),
+        }
+    }
+
+    /// Takes the stream that will yield the chunks of the body.
+    pub(crate) fn take_stream(&mut self) -> Option<BodyStream> {
+        self.stream.take()
+    }
+
+    /// Returns `true` if the buffer of unconsumed chunks is full.
+    ///
+    /// If so, the current task will be notified when some chunks are consumed.
+    pub(crate) fn is_full(&self) -> bool {
+        let mut channel = self.channel.lock().expect("Never fails");
+        if channel.buffered < channel.capacity {
+            false
+        } else {
+            channel.producer = Some(task::current());
+            true
+        }
+    }
+}
+impl Default for BodyStreamDecoder {
+    fn default() -> Self {
+        Self::new(64 * 1024)
+    }
+}
+impl Decode for BodyStreamDecoder {
+    type Item = BodyStream;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.decode(buf, eos))
+    }
+
+    /// Finishes decoding.
+    ///
+    /// If the stream has already been taken by the server, an empty stream is returned.
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        track!(self.inner.finish_decoding())?;
+        Ok(self.stream.take().unwrap_or_else(|| {
+            let stream = BodyStream::new(0);
+            stream.channel.lock().expect("Never fails").is_eos = true;
+            stream
+        }))
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl BodyDecode for BodyStreamDecoder {
+    fn initialize(&mut self, header: &Header) -> bytecodec::Result<()> {
+        track!(self.inner.initialize(header))
+    }
+}
+
+/// A decoder that forwards the received bytes to a `BodyStream`.
+#[derive(Debug)]
+struct ChunkSink {
+    channel: Arc<Mutex<Channel>>,
+}
+impl Decode for ChunkSink {
+    type Item = ();
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        let mut channel = self.channel.lock().expect("Never fails");
+        if channel.is_eos {
+            return Ok(0);
+        }
+        // The limit is not strict so that a whole (e.g., decompressed) body can be fed at once
+        let size = if channel.buffered < channel.capacity {
+            buf.len()
+        } else {
+            0
+        };
+        if size > 0 {
+            channel.chunks.push_back(buf[..size].to_vec());
+            channel.buffered += size;
+        }
+        if eos.is_reached() {
+            channel.is_eos = true;
+        }
+        if size > 0 || channel.is_eos {
+            if let Some(consumer) = channel.consumer.take() {
+                consumer.notify();
+            }
+        }
+        Ok(size)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        let mut channel = self.channel.lock().expect("Never fails");
+        channel.is_eos = true;
+        if let Some(consumer) = channel.consumer.take() {
+            consumer.notify();
+        }
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.channel.lock().expect("Never fails").is_eos
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.is_idle() {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Infinite
+        }
+    }
+}
+impl Drop for ChunkSink {
+    fn drop(&mut self) {
+        if let Ok(mut channel) = self.channel.lock() {
+            if !channel.is_eos {
+                channel.is_aborted = true;
+                if let Some(consumer) = channel.consumer.take() {
+                    consumer.notify();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Channel {
+    chunks: VecDeque<Vec<u8>>,
+    buffered: usize,
+    capacity: usize,
+    is_eos: bool,
+    is_aborted: bool,
+    consumer: Option<Task>,
+    producer: Option<Task>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::DecodeExt;
+    use futures::Future;
+    use httpcodec::{BodyEncoder, HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+    fn req(content_length: usize) -> Request<()> {
+        let mut req = Request::new(
+            Method::new("POST").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        req.header_mut()
+            .add_field(HeaderField::new("Content-Length", &content_length.to_string()).unwrap());
+        req
+    }
+
+    #[test]
+    fn body_stream_decoder_works() {
+        let mut decoder = BodyStreamDecoder::new(4);
+        track_try_unwrap!(decoder.initialize(&req(6).header()));
+        let stream = decoder.take_stream().unwrap();
+        assert_eq!(
+            track_try_unwrap!(decoder.decode(b"foo", Eos::new(false))),
+            3
+        );
+        assert_eq!(track_try_unwrap!(decoder.decode(b"ba", Eos::new(false))), 2);
+        assert!(!decoder.is_idle());
+        assert_eq!(track_try_unwrap!(decoder.decode(b"r", Eos::new(false))), 0);
+        drop(decoder);
+
+        let result = stream.collect().wait();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn finished_body_stream_works() {
+        let mut decoder = BodyStreamDecoder::default();
+        track_try_unwrap!(decoder.initialize(&req(6).header()));
+        let stream = track_try_unwrap!(decoder.decode_from_bytes(b"foobar"));
+        let chunks = track_try_unwrap!(stream.concat2().wait());
+        assert_eq!(chunks, b"foobar");
+    }
+
+    #[test]
+    fn body_stream_works() {
+        use futures::Stream;
+        use httpcodec::{HttpVersion, Method, Request, RequestTarget};
+
+        struct Upload;
+        impl HandleRequest for Upload {
+            const METHOD: &'static str = "PUT";
+            const PATH: &'static str = "/upload";
+
+            type ReqBody = BodyStream;
+            type ResBody = String;
+            type Decoder = BodyStreamDecoder;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let future = req
+                    .into_body()
+                    .fold((0, 0), |(chunks, bytes), chunk| {
+                        Ok::<_, Error>((chunks + 1, bytes + chunk.len()))
+                    })
+                    .then(|result| {
+                        let (chunks, bytes) = result.unwrap();
+                        let body = format!("{} {}", chunks > 1, bytes);
+                        Ok(Res::new(Status::Ok, body))
+                    });
+                Box::new(future)
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Upload).unwrap();
+        let client = builder.finish_test_client();
+
+        let req = Request::new(
+            Method::new("PUT").unwrap(),
+            RequestTarget::new("/upload").unwrap(),
+            HttpVersion::V1_1,
+            vec![0; 200 * 1024],
+        );
+        let res = fibers_global::execute(client.send(req)).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"true 204800");
+    }
+}
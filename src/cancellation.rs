@@ -0,0 +1,145 @@
+use bytecodec::marker::Never;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A future that completes when the request being handled is cancelled.
+///
+/// A request is cancelled when the client disconnects (or the connection is terminated for
+/// other reasons) before the response is returned by the handler.
+/// In that case, the reply of the handler is dropped, but the work that has been started
+/// outside of the reply (e.g., on a thread pool) keeps running unless it checks this token.
+///
+/// This can be obtained via `req.extensions().get::<CancellationToken>()`.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{CancellationToken, Req};
+///
+/// fn handle(req: &Req<()>) {
+///     let token = req.extensions().get::<CancellationToken>().cloned();
+///     std::thread::spawn(move || {
+///         for _ in 0..100 {
+///             if token.as_ref().map_or(false, |t| t.is_cancelled()) {
+///                 return;
+///             }
+///             // ...expensive work...
+///         }
+///     });
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<CancellationState>,
+}
+impl CancellationToken {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the request has been cancelled, otherwise `false`.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.is_cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the request, and wakes up the tasks waiting for the cancellation.
+    pub(crate) fn cancel(&self) {
+        self.state.is_cancelled.store(true, Ordering::SeqCst);
+        let mut waiters = self.state.waiters.lock().unwrap_or_else(|e| e.into_inner());
+        for waiter in waiters.drain(..) {
+            waiter.notify();
+        }
+    }
+}
+impl Future for CancellationToken {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.is_cancelled() {
+            return Ok(Async::Ready(()));
+        }
+        let mut waiters = self.state.waiters.lock().unwrap_or_else(|e| e.into_inner());
+        if self.is_cancelled() {
+            Ok(Async::Ready(()))
+        } else {
+            waiters.push(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    is_cancelled: AtomicBool,
+    waiters: Mutex<Vec<Task>>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+
+    #[test]
+    fn cancellation_token_works() {
+        let token = CancellationToken::new();
+        let mut waiter = token.clone();
+        future::lazy(|| {
+            assert!(matches!(waiter.poll(), Ok(Async::NotReady)));
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+        assert!(!waiter.is_cancelled());
+
+        token.cancel();
+        assert!(waiter.is_cancelled());
+        assert!(waiter.wait().is_ok());
+    }
+
+    #[test]
+    fn cancellation_works() {
+        struct Forever(Arc<Mutex<Option<CancellationToken>>>);
+        impl HandleRequest for Forever {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/forever";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let token = req.extensions().get::<CancellationToken>().unwrap();
+                *self.0.lock().unwrap() = Some(token.clone());
+                let future = token.clone();
+                Box::new(future.then(|_| Ok(Res::new(Status::Ok, "cancelled".to_owned()))))
+            }
+        }
+
+        let token = Arc::new(Mutex::new(None));
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Forever(Arc::clone(&token))).unwrap();
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /forever HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+
+        let token = token.lock().unwrap().take().unwrap();
+        assert!(!token.is_cancelled());
+        assert_eq!(sim.metrics().cancelled_replies(), 0);
+
+        conn.shutdown();
+        sim.run().unwrap();
+        assert!(token.is_cancelled());
+        assert_eq!(sim.metrics().cancelled_replies(), 1);
+    }
+}
@@ -0,0 +1,205 @@
+//! Request/response body codecs for [CBOR] messages.
+//!
+//! This module is available only if the `cbor` feature is enabled.
+//!
+//! [CBOR]: https://cbor.io/
+use crate::codec::{RawBodyDecoder, RawBodyEncoder};
+use bytecodec::{self, ByteCount, Decode, Encode, Eos};
+use httpcodec::{BodyDecode, BodyEncode, Header, HeaderMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// The media types accepted by `CborDecoder`.
+pub const ACCEPTABLE_MEDIA_TYPES: &[&str] = &["application/cbor"];
+
+/// The media type set by `CborEncoder`.
+pub const MEDIA_TYPE: &str = "application/cbor";
+
+/// A request body decoder for CBOR messages.
+///
+/// If the media type of the `Content-Type` header of a request is not one of `ACCEPTABLE_MEDIA_TYPES`,
+/// the `415 Unsupported Media Type` response will be returned.
+/// If the body cannot be deserialized into `T`, the `400 Bad Request` response will be returned.
+pub struct CborDecoder<T> {
+    inner: RawBodyDecoder,
+    _message: PhantomData<fn() -> T>,
+}
+impl<T: DeserializeOwned> CborDecoder<T> {
+    /// Makes a new `CborDecoder` instance.
+    pub fn new() -> Self {
+        CborDecoder {
+            inner: RawBodyDecoder::new(ACCEPTABLE_MEDIA_TYPES),
+            _message: PhantomData,
+        }
+    }
+}
+impl<T: DeserializeOwned> Decode for CborDecoder<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        track!(self
+            .inner
+            .finish_decoding(|bytes| ciborium::de::from_reader(bytes)))
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl<T: DeserializeOwned> BodyDecode for CborDecoder<T> {
+    fn initialize(&mut self, header: &Header) -> bytecodec::Result<()> {
+        track!(self.inner.initialize(header))
+    }
+}
+impl<T: DeserializeOwned> Default for CborDecoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> fmt::Debug for CborDecoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CborDecoder {{ .. }}")
+    }
+}
+
+/// A response body encoder for CBOR messages.
+///
+/// The `Content-Type` header of a response is set to `MEDIA_TYPE`
+/// unless the handler has already set the header.
+pub struct CborEncoder<T> {
+    inner: RawBodyEncoder,
+    _message: PhantomData<fn(T)>,
+}
+impl<T: Serialize> CborEncoder<T> {
+    /// Makes a new `CborEncoder` instance.
+    pub fn new() -> Self {
+        CborEncoder {
+            inner: RawBodyEncoder::new(MEDIA_TYPE),
+            _message: PhantomData,
+        }
+    }
+}
+impl<T: Serialize> Encode for CborEncoder<T> {
+    type Item = T;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        track!(self.inner.start_encoding(item, |m| {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(m, &mut bytes).map(|()| bytes)
+        }))
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl<T: Serialize> BodyEncode for CborEncoder<T> {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        track!(self.inner.update_header(header))
+    }
+}
+impl<T: Serialize> Default for CborEncoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> fmt::Debug for CborEncoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CborEncoder {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use futures::future::ok;
+    use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct Greeting {
+        name: String,
+    }
+
+    struct Greet;
+    impl HandleRequest for Greet {
+        const METHOD: &'static str = "POST";
+        const PATH: &'static str = "/greet";
+
+        type ReqBody = Greeting;
+        type ResBody = Greeting;
+        type Decoder = CborDecoder<Greeting>;
+        type Encoder = CborEncoder<Greeting>;
+        type Reply = Reply<Self::ResBody>;
+
+        fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+            let name = format!("Hello {}", req.into_body().name);
+            Box::new(ok(Res::new(Status::Ok, Greeting { name })))
+        }
+    }
+
+    fn req(content_type: &str, body: Vec<u8>) -> Request<Vec<u8>> {
+        let mut req = Request::new(
+            Method::new("POST").unwrap(),
+            RequestTarget::new("/greet").unwrap(),
+            HttpVersion::V1_1,
+            body,
+        );
+        req.header_mut()
+            .add_field(HeaderField::new("Content-Type", content_type).unwrap());
+        req
+    }
+
+    fn encode(greeting: &Greeting) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(greeting, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn cbor_codec_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Greet).unwrap();
+        let client = builder.finish_test_client();
+
+        let body = encode(&Greeting {
+            name: "foo".to_owned(),
+        });
+        let res = fibers_global::execute(client.send(req("application/cbor", body))).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/cbor")
+        );
+        let greeting: Greeting = ciborium::de::from_reader(&res.body()[..]).unwrap();
+        assert_eq!(greeting.name, "Hello foo");
+
+        let body = encode(&Greeting {
+            name: "foo".to_owned(),
+        });
+        let res = fibers_global::execute(client.send(req("application/json", body))).unwrap();
+        assert_eq!(res.status_code(), 415);
+
+        let res = fibers_global::execute(client.send(req("application/cbor", vec![0xFF]))).unwrap();
+        assert_eq!(res.status_code(), 400);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{Error, ErrorKind, Result};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A block of IP addresses represented in the CIDR notation (e.g., `192.168.0.0/16`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+impl Cidr {
+    /// Makes a new `Cidr` instance.
+    ///
+    /// # Errors
+    ///
+    /// If `prefix_len` exceeds the bit length of `addr`,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        track_assert!(
+            prefix_len <= max,
+            ErrorKind::InvalidInput,
+            "Too large prefix length: addr={}, prefix_len={}",
+            addr,
+            prefix_len
+        );
+        Ok(Cidr { addr, prefix_len })
+    }
+
+    /// Returns the base address of the block.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the prefix length of the block.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns `true` if the block contains the given address, otherwise `false`.
+    ///
+    /// IPv4-mapped IPv6 addresses are regarded as the corresponding IPv4 addresses.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(a) => a.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            IpAddr::V4(_) => addr,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u32::from(a) & mask == u32::from(b) & mask
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u128::from(a) & mask == u128::from(b) & mask
+            }
+            _ => false,
+        }
+    }
+}
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix_len) = match s.find('/') {
+            None => {
+                let addr: IpAddr = track_assert_some!(s.parse().ok(), ErrorKind::InvalidInput; s);
+                (addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+            Some(i) => {
+                let addr = track_assert_some!(s[..i].parse().ok(), ErrorKind::InvalidInput; s);
+                let prefix_len =
+                    track_assert_some!(s[i + 1..].parse().ok(), ErrorKind::InvalidInput; s);
+                (addr, prefix_len)
+            }
+        };
+        track!(Cidr::new(addr, prefix_len))
+    }
+}
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct AccessControl {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+impl AccessControl {
+    pub fn allow<I>(&mut self, cidrs: I)
+    where
+        I: IntoIterator<Item = Cidr>,
+    {
+        self.allow.extend(cidrs);
+    }
+
+    pub fn deny<I>(&mut self, cidrs: I)
+    where
+        I: IntoIterator<Item = Cidr>,
+    {
+        self.deny.extend(cidrs);
+    }
+
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(addr)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(addr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        track_try_unwrap!(s.parse())
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_works() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.1.2.3")));
+        assert!(cidr("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!cidr("10.0.0.1").contains(ip("10.0.0.2")));
+        assert!(cidr("0.0.0.0/0").contains(ip("192.168.0.1")));
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(cidr("fe80::/10").contains(ip("fe80::1")));
+        assert!(!cidr("fe80::/10").contains(ip("10.0.0.1")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn access_control_works() {
+        let mut acl = AccessControl::default();
+        assert!(acl.is_allowed(ip("10.0.0.1")));
+
+        acl.allow(vec![cidr("10.0.0.0/8")]);
+        acl.deny(vec![cidr("10.0.0.0/24")]);
+        assert!(acl.is_allowed(ip("10.1.0.1")));
+        assert!(!acl.is_allowed(ip("10.0.0.1")));
+        assert!(!acl.is_allowed(ip("192.168.0.1")));
+    }
+}
@@ -0,0 +1,250 @@
+use fibers::time::timer;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A future that completes after a duration has elapsed on a `Clock`.
+pub struct Sleep(Box<dyn Future<Item = (), Error = ()> + Send + 'static>);
+impl Sleep {
+    /// Makes a new `Sleep` instance.
+    pub fn new<F>(future: F) -> Self
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        Sleep(Box::new(future))
+    }
+}
+impl Future for Sleep {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+impl fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sleep(_)")
+    }
+}
+
+/// `Clock` is the source of time used by a server.
+///
+/// The server uses the clock for timeouts, rate limiting, bandwidth throttling and
+/// measuring the duration of requests (see `ServerBuilder::clock`).
+/// Tests can replace the clock with `ManualClock` to control time deterministically.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes after `duration` has elapsed.
+    ///
+    /// The future has to be polled on fibers.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// The clock that follows the real time.
+///
+/// This is the default clock of servers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Sleep::new(timer::timeout(duration).map_err(|_| ()))
+    }
+}
+
+/// A clock whose time advances only when `ManualClock::advance` is called.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{Clock, ManualClock};
+/// use std::time::Duration;
+///
+/// let clock = ManualClock::new();
+/// let start = clock.now();
+///
+/// clock.advance(Duration::from_secs(10));
+/// assert_eq!(clock.now() - start, Duration::from_secs(10));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    base: Instant,
+    state: Arc<Mutex<ManualClockState>>,
+}
+impl ManualClock {
+    /// Makes a new `ManualClock` instance.
+    pub fn new() -> Self {
+        ManualClock {
+            base: Instant::now(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Advances the time of the clock by `duration`.
+    ///
+    /// The `Sleep` futures that have expired are woken up.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.elapsed += duration;
+        for waiter in state.waiters.drain(..) {
+            waiter.notify();
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).elapsed
+    }
+}
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let deadline = self.now() + duration;
+        Sleep::new(ManualSleep {
+            clock: self.clone(),
+            deadline,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct ManualClockState {
+    elapsed: Duration,
+    waiters: Vec<Task>,
+}
+
+#[derive(Debug)]
+struct ManualSleep {
+    clock: ManualClock,
+    deadline: Instant,
+}
+impl Future for ManualSleep {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut state = self.clock.state.lock().unwrap_or_else(|e| e.into_inner());
+        if self.clock.base + state.elapsed >= self.deadline {
+            Ok(Async::Ready(()))
+        } else {
+            state.waiters.push(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// A shared `Clock`.
+///
+/// The clock of a server can be obtained from requests via `req.extensions().get::<SharedClock>()`.
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+impl SharedClock {
+    /// Makes a new `SharedClock` instance.
+    pub fn new<C: Clock>(clock: C) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+}
+impl Clock for SharedClock {
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        self.0.sleep(duration)
+    }
+}
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(SystemClock)
+    }
+}
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedClock(_)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::thread;
+
+    #[test]
+    fn manual_clock_works() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut sleep = clock.sleep(Duration::from_secs(3));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(sleep.poll(), Ok(Async::Ready(())));
+    }
+
+    #[test]
+    fn server_manual_clock_works() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct Sleepy;
+        impl HandleRequest for Sleepy {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/sleepy";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let clock = req.extensions().get::<SharedClock>().unwrap();
+                let sleep = clock.sleep(Duration::from_secs(2 * 60 * 60));
+                Box::new(sleep.then(|_| Ok(Res::new(Status::Ok, "zzz".to_owned()))))
+            }
+        }
+
+        let clock = ManualClock::new();
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Sleepy).unwrap();
+        builder.clock(clock.clone());
+        builder.slow_request_threshold(Duration::from_secs(60 * 60));
+        let client = builder.finish_test_client();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = {
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(10));
+                    clock.advance(Duration::from_secs(60 * 60));
+                }
+            })
+        };
+        let res = fibers_global::execute(client.get("/sleepy").unwrap()).unwrap();
+        done.store(true, Ordering::SeqCst);
+        ticker.join().unwrap();
+
+        assert_eq!(res.body(), b"zzz");
+        assert_eq!(client.metrics().slow_requests("GET", "/sleepy"), Some(1));
+    }
+}
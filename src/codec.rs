@@ -0,0 +1,111 @@
+//! Building blocks of the body codecs for serialized messages (e.g., Protocol Buffers).
+use crate::header::{self, ContentType, TypedHeader};
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use bytecodec::{self, ByteCount, Decode, Encode, Eos};
+use httpcodec::{BodyDecode, BodyDecoder, BodyEncode, BodyEncoder, Header, HeaderField, HeaderMut};
+use std::error;
+use trackable::error::ErrorKindExt;
+
+/// A decoder that collects the whole body of a request whose `Content-Type` is one of `media_types`.
+#[derive(Debug)]
+pub struct RawBodyDecoder {
+    inner: BodyDecoder<RemainingBytesDecoder>,
+    media_types: &'static [&'static str],
+}
+impl RawBodyDecoder {
+    pub fn new(media_types: &'static [&'static str]) -> Self {
+        RawBodyDecoder {
+            inner: BodyDecoder::new(RemainingBytesDecoder::new()),
+            media_types,
+        }
+    }
+
+    pub fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.decode(buf, eos))
+    }
+
+    /// Finishes decoding, and deserializes the body by using `f`.
+    ///
+    /// Deserialization errors are reported as `bytecodec::ErrorKind::InvalidInput`.
+    pub fn finish_decoding<T, E, F>(&mut self, f: F) -> bytecodec::Result<T>
+    where
+        F: FnOnce(&[u8]) -> Result<T, E>,
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        let bytes = track!(self.inner.finish_decoding())?;
+        f(&bytes).map_err(|e| {
+            track!(bytecodec::Error::from(
+                bytecodec::ErrorKind::InvalidInput.cause(e)
+            ))
+        })
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    pub fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+
+    pub fn initialize(&mut self, header: &Header) -> bytecodec::Result<()> {
+        track!(header::check_content_type(header, self.media_types))?;
+        track!(self.inner.initialize(header))
+    }
+}
+
+/// An encoder of already serialized response bodies.
+///
+/// The `Content-Type` header is set to `media_type` unless the handler has already set the header.
+#[derive(Debug)]
+pub struct RawBodyEncoder {
+    inner: BodyEncoder<BytesEncoder>,
+    media_type: &'static str,
+}
+impl RawBodyEncoder {
+    pub fn new(media_type: &'static str) -> Self {
+        RawBodyEncoder {
+            inner: BodyEncoder::new(BytesEncoder::new()),
+            media_type,
+        }
+    }
+
+    pub fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.encode(buf, eos))
+    }
+
+    /// Serializes `item` by using `f`, and starts encoding the result.
+    ///
+    /// Serialization errors are reported as `bytecodec::ErrorKind::InvalidInput`.
+    pub fn start_encoding<T, E, F>(&mut self, item: T, f: F) -> bytecodec::Result<()>
+    where
+        F: FnOnce(&T) -> Result<Vec<u8>, E>,
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        let bytes = f(&item).map_err(|e| {
+            track!(bytecodec::Error::from(
+                bytecodec::ErrorKind::InvalidInput.cause(e)
+            ))
+        })?;
+        track!(self.inner.start_encoding(bytes))
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    pub fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+
+    pub fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        let has_content_type = header
+            .fields()
+            .any(|f| f.name().eq_ignore_ascii_case(ContentType::NAME));
+        if !has_content_type {
+            let field = unsafe { HeaderField::new_unchecked(ContentType::NAME, self.media_type) };
+            header.add_field(field);
+        }
+        track!(self.inner.update_header(header))
+    }
+}
@@ -0,0 +1,336 @@
+//! Compatibility layer for the [`http`] crate.
+//!
+//! This module provides conversions between `Req`/`Res` and `http::Request`/`http::Response`,
+//! and adapters between `HandleRequest` and a service-like interface based on the `http` types.
+//!
+//! [`http`]: https://crates.io/crates/http
+use crate::{Error, ErrorKind, HandleRequest, Priority, Reply, Req, Res, Result, Status};
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use futures::{Future, Poll};
+use httpcodec::{
+    BodyDecoder, BodyEncoder, HeaderField, HttpVersion, Method, ReasonPhrase, Request,
+    RequestTarget, Response, StatusCode,
+};
+use trackable::error::ErrorKindExt;
+use url::Url;
+
+/// `Service` is a service-like interface based on the types of the `http` crate.
+///
+/// Implementations can be registered to a server via `ServiceHandler`.
+pub trait Service: Send + Sync + 'static {
+    /// The method that the service can handle.
+    const METHOD: &'static str;
+
+    /// The methods that the service can handle.
+    ///
+    /// See the documentation of `HandleRequest::METHODS` for the details.
+    const METHODS: &'static [&'static str] = &[Self::METHOD];
+
+    /// The request path that the service can handle.
+    ///
+    /// See the documentation of `HandleRequest::PATH` for the syntax.
+    const PATH: &'static str;
+
+    /// The priority class of the requests handled by the service.
+    ///
+    /// See the documentation of `HandleRequest::PRIORITY` for the details.
+    const PRIORITY: Priority = Priority::Normal;
+
+    /// The query parameters that the requests handled by the service must have.
+    ///
+    /// See the documentation of `HandleRequest::QUERY` for the details.
+    const QUERY: &'static [(&'static str, &'static str)] = &[];
+
+    /// `Future` that represents the response to a request.
+    ///
+    /// If it fails, the error is logged and the `500 Internal Server Error` response
+    /// (with the reason phrase as the body) will be returned to the client.
+    type Future: Future<Item = http::Response<Vec<u8>>, Error = Error> + Send + 'static;
+
+    /// Handles a request.
+    fn call(&self, req: http::Request<Vec<u8>>) -> Self::Future;
+}
+
+/// A `HandleRequest` implementation that delegates requests to the inner `Service`.
+#[derive(Debug)]
+pub struct ServiceHandler<S>(S);
+impl<S: Service> ServiceHandler<S> {
+    /// Makes a new `ServiceHandler` instance.
+    pub fn new(service: S) -> Self {
+        ServiceHandler(service)
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn inner_ref(&self) -> &S {
+        &self.0
+    }
+
+    /// Takes ownership of the handler, and returns the inner service.
+    pub fn into_inner(self) -> S {
+        self.0
+    }
+}
+impl<S: Service> HandleRequest for ServiceHandler<S> {
+    const METHOD: &'static str = S::METHOD;
+    const METHODS: &'static [&'static str] = S::METHODS;
+    const PATH: &'static str = S::PATH;
+    const PRIORITY: Priority = S::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = S::QUERY;
+
+    type ReqBody = Vec<u8>;
+    type ResBody = Vec<u8>;
+    type Decoder = BodyDecoder<RemainingBytesDecoder>;
+    type Encoder = BodyEncoder<BytesEncoder>;
+    type Reply = Reply<Self::ResBody>;
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let logger = req.logger().clone();
+        let future = self.0.call(req.into()).then(move |result| {
+            let res = result
+                .and_then(|res| track!(Res::from_http(res)))
+                .unwrap_or_else(|e| {
+                    // The details of the error are not exposed to the client
+                    error!(logger, "Cannot handle a HTTP request by the service: {}", e);
+                    let status = Status::InternalServerError;
+                    Res::new(status, status.reason_phrase().as_bytes().to_owned())
+                });
+            Ok(res)
+        });
+        Box::new(future)
+    }
+}
+
+/// A service-like wrapper of `HandleRequest` that handles `http::Request`s.
+///
+/// Note that `HandleRequest::handle_request_head` is invoked before `HandleRequest::handle_request`
+/// as in the case of being driven by a server.
+#[derive(Debug)]
+pub struct HandlerService<H>(H);
+impl<H: HandleRequest> HandlerService<H> {
+    /// Makes a new `HandlerService` instance.
+    pub fn new(handler: H) -> Self {
+        HandlerService(handler)
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn inner_ref(&self) -> &H {
+        &self.0
+    }
+
+    /// Takes ownership of the service, and returns the inner handler.
+    pub fn into_inner(self) -> H {
+        self.0
+    }
+
+    /// Handles a request.
+    pub fn call(&self, req: http::Request<H::ReqBody>) -> HandlerServiceFuture<H> {
+        let req = match track!(Req::from_http(req)) {
+            Err(e) => return HandlerServiceFuture(Err(Some(e))),
+            Ok(req) => req,
+        };
+        let (head, body) = req.take_body();
+        if let Some(res) = self.0.handle_request_head(&head) {
+            let future = Box::new(futures::finished(res));
+            return HandlerServiceFuture(Ok(future));
+        }
+        let req = head.map_body(|()| body);
+        let future = Box::new(self.0.handle_request(req));
+        HandlerServiceFuture(Ok(future))
+    }
+}
+
+/// `Future` that returned by `HandlerService::call` method.
+pub struct HandlerServiceFuture<H: HandleRequest>(
+    std::result::Result<Reply<H::ResBody>, Option<Error>>,
+);
+impl<H: HandleRequest> Future for HandlerServiceFuture<H> {
+    type Item = http::Response<H::ResBody>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Err(ref mut e) => Err(e.take().expect("Cannot poll HandlerServiceFuture twice")),
+            Ok(ref mut f) => Ok(f.poll().expect("Never fails").map(From::from)),
+        }
+    }
+}
+impl<H: HandleRequest> std::fmt::Debug for HandlerServiceFuture<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "HandlerServiceFuture(_)")
+    }
+}
+
+impl<T> Req<T> {
+    /// Makes a new `Req` instance from the given `http::Request`.
+    ///
+    /// If the URI of the request has no authority, `localhost` is used as the host of the URL.
+    ///
+    /// Because `http::Request` does not hold the address of the client,
+    /// `0.0.0.0:0` is used as the peer address of the resulting request.
+    ///
+    /// # Errors
+    ///
+    /// If the request contains elements that cannot be represented by `Req`,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn from_http(req: http::Request<T>) -> Result<Self> {
+        let (parts, body) = req.into_parts();
+        let version = track!(version_from_http(parts.version))?;
+        let target = parts
+            .uri
+            .path_and_query()
+            .map_or("/", |x| x.as_str())
+            .to_owned();
+        let base_url = format!(
+            "{}://{}/",
+            parts.uri.scheme_str().unwrap_or("http"),
+            parts.uri.authority().map_or("localhost", |x| x.as_str())
+        );
+        let base_url = track!(Url::parse(&base_url).map_err(Error::from))?;
+
+        let method = track!(Method::new(parts.method.as_str()).map_err(Error::from))?;
+        let target = track!(RequestTarget::new(&target).map_err(Error::from))?;
+        let mut inner = Request::new(method, target, version, body);
+        for (name, value) in &parts.headers {
+            let value = track!(value
+                .to_str()
+                .map_err(|e| Error::from(ErrorKind::InvalidInput.cause(e))))?;
+            let field = track!(HeaderField::new(name.as_str(), value).map_err(Error::from))?;
+            inner.header_mut().add_field(field);
+        }
+        track!(Req::new(inner, &base_url, ([0, 0, 0, 0], 0).into()))
+    }
+}
+impl<T> From<Req<T>> for http::Request<T> {
+    fn from(f: Req<T>) -> Self {
+        let mut builder = http::Request::builder()
+            .method(f.method())
+            .uri(f.url().as_str())
+            .version(version_into_http(f.version()));
+        for field in f.header().fields() {
+            builder = builder.header(field.name(), field.value());
+        }
+        builder
+            .body(f.into_body())
+            .expect("`Req` always consists of valid components")
+    }
+}
+
+impl<T> Res<T> {
+    /// Makes a new `Res` instance from the given `http::Response`.
+    ///
+    /// # Errors
+    ///
+    /// If the response contains elements that cannot be represented by `Res`,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn from_http(res: http::Response<T>) -> Result<Self> {
+        let (parts, body) = res.into_parts();
+        let version = track!(version_from_http(parts.version))?;
+        let status = track!(StatusCode::new(parts.status.as_u16()).map_err(Error::from))?;
+        let reason = parts.status.canonical_reason().unwrap_or("Unknown");
+        let reason = track!(ReasonPhrase::new(reason).map_err(Error::from))?;
+        let mut inner = Response::new(version, status, reason, body);
+        for (name, value) in &parts.headers {
+            let value = track!(value
+                .to_str()
+                .map_err(|e| Error::from(ErrorKind::InvalidInput.cause(e))))?;
+            let field = track!(HeaderField::new(name.as_str(), value).map_err(Error::from))?;
+            inner.header_mut().add_field(field);
+        }
+        Ok(Res(inner))
+    }
+}
+impl<T> From<Res<T>> for http::Response<T> {
+    fn from(f: Res<T>) -> Self {
+        let mut builder = http::Response::builder()
+            .status(f.status_code())
+            .version(version_into_http(f.version()));
+        for field in f.header().fields() {
+            builder = builder.header(field.name(), field.value());
+        }
+        builder
+            .body(f.0.into_body())
+            .expect("`Res` always consists of valid components")
+    }
+}
+
+fn version_from_http(version: http::Version) -> Result<HttpVersion> {
+    match version {
+        http::Version::HTTP_10 => Ok(HttpVersion::V1_0),
+        http::Version::HTTP_11 => Ok(HttpVersion::V1_1),
+        _ => track_panic!(
+            ErrorKind::InvalidInput,
+            "Unsupported version: {:?}",
+            version
+        ),
+    }
+}
+
+fn version_into_http(version: HttpVersion) -> http::Version {
+    match version {
+        HttpVersion::V1_0 => http::Version::HTTP_10,
+        HttpVersion::V1_1 => http::Version::HTTP_11,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::ok;
+
+    #[test]
+    fn res_conversion_works() {
+        let mut res = Res::new(Status::NotFound, "foo");
+        res.header_mut()
+            .add_field(HeaderField::new("X-Foo", "bar").unwrap());
+
+        let res: http::Response<_> = res.into();
+        assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["x-foo"], "bar");
+
+        let res = track_try_unwrap!(Res::from_http(res));
+        assert_eq!(res.status_code(), 404);
+        assert_eq!(res.header().get_field("X-Foo"), Some("bar"));
+        assert_eq!(*res.body(), "foo");
+    }
+
+    #[test]
+    fn req_conversion_works() {
+        let req = http::Request::put("/foo/bar?baz=1")
+            .header("Content-Length", "3")
+            .body("qux")
+            .unwrap();
+        let req = track_try_unwrap!(Req::from_http(req));
+        assert_eq!(req.method(), "PUT");
+        assert_eq!(req.url().path(), "/foo/bar");
+        assert_eq!(req.url().query(), Some("baz=1"));
+        assert_eq!(req.header().get_field("content-length"), Some("3"));
+
+        let req: http::Request<_> = req.into();
+        assert_eq!(req.method(), http::Method::PUT);
+        assert_eq!(req.uri().path(), "/foo/bar");
+        assert_eq!(*req.body(), "qux");
+    }
+
+    struct Echo;
+    impl Service for Echo {
+        const METHOD: &'static str = "POST";
+        const PATH: &'static str = "/echo";
+
+        type Future = futures::future::FutureResult<http::Response<Vec<u8>>, Error>;
+
+        fn call(&self, req: http::Request<Vec<u8>>) -> Self::Future {
+            ok(http::Response::new(req.into_body()))
+        }
+    }
+
+    #[test]
+    fn service_adapters_work() {
+        let service = HandlerService::new(ServiceHandler::new(Echo));
+        let req = http::Request::post("/echo")
+            .body(b"hello".to_vec())
+            .unwrap();
+        let res = track_try_unwrap!(service.call(req).wait());
+        assert_eq!(res.status(), http::StatusCode::OK);
+        assert_eq!(res.body(), b"hello");
+    }
+}
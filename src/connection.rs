@@ -1,62 +1,301 @@
-use crate::dispatcher::Dispatcher;
-use crate::handler::{BoxReply, HandleInput, RequestHandlerInstance};
+use crate::access_log::{AccessLogEntry, SharedAccessLog};
+use crate::bandwidth::Throttled;
+use crate::cancellation::CancellationToken;
+use crate::clock::{Clock, SharedClock, Sleep};
+use crate::csrf::CsrfProtection;
+use crate::debug::{ConnectionRegistry, RegisteredConnection};
+use crate::decompression::DecompressionError;
+use crate::dispatcher::{DispatchError, DispatchErrorHandler, Dispatcher, Route};
+use crate::drain::{DrainState, DrainWatch, ShutdownSignal};
+use crate::extensions::Extensions;
+use crate::forwarded;
+use crate::handle::{ReloadableOptions, ReloadableValues};
+use crate::handler::{BoxReply, FlushMode, HandleInput, RequestHandlerInstance};
+use crate::head_limits::HeadLimitsDecoder;
+use crate::header::{self, UnsupportedMediaType};
+use crate::host_validation::HostValidation;
+use crate::load_shedding::{InFlightRequest, LoadShedder};
 use crate::metrics::ServerMetrics;
+use crate::observer::{ConnectionPhase, RequestTraffic, SharedObserver};
+use crate::path_decoding::PathDecoding;
+use crate::rate_limit::RateLimiter;
 use crate::response::ResEncoder;
-use crate::server::ServerOptions;
-use crate::{Error, Req, Result, Status};
+use crate::sampling::{Sample, Tee};
+use crate::server::{ConnectionErrorHook, RequestHook, ResponseHook, ServerOptions};
+use crate::smuggling::{self, ChunkValidator, SmugglingViolation};
+use crate::trace::{self, TracePolicy};
+use crate::tunnel::{ConnectHandler, PendingTunnel, Relay};
+use crate::{Error, ErrorKind, ReadinessGate, Req, RequestSampler, Result, Status};
 use bytecodec::combinator::MaybeEos;
 use bytecodec::io::{BufferedIo, IoDecodeExt, IoEncodeExt};
-use bytecodec::{Decode, DecodeExt, Encode};
+use bytecodec::{self, ByteCount, Decode, DecodeExt, Encode, Eos};
 use fibers::net::TcpStream;
 use futures::{Async, Future, Poll};
-use httpcodec::{NoBodyDecoder, RequestDecoder};
+use httpcodec::{NoBodyDecoder, Request, RequestDecoder};
 use slog::Logger;
+use std::cmp;
+use std::io::{self, Read, Write};
 use std::mem;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use url::Url;
+use std::time::{Duration, Instant, SystemTime};
+use url::{Position, Url};
+
+/// The underlying stream of a connection.
+pub trait Transport: Read + Write + Send + 'static {
+    /// Returns the TCP stream to which responses can be written directly (see `ResEncoder::write_to`).
+    fn tcp_stream(&mut self) -> Option<&mut TcpStream> {
+        None
+    }
+
+    /// Corks or uncorks the stream (see `FlushMode::Cork`).
+    fn set_cork(&mut self, _cork: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Transport for TcpStream {
+    fn tcp_stream(&mut self) -> Option<&mut TcpStream> {
+        Some(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_cork(&mut self, cork: bool) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = self.with_inner(|s| s.as_raw_fd());
+        let value = libc::c_int::from(cork);
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_CORK,
+                &value as *const _ as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct Connection {
+pub struct Connection<S = TcpStream> {
     logger: Logger,
     metrics: ServerMetrics,
-    stream: BufferedIo<TcpStream>,
-    req_head_decoder: MaybeEos<RequestDecoder<NoBodyDecoder>>,
+    stream: BufferedIo<Throttled<S>>,
+    peer_addr: SocketAddr,
+    req_head_decoder: HeadLimitsDecoder<MaybeEos<RequestDecoder<NoBodyDecoder>>>,
     dispatcher: Dispatcher,
+    dispatch_error_handler: Option<DispatchErrorHandler>,
+    rate_limiter: Option<RateLimiter>,
+    load_shedder: Option<LoadShedder>,
+    readiness_gate: Option<ReadinessGate>,
+    in_flight: Option<InFlightRequest>,
+    debug_entry: Option<RegisteredConnection>,
+    csrf_protection: Option<Arc<CsrfProtection>>,
+    host_validation: Option<Arc<HostValidation>>,
+    smuggling_protection: bool,
+    chunk_validator: Option<ChunkValidator>,
+    trace_policy: TracePolicy,
+    connect_handler: Option<ConnectHandler>,
+    observer: Option<SharedObserver>,
+    access_log: Option<SharedAccessLog>,
+    request_id: Option<String>,
+    user_agent: Option<String>,
+    sampler: Option<RequestSampler>,
+    sample: Option<Sample>,
+    request_hook: Option<RequestHook>,
+    response_hook: Option<ResponseHook>,
+    connection_error_hook: Option<ConnectionErrorHook>,
+    method_override: bool,
+    is_trusted_proxy: bool,
+    default_headers: Arc<Vec<(String, String)>>,
+    state: Arc<Extensions>,
+    request_ids: Arc<AtomicU64>,
+    request_logger: Option<Logger>,
+    cancellation: Option<CancellationToken>,
+    reloadable: Arc<ReloadableOptions>,
+    reload_version: usize,
+    connection_id: u64,
+    traffic: RequestTraffic,
     is_server_alive: Arc<AtomicBool>,
+    drain: DrainWatch,
+    shutdown_signal: ShutdownSignal,
     base_url: Url,
     phase: Phase,
+    last_phase: ConnectionPhase,
     do_close: bool,
+    timeouts: Timeouts,
+    timeout: Option<(TimeoutKind, Sleep)>,
+    buffer_sizes: BufferSizes,
+    buffer_usage: BufferUsage,
+    vectored_write_threshold: usize,
+    write_high_watermark: usize,
+    written_since_yield: usize,
+    max_decompressed_size: Option<usize>,
+    max_drained_size: usize,
+    body_drainer: BodyDrainer,
+    cached_handler: Option<RequestHandlerInstance>,
+    slow_request_threshold: Option<Duration>,
+    request_started_at: Option<Instant>,
+    first_byte: Option<FirstByte>,
+    clock: SharedClock,
+    route: Option<Route>,
+    flush_mode: FlushMode,
+    path_decoding: Option<PathDecoding>,
 }
 impl Connection {
     pub fn new(
         logger: Logger,
         metrics: ServerMetrics,
         stream: TcpStream,
+        peer_addr: SocketAddr,
         dispatcher: Dispatcher,
         is_server_alive: Arc<AtomicBool>,
         options: &ServerOptions,
     ) -> Result<Self> {
-        let _ = stream.set_nodelay(true);
-        let base_url = format!(
-            "http://{}/",
-            track!(stream.local_addr().map_err(Error::from))?
-        );
+        if let Err(e) = options.socket.apply(&stream) {
+            warn!(logger, "Cannot set socket options: {}", e);
+        }
+        let local_addr = track!(stream.local_addr().map_err(Error::from))?;
+        track!(Connection::with_transport(
+            logger,
+            metrics,
+            stream,
+            peer_addr,
+            local_addr,
+            dispatcher,
+            is_server_alive,
+            options
+        ))
+    }
+}
+impl<S: Transport> Connection<S> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_transport(
+        logger: Logger,
+        metrics: ServerMetrics,
+        stream: S,
+        peer_addr: SocketAddr,
+        local_addr: SocketAddr,
+        dispatcher: Dispatcher,
+        is_server_alive: Arc<AtomicBool>,
+        options: &ServerOptions,
+    ) -> Result<Self> {
+        let base_url = match options.base_url {
+            Some(ref url) => format!(
+                "{}://{}/",
+                url.scheme(),
+                &url[Position::BeforeHost..Position::AfterPort]
+            ),
+            None => format!("http://{}/", local_addr),
+        };
         let base_url = track!(Url::parse(&base_url).map_err(Error::from))?;
 
+        let connection_id = options.connection_ids.fetch_add(1, Ordering::SeqCst);
+        let logger = logger.new(o!("connection_id" => connection_id));
         metrics.connected_tcp_clients.increment();
         let req_head_decoder =
             RequestDecoder::with_options(NoBodyDecoder, options.decode_options.clone());
+        let reload_version = options.reloadable.version();
+        let values = options.reloadable.values();
+        let timeouts = Timeouts::new(&values, &options.clock);
+        let timeout = timeouts.start(&Phase::ReadRequestHead);
+        let buffer_sizes = BufferSizes {
+            read: options.read_buffer_size,
+            max_read: options.max_read_buffer_size,
+            write: options.write_buffer_size,
+            max_write: options.max_write_buffer_size,
+        };
+        metrics.update_buffer_high_watermarks(buffer_sizes.read, buffer_sizes.write);
         Ok(Connection {
             logger,
             metrics,
-            stream: BufferedIo::new(stream, options.read_buffer_size, options.write_buffer_size),
-            req_head_decoder: req_head_decoder.maybe_eos(),
+            stream: BufferedIo::new(
+                Throttled::new(
+                    stream,
+                    options.read_bandwidth_limit,
+                    options.write_bandwidth_limit,
+                    options.clock.clone(),
+                ),
+                options.read_buffer_size,
+                options.write_buffer_size,
+            ),
+            peer_addr,
+            req_head_decoder: HeadLimitsDecoder::new(
+                req_head_decoder.maybe_eos(),
+                values.head_limits,
+                options.decode_options.clone(),
+            ),
             dispatcher,
+            dispatch_error_handler: options.dispatch_error_handler.clone(),
+            rate_limiter: options.rate_limiter.clone(),
+            load_shedder: options.load_shedder.clone(),
+            readiness_gate: options.readiness_gate.clone(),
+            in_flight: None,
+            debug_entry: options
+                .debug_connections
+                .as_ref()
+                .map(|r| ConnectionRegistry::register(r, connection_id, peer_addr)),
+            csrf_protection: options.csrf_protection.clone(),
+            host_validation: options.host_validation.clone(),
+            smuggling_protection: options.smuggling_protection,
+            chunk_validator: None,
+            trace_policy: options.trace_policy,
+            connect_handler: options.connect_handler.clone(),
+            observer: options.connection_observer.clone(),
+            access_log: options.access_log.clone(),
+            request_id: None,
+            user_agent: None,
+            sampler: options.request_sampler.clone(),
+            sample: None,
+            request_hook: options.request_hook.clone(),
+            response_hook: options.response_hook.clone(),
+            connection_error_hook: options.connection_error_hook.clone(),
+            method_override: options.method_override,
+            is_trusted_proxy: options.base_url.is_none()
+                && options
+                    .trusted_proxies
+                    .iter()
+                    .any(|c| c.contains(peer_addr.ip())),
+            default_headers: Arc::clone(&options.default_headers),
+            state: Arc::clone(&options.state),
+            request_ids: Arc::clone(&options.request_ids),
+            request_logger: None,
+            cancellation: None,
+            reloadable: Arc::clone(&options.reloadable),
+            reload_version,
+            connection_id,
+            traffic: RequestTraffic::new(connection_id, peer_addr),
             is_server_alive,
+            drain: DrainState::watch(&options.drain),
+            shutdown_signal: ShutdownSignal::new(Arc::clone(&options.drain)),
             base_url,
             phase: Phase::ReadRequestHead,
+            last_phase: ConnectionPhase::Idle,
             do_close: false,
+            timeouts,
+            timeout,
+            buffer_sizes,
+            buffer_usage: BufferUsage::default(),
+            vectored_write_threshold: options.vectored_write_threshold,
+            write_high_watermark: options.write_high_watermark,
+            written_since_yield: 0,
+            max_decompressed_size: options.max_decompressed_request_body_size,
+            max_drained_size: options.max_drained_request_body_size,
+            body_drainer: BodyDrainer::default(),
+            cached_handler: None,
+            slow_request_threshold: options.slow_request_threshold,
+            request_started_at: None,
+            first_byte: None,
+            clock: options.clock.clone(),
+            route: None,
+            flush_mode: FlushMode::Auto,
+            path_decoding: options.path_decoding.clone(),
         })
     }
 
@@ -66,7 +305,64 @@ impl Connection {
             || !self.is_server_alive.load(Ordering::SeqCst)
     }
 
+    fn adjust_buffers(&mut self) {
+        let sizes = &self.buffer_sizes;
+        let mut grown = false;
+
+        let rbuf = self.stream.read_buf_mut();
+        if let Some(size) = grown_buffer_size(rbuf.is_full(), rbuf.capacity(), sizes.max_read) {
+            rbuf.inner_mut().resize(size, 0);
+            grown = true;
+        } else if rbuf.is_empty() && rbuf.capacity() > sizes.read && self.phase.is_idle() {
+            rbuf.inner_mut().truncate(sizes.read);
+            rbuf.inner_mut().shrink_to_fit();
+        }
+
+        let pending = matches!(self.phase, Phase::WriteResponse(_));
+        let wbuf = self.stream.write_buf_mut();
+        if let Some(size) =
+            grown_buffer_size(pending && wbuf.is_full(), wbuf.capacity(), sizes.max_write)
+        {
+            wbuf.inner_mut().resize(size, 0);
+            grown = true;
+        } else if wbuf.is_empty() && wbuf.capacity() > sizes.write && self.phase.is_idle() {
+            wbuf.inner_mut().truncate(sizes.write);
+            wbuf.inner_mut().shrink_to_fit();
+        }
+
+        if grown {
+            self.metrics.update_buffer_high_watermarks(
+                self.stream.read_buf_ref().capacity(),
+                self.stream.write_buf_ref().capacity(),
+            );
+        }
+
+        // The buffers that are still full could not be grown
+        let is_read_full = self.stream.read_buf_ref().is_full();
+        if is_read_full && !self.buffer_usage.is_read_full {
+            self.metrics.read_buffer_full_stalls.increment();
+        }
+        self.buffer_usage.is_read_full = is_read_full;
+
+        let is_write_full = pending && self.stream.write_buf_ref().is_full();
+        if is_write_full && !self.buffer_usage.is_write_full {
+            self.metrics.write_buffer_full_stalls.increment();
+        }
+        self.buffer_usage.is_write_full = is_write_full;
+    }
+
     fn read_request_head(&mut self) -> Phase {
+        if !self.body_drainer.is_idle() {
+            // Skips the body of the previous request that has been left unread by the handler
+            let _ = self
+                .body_drainer
+                .decode_from_read_buf(self.stream.read_buf_mut());
+            if !self.body_drainer.is_idle() {
+                return Phase::ReadRequestHead;
+            }
+        }
+
+        let before = self.stream.read_buf_ref().len();
         let result = self
             .req_head_decoder
             .decode_from_read_buf(self.stream.read_buf_mut())
@@ -77,54 +373,368 @@ impl Connection {
                     Ok(None)
                 }
             });
+        self.traffic.bytes_read += (before - self.stream.read_buf_ref().len()) as u64;
         match result {
             Err(e) => {
                 warn!(
                     self.logger,
                     "Cannot decode the head part of a HTTP request: {}", e
                 );
-                self.metrics.read_request_head_errors.increment();
                 self.do_close = true;
-                Phase::WriteResponse(ResEncoder::error(Status::BadRequest))
+                if let Some(violation) = self.req_head_decoder.violation() {
+                    self.metrics.increment_head_limit_violation(violation);
+                    Phase::WriteResponse(ResEncoder::error(violation.status()))
+                } else {
+                    self.metrics.read_request_head_errors.increment();
+                    Phase::WriteResponse(ResEncoder::error(Status::BadRequest))
+                }
             }
             Ok(None) => Phase::ReadRequestHead,
-            Ok(Some(head)) => match track!(Req::new(head, &self.base_url)) {
-                Err(e) => {
-                    warn!(
-                        self.logger,
-                        "Cannot parse the path of a HTTP request: {}", e
-                    );
-                    self.metrics.parse_request_path_errors.increment();
+            Ok(Some(head)) => {
+                if let Some(status) = self.check_host(&head) {
+                    self.metrics.host_rejected_requests.increment();
                     self.do_close = true;
-                    Phase::WriteResponse(ResEncoder::error(Status::BadRequest))
+                    return Phase::WriteResponse(ResEncoder::error(status));
                 }
-                Ok(head) => Phase::DispatchRequest(head),
-            },
+                if self.smuggling_protection {
+                    match smuggling::check_framing(&head.header()) {
+                        Err(violation) => return self.reject_smuggling(violation),
+                        Ok(is_chunked) => {
+                            self.chunk_validator = if is_chunked {
+                                Some(ChunkValidator::default())
+                            } else {
+                                None
+                            };
+                        }
+                    }
+                }
+                // `CONNECT` requests in the authority-form cannot be dispatched to handlers
+                if head.method().as_str() == "CONNECT"
+                    && !head.request_target().as_str().starts_with('/')
+                {
+                    return self.handle_connect(&head);
+                }
+                match track!(self.new_req(head)) {
+                    Err(e) => {
+                        warn!(
+                            self.logger,
+                            "Cannot parse the path of a HTTP request: {}", e
+                        );
+                        self.metrics.parse_request_path_errors.increment();
+                        self.do_close = true;
+                        Phase::WriteResponse(ResEncoder::error(Status::BadRequest))
+                    }
+                    Ok(mut head) => {
+                        if let Err(e) = track!(self.decode_path(&mut head)) {
+                            warn!(
+                                self.logger,
+                                "Cannot decode the path of a HTTP request: {}", e
+                            );
+                            return self.reject_dispatch(&head, &DispatchError::BadTarget);
+                        }
+                        self.request_started_at = Some(self.clock.now());
+                        head.set_state(Arc::clone(&self.state));
+                        head.set_default_res_fields(Arc::clone(&self.default_headers));
+                        head.set_response_hook(self.response_hook.clone());
+                        head.extensions_mut().insert(self.shutdown_signal.clone());
+                        head.extensions_mut().insert(self.clock.clone());
+                        let cancellation = CancellationToken::new();
+                        head.extensions_mut().insert(cancellation.clone());
+                        self.cancellation = Some(cancellation);
+                        if self.method_override {
+                            head.apply_method_override();
+                        }
+                        let request_id = self.request_id(&head);
+                        let logger = self.request_logger(&head, request_id.clone());
+                        head.set_logger(logger.clone());
+                        self.request_logger = Some(logger);
+                        if self.access_log.is_some() {
+                            self.request_id = Some(request_id);
+                            self.user_agent =
+                                head.header().get_field("User-Agent").map(|v| v.to_owned());
+                        }
+                        if let Some(ref hook) = self.request_hook {
+                            hook.call(&mut head);
+                        }
+                        self.sample = self.sampler.as_ref().and_then(|s| s.start(&head));
+                        if self.observer.is_some() || self.access_log.is_some() {
+                            self.traffic.method = Some(head.method().to_owned());
+                            self.traffic.path = Some(head.url().path().to_owned());
+                        }
+                        Phase::DispatchRequest(Box::new(head))
+                    }
+                }
+            }
         }
     }
 
-    fn dispatch_request(&mut self, head: Req<()>) -> Phase {
-        match self.dispatcher.dispatch(&head) {
-            Err(status) => {
-                self.metrics.dispatch_request_errors.increment();
+    fn check_host(&self, head: &Request<()>) -> Option<Status> {
+        let validation = self.host_validation.as_ref()?;
+        let status = validation.check(&head.header(), head.http_version())?;
+        debug!(
+            self.logger,
+            "Rejected a HTTP request by the validation of the `Host` header";
+            "host" => head.header().get_field("Host"), "status" => status.code()
+        );
+        Some(status)
+    }
+
+    fn handle_trace(&mut self, head: &Request<()>) -> Phase {
+        match self.trace_policy {
+            TracePolicy::Reject => {
+                debug!(self.logger, "Rejected a TRACE request");
                 self.do_close = true;
-                Phase::WriteResponse(ResEncoder::error(status))
+                Phase::WriteResponse(ResEncoder::error(Status::MethodNotAllowed))
             }
-            Ok(mut handler) => match track!(handler.init(head)) {
-                Err(e) => {
-                    warn!(self.logger, "Cannot initialize a request handler: {}", e);
-                    self.metrics.initialize_handler_errors.increment();
+            TracePolicy::Echo => {
+                // The body of a `TRACE` request (if any) is not skipped
+                let fields = head.header();
+                let has_body = fields.get_field("Transfer-Encoding").is_some()
+                    || fields
+                        .get_field("Content-Length")
+                        .map_or(false, |v| v != "0");
+                self.do_close |= has_body || !header::is_keep_alive(head.http_version(), &fields);
+                Phase::WriteResponse(ResEncoder::with_bytes_body(trace::echo(head).0))
+            }
+        }
+    }
+
+    fn handle_connect(&mut self, head: &Request<()>) -> Phase {
+        self.do_close = true;
+        let authority = head.request_target().as_str();
+        match self.connect_handler {
+            None => {
+                debug!(self.logger, "Rejected a CONNECT request"; "authority" => authority);
+                Phase::WriteResponse(ResEncoder::error(Status::NotImplemented))
+            }
+            Some(ref handler) => {
+                debug!(self.logger, "Opening a tunnel"; "authority" => authority);
+                Phase::OpenTunnel(handler.call(authority, &head.header(), self.peer_addr))
+            }
+        }
+    }
+
+    fn open_tunnel(&mut self, mut tunnel: PendingTunnel) -> Phase {
+        match tunnel.poll() {
+            Ok(Async::NotReady) => Phase::OpenTunnel(tunnel),
+            Ok(Async::Ready(tunnel)) => {
+                debug!(self.logger, "Tunnel established");
+                Phase::Tunnel(Box::new(Relay::new(tunnel)))
+            }
+            Err(res) => {
+                debug!(self.logger, "Cannot open a tunnel"; "status" => res.status_code());
+                Phase::WriteResponse(ResEncoder::custom_error(res))
+            }
+        }
+    }
+
+    fn relay(&mut self, mut relay: Box<Relay>) -> Result<Phase> {
+        let read_before = self.stream.read_buf_ref().len();
+        let write_before = self.stream.write_buf_ref().len();
+        track!(relay.relay(self.stream.read_buf_mut(), self.stream.write_buf_mut()))?;
+        self.traffic.bytes_read += (read_before - self.stream.read_buf_ref().len()) as u64;
+        self.traffic.bytes_written += (self.stream.write_buf_ref().len() - write_before) as u64;
+        if relay.is_tunnel_eos() {
+            debug!(self.logger, "Tunnel closed by the destination");
+            Ok(Phase::Closed)
+        } else {
+            Ok(Phase::Tunnel(relay))
+        }
+    }
+
+    fn new_req(&self, head: Request<()>) -> Result<Req<()>> {
+        let forwarded = if self.is_trusted_proxy {
+            forwarded::forwarded_base_url(&head.header(), &self.base_url)
+        } else {
+            None
+        };
+        let base_url = forwarded.as_ref().unwrap_or(&self.base_url);
+        track!(Req::new(head, base_url, self.peer_addr))
+    }
+
+    fn decode_path(&self, head: &mut Req<()>) -> Result<()> {
+        if let Some(ref decoding) = self.path_decoding {
+            track!(head.decode_path(decoding); head.url().path())?;
+        }
+        Ok(())
+    }
+
+    fn request_id(&self, head: &Req<()>) -> String {
+        match head.header().get_field("X-Request-Id") {
+            Some(id) => id.to_owned(),
+            None => self.request_ids.fetch_add(1, Ordering::SeqCst).to_string(),
+        }
+    }
+
+    fn request_logger(&self, head: &Req<()>, request_id: String) -> Logger {
+        self.logger.new(o!(
+            "method" => head.method().to_owned(),
+            "path" => head.url().path().to_owned(),
+            "request_id" => request_id
+        ))
+    }
+
+    fn dispatch_request(&mut self, mut head: Req<()>) -> Phase {
+        if let Some(ref gate) = self.readiness_gate {
+            if !gate.is_ready() {
+                debug!(self.logger, "Rejected a HTTP request during the warmup");
+                self.metrics.warmup_rejected_requests.increment();
+                self.do_close = true;
+                return Phase::WriteResponse(ResEncoder::service_unavailable(gate.retry_after()));
+            }
+        }
+        match self
+            .dispatcher
+            .dispatch(&mut head, self.cached_handler.take())
+        {
+            Err(e) => {
+                // The requests that no handlers can handle are left to the server-wide settings
+                if let DispatchError::NotFound | DispatchError::MethodNotAllowed { .. } = e {
+                    match head.original_method() {
+                        "TRACE" => return self.handle_trace(head.as_request()),
+                        "CONNECT" => return self.handle_connect(head.as_request()),
+                        _ => {}
+                    }
+                }
+                debug!(self.logger, "Cannot dispatch a HTTP request: {}", e);
+                self.reject_dispatch(&head, &e)
+            }
+            Ok((handler, route)) => {
+                self.route = Some(route.clone());
+                self.first_byte = self.request_started_at.map(|started_at| FirstByte {
+                    started_at,
+                    route: route.clone(),
+                    ahead: None,
+                });
+                if self.observer.is_some() || self.access_log.is_some() {
+                    self.traffic.route = Some(route.clone());
+                }
+                if let Some(ref mut sample) = self.sample {
+                    sample.set_route(route.clone());
+                }
+                if let Some(logger) = self.request_logger.take() {
+                    let logger = logger.new(o!("route" => route.path().to_owned()));
+                    head.set_logger(logger.clone());
+                    self.request_logger = Some(logger);
+                }
+                let (read_limit, write_limit) = handler.bandwidth_limits();
+                self.stream.stream_mut().set_limits(read_limit, write_limit);
+                self.set_flush_mode(handler.flush_mode());
+                if let Some(retry_after) = self.check_rate_limit(&route) {
+                    self.metrics.throttled_requests.increment();
                     self.do_close = true;
-                    Phase::WriteResponse(ResEncoder::error(Status::InternalServerError))
+                    return Phase::WriteResponse(ResEncoder::too_many_requests(retry_after));
                 }
-                Ok(()) => Phase::HandleRequest(handler),
-            },
+                if let Some(ref shedder) = self.load_shedder {
+                    self.in_flight = shedder.admit(handler.priority());
+                    if self.in_flight.is_none() {
+                        debug!(self.logger, "Shed a HTTP request"; "priority" => ?handler.priority());
+                        self.metrics.shed_requests.increment();
+                        self.do_close = true;
+                        return Phase::WriteResponse(ResEncoder::error(Status::ServiceUnavailable));
+                    }
+                }
+                if let Some(ref csrf) = self.csrf_protection {
+                    if !csrf.check(&mut head) {
+                        debug!(
+                            self.logger,
+                            "Rejected a HTTP request by the CSRF protection"
+                        );
+                        self.metrics.csrf_rejected_requests.increment();
+                        self.do_close = true;
+                        return Phase::WriteResponse(ResEncoder::error(Status::Forbidden));
+                    }
+                }
+                if let Some(res) = handler.apply_middleware(&mut head) {
+                    debug!(self.logger, "Rejected a HTTP request by a middleware";
+                           "status" => res.status_code());
+                    self.do_close = true;
+                    return Phase::WriteResponse(ResEncoder::custom_error(res));
+                }
+                self.init_handler(handler, head)
+            }
+        }
+    }
+
+    fn reject_dispatch(&mut self, head: &Req<()>, e: &DispatchError) -> Phase {
+        self.metrics.increment_dispatch_error(e);
+        self.do_close = true;
+        let res = self
+            .dispatch_error_handler
+            .as_ref()
+            .and_then(|h| h.handle(head, e));
+        if let Some(res) = res {
+            Phase::WriteResponse(ResEncoder::custom_error(res))
+        } else {
+            Phase::WriteResponse(ResEncoder::dispatch_error(e))
+        }
+    }
+
+    fn check_rate_limit(&self, route: &Route) -> Option<Duration> {
+        let limiter = self.rate_limiter.as_ref()?;
+        let client = self.peer_addr.ip();
+        limiter.acquire(client, route.key(), self.clock.now()).err()
+    }
+
+    fn init_handler(&mut self, mut handler: RequestHandlerInstance, head: Req<()>) -> Phase {
+        match track!(handler.init(head, self.max_decompressed_size, self.max_drained_size)) {
+            Err(e) if e.concrete_cause::<UnsupportedMediaType>().is_some() => {
+                debug!(self.logger, "Unsupported media type: {}", e);
+                self.metrics.decode_request_body_errors.increment();
+                self.do_close = true;
+                Phase::WriteResponse(ResEncoder::error(Status::UnsupportedMediaType))
+            }
+            Err(e) => {
+                warn!(self.logger, "Cannot initialize a request handler: {}", e);
+                self.metrics.initialize_handler_errors.increment();
+                self.do_close = true;
+                Phase::WriteResponse(ResEncoder::error(Status::InternalServerError))
+            }
+            Ok(()) => Phase::HandleRequest(handler),
         }
     }
 
+    fn reject_smuggling(&mut self, violation: SmugglingViolation) -> Phase {
+        debug!(
+            self.logger,
+            "Rejected a HTTP request by the request smuggling protection: {}", violation
+        );
+        self.metrics.increment_smuggling_violation(violation);
+        self.do_close = true;
+        Phase::WriteResponse(ResEncoder::error(Status::BadRequest))
+    }
+
     fn handle_request(&mut self, mut handler: RequestHandlerInstance) -> Phase {
-        match track!(handler.handle_input(self.stream.read_buf_mut())) {
+        if let Some(ref mut validator) = self.chunk_validator {
+            if let Err(violation) = validator.scan_read_buf(self.stream.read_buf_mut()) {
+                return self.reject_smuggling(violation);
+            }
+        }
+        let peeked = match self.sample {
+            Some(ref sample) => Some(sample.peek_request_body(self.stream.read_buf_mut())),
+            None => None,
+        };
+        let before = self.stream.read_buf_ref().len();
+        let result = track!(handler.handle_input(self.stream.read_buf_mut()));
+        let consumed = before - self.stream.read_buf_ref().len();
+        self.traffic.bytes_read += consumed as u64;
+        if let (Some(sample), Some(peeked)) = (self.sample.as_mut(), peeked) {
+            sample.capture_request_body(&peeked, consumed);
+        }
+        if let Some(ref mut validator) = self.chunk_validator {
+            validator.consume(consumed);
+        }
+        match result {
             Err(e) => {
+                if let Some(cause) = e.concrete_cause::<DecompressionError>() {
+                    debug!(
+                        self.logger,
+                        "Cannot decompress the body of a HTTP request: {}", e
+                    );
+                    self.metrics.decompress_request_body_errors.increment();
+                    self.do_close = true;
+                    return Phase::WriteResponse(ResEncoder::error(cause.status()));
+                }
                 warn!(
                     self.logger,
                     "Cannot decode the body of a HTTP request: {}", e
@@ -134,9 +744,17 @@ impl Connection {
                 Phase::WriteResponse(ResEncoder::error(Status::BadRequest))
             }
             Ok(None) => Phase::HandleRequest(handler),
-            Ok(Some(reply)) => {
-                self.do_close = handler.is_closed();
-                Phase::PollReply(reply)
+            Ok(Some(mut reply)) => {
+                self.do_close |= handler.is_closed();
+                self.body_drainer.remaining = handler.unread_body_len();
+                if !self.do_close {
+                    self.cached_handler = Some(handler);
+                }
+                if let Some(res_encoder) = reply.take_ready() {
+                    Phase::WriteResponse(res_encoder)
+                } else {
+                    Phase::PollReply(reply)
+                }
             }
         }
     }
@@ -150,14 +768,86 @@ impl Connection {
     }
 
     fn write_response(&mut self, mut encoder: ResEncoder) -> Result<Phase> {
-        track!(encoder.encode_to_write_buf(self.stream.write_buf_mut())).map_err(|e| {
-            self.metrics.write_response_errors.increment();
-            e
-        })?;
+        // The responses being sampled are captured while they are encoded into the write buffer
+        let direct_write = encoder.prefers_direct_write(self.vectored_write_threshold)
+            && self.sample.is_none()
+            && self.stream.stream_mut().tcp_stream().is_some();
+        if direct_write {
+            // The buffered bytes (e.g., the previous response) have to be flushed first.
+            if self.stream.write_buf_ref().is_empty() {
+                let stream = self.stream.stream_mut().tcp_stream().expect("Never fails");
+                let written =
+                    track!(encoder.write_to(stream).map_err(Error::from)).map_err(|e| {
+                        self.metrics.write_response_errors.increment();
+                        e
+                    })?;
+                self.traffic.bytes_written += written as u64;
+                self.written_since_yield += written;
+                if written > 0 {
+                    self.queue_first_byte(0);
+                    self.observe_flushed(written);
+                }
+            }
+        } else {
+            let before = self.stream.write_buf_ref().len();
+            let result = match self.sample {
+                Some(ref mut sample) => {
+                    Tee::new(&mut encoder, sample).encode_to_write_buf(self.stream.write_buf_mut())
+                }
+                None => encoder.encode_to_write_buf(self.stream.write_buf_mut()),
+            };
+            track!(result).map_err(|e| {
+                self.metrics.write_response_errors.increment();
+                e
+            })?;
+            let written = self.stream.write_buf_ref().len() - before;
+            self.traffic.bytes_written += written as u64;
+            self.written_since_yield += written;
+            if written > 0 {
+                self.queue_first_byte(before);
+                if self.flush_mode == FlushMode::Immediate {
+                    track!(self.execute_io())?;
+                }
+            }
+        }
         if encoder.is_idle() {
-            if self.do_close {
+            self.in_flight = None;
+            let traffic = mem::replace(
+                &mut self.traffic,
+                RequestTraffic::new(self.connection_id, self.peer_addr),
+            );
+            if let Some(ref observer) = self.observer {
+                observer.on_request_completed(&traffic);
+            }
+            if let Some(logger) = self.request_logger.take() {
+                debug!(logger, "Request completed"; "status" => encoder.status_code());
+                self.check_slow_request(&logger, &traffic, encoder.status_code());
+            }
+            if let Some(sample) = self.sample.take() {
+                sample.finish();
+            }
+            if let Some(ref access_log) = self.access_log {
+                let entry = AccessLogEntry {
+                    time: SystemTime::now(),
+                    duration: self
+                        .request_started_at
+                        .map(|started_at| self.clock.now().duration_since(started_at)),
+                    traffic,
+                    status: encoder.status_code(),
+                    request_id: self.request_id.take(),
+                    user_agent: self.user_agent.take(),
+                };
+                access_log.log(&entry);
+            }
+            self.request_started_at = None;
+            self.route = None;
+            self.cancellation = None;
+            self.stream.stream_mut().reset_limits();
+            self.set_flush_mode(FlushMode::Auto);
+            if self.do_close || encoder.closes_connection() {
                 Ok(Phase::Closed)
             } else {
+                self.reload_options();
                 Ok(Phase::ReadRequestHead)
             }
         } else {
@@ -165,35 +855,255 @@ impl Connection {
         }
     }
 
-    fn poll_once(&mut self) -> Result<bool> {
+    /// Executes the I/O of the stream, and observes the time to first byte of the response (if flushed).
+    fn execute_io(&mut self) -> Result<()> {
+        let before = self.stream.write_buf_ref().len();
         track!(self.stream.execute_io())?;
+        self.buffer_usage
+            .update_peaks(self.stream.read_buf_ref().len(), before);
+        let flushed = before.saturating_sub(self.stream.write_buf_ref().len());
+        self.observe_flushed(flushed);
+        Ok(())
+    }
+
+    /// Marks that the first byte of the response has been queued after `ahead` bytes.
+    fn queue_first_byte(&mut self, ahead: usize) {
+        if let Some(ref mut first_byte) = self.first_byte {
+            if first_byte.ahead.is_none() {
+                first_byte.ahead = Some(ahead);
+            }
+        }
+    }
+
+    /// Updates the pending measurement of the time to first byte with the number of flushed bytes.
+    fn observe_flushed(&mut self, flushed: usize) {
+        let is_flushed = match self.first_byte {
+            Some(FirstByte {
+                ahead: Some(ref mut ahead),
+                ..
+            }) => {
+                if flushed > *ahead {
+                    true
+                } else {
+                    *ahead -= flushed;
+                    false
+                }
+            }
+            _ => false,
+        };
+        if is_flushed {
+            let first_byte = self.first_byte.take().expect("Never fails");
+            let elapsed = self.clock.now().duration_since(first_byte.started_at);
+            self.metrics
+                .observe_time_to_first_byte(&first_byte.route, elapsed);
+        }
+    }
+
+    fn set_flush_mode(&mut self, mode: FlushMode) {
+        let was_corked = self.flush_mode == FlushMode::Cork;
+        let is_corked = mode == FlushMode::Cork;
+        self.flush_mode = mode;
+        if was_corked != is_corked {
+            if let Err(e) = self.stream.stream_mut().set_cork(is_corked) {
+                debug!(self.logger, "Cannot cork or uncork the socket: {}", e);
+            }
+        }
+    }
+
+    fn check_slow_request(&self, logger: &Logger, traffic: &RequestTraffic, status: u16) {
+        let (threshold, started_at) = match (self.slow_request_threshold, self.request_started_at) {
+            (Some(threshold), Some(started_at)) => (threshold, started_at),
+            _ => return,
+        };
+        let elapsed = self.clock.now().duration_since(started_at);
+        if elapsed < threshold {
+            return;
+        }
+        warn!(logger, "Slow request";
+              "status" => status,
+              "elapsed_secs" => elapsed.as_secs_f64(),
+              "threshold_secs" => threshold.as_secs_f64(),
+              "route" => self.route.as_ref().map(|r| r.path()),
+              "peer_addr" => %traffic.peer_addr(),
+              "bytes_read" => traffic.bytes_read(),
+              "bytes_written" => traffic.bytes_written());
+        if let Some(ref route) = self.route {
+            self.metrics.increment_slow_request(route);
+        }
+    }
+
+    /// Applies the options updated via `ServerHandle` (if any).
+    ///
+    /// This must be called only between requests.
+    fn reload_options(&mut self) {
+        let version = self.reloadable.version();
+        if version == self.reload_version {
+            return;
+        }
+        let values = self.reloadable.values();
+        self.timeouts = Timeouts::new(&values, &self.clock);
+        self.req_head_decoder.set_limits(values.head_limits);
+        self.reload_version = version;
+    }
+
+    fn handle_timeout(&mut self) -> Result<()> {
+        let expired = match self.timeout {
+            None => false,
+            Some((_, ref mut timeout)) => timeout.poll().map_or(true, |a| a.is_ready()),
+        };
+        if !expired {
+            return Ok(());
+        }
+
+        let (kind, _) = self.timeout.take().expect("Never fails");
+        match kind {
+            TimeoutKind::ReadRequestHead | TimeoutKind::ReadRequestBody => {
+                if let TimeoutKind::ReadRequestHead = kind {
+                    self.metrics.read_request_head_timeouts.increment();
+                } else {
+                    self.metrics.read_request_body_timeouts.increment();
+                }
+                warn!(self.logger, "Timeout while reading a HTTP request"; "phase" => ?kind);
+                self.do_close = true;
+                self.phase = Phase::WriteResponse(ResEncoder::error(Status::RequestTimeout));
+                self.timeout = self.timeouts.start(&self.phase);
+                Ok(())
+            }
+            TimeoutKind::WriteResponse => {
+                self.metrics.write_response_timeouts.increment();
+                track_panic!(ErrorKind::Other, "Timeout while writing a HTTP response")
+            }
+        }
+    }
+
+    /// Closes the connection if it is idle, otherwise marks it to be closed after the current request.
+    fn handle_drain(&mut self) {
+        self.do_close = true;
+        let is_idle = self.phase.is_idle()
+            && self.traffic.bytes_read == 0
+            && self.stream.read_buf_ref().is_empty();
+        if is_idle {
+            debug!(self.logger, "Closes an idle connection due to draining");
+            self.phase = Phase::Closed;
+        }
+    }
+
+    fn connection_phase(&self) -> ConnectionPhase {
+        match self.phase {
+            Phase::ReadRequestHead
+                if self.traffic.bytes_read == 0 && self.stream.read_buf_ref().is_empty() =>
+            {
+                ConnectionPhase::Idle
+            }
+            Phase::ReadRequestHead | Phase::DispatchRequest(_) => ConnectionPhase::ReadRequestHead,
+            Phase::HandleRequest(_) => ConnectionPhase::ReadRequestBody,
+            Phase::PollReply(_) | Phase::OpenTunnel(_) | Phase::Tunnel(_) => {
+                ConnectionPhase::HandleRequest
+            }
+            Phase::WriteResponse(_) | Phase::Closed => ConnectionPhase::WriteResponse,
+        }
+    }
+
+    fn handle_error(&mut self, e: &Error) {
+        if let Some(ref hook) = self.connection_error_hook {
+            hook.call(self.last_phase, e, self.peer_addr);
+        }
+
+        let kind = e.concrete_cause::<io::Error>().map(|e| e.kind());
+        let is_aborted = matches!(
+            kind,
+            Some(io::ErrorKind::ConnectionReset)
+                | Some(io::ErrorKind::ConnectionAborted)
+                | Some(io::ErrorKind::BrokenPipe)
+        );
+        if !is_aborted {
+            warn!(self.logger, "Connection aborted: {}", e);
+            return;
+        }
+        match self.last_phase {
+            ConnectionPhase::Idle => {
+                debug!(self.logger, "Connection closed by the client"; "kind" => ?kind);
+            }
+            ConnectionPhase::ReadRequestHead | ConnectionPhase::ReadRequestBody => {
+                info!(self.logger, "Request aborted by the client while reading"; "kind" => ?kind);
+                self.metrics.client_aborted_reads.increment();
+            }
+            ConnectionPhase::HandleRequest | ConnectionPhase::WriteResponse => {
+                info!(self.logger, "Request aborted by the client while writing"; "kind" => ?kind);
+                self.metrics.client_aborted_writes.increment();
+            }
+        }
+    }
+
+    /// Cancels the request being handled (if any).
+    ///
+    /// This is called when the connection is terminated before the response is returned.
+    fn cancel_request(&mut self) {
+        let token = match self.cancellation.take() {
+            None => return,
+            Some(token) => token,
+        };
+        if let Phase::PollReply(_) = self.phase {
+            debug!(self.logger, "Cancelled a HTTP request"; "route" => self.route.as_ref().map(|r| r.path()));
+            self.metrics.cancelled_replies.increment();
+        }
+        self.phase = Phase::Closed;
+        token.cancel();
+    }
+
+    fn poll_once(&mut self) -> Result<bool> {
+        self.last_phase = self.connection_phase();
+        track!(self.execute_io())?;
+        track!(self.handle_timeout())?;
+        if !self.do_close && self.drain.is_draining() {
+            self.handle_drain();
+        }
         let old = mem::discriminant(&self.phase);
         let next = match self.phase.take() {
             Phase::ReadRequestHead => self.read_request_head(),
-            Phase::DispatchRequest(req) => self.dispatch_request(req),
-            Phase::HandleRequest(handler) => self.handle_request(handler),
+            Phase::DispatchRequest(req) => self.dispatch_request(*req),
+            Phase::HandleRequest(handler) => match self.handle_request(handler) {
+                // The responses that are ready immediately are written in the same step
+                Phase::WriteResponse(res) => track!(self.write_response(res))?,
+                next => next,
+            },
             Phase::PollReply(reply) => self.poll_reply(reply),
             Phase::WriteResponse(res) => track!(self.write_response(res))?,
+            Phase::OpenTunnel(tunnel) => self.open_tunnel(tunnel),
+            Phase::Tunnel(relay) => track!(self.relay(relay))?,
             Phase::Closed => Phase::Closed,
         };
         self.phase = next;
         let changed = mem::discriminant(&self.phase) != old;
-        Ok(changed || !self.stream.would_block())
+        if changed {
+            self.timeout = self.timeouts.start(&self.phase);
+        }
+        self.adjust_buffers();
+        if let Some(ref entry) = self.debug_entry {
+            entry.set_phase(self.connection_phase());
+        }
+        Ok(changed || (!self.stream.would_block() && !self.phase.is_blocked()))
     }
 }
-impl Future for Connection {
+impl<S: Transport> Future for Connection<S> {
     type Item = ();
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.written_since_yield = 0;
         while !self.is_closed() {
             match track!(self.poll_once()) {
                 Err(e) => {
-                    warn!(self.logger, "Connection aborted: {}", e);
+                    self.handle_error(&e);
+                    self.cancel_request();
                     self.metrics.disconnected_tcp_clients.increment();
                     return Err(());
                 }
                 Ok(do_continue) => {
+                    if do_continue && self.written_since_yield >= self.write_high_watermark {
+                        // Yields to the executor to give the other connections a chance to run
+                        return fibers::fiber::yield_poll();
+                    }
                     if !do_continue {
                         if self.is_closed() {
                             break;
@@ -204,19 +1114,140 @@ impl Future for Connection {
             }
         }
 
+        self.cancel_request();
         debug!(self.logger, "Connection closed");
         self.metrics.disconnected_tcp_clients.increment();
         Ok(Async::Ready(()))
     }
 }
 
+impl<S> Drop for Connection<S> {
+    fn drop(&mut self) {
+        if let Some(token) = self.cancellation.take() {
+            token.cancel();
+        }
+        self.metrics
+            .observe_buffer_peak_fills(self.buffer_usage.peak_read, self.buffer_usage.peak_write);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TimeoutKind {
+    ReadRequestHead,
+    ReadRequestBody,
+    WriteResponse,
+}
+
+#[derive(Debug)]
+struct Timeouts {
+    read_request_head: Option<Duration>,
+    read_request_body: Option<Duration>,
+    write_response: Option<Duration>,
+    clock: SharedClock,
+}
+impl Timeouts {
+    fn new(values: &ReloadableValues, clock: &SharedClock) -> Self {
+        Timeouts {
+            read_request_head: values.read_request_head_timeout,
+            read_request_body: values.read_request_body_timeout,
+            write_response: values.write_response_timeout,
+            clock: clock.clone(),
+        }
+    }
+
+    fn start(&self, phase: &Phase) -> Option<(TimeoutKind, Sleep)> {
+        let (kind, duration) = match *phase {
+            Phase::ReadRequestHead => (TimeoutKind::ReadRequestHead, self.read_request_head?),
+            Phase::HandleRequest(_) => (TimeoutKind::ReadRequestBody, self.read_request_body?),
+            Phase::WriteResponse(_) | Phase::Closed => {
+                (TimeoutKind::WriteResponse, self.write_response?)
+            }
+            _ => return None,
+        };
+        Some((kind, self.clock.sleep(duration)))
+    }
+}
+
+/// A decoder that discards a fixed number of bytes.
+///
+/// This is used for skipping the request bodies left unread by handlers.
+#[derive(Debug, Default)]
+struct BodyDrainer {
+    remaining: u64,
+}
+impl Decode for BodyDrainer {
+    type Item = ();
+
+    fn decode(&mut self, buf: &[u8], _eos: Eos) -> bytecodec::Result<usize> {
+        let size = cmp::min(buf.len() as u64, self.remaining) as usize;
+        self.remaining -= size as u64;
+        Ok(size)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        track_assert_eq!(self.remaining, 0, bytecodec::ErrorKind::IncompleteDecoding);
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.remaining)
+    }
+}
+
+/// A pending measurement of the time to first byte (see `ServerMetrics::time_to_first_byte_seconds`).
+#[derive(Debug)]
+struct FirstByte {
+    started_at: Instant,
+    route: Route,
+    // The number of the bytes that have to be flushed before the first byte of the response,
+    // or `None` if the response has not been encoded yet.
+    ahead: Option<usize>,
+}
+
+/// The utilization of the buffers of a connection (see `ServerMetrics::read_buffer_full_stalls` and so on).
+#[derive(Debug, Default)]
+struct BufferUsage {
+    peak_read: usize,
+    peak_write: usize,
+    is_read_full: bool,
+    is_write_full: bool,
+}
+impl BufferUsage {
+    fn update_peaks(&mut self, read_len: usize, write_len: usize) {
+        self.peak_write = cmp::max(self.peak_write, write_len);
+        self.peak_read = cmp::max(self.peak_read, read_len);
+    }
+}
+
+#[derive(Debug)]
+struct BufferSizes {
+    read: usize,
+    max_read: usize,
+    write: usize,
+    max_write: usize,
+}
+
+fn grown_buffer_size(is_full: bool, current: usize, max: usize) -> Option<usize> {
+    if is_full && current < max {
+        Some(current.saturating_mul(2).clamp(1, max))
+    } else {
+        None
+    }
+}
+
 #[derive(Debug)]
 enum Phase {
     ReadRequestHead,
-    DispatchRequest(Req<()>),
+    DispatchRequest(Box<Req<()>>),
     HandleRequest(RequestHandlerInstance),
     PollReply(BoxReply),
     WriteResponse(ResEncoder),
+    OpenTunnel(PendingTunnel),
+    Tunnel(Box<Relay>),
     Closed,
 }
 impl Phase {
@@ -225,10 +1256,370 @@ impl Phase {
     }
 
     fn is_closed(&self) -> bool {
-        if let Phase::Closed = *self {
-            true
-        } else {
-            false
+        matches!(*self, Phase::Closed)
+    }
+
+    fn is_idle(&self) -> bool {
+        matches!(*self, Phase::ReadRequestHead)
+    }
+
+    fn is_blocked(&self) -> bool {
+        match *self {
+            Phase::HandleRequest(ref handler) => handler.is_blocked(),
+            Phase::Tunnel(ref relay) => relay.is_blocked(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{spawn_server, Hello};
+    use crate::{HandleRequest, Reply, Res, ServerBuilder};
+    use bytecodec::bytes::{BytesEncoder, Utf8Encoder};
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::net::TcpStream;
+    use std::sync::Mutex;
+
+    #[test]
+    fn read_request_head_timeout_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.read_request_head_timeout(Duration::from_millis(100));
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.1\r\n");
+        sim.run().unwrap();
+        assert!(!conn.is_closed());
+
+        sim.advance(Duration::from_millis(100)).unwrap();
+        assert!(conn.is_closed());
+        assert_eq!(
+            String::from_utf8(conn.take_received()).unwrap(),
+            "HTTP/1.1 408 Request Timeout\r\nConnection: close\r\nContent-Length: 15\r\n\r\nRequest Timeout"
+        );
+    }
+
+    #[test]
+    fn growable_buffers_work() {
+        struct Large;
+        impl HandleRequest for Large {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/large";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "a".repeat(20_000))))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Large).unwrap();
+        builder
+            .write_buffer_size(1024)
+            .max_write_buffer_size(16 * 1024);
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /large HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.ends_with(&"a".repeat(20_000)));
+        assert_eq!(sim.metrics().write_buffer_high_watermark(), 16 * 1024);
+        assert_eq!(sim.metrics().read_buffer_high_watermark(), 8192);
+    }
+
+    #[test]
+    fn vectored_write_works() {
+        struct Large;
+        impl HandleRequest for Large {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/large";
+
+            type ReqBody = ();
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, vec![b'a'; 1024 * 1024])))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Large).unwrap();
+        builder.add_handler(Hello).unwrap();
+        let addr = spawn_server(builder);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /large HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        client
+            .write_all(b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+
+        let mut buf = Vec::new();
+        while !buf.ends_with(b"hello") {
+            let mut tmp = [0; 64 * 1024];
+            let size = client.read(&mut tmp).unwrap();
+            assert_ne!(size, 0);
+            buf.extend_from_slice(&tmp[..size]);
+        }
+        let head = b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 1048576\r\n\r\n";
+        assert!(buf.starts_with(head));
+        assert!(buf[head.len()..][..1024 * 1024].iter().all(|&b| b == b'a'));
+        assert!(buf[head.len() + 1024 * 1024..].starts_with(b"HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn method_override_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.method_override(true);
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(
+            b"POST /hello HTTP/1.1\r\nX-HTTP-Method-Override: GET\r\nContent-Length: 0\r\n\r\n",
+        );
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+
+        let conn = sim.connect().unwrap();
+        conn.write(
+            b"PUT /hello HTTP/1.1\r\nX-HTTP-Method-Override: GET\r\nContent-Length: 0\r\n\r\n",
+        );
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "{}",
+            received
+        );
+    }
+
+    #[test]
+    fn connection_header_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        let mut sim = builder.finish_simulation(0);
+
+        for req in [
+            "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n",
+            "GET /hello HTTP/1.0\r\n\r\n",
+        ] {
+            let conn = sim.connect().unwrap();
+            conn.write(req.as_bytes());
+            sim.run().unwrap();
+
+            let received = String::from_utf8(conn.take_received()).unwrap();
+            assert!(received.starts_with("HTTP/1.1 200 OK\r\nConnection: close\r\n"));
+            assert!(received.ends_with("hello"));
+            assert!(conn.is_closed());
+        }
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\nConnection: keep-alive\r\n"));
+        assert!(!conn.is_closed());
+
+        conn.write(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.ends_with("hello"));
+        assert!(conn.is_closed());
+    }
+
+    #[test]
+    fn client_abort_works() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        {
+            let errors = Arc::clone(&errors);
+            builder.on_connection_error(move |phase, _error, peer| {
+                errors.lock().unwrap().push((phase, peer));
+            });
+        }
+        let mut sim = builder.finish_simulation(0);
+
+        // Aborted while reading a request
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.1\r\n");
+        sim.run().unwrap();
+        conn.reset();
+        sim.run().unwrap();
+        assert_eq!(sim.metrics().client_aborted_reads(), 1);
+        assert_eq!(sim.metrics().disconnected_tcp_clients(), 1);
+        assert_eq!(
+            *errors.lock().unwrap(),
+            [(ConnectionPhase::ReadRequestHead, conn.peer_addr())]
+        );
+
+        // Reset between requests
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        assert!(!conn.take_received().is_empty());
+        conn.reset();
+        sim.run().unwrap();
+        assert_eq!(sim.metrics().client_aborted_reads(), 1);
+        assert_eq!(sim.metrics().client_aborted_writes(), 0);
+        assert_eq!(sim.metrics().disconnected_tcp_clients(), 2);
+        assert_eq!(errors.lock().unwrap().len(), 2);
+        assert_eq!(errors.lock().unwrap()[1].0, ConnectionPhase::Idle);
+    }
+
+    #[test]
+    fn write_high_watermark_works() {
+        struct Large;
+        impl HandleRequest for Large {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/large";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "a".repeat(4 * 1024 * 1024))))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Large).unwrap();
+        builder.add_handler(Hello).unwrap();
+        builder.write_high_watermark(4096);
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /large HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        conn.write(b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+
+        let buf = conn.take_received();
+        let head = b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 4194304\r\n\r\n";
+        assert!(buf.starts_with(head));
+        assert!(buf[head.len()..][..4 * 1024 * 1024]
+            .iter()
+            .all(|&b| b == b'a'));
+        assert!(buf[head.len() + 4 * 1024 * 1024..].starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(buf.ends_with(b"hello"));
+    }
+
+    #[test]
+    fn early_response_drains_request_body() {
+        use bytecodec::bytes::RemainingBytesDecoder;
+
+        struct Reject;
+        impl HandleRequest for Reject {
+            const METHOD: &'static str = "POST";
+            const PATH: &'static str = "/reject";
+
+            type ReqBody = Vec<u8>;
+            type ResBody = String;
+            type Decoder = BodyDecoder<RemainingBytesDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request_head(&self, _req: &Req<()>) -> Option<Res<Self::ResBody>> {
+                Some(Res::new(Status::Forbidden, "rejected".to_owned()))
+            }
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                unreachable!()
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_handler(Reject).unwrap();
+        builder.max_drained_request_body_size(8);
+        let mut sim = builder.finish_simulation(0);
+
+        // The body is skipped, and the connection is kept alive
+        let conn = sim.connect().unwrap();
+        conn.write(b"POST /reject HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+        sim.run().unwrap();
+        conn.write(b"abcdeGET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        assert_eq!(
+            String::from_utf8(conn.take_received()).unwrap(),
+            concat!(
+                "HTTP/1.1 403 Forbidden\r\nConnection: keep-alive\r\nContent-Length: 8\r\n\r\nrejected",
+                "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\nhello"
+            )
+        );
+        assert!(!conn.is_closed());
+
+        // The body is too large to be skipped
+        let conn = sim.connect().unwrap();
+        conn.write(b"POST /reject HTTP/1.1\r\nContent-Length: 9\r\n\r\n");
+        sim.run().unwrap();
+        assert_eq!(
+            String::from_utf8(conn.take_received()).unwrap(),
+            "HTTP/1.1 403 Forbidden\r\nConnection: close\r\nContent-Length: 8\r\n\r\nrejected"
+        );
+        assert!(conn.is_closed());
+    }
+
+    #[test]
+    fn handler_instances_are_reused() {
+        use bytecodec::bytes::RemainingBytesDecoder;
+
+        struct Echo;
+        impl HandleRequest for Echo {
+            const METHOD: &'static str = "POST";
+            const PATH: &'static str = "/echo";
+
+            type ReqBody = Vec<u8>;
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<RemainingBytesDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, req.into_body())))
+            }
         }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_handler(Echo).unwrap();
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\nfoo");
+        conn.write(b"POST /echo HTTP/1.1\r\nContent-Length: 4\r\n\r\nbarr");
+        conn.write(b"GET /hello HTTP/1.1\r\n\r\n");
+        conn.write(b"POST /echo HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        conn.write(b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\nbaz");
+        sim.run().unwrap();
+
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        let bodies = received
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .map(|res| res.splitn(2, "\r\n\r\n").nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bodies, ["foo", "barr", "hello", "", "baz"]);
+        assert!(!conn.is_closed());
     }
 }
@@ -0,0 +1,370 @@
+use crate::header::ContentType;
+use crate::{ErrorKind, Req, Result};
+
+/// Configuration of the CSRF protection.
+///
+/// This implements the double-submit cookie pattern as follows:
+/// - If a request of a safe method (i.e., `GET`, `HEAD`, `OPTIONS` or `TRACE`) does not have the token cookie,
+///   a new token is issued via the `Set-Cookie` header of the response
+/// - A request of an unsafe method has to have the token cookie and the same token in the token header,
+///   otherwise the `403 Forbidden` response will be returned
+/// - If `form_field` is specified, form requests (i.e., `application/x-www-form-urlencoded` or
+///   `multipart/form-data`) without the token header are passed to the handlers,
+///   and the handlers have to verify the form field by `CsrfToken::verify` method
+///
+/// The token of each protected request is available as the `CsrfToken` extension of the request.
+#[derive(Debug, Clone)]
+pub struct CsrfProtection {
+    cookie_name: String,
+    header_name: String,
+    form_field: Option<String>,
+    prefixes: Vec<String>,
+    secure_cookie: bool,
+}
+impl CsrfProtection {
+    /// Makes a new `CsrfProtection` instance.
+    ///
+    /// By default, the name of the token cookie is `csrf_token`, the name of the token header is
+    /// `X-CSRF-Token`, and all paths are protected.
+    pub fn new() -> Self {
+        CsrfProtection {
+            cookie_name: "csrf_token".to_owned(),
+            header_name: "X-CSRF-Token".to_owned(),
+            form_field: None,
+            prefixes: Vec::new(),
+            secure_cookie: false,
+        }
+    }
+
+    /// Sets the name of the token cookie.
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    /// Sets the name of the token header.
+    pub fn header_name(mut self, name: &str) -> Self {
+        self.header_name = name.to_owned();
+        self
+    }
+
+    /// Specifies the name of the form field that may carry the token instead of the token header.
+    pub fn form_field(mut self, name: &str) -> Self {
+        self.form_field = Some(name.to_owned());
+        self
+    }
+
+    /// Adds a path prefix to be protected.
+    ///
+    /// If one or more prefixes are added, the requests for the other paths are not protected.
+    pub fn protect_prefix(mut self, prefix: &str) -> Self {
+        self.prefixes.push(prefix.to_owned());
+        self
+    }
+
+    /// Adds the `Secure` attribute to the token cookie.
+    pub fn secure_cookie(mut self) -> Self {
+        self.secure_cookie = true;
+        self
+    }
+
+    /// Checks the given request.
+    ///
+    /// Returns `false` if the request should be rejected.
+    pub(crate) fn check(&self, req: &mut Req<()>) -> bool {
+        if !self.is_protected(req.url().path()) {
+            return true;
+        }
+
+        let cookie = get_cookie(req, &self.cookie_name);
+        if is_safe_method(req.method()) {
+            let token = match cookie {
+                Some(token) => token,
+                None => match generate_token() {
+                    Err(e) => {
+                        // The request itself is safe, so it is handled without issuing a token
+                        warn!(req.logger(), "Cannot generate a CSRF token: {}", e);
+                        return true;
+                    }
+                    Ok(token) => {
+                        let mut cookie =
+                            format!("{}={}; Path=/; SameSite=Strict", self.cookie_name, token);
+                        if self.secure_cookie {
+                            cookie.push_str("; Secure");
+                        }
+                        req.add_res_field("Set-Cookie", &cookie);
+                        token
+                    }
+                },
+            };
+            req.extensions_mut().insert(CsrfToken {
+                value: token,
+                form_field: self.form_field.clone(),
+                is_verified: true,
+            });
+            return true;
+        }
+
+        let token = match cookie {
+            None => return false,
+            Some(token) => token,
+        };
+        let is_verified = match req.header().get_field(&self.header_name) {
+            Some(value) => {
+                if !constant_time_eq(value, &token) {
+                    return false;
+                }
+                true
+            }
+            None => {
+                if self.form_field.is_none() || !is_form(req) {
+                    return false;
+                }
+                false
+            }
+        };
+        req.extensions_mut().insert(CsrfToken {
+            value: token,
+            form_field: self.form_field.clone(),
+            is_verified,
+        });
+        true
+    }
+
+    fn is_protected(&self, path: &str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| path.starts_with(p.as_str()))
+    }
+}
+impl Default for CsrfProtection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CSRF token associated with a request protected by `CsrfProtection`.
+///
+/// This can be retrieved via `req.extensions().get::<CsrfToken>()`.
+#[derive(Debug, Clone)]
+pub struct CsrfToken {
+    value: String,
+    form_field: Option<String>,
+    is_verified: bool,
+}
+impl CsrfToken {
+    /// Returns the value of the token.
+    ///
+    /// This can be embedded in HTML forms or passed to scripts.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the name of the form field that may carry the token.
+    pub fn form_field(&self) -> Option<&str> {
+        self.form_field.as_deref()
+    }
+
+    /// Returns `true` if the request has been verified by the server, otherwise `false`.
+    ///
+    /// Unverified requests are the form requests that do not have the token header,
+    /// and the handlers have to verify them by `verify` method.
+    pub fn is_verified(&self) -> bool {
+        self.is_verified
+    }
+
+    /// Returns `true` if `submitted` (e.g., the value of the form field) equals to the token, otherwise `false`.
+    pub fn verify(&self, submitted: &str) -> bool {
+        constant_time_eq(submitted, &self.value)
+    }
+}
+
+fn is_safe_method(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE")
+}
+
+fn is_form(req: &Req<()>) -> bool {
+    match req.typed_header::<ContentType>() {
+        Ok(Some(t)) => {
+            t.media_type() == "application/x-www-form-urlencoded"
+                || t.media_type() == "multipart/form-data"
+        }
+        _ => false,
+    }
+}
+
+fn get_cookie(req: &Req<()>, name: &str) -> Option<String> {
+    req.header()
+        .fields()
+        .filter(|f| f.name().eq_ignore_ascii_case("Cookie"))
+        .flat_map(|f| f.value().split(';'))
+        .filter_map(|pair| {
+            let mut iter = pair.trim().splitn(2, '=');
+            Some((iter.next()?, iter.next()?))
+        })
+        .find(|&(n, v)| n == name && !v.is_empty())
+        .map(|(_, v)| v.to_owned())
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// Generates a token from 128 bits read from the OS random number generator (hex-encoded).
+fn generate_token() -> Result<String> {
+    let mut bytes = [0; 16];
+    if let Err(e) = getrandom::getrandom(&mut bytes) {
+        track_panic!(ErrorKind::Other, "Cannot get random bytes: {}", e);
+    }
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::{
+        BodyDecoder, BodyEncoder, HeaderField, HttpVersion, Method, Request, RequestTarget,
+    };
+    use url::Url;
+
+    fn req(method: &str, path: &str, fields: &[(&str, &str)]) -> Req<()> {
+        let mut inner = Request::new(
+            Method::new(method).unwrap(),
+            RequestTarget::new(path).unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        for &(name, value) in fields {
+            let field = unsafe { HeaderField::new_unchecked(name, value) };
+            inner.header_mut().add_field(field);
+        }
+        let base_url = Url::parse("http://localhost/").unwrap();
+        track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()))
+    }
+
+    #[test]
+    fn csrf_protection_works() {
+        let csrf = CsrfProtection::new()
+            .protect_prefix("/app/")
+            .form_field("_csrf");
+
+        // Issuing
+        let mut r = req("GET", "/app/form", &[]);
+        assert!(csrf.check(&mut r));
+        let token = r
+            .extensions()
+            .get::<CsrfToken>()
+            .unwrap()
+            .value()
+            .to_owned();
+        assert_eq!(token.len(), 32);
+        let fields = r.take_res_fields();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(
+            fields[0].1,
+            format!("csrf_token={}; Path=/; SameSite=Strict", token)
+        );
+
+        let cookie = format!("foo=bar; csrf_token={}", token);
+        let mut r = req("GET", "/app/form", &[("Cookie", &cookie)]);
+        assert!(csrf.check(&mut r));
+        assert!(r.take_res_fields().is_empty());
+
+        // Validation
+        let mut r = req("POST", "/app/form", &[]);
+        assert!(!csrf.check(&mut r));
+
+        let mut r = req("POST", "/app/form", &[("Cookie", &cookie)]);
+        assert!(!csrf.check(&mut r));
+
+        let mut r = req(
+            "POST",
+            "/app/form",
+            &[("Cookie", &cookie), ("X-CSRF-Token", "foo")],
+        );
+        assert!(!csrf.check(&mut r));
+
+        let mut r = req(
+            "POST",
+            "/app/form",
+            &[("Cookie", &cookie), ("X-CSRF-Token", &token)],
+        );
+        assert!(csrf.check(&mut r));
+        assert!(r.extensions().get::<CsrfToken>().unwrap().is_verified());
+
+        let mut r = req(
+            "POST",
+            "/app/form",
+            &[
+                ("Cookie", &cookie),
+                ("Content-Type", "application/x-www-form-urlencoded"),
+            ],
+        );
+        assert!(csrf.check(&mut r));
+        let t = r.extensions().get::<CsrfToken>().unwrap();
+        assert!(!t.is_verified());
+        assert_eq!(t.form_field(), Some("_csrf"));
+        assert!(t.verify(&token));
+        assert!(!t.verify("foo"));
+
+        // Unprotected paths
+        let mut r = req("POST", "/api/foo", &[]);
+        assert!(csrf.check(&mut r));
+    }
+
+    #[test]
+    fn generate_token_works() {
+        let a = track_try_unwrap!(generate_token());
+        let b = track_try_unwrap!(generate_token());
+        assert_eq!(a.len(), 32);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn server_csrf_protection_works() {
+        struct Form;
+        impl HandleRequest for Form {
+            const METHOD: &'static str = "GET";
+            const METHODS: &'static [&'static str] = &["GET", "POST"];
+            const PATH: &'static str = "/hello";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "hello".to_owned())))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Form).unwrap();
+        builder.csrf_protection(CsrfProtection::new());
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        let cookie = res.header().get_field("Set-Cookie").unwrap().to_owned();
+        assert!(cookie.starts_with("csrf_token="));
+
+        let req = httpcodec::Request::new(
+            httpcodec::Method::new("POST").unwrap(),
+            httpcodec::RequestTarget::new("/hello").unwrap(),
+            httpcodec::HttpVersion::V1_1,
+            Vec::new(),
+        );
+        let res = fibers_global::execute(client.send(req)).unwrap();
+        assert_eq!(res.status_code(), 403);
+        assert_eq!(client.metrics().csrf_rejected_requests(), 1);
+    }
+}
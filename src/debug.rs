@@ -0,0 +1,329 @@
+use crate::clock::{Clock, SharedClock};
+use crate::handle::ReloadableOptions;
+use crate::observer::ConnectionPhase;
+use crate::options::EffectiveOptions;
+use crate::{HandleRequest, Priority, Reply, Req, Res, Status};
+use bytecodec::bytes::Utf8Encoder;
+use bytecodec::null::NullDecoder;
+use futures::future::ok;
+use httpcodec::{BodyDecoder, BodyEncoder};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A handler for exposing the live state of the server as JSON.
+///
+/// The response contains the uptime, the open connections (with the peer addresses and current phases),
+/// the registered routes and the option values of the server.
+///
+/// Since the state is sensitive, this handler responds with `404 Not Found`
+/// unless the endpoint is enabled via `ServerBuilder::debug_endpoint` method.
+#[derive(Debug)]
+pub struct DebugHandler;
+impl HandleRequest for DebugHandler {
+    const METHOD: &'static str = "GET";
+    const PATH: &'static str = "/debug/server";
+    const PRIORITY: Priority = Priority::Critical;
+
+    type ReqBody = ();
+    type ResBody = String;
+    type Decoder = BodyDecoder<NullDecoder>;
+    type Encoder = BodyEncoder<Utf8Encoder>;
+    type Reply = Reply<Self::ResBody>;
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let res = match req.state::<DebugState>() {
+            None => Res::new(Status::NotFound, String::new()),
+            Some(state) => Res::json(Status::Ok, state.to_json()),
+        };
+        Box::new(ok(res))
+    }
+}
+
+/// The server state exposed by `DebugHandler`.
+///
+/// This is registered as a shared value of the server if the debug endpoint is enabled.
+#[derive(Debug)]
+pub struct DebugState {
+    started_at: Instant,
+    routes: Vec<(&'static str, String)>,
+    options: EffectiveOptions,
+    reloadable: Arc<ReloadableOptions>,
+    connections: Arc<ConnectionRegistry>,
+    clock: SharedClock,
+}
+impl DebugState {
+    pub fn new(
+        routes: &[(&'static str, &str)],
+        options: EffectiveOptions,
+        reloadable: Arc<ReloadableOptions>,
+        connections: Arc<ConnectionRegistry>,
+        clock: SharedClock,
+    ) -> Self {
+        DebugState {
+            started_at: clock.now(),
+            routes: routes.iter().map(|r| (r.0, r.1.to_owned())).collect(),
+            options,
+            reloadable,
+            connections,
+            clock,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"uptime_secs\":{}",
+            self.clock
+                .now()
+                .duration_since(self.started_at)
+                .as_secs_f64()
+        );
+
+        json.push_str(",\"connections\":[");
+        for (i, (id, peer_addr, phase)) in self.connections.snapshot().into_iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"id\":{},\"peer_addr\":\"{}\",\"phase\":\"{}\"}}",
+                id,
+                peer_addr,
+                phase_name(phase)
+            );
+        }
+
+        json.push_str("],\"routes\":[");
+        for (i, &(method, ref path)) in self.routes.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"method\":{},\"path\":{}}}",
+                json_string(method),
+                json_string(path)
+            );
+        }
+
+        let o = self.options.reload(&self.reloadable);
+        let secs =
+            |d: Option<Duration>| d.map_or("null".to_owned(), |d| d.as_secs_f64().to_string());
+        let _ = write!(
+            json,
+            "],\"options\":{{\
+             \"read_buffer_size\":{},\"write_buffer_size\":{},\
+             \"max_read_buffer_size\":{},\"max_write_buffer_size\":{},\
+             \"vectored_write_threshold\":{},\"write_high_watermark\":{},\
+             \"max_decompressed_request_body_size\":{},\"slow_request_threshold_secs\":{},\
+             \"max_start_line_size\":{},\"max_header_size\":{},\
+             \"read_request_head_timeout_secs\":{},\"read_request_body_timeout_secs\":{},\
+             \"write_response_timeout_secs\":{},\
+             \"max_request_line_size\":{},\"max_header_count\":{},\"max_header_field_size\":{},\
+             \"strict_parsing\":{},\
+             \"max_pending_connections\":{},\"max_active_connections\":{}}}}}",
+            o.read_buffer_size(),
+            o.write_buffer_size(),
+            o.max_read_buffer_size(),
+            o.max_write_buffer_size(),
+            o.vectored_write_threshold(),
+            o.write_high_watermark(),
+            o.max_decompressed_request_body_size()
+                .map_or("null".to_owned(), |n| n.to_string()),
+            secs(o.slow_request_threshold()),
+            o.decode_options().max_start_line_size,
+            o.decode_options().max_header_size,
+            secs(o.read_request_head_timeout()),
+            secs(o.read_request_body_timeout()),
+            secs(o.write_response_timeout()),
+            o.max_request_line_size(),
+            o.max_header_count(),
+            o.max_header_field_size(),
+            o.strict_parsing(),
+            o.max_pending_connections(),
+            o.max_active_connections(),
+        );
+        json
+    }
+}
+
+/// The registry of the open connections of a server.
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    entries: Mutex<BTreeMap<u64, Arc<ConnectionEntry>>>,
+}
+impl ConnectionRegistry {
+    /// Registers the connection identified by `id` (see `ServerOptions::connection_ids`).
+    pub fn register(this: &Arc<Self>, id: u64, peer_addr: SocketAddr) -> RegisteredConnection {
+        let entry = Arc::new(ConnectionEntry {
+            peer_addr,
+            phase: AtomicUsize::new(phase_index(ConnectionPhase::Idle)),
+        });
+        this.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, Arc::clone(&entry));
+        RegisteredConnection {
+            id,
+            registry: Arc::clone(this),
+            entry,
+        }
+    }
+
+    fn snapshot(&self) -> Vec<(u64, SocketAddr, ConnectionPhase)> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .map(|(&id, e)| {
+                let phase = PHASES[e.phase.load(Ordering::SeqCst)];
+                (id, e.peer_addr, phase)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct ConnectionEntry {
+    peer_addr: SocketAddr,
+    phase: AtomicUsize,
+}
+
+/// A connection registered to `ConnectionRegistry`.
+///
+/// The connection is removed from the registry when this is dropped.
+#[derive(Debug)]
+pub struct RegisteredConnection {
+    id: u64,
+    registry: Arc<ConnectionRegistry>,
+    entry: Arc<ConnectionEntry>,
+}
+impl RegisteredConnection {
+    pub fn set_phase(&self, phase: ConnectionPhase) {
+        self.entry.phase.store(phase_index(phase), Ordering::SeqCst);
+    }
+}
+impl Drop for RegisteredConnection {
+    fn drop(&mut self) {
+        self.registry
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+const PHASES: [ConnectionPhase; 5] = [
+    ConnectionPhase::Idle,
+    ConnectionPhase::ReadRequestHead,
+    ConnectionPhase::ReadRequestBody,
+    ConnectionPhase::HandleRequest,
+    ConnectionPhase::WriteResponse,
+];
+
+fn phase_index(phase: ConnectionPhase) -> usize {
+    PHASES
+        .iter()
+        .position(|&p| p == phase)
+        .expect("Never fails")
+}
+
+fn phase_name(phase: ConnectionPhase) -> &'static str {
+    match phase {
+        ConnectionPhase::Idle => "idle",
+        ConnectionPhase::ReadRequestHead => "read_request_head",
+        ConnectionPhase::ReadRequestBody => "read_request_body",
+        ConnectionPhase::HandleRequest => "handle_request",
+        ConnectionPhase::WriteResponse => "write_response",
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c < ' ' => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+
+    #[test]
+    fn connection_registry_works() {
+        let registry = Arc::new(ConnectionRegistry::default());
+        let a = ConnectionRegistry::register(&registry, 3, ([127, 0, 0, 1], 1000).into());
+        let b = ConnectionRegistry::register(&registry, 5, ([127, 0, 0, 1], 2000).into());
+        b.set_phase(ConnectionPhase::WriteResponse);
+        assert_eq!(
+            registry.snapshot(),
+            [
+                (3, ([127, 0, 0, 1], 1000).into(), ConnectionPhase::Idle),
+                (
+                    5,
+                    ([127, 0, 0, 1], 2000).into(),
+                    ConnectionPhase::WriteResponse
+                )
+            ]
+        );
+
+        drop(a);
+        assert_eq!(registry.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn json_string_works() {
+        assert_eq!(json_string("/foo"), r#""/foo""#);
+        assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+    }
+
+    #[test]
+    fn debug_endpoint_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(DebugHandler).unwrap();
+        let client = builder.finish_test_client();
+        let res = fibers_global::execute(client.get("/debug/server").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 404);
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(DebugHandler).unwrap();
+        builder.add_handler(Hello).unwrap();
+        builder.debug_endpoint(true).write_buffer_size(1024);
+        let mut sim = builder.finish_simulation(0);
+
+        let idle = sim.connect().unwrap();
+        sim.run().unwrap();
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /debug/server HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        let res = String::from_utf8(conn.take_received()).unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("Content-Type: application/json\r\n"));
+        assert!(res.contains(&format!(
+            r#"{{"id":0,"peer_addr":"{}","phase":"idle"}}"#,
+            idle.peer_addr()
+        )));
+        assert!(res.contains(
+            r#""routes":[{"method":"GET","path":"/debug/server"},{"method":"GET","path":"/hello"}]"#
+        ));
+        assert!(res.contains(r#""write_buffer_size":1024,"#));
+        assert!(res.contains(r#""read_request_head_timeout_secs":null,"#));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::Status;
+use bytecodec::bytes::RemainingBytesDecoder;
+use bytecodec::{self, ByteCount, Decode, Eos};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use httpcodec::{
+    BodyDecode, BodyDecoder, Header, HeaderField, HttpVersion, Method, Request, RequestTarget,
+};
+use std::fmt;
+use std::io::Read;
+use trackable::error::ErrorKindExt;
+
+/// The content codings of request bodies that can be decompressed by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+}
+impl ContentCoding {
+    /// Returns the coding specified by the `Content-Encoding` header (if it is supported).
+    pub fn from_header(header: &Header) -> Option<Self> {
+        let value = header.get_field("Content-Encoding")?.trim();
+        if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentCoding::Gzip)
+        } else if value.eq_ignore_ascii_case("deflate") {
+            Some(ContentCoding::Deflate)
+        } else {
+            None
+        }
+    }
+}
+
+/// The cause of the errors returned by `BodyDecompressor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressionError {
+    /// The (compressed or decompressed) body exceeds the size limit.
+    TooLarge,
+
+    /// The body is not a valid compressed data.
+    Malformed,
+}
+impl DecompressionError {
+    /// Returns the response status corresponding to the error.
+    pub fn status(self) -> Status {
+        match self {
+            DecompressionError::TooLarge => Status::PayloadTooLarge,
+            DecompressionError::Malformed => Status::BadRequest,
+        }
+    }
+}
+impl fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecompressionError::TooLarge => write!(f, "Too large request body"),
+            DecompressionError::Malformed => write!(f, "Malformed compressed request body"),
+        }
+    }
+}
+impl std::error::Error for DecompressionError {}
+
+/// A decoder that reads the whole compressed body of a request and decompresses it.
+///
+/// Both the compressed and the decompressed bodies are limited to `max_size` bytes.
+#[derive(Debug)]
+pub struct BodyDecompressor {
+    coding: ContentCoding,
+    max_size: usize,
+    received: usize,
+    inner: BodyDecoder<RemainingBytesDecoder>,
+}
+impl BodyDecompressor {
+    pub fn new(coding: ContentCoding, max_size: usize) -> Self {
+        BodyDecompressor {
+            coding,
+            max_size,
+            received: 0,
+            inner: BodyDecoder::new(RemainingBytesDecoder::new()),
+        }
+    }
+}
+impl Decode for BodyDecompressor {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        let size = track!(self.inner.decode(buf, eos))?;
+        self.received += size;
+        if self.received > self.max_size {
+            return Err(track!(
+                bytecodec::Error::from(
+                    bytecodec::ErrorKind::InvalidInput.cause(DecompressionError::TooLarge)
+                ),
+                "received={}, max_size={}",
+                self.received,
+                self.max_size
+            ));
+        }
+        Ok(size)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        let compressed = track!(self.inner.finish_decoding())?;
+        let limit = self.max_size as u64 + 1;
+        let mut decompressed = Vec::new();
+        let result = match self.coding {
+            ContentCoding::Gzip => GzDecoder::new(&compressed[..])
+                .take(limit)
+                .read_to_end(&mut decompressed),
+            ContentCoding::Deflate => ZlibDecoder::new(&compressed[..])
+                .take(limit)
+                .read_to_end(&mut decompressed),
+        };
+        if let Err(e) = result {
+            return Err(track!(
+                bytecodec::Error::from(
+                    bytecodec::ErrorKind::InvalidInput.cause(DecompressionError::Malformed)
+                ),
+                "{}",
+                e
+            ));
+        }
+        if decompressed.len() > self.max_size {
+            return Err(track!(
+                bytecodec::Error::from(
+                    bytecodec::ErrorKind::InvalidInput.cause(DecompressionError::TooLarge)
+                ),
+                "max_size={}",
+                self.max_size
+            ));
+        }
+        Ok(decompressed)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl BodyDecode for BodyDecompressor {
+    fn initialize(&mut self, header: &Header) -> bytecodec::Result<()> {
+        track!(self.inner.initialize(header))
+    }
+}
+
+/// Makes a header for decoding the decompressed body of a request that has `header`.
+///
+/// The fields about the encoding and framing of the body are excluded from the resulting header,
+/// so body decoders initialized with it read the decompressed body until the end.
+pub fn decompressed_header(header: &Header) -> Request<()> {
+    let mut req = Request::new(
+        Method::new("POST").expect("Never fails"),
+        RequestTarget::new("/").expect("Never fails"),
+        HttpVersion::V1_1,
+        (),
+    );
+    for field in header.fields() {
+        let excluded = ["Content-Encoding", "Content-Length", "Transfer-Encoding"]
+            .iter()
+            .any(|name| field.name().eq_ignore_ascii_case(name));
+        if !excluded {
+            // The field has been validated by the request decoder.
+            let field = unsafe { HeaderField::new_unchecked(field.name(), field.value()) };
+            req.header_mut().add_field(field);
+        }
+    }
+    req
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder};
+    use bytecodec::bytes::BytesEncoder;
+    use bytecodec::DecodeExt;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use futures::future::ok;
+    use httpcodec::BodyEncoder;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(body: &[u8], max_size: usize) -> bytecodec::Result<Vec<u8>> {
+        let mut header = Request::new(
+            Method::new("POST").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        header
+            .header_mut()
+            .add_field(HeaderField::new("Content-Length", &body.len().to_string()).unwrap());
+        let mut decoder = BodyDecompressor::new(ContentCoding::Gzip, max_size);
+        decoder.initialize(&header.header()).unwrap();
+        decoder.decode_from_bytes(body)
+    }
+
+    fn cause(e: &bytecodec::Error) -> Option<DecompressionError> {
+        e.concrete_cause::<DecompressionError>().cloned()
+    }
+
+    #[test]
+    fn body_decompressor_works() {
+        let body = gzip(b"hello");
+        assert_eq!(decompress(&body, 100).unwrap(), b"hello");
+
+        let e = decompress(&body, 4).err().unwrap();
+        assert_eq!(cause(&e), Some(DecompressionError::TooLarge));
+
+        let bomb = gzip(&[0; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+        let e = decompress(&bomb, 4096).err().unwrap();
+        assert_eq!(cause(&e), Some(DecompressionError::TooLarge));
+
+        let e = decompress(b"hello", 100).err().unwrap();
+        assert_eq!(cause(&e), Some(DecompressionError::Malformed));
+    }
+
+    #[test]
+    fn request_decompression_works() {
+        use bytecodec::bytes::RemainingBytesDecoder;
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+        struct Echo;
+        impl HandleRequest for Echo {
+            const METHOD: &'static str = "POST";
+            const PATH: &'static str = "/echo";
+
+            type ReqBody = Vec<u8>;
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<RemainingBytesDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, req.into_body())))
+            }
+        }
+
+        fn req(encoding: &str, body: Vec<u8>) -> Request<Vec<u8>> {
+            let mut req = Request::new(
+                Method::new("POST").unwrap(),
+                RequestTarget::new("/echo").unwrap(),
+                HttpVersion::V1_1,
+                body,
+            );
+            req.header_mut()
+                .add_field(HeaderField::new("Content-Encoding", encoding).unwrap());
+            req
+        }
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"hello").unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(b"hello").unwrap();
+        let deflate = deflate.finish().unwrap();
+
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::default());
+        bomb.write_all(&[0; 1024 * 1024]).unwrap();
+        let bomb = bomb.finish().unwrap();
+
+        // Disabled
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Echo).unwrap();
+        let client = builder.finish_test_client();
+        let res = fibers_global::execute(client.send(req("gzip", gzip.clone()))).unwrap();
+        assert_eq!(res.body(), &gzip);
+
+        // Enabled
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Echo).unwrap();
+        builder.request_decompression(Some(64 * 1024));
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.send(req("gzip", gzip))).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"hello");
+
+        let res = fibers_global::execute(client.send(req("deflate", deflate))).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"hello");
+
+        let res = fibers_global::execute(client.send(req("identity", b"hi".to_vec()))).unwrap();
+        assert_eq!(res.body(), b"hi");
+
+        let res = fibers_global::execute(client.send(req("gzip", bomb))).unwrap();
+        assert_eq!(res.status_code(), 413);
+
+        let res = fibers_global::execute(client.send(req("gzip", b"hello".to_vec()))).unwrap();
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(client.metrics().decompress_request_body_errors(), 2);
+        assert_eq!(client.metrics().decode_request_body_errors(), 0);
+    }
+}
@@ -1,31 +1,341 @@
 use crate::handler::{RequestHandlerFactory, RequestHandlerInstance};
-use crate::{ErrorKind, HandleRequest, HandlerOptions, Req, Result, Status};
+use crate::path_decoding::PathDecoding;
+use crate::request::PathCaptures;
+use crate::{Error, ErrorKind, HandleRequest, HandlerOptions, Req, Res, Result, Status};
 use factory::Factory;
+use std::collections::HashMap;
+use std::fmt;
 use std::result::Result as StdResult;
 use std::sync::Arc;
+use trackable::error::ErrorKindExt;
 use url::Url;
 
 type Method = &'static str;
+type Query = &'static [(&'static str, &'static str)];
+
+/// The reason why a request could not be dispatched to any handler.
+///
+/// Variants may be added in future releases, so matches on this type need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DispatchError {
+    /// There are no handlers that match the path of the request.
+    NotFound,
+
+    /// There are handlers that match the path of the request, but none of them accept the method.
+    ///
+    /// By default, `405 Method Not Allowed` with the `Allow` header is returned.
+    MethodNotAllowed {
+        /// The methods which are acceptable for the path.
+        allowed: Vec<&'static str>,
+    },
+
+    /// The request target is longer than the limit (see `ServerBuilder::max_request_target_len`).
+    UriTooLong {
+        /// The maximum length of request targets in bytes.
+        limit: usize,
+    },
+
+    /// The path of the request cannot be decoded (see `ServerBuilder::path_decoding`).
+    BadTarget,
+}
+impl DispatchError {
+    /// Returns the response status corresponding to the error.
+    pub fn status(&self) -> Status {
+        match *self {
+            DispatchError::NotFound => Status::NotFound,
+            DispatchError::MethodNotAllowed { .. } => Status::MethodNotAllowed,
+            DispatchError::UriTooLong { .. } => Status::UriTooLong,
+            DispatchError::BadTarget => Status::BadRequest,
+        }
+    }
+}
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DispatchError::NotFound => write!(f, "No handler matches the path"),
+            DispatchError::MethodNotAllowed { ref allowed } => write!(
+                f,
+                "The method is not allowed (allowed methods: {})",
+                allowed.join(", ")
+            ),
+            DispatchError::UriTooLong { limit } => {
+                write!(f, "The request target is too long (limit: {} bytes)", limit)
+            }
+            DispatchError::BadTarget => write!(f, "The path cannot be decoded"),
+        }
+    }
+}
+
+/// The cause of the errors returned when a handler conflicts with an already registered one.
+///
+/// This can be retrieved from the errors returned by `ServerBuilder::add_handler` via `Error::concrete_cause`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RouteConflict {
+    method: &'static str,
+    path: Arc<str>,
+    existing_path: Arc<str>,
+}
+impl RouteConflict {
+    /// Returns the method of the handler that could not be registered.
+    pub fn method(&self) -> &'static str {
+        self.method
+    }
+
+    /// Returns the path of the handler that could not be registered.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the path of the already registered handler that conflicts with the new one.
+    pub fn existing_path(&self) -> &str {
+        &self.existing_path
+    }
+}
+impl fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The route `{} {}` conflicts with the already registered path `{}`",
+            self.method, self.path, self.existing_path
+        )
+    }
+}
+impl std::error::Error for RouteConflict {}
+
+/// A registered route (i.e., a pair of a method and a path pattern).
+///
+/// Each route has a numeric identifier that is assigned in the order of registration
+/// (so it is stable as long as the handlers are registered in the same order).
+/// Since the path is the pattern of the handler (e.g., `/users/*`) rather than the actual path of a request,
+/// routes are suitable for labeling metrics and logs without unbounded cardinality.
+///
+/// The route of a request can be retrieved via `req.extensions().get::<Route>()` after the request is dispatched.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Route {
+    id: usize,
+    method: &'static str,
+    path: Arc<str>,
+}
+impl Route {
+    /// Returns the identifier of the route.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the method of the route.
+    pub fn method(&self) -> &'static str {
+        self.method
+    }
+
+    /// Returns the path pattern of the route.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the pair of the method and path pattern (e.g., to key per-route state).
+    pub(crate) fn key(&self) -> (&'static str, Arc<str>) {
+        (self.method, Arc::clone(&self.path))
+    }
+}
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.path)
+    }
+}
+
+/// A registered route that can never be dispatched.
+///
+/// This is reported by `ServerBuilder::check_routes`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShadowedRoute {
+    method: &'static str,
+    path: Arc<str>,
+    segment: String,
+}
+impl ShadowedRoute {
+    /// Returns the method of the route.
+    pub fn method(&self) -> &'static str {
+        self.method
+    }
+
+    /// Returns the path of the route.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the literal segment of the path that never matches the paths of requests.
+    ///
+    /// Since the paths of requests are normalized before being dispatched
+    /// (e.g., `.` and `..` segments are removed and some characters are percent-encoded),
+    /// segments that would be changed by the normalization are unreachable
+    /// (if the path decoding is enabled, only the segments that can never be produced by decoding are unreachable).
+    pub fn segment(&self) -> &str {
+        &self.segment
+    }
+}
+impl fmt::Display for ShadowedRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The route `{} {}` is unreachable: the segment {:?} never matches normalized request paths",
+            self.method, self.path, self.segment
+        )
+    }
+}
+
+type DispatchErrorHandlerFn =
+    dyn Fn(&Req<()>, &DispatchError) -> Option<Res<Vec<u8>>> + Send + Sync + 'static;
+
+#[derive(Clone)]
+pub struct DispatchErrorHandler(Arc<DispatchErrorHandlerFn>);
+impl DispatchErrorHandler {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Req<()>, &DispatchError) -> Option<Res<Vec<u8>>> + Send + Sync + 'static,
+    {
+        DispatchErrorHandler(Arc::new(f))
+    }
+
+    pub fn handle(&self, req: &Req<()>, error: &DispatchError) -> Option<Res<Vec<u8>>> {
+        (self.0)(req, error)
+    }
+}
+impl fmt::Debug for DispatchErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DispatchErrorHandler(_)")
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Dispatcher {
     trie: Arc<Trie>,
+    fallback: Option<Arc<Fallback>>,
+    routes: Arc<Vec<(Method, Arc<str>)>>,
+    max_target_len: Option<usize>,
 }
 impl Dispatcher {
-    pub fn dispatch(&self, req: &Req<()>) -> StdResult<RequestHandlerInstance, Status> {
-        self.trie.dispatch(req.method(), req.url())
+    /// Returns the methods and paths of the registered handlers.
+    pub fn routes(&self) -> Vec<(Method, &str)> {
+        self.routes.iter().map(|r| (r.0, &*r.1)).collect()
+    }
+
+    /// Selects the handler for the request, and sets the path segments matched by the wildcards
+    /// and the selected route to the request.
+    ///
+    /// If `cached` has been created for the same handler, it is reused instead of allocating a new instance.
+    pub fn dispatch(
+        &self,
+        req: &mut Req<()>,
+        cached: Option<RequestHandlerInstance>,
+    ) -> StdResult<(RequestHandlerInstance, Route), DispatchError> {
+        if let Some(limit) = self.max_target_len {
+            if req.request_target().len() > limit {
+                return Err(DispatchError::UriTooLong { limit });
+            }
+        }
+        let (handler, captures) =
+            self.dispatch_url(req.method(), req.url(), req.decoded_path_segments())?;
+        req.set_captures(captures);
+        req.extensions_mut().insert(handler.route.clone());
+        Ok((
+            handler.factory.create_or_reuse(cached),
+            handler.route.clone(),
+        ))
+    }
+
+    fn dispatch_url(
+        &self,
+        method: &str,
+        url: &Url,
+        decoded_segments: Option<&[String]>,
+    ) -> StdResult<(&RouteHandler, PathCaptures), DispatchError> {
+        match (
+            self.trie.dispatch(method, url, decoded_segments),
+            &self.fallback,
+        ) {
+            (Err(DispatchError::NotFound), Some(fallback)) => fallback
+                .dispatch(method)
+                .map(|handler| (handler, PathCaptures::default())),
+            (result, _) => result,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct DispatcherBuilder {
     trie: Trie,
+    fallback: Option<Fallback>,
+    routes: Vec<(Method, Arc<str>)>,
+    next_route_id: usize,
+    matchers: HashMap<String, SegmentMatcher>,
+    override_routes: bool,
+    max_target_len: Option<usize>,
 }
 impl DispatcherBuilder {
     pub fn new() -> Self {
         DispatcherBuilder {
             trie: Trie::default(),
+            fallback: None,
+            routes: Vec::new(),
+            next_route_id: 0,
+            matchers: HashMap::new(),
+            override_routes: false,
+            max_target_len: None,
+        }
+    }
+
+    /// Sets whether the handlers registered after this call replace the existing handlers
+    /// of the same method and path (see `ServerBuilder::override_routes`).
+    pub fn set_override_routes(&mut self, enabled: bool) {
+        self.override_routes = enabled;
+    }
+
+    /// Sets the maximum length of the request targets that can be dispatched
+    /// (see `ServerBuilder::max_request_target_len`).
+    pub fn set_max_target_len(&mut self, limit: Option<usize>) {
+        self.max_target_len = limit;
+    }
+
+    /// Registers the named predicate that can be referred by `<name:matcher>` segments of paths.
+    pub fn add_segment_matcher<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.matchers
+            .insert(name.to_owned(), SegmentMatcher(Arc::new(f)));
+    }
+
+    /// Returns the methods and paths of the registered handlers.
+    pub fn routes(&self) -> Vec<(Method, &str)> {
+        self.routes.iter().map(|r| (r.0, &*r.1)).collect()
+    }
+
+    pub fn set_fallback_handler<H, D, E>(
+        &mut self,
+        handler: H,
+        mut options: HandlerOptions<H, D, E>,
+    ) -> Result<()>
+    where
+        H: HandleRequest,
+        D: Factory<Item = H::Decoder> + Send + Sync + 'static,
+        E: Factory<Item = H::Encoder> + Send + Sync + 'static,
+    {
+        if let Some(e) = options.take_error() {
+            return Err(track!(e));
         }
+        track_assert!(!H::METHODS.is_empty(), ErrorKind::InvalidInput; H::PATH);
+        let factory = RequestHandlerFactory::new(handler, options);
+        let path: Arc<str> = Arc::from(H::PATH);
+        let handlers = H::METHODS
+            .iter()
+            .map(|&method| self.route_handler(method, &path, factory.clone()))
+            .collect();
+        self.fallback = Some(Fallback {
+            methods: H::METHODS,
+            handlers,
+        });
+        Ok(())
     }
 
     pub fn register_handler<H, D, E>(
@@ -38,16 +348,147 @@ impl DispatcherBuilder {
         D: Factory<Item = H::Decoder> + Send + Sync + 'static,
         E: Factory<Item = H::Encoder> + Send + Sync + 'static,
     {
-        let method = H::METHOD;
-        let path = track!(Path::parse(H::PATH))?;
-        let handler = RequestHandlerFactory::new(handler, options);
-        track!(self.trie.register(method, path, handler); method, H::PATH)?;
+        track!(self.register_handler_at(handler, options, Arc::from(H::PATH)))
+    }
+
+    /// Registers the handler at `pattern` instead of `HandleRequest::PATH` (e.g., prefixed by a group).
+    pub fn register_handler_at<H, D, E>(
+        &mut self,
+        handler: H,
+        mut options: HandlerOptions<H, D, E>,
+        pattern: Arc<str>,
+    ) -> Result<()>
+    where
+        H: HandleRequest,
+        D: Factory<Item = H::Decoder> + Send + Sync + 'static,
+        E: Factory<Item = H::Encoder> + Send + Sync + 'static,
+    {
+        if let Some(e) = options.take_error() {
+            return Err(track!(e));
+        }
+        track_assert!(!H::METHODS.is_empty(), ErrorKind::InvalidInput; pattern);
+        for (i, method) in H::METHODS.iter().enumerate() {
+            track_assert!(!H::METHODS[..i].contains(method), ErrorKind::InvalidInput;
+                          pattern, method);
+        }
+
+        // All the methods are checked by dry runs before registering any of them,
+        // so that the builder is left unchanged if one of them conflicts
+        let path = track!(Path::parse(&pattern, &self.matchers))?;
+        for &method in H::METHODS {
+            track!(self.trie.register(
+                method,
+                H::QUERY,
+                &pattern,
+                &path,
+                None,
+                self.override_routes
+            ))?;
+        }
+
+        let factory = RequestHandlerFactory::new(handler, options);
+        for &method in H::METHODS {
+            let handler = self.route_handler(method, &pattern, factory.clone());
+            let overridden = track!(self.trie.register(
+                method,
+                H::QUERY,
+                &pattern,
+                &path,
+                Some(handler),
+                self.override_routes
+            ))?;
+            if let Some(overridden) = overridden {
+                if let Some(i) = self
+                    .routes
+                    .iter()
+                    .position(|r| r.0 == method && r.1 == overridden)
+                {
+                    self.routes.remove(i);
+                }
+            }
+            self.routes.push((method, Arc::clone(&pattern)));
+        }
         Ok(())
     }
 
+    /// Assigns a new route identifier to the handler.
+    fn route_handler(
+        &mut self,
+        method: Method,
+        path: &Arc<str>,
+        factory: RequestHandlerFactory,
+    ) -> RouteHandler {
+        let route = Route {
+            id: self.next_route_id,
+            method,
+            path: Arc::clone(path),
+        };
+        self.next_route_id += 1;
+        RouteHandler { route, factory }
+    }
+
+    /// Returns the registered routes that can never be dispatched.
+    ///
+    /// `decoding` is the path decoding options of the server (if enabled).
+    pub fn check(&self, decoding: Option<&PathDecoding>) -> Vec<ShadowedRoute> {
+        let mut shadowed = Vec::new();
+        for &(method, ref path) in &self.routes {
+            let segments = Path::parse(path, &self.matchers).expect("Never fails").0;
+            let unreachable = segments.into_iter().find_map(|s| match s {
+                Segment::Val(v) => {
+                    let reachable = match decoding {
+                        None => is_normalized_segment(&v),
+                        Some(decoding) => decoding.can_match(&v),
+                    };
+                    if reachable {
+                        None
+                    } else {
+                        Some(v)
+                    }
+                }
+                _ => None,
+            });
+            if let Some(segment) = unreachable {
+                shadowed.push(ShadowedRoute {
+                    method,
+                    path: Arc::clone(path),
+                    segment,
+                });
+            }
+        }
+        shadowed
+    }
+
     pub fn finish(self) -> Dispatcher {
         Dispatcher {
             trie: Arc::new(self.trie),
+            fallback: self.fallback.map(Arc::new),
+            routes: Arc::new(self.routes),
+            max_target_len: self.max_target_len,
+        }
+    }
+}
+
+/// A handler and the route that it is registered to.
+#[derive(Debug, Clone)]
+struct RouteHandler {
+    route: Route,
+    factory: RequestHandlerFactory,
+}
+
+/// The handler for the requests that do not match any registered paths.
+#[derive(Debug)]
+struct Fallback {
+    methods: &'static [Method],
+    handlers: Vec<RouteHandler>,
+}
+impl Fallback {
+    fn dispatch(&self, method: &str) -> StdResult<&RouteHandler, DispatchError> {
+        if let Some(i) = self.methods.iter().position(|&m| m == method) {
+            Ok(&self.handlers[i])
+        } else {
+            let allowed = self.methods.to_vec();
+            Err(DispatchError::MethodNotAllowed { allowed })
         }
     }
 }
@@ -55,110 +496,203 @@ impl DispatcherBuilder {
 #[derive(Debug, Default)]
 struct Trie(TrieNode);
 impl Trie {
+    /// Registers `handler` for `path`.
+    ///
+    /// If `overriding` is `true` and a handler has already been registered for the same method, path and query,
+    /// the handler is replaced and the pattern of its path is returned.
+    ///
+    /// If `handler` is `None`, this only checks whether the route can be registered
+    /// (i.e., returns the same error as the actual registration) without modifying the trie.
     fn register(
         &mut self,
         method: Method,
-        path: Path,
-        handler: RequestHandlerFactory,
-    ) -> Result<()> {
+        query: Query,
+        pattern: &Arc<str>,
+        path: &Path,
+        handler: Option<RouteHandler>,
+        overriding: bool,
+    ) -> Result<Option<Arc<str>>> {
+        let conflict = |existing_path: &Arc<str>| {
+            let conflict = RouteConflict {
+                method,
+                path: Arc::clone(pattern),
+                existing_path: Arc::clone(existing_path),
+            };
+            track!(Error::from(ErrorKind::InvalidInput.cause(conflict)))
+        };
         let mut node = &mut self.0;
-        let mut segments = path.0.into_iter().peekable();
-        while let Some(segment) = segments.next() {
-            match segment {
-                Segment::Val(v) => {
-                    let mut i = 0;
-                    while i < node.segments.len() {
-                        match node.segments[i].0 {
+        for segment in &path.0 {
+            let position = match *segment {
+                Segment::Val(_) | Segment::Param(_) => {
+                    let mut position = None;
+                    for (i, (s, n)) in node.segments.iter().enumerate() {
+                        match *s {
                             Segment::Any | Segment::AllTheRest => {
-                                track_panic!(ErrorKind::InvalidInput)
+                                return Err(conflict(&n.pattern));
                             }
-                            Segment::Val(w) if v == w => {
+                            _ if s == segment => {
+                                position = Some(i);
                                 break;
                             }
-                            Segment::Val(_) => {
-                                i += 1;
-                            }
+                            _ => {}
                         }
                     }
-                    if i == node.segments.len() {
-                        node.segments.push((segment, Box::new(TrieNode::default())));
-                    }
-                    node = &mut { node }.segments[i].1;
+                    position
                 }
-                Segment::Any => {
-                    if node.segments.is_empty() {
-                        node.segments.push((segment, Box::new(TrieNode::default())));
-                    } else {
-                        track_assert_eq!(node.segments[0].0, Segment::Any, ErrorKind::InvalidInput);
-                    }
-                    node = &mut { node }.segments[0].1;
+                Segment::Any | Segment::AllTheRest => match node.segments.first() {
+                    None => None,
+                    Some((s, _)) if s == segment => Some(0),
+                    Some((_, n)) => return Err(conflict(&n.pattern)),
+                },
+            };
+            let i = match position {
+                Some(i) => i,
+                // The rest of the path is new, so no conflicts can occur
+                None if handler.is_none() => return Ok(None),
+                None => {
+                    node.segments
+                        .push((segment.clone(), TrieNode::new(pattern)));
+                    node.segments.len() - 1
                 }
-                Segment::AllTheRest => {
-                    if node.segments.is_empty() {
-                        node.segments.push((segment, Box::new(TrieNode::default())));
-                    } else {
-                        track_assert_eq!(
-                            node.segments[0].0,
-                            Segment::AllTheRest,
-                            ErrorKind::InvalidInput
-                        );
-                    }
-                    node = &mut { node }.segments[0].1;
-                    track_assert_eq!(segments.peek(), None, ErrorKind::InvalidInput);
-                }
-            }
+            };
+            node = &mut { node }.segments[i].1;
         }
-        track_assert!(
-            node.handlers.iter().find(|x| x.0 == method).is_none(),
-            ErrorKind::InvalidInput
-        );
-        node.handlers.push((method, handler));
 
-        Ok(())
+        let existing = node
+            .handlers
+            .iter()
+            .position(|x| x.0 == method && is_same_query(x.1, query));
+        if existing.is_some() && !overriding {
+            return Err(conflict(&node.pattern));
+        }
+        match (existing, handler) {
+            (_, None) => Ok(None),
+            (Some(i), Some(handler)) => {
+                let overridden = Arc::clone(&node.handlers[i].2.route.path);
+                node.handlers[i].2 = handler;
+                Ok(Some(overridden))
+            }
+            (None, Some(handler)) => {
+                node.handlers.push((method, query, handler));
+                Ok(None)
+            }
+        }
     }
 
-    fn dispatch(&self, method: &str, url: &Url) -> StdResult<RequestHandlerInstance, Status> {
+    /// Finds the handler for `url`.
+    ///
+    /// Literal segments take precedence over `<name:matcher>` segments,
+    /// and the latter are tried in the order of registration (there is no backtracking).
+    ///
+    /// If `decoded_segments` is `Some(..)`, the segments are compared with the path of the handlers
+    /// instead of the (percent-encoded) ones of `url`.
+    fn dispatch(
+        &self,
+        method: &str,
+        url: &Url,
+        decoded_segments: Option<&[String]>,
+    ) -> StdResult<(&RouteHandler, PathCaptures), DispatchError> {
         let mut node = &self.0;
-        'root: for actual in url.path_segments().expect("Never fails") {
+        let mut captures = PathCaptures::default();
+        let mut offset = 1; // The position of the current segment in `url.path()`
+        let segments = url.path_segments().expect("Never fails").enumerate();
+        'root: for (i, actual) in segments {
+            let range = offset..offset + actual.len();
+            offset = range.end + 1;
+            let actual = decoded_segments.map_or(actual, |s| s[i].as_str());
+            let mut param = None;
             for expected in &node.segments {
                 match *expected {
                     (Segment::Any, ref next) => {
+                        captures.wildcards.push((i, range));
                         node = next;
                         continue 'root;
                     }
                     (Segment::AllTheRest, ref next) => {
+                        captures.rest = Some(range.start..url.path().len());
                         node = next;
                         break 'root;
                     }
-                    (Segment::Val(v), ref next) => {
+                    (Segment::Val(ref v), ref next) => {
                         if v == actual {
                             node = next;
                             continue 'root;
                         }
                     }
+                    (Segment::Param(ref p), ref next) => {
+                        if param.is_none() && (p.matcher.0)(actual) {
+                            param = Some((&p.name, next));
+                        }
+                    }
+                }
+            }
+            if let Some((name, next)) = param {
+                captures.params.push((Arc::clone(name), i, range));
+                node = next;
+                continue;
+            }
+            return Err(DispatchError::NotFound);
+        }
+        let mut candidates = node.handlers.iter().filter(|h| h.0 == method).peekable();
+        if candidates.peek().is_some() {
+            let mut unconstrained = None;
+            for handler in candidates {
+                if handler.1.is_empty() {
+                    unconstrained = Some(handler);
+                } else if is_query_satisfied(handler.1, url) {
+                    return Ok((&handler.2, captures));
                 }
             }
-            return Err(Status::NotFound);
+            return unconstrained
+                .map(|h| (&h.2, captures))
+                .ok_or(DispatchError::NotFound);
         }
-        for handler in &node.handlers {
-            if handler.0 == method {
-                return Ok(handler.1.create());
+        if node.handlers.is_empty() {
+            Err(DispatchError::NotFound)
+        } else {
+            let mut allowed = Vec::new();
+            for h in &node.handlers {
+                if !allowed.contains(&h.0) {
+                    allowed.push(h.0);
+                }
             }
+            Err(DispatchError::MethodNotAllowed { allowed })
         }
-        Err(Status::MethodNotAllowed)
     }
 }
 
+/// Returns `true` if the query string of `url` contains all of the pairs of `query`.
+fn is_query_satisfied(query: Query, url: &Url) -> bool {
+    query
+        .iter()
+        .all(|&(name, value)| url.query_pairs().any(|(n, v)| n == name && v == value))
+}
+
+/// Returns `true` if `a` and `b` consist of the same pairs.
+fn is_same_query(a: Query, b: Query) -> bool {
+    a.iter().all(|x| b.contains(x)) && b.iter().all(|x| a.contains(x))
+}
+
 #[derive(Debug, Default)]
 struct TrieNode {
+    /// The path of the handler that created this node.
+    pattern: Arc<str>,
     segments: Vec<(Segment, Box<TrieNode>)>,
-    handlers: Vec<(Method, RequestHandlerFactory)>,
+    handlers: Vec<(Method, Query, RouteHandler)>,
+}
+impl TrieNode {
+    fn new(pattern: &Arc<str>) -> Box<Self> {
+        Box::new(TrieNode {
+            pattern: Arc::clone(pattern),
+            ..TrieNode::default()
+        })
+    }
 }
 
 #[derive(Debug)]
 struct Path(Vec<Segment>);
 impl Path {
-    fn parse(path: &'static str) -> Result<Path> {
+    fn parse(path: &str, matchers: &HashMap<String, SegmentMatcher>) -> Result<Path> {
         track_assert!(!path.is_empty(), ErrorKind::InvalidInput);
         track_assert_eq!(path.chars().nth(0), Some('/'), ErrorKind::InvalidInput; path);
         let mut segments = Vec::new();
@@ -177,8 +711,12 @@ impl Path {
                     segments.push(Segment::AllTheRest);
                     is_last = true;
                 }
+                _ if segment.starts_with('<') && segment.ends_with('>') => {
+                    let param = track!(Param::parse(segment, matchers); path)?;
+                    segments.push(Segment::Param(param));
+                }
                 _ => {
-                    segments.push(Segment::Val(segment));
+                    segments.push(Segment::Val(segment.to_owned()));
                 }
             }
         }
@@ -186,20 +724,97 @@ impl Path {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Returns `true` if `segment` is left unchanged by the normalization of request paths.
+fn is_normalized_segment(segment: &str) -> bool {
+    Url::parse(&format!("http://localhost/{}", segment))
+        .ok()
+        .and_then(|url| {
+            url.path_segments()
+                .map(|mut s| s.next() == Some(segment) && s.next().is_none())
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Segment {
-    Val(&'static str),
+    Val(String),
+    Param(Param),
     Any,
     AllTheRest,
 }
 
+/// A `<name:matcher>` segment.
+#[derive(Debug, Clone)]
+struct Param {
+    name: Arc<str>,
+    spec: String,
+    matcher: SegmentMatcher,
+}
+impl Param {
+    /// Parses `segment`.
+    ///
+    /// `matcher` is the name of a predicate registered via `ServerBuilder::segment_matcher`,
+    /// or a regular expression (only if the `regex` feature is enabled).
+    fn parse(segment: &str, matchers: &HashMap<String, SegmentMatcher>) -> Result<Self> {
+        let inner = &segment[1..segment.len() - 1];
+        let colon = track_assert_some!(inner.find(':'), ErrorKind::InvalidInput; segment);
+        let (name, spec) = (&inner[..colon], &inner[colon + 1..]);
+        track_assert!(!name.is_empty(), ErrorKind::InvalidInput; segment);
+        track_assert!(!spec.is_empty(), ErrorKind::InvalidInput; segment);
+        let matcher = if let Some(matcher) = matchers.get(spec) {
+            matcher.clone()
+        } else {
+            track!(SegmentMatcher::regex(spec); segment)?
+        };
+        Ok(Param {
+            name: Arc::from(name),
+            spec: spec.to_owned(),
+            matcher,
+        })
+    }
+}
+impl PartialEq for Param {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.spec == other.spec
+    }
+}
+impl Eq for Param {}
+
+#[derive(Clone)]
+pub struct SegmentMatcher(Arc<dyn Fn(&str) -> bool + Send + Sync + 'static>);
+impl SegmentMatcher {
+    #[cfg(feature = "regex")]
+    fn regex(pattern: &str) -> Result<Self> {
+        let regex = track!(regex::Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| Error::from(ErrorKind::InvalidInput.cause(e))))?;
+        Ok(SegmentMatcher(Arc::new(move |s| regex.is_match(s))))
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn regex(pattern: &str) -> Result<Self> {
+        track_panic!(
+            ErrorKind::InvalidInput,
+            "Unknown segment matcher (regular expressions require the `regex` feature)";
+            pattern
+        );
+    }
+}
+impl fmt::Debug for SegmentMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SegmentMatcher(_)")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{Reply, Req, Res, Status};
+    use crate::test::Hello;
+    use crate::{Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
     use bytecodec::null::NullDecoder;
     use futures::future::ok;
-    use httpcodec::{BodyDecoder, NoBodyEncoder};
+    use httpcodec::{BodyDecoder, BodyEncoder, NoBodyEncoder};
+    use std::ops::Range;
     use url::Url;
 
     macro_rules! define_handler {
@@ -227,6 +842,13 @@ mod test {
     define_handler!(Handler2, "PUT", "/foo/bar/");
     define_handler!(Handler3, "GET", "/aaa/*/bbb");
     define_handler!(Handler4, "GET", "/111/**");
+    define_handler!(Handler5, "GET", "/aaa/ccc/bbb");
+    define_handler!(Handler6, "GET", "/foo/../bar");
+    define_handler!(Handler7, "GET", "/foo bar");
+    define_handler!(Handler8, "GET", "/items/<id:uint>");
+    define_handler!(Handler9, "GET", "/items/new");
+    define_handler!(Handler10, "GET", "/items/<name:lower>/detail");
+    define_handler!(Handler11, "GET", "/items/<id:[0-9]+>");
 
     fn url(path: &str) -> Url {
         Url::parse(&format!("http://localhost{}", path)).unwrap()
@@ -242,19 +864,542 @@ mod test {
         track_try_unwrap!(builder.register_handler(Handler4, Default::default()));
 
         let trie = builder.finish().trie;
-        assert!(trie.dispatch("GET", &url("/")).is_ok());
-        assert!(trie.dispatch("PUT", &url("/")).is_err());
-        assert!(trie.dispatch("GET", &url("/f")).is_err());
-        assert!(trie.dispatch("GET", &url("/foo/bar")).is_ok());
-        assert!(trie.dispatch("GET", &url("/foo/bar/")).is_err());
-        assert!(trie.dispatch("PUT", &url("/foo/bar/")).is_ok());
-        assert!(trie.dispatch("GET", &url("/aaa/0/bbb")).is_ok());
-        assert!(trie.dispatch("GET", &url("/aaa/012/bbb")).is_ok());
-        assert!(trie.dispatch("GET", &url("/aaa/bbb")).is_err());
-        assert!(trie.dispatch("GET", &url("/aaa/0/bbb/")).is_err());
-        assert!(trie.dispatch("GET", &url("/111/")).is_ok());
-        assert!(trie.dispatch("GET", &url("/111/222")).is_ok());
-        assert!(trie.dispatch("GET", &url("/111/222/")).is_ok());
-        assert!(trie.dispatch("GET", &url("/111/222/333")).is_ok());
+        assert!(trie.dispatch("GET", &url("/"), None).is_ok());
+        assert!(trie.dispatch("PUT", &url("/"), None).is_err());
+        assert!(trie.dispatch("GET", &url("/f"), None).is_err());
+        assert!(trie.dispatch("GET", &url("/foo/bar"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/foo/bar/"), None).is_err());
+        assert!(trie.dispatch("PUT", &url("/foo/bar/"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/aaa/0/bbb"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/aaa/012/bbb"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/aaa/bbb"), None).is_err());
+        assert!(trie.dispatch("GET", &url("/aaa/0/bbb/"), None).is_err());
+        assert!(trie.dispatch("GET", &url("/111/"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/111/222"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/111/222/"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/111/222/333"), None).is_ok());
+    }
+
+    #[test]
+    fn dispatch_error_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler2, Default::default()));
+
+        let trie = builder.finish().trie;
+        assert_eq!(
+            trie.dispatch("GET", &url("/foo"), None).err(),
+            Some(DispatchError::NotFound)
+        );
+        assert_eq!(
+            trie.dispatch("GET", &url("/bar"), None).err(),
+            Some(DispatchError::NotFound)
+        );
+        assert_eq!(
+            trie.dispatch("PUT", &url("/foo/bar"), None).err(),
+            Some(DispatchError::MethodNotAllowed {
+                allowed: vec!["GET"]
+            })
+        );
+    }
+
+    #[test]
+    fn fallback_handler_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        track_try_unwrap!(builder.set_fallback_handler(Handler3, Default::default()));
+
+        let dispatcher = builder.finish();
+        assert!(dispatcher
+            .dispatch_url("GET", &url("/foo/bar"), None)
+            .is_ok());
+        assert!(dispatcher.dispatch_url("GET", &url("/"), None).is_ok());
+        assert!(dispatcher
+            .dispatch_url("GET", &url("/foo/bar/baz"), None)
+            .is_ok());
+        assert_eq!(
+            dispatcher.dispatch_url("PUT", &url("/foo/bar"), None).err(),
+            Some(DispatchError::MethodNotAllowed {
+                allowed: vec!["GET"]
+            })
+        );
+        assert_eq!(
+            dispatcher.dispatch_url("PUT", &url("/baz"), None).err(),
+            Some(DispatchError::MethodNotAllowed {
+                allowed: vec!["GET"]
+            })
+        );
+    }
+
+    #[test]
+    fn route_ids_work() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler3, Default::default()));
+        track_try_unwrap!(builder.set_fallback_handler(Handler0, Default::default()));
+
+        let dispatcher = builder.finish();
+        let route = |path| {
+            let (handler, _) = dispatcher
+                .dispatch_url("GET", &url(path), None)
+                .ok()
+                .unwrap();
+            handler.route.clone()
+        };
+        assert_eq!(route("/foo/bar").id(), 0);
+        assert_eq!(route("/aaa/0/bbb").id(), 1);
+        assert_eq!(route("/aaa/1/bbb").id(), 1);
+        assert_eq!(route("/aaa/1/bbb").path(), "/aaa/*/bbb");
+        assert_eq!(route("/aaa/1/bbb").to_string(), "GET /aaa/*/bbb");
+        assert_eq!(route("/unknown").id(), 2);
+        assert_eq!(route("/unknown").path(), "/");
+    }
+
+    #[test]
+    fn multi_method_handler_works() {
+        struct Handler;
+        impl HandleRequest for Handler {
+            const METHOD: &'static str = "PUT";
+            const METHODS: &'static [&'static str] = &["PUT", "PATCH"];
+            const PATH: &'static str = "/foo/bar";
+
+            type ReqBody = ();
+            type ResBody = ();
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = NoBodyEncoder;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, ())))
+            }
+        }
+
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        assert!(builder
+            .register_handler(Handler, Default::default())
+            .is_err());
+
+        let trie = builder.finish().trie;
+        assert!(trie.dispatch("PUT", &url("/foo/bar"), None).is_ok());
+        assert!(trie.dispatch("PATCH", &url("/foo/bar"), None).is_ok());
+        assert_eq!(
+            trie.dispatch("DELETE", &url("/foo/bar"), None).err(),
+            Some(DispatchError::MethodNotAllowed {
+                allowed: vec!["PUT", "PATCH", "GET"]
+            })
+        );
+    }
+
+    #[test]
+    fn route_conflict_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler3, Default::default()));
+
+        let e = builder
+            .register_handler(Handler5, Default::default())
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let conflict = e.concrete_cause::<RouteConflict>().unwrap();
+        assert_eq!(conflict.method(), "GET");
+        assert_eq!(conflict.path(), "/aaa/ccc/bbb");
+        assert_eq!(conflict.existing_path(), "/aaa/*/bbb");
+
+        let e = builder
+            .register_handler(Handler1, Default::default())
+            .err()
+            .unwrap();
+        let conflict = e.concrete_cause::<RouteConflict>().unwrap();
+        assert_eq!(conflict.path(), "/foo/bar");
+        assert_eq!(conflict.existing_path(), "/foo/bar");
+    }
+
+    #[test]
+    fn conflicting_multi_method_handler_is_not_registered() {
+        struct Handler;
+        impl HandleRequest for Handler {
+            const METHOD: &'static str = "PATCH";
+            const METHODS: &'static [&'static str] = &["PATCH", "GET"];
+            const PATH: &'static str = "/foo/bar";
+
+            type ReqBody = ();
+            type ResBody = ();
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = NoBodyEncoder;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, ())))
+            }
+        }
+
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        assert!(builder
+            .register_handler(Handler, Default::default())
+            .is_err());
+        assert_eq!(builder.routes(), [("GET", "/foo/bar")]);
+
+        let trie = builder.finish().trie;
+        assert!(trie.dispatch("PATCH", &url("/foo/bar"), None).is_err());
+    }
+
+    #[test]
+    fn override_routes_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler3, Default::default()));
+
+        builder.set_override_routes(true);
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        assert_eq!(
+            builder.routes(),
+            [("GET", "/aaa/*/bbb"), ("GET", "/foo/bar")]
+        );
+
+        // Structural conflicts are still rejected
+        assert!(builder
+            .register_handler(Handler5, Default::default())
+            .is_err());
+
+        let trie = builder.finish().trie;
+        let (handler, _) = trie.dispatch("GET", &url("/foo/bar"), None).unwrap();
+        assert_eq!(handler.route.id(), 2);
+    }
+
+    #[test]
+    fn check_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler0, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler2, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler3, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler6, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler7, Default::default()));
+
+        let shadowed = builder.check(None);
+        assert_eq!(shadowed.len(), 2);
+        assert_eq!(shadowed[0].path(), "/foo/../bar");
+        assert_eq!(shadowed[0].segment(), "..");
+        assert_eq!(shadowed[1].path(), "/foo bar");
+        assert_eq!(shadowed[1].segment(), "foo bar");
+
+        let shadowed = builder.check(Some(&PathDecoding::new()));
+        assert_eq!(shadowed.len(), 1);
+        assert_eq!(shadowed[0].path(), "/foo/../bar");
+
+        let trie = builder.finish().trie;
+        assert!(trie.dispatch("GET", &url("/foo/../bar"), None).is_err());
+    }
+
+    #[test]
+    fn query_constraints_works() {
+        macro_rules! define_query_handler {
+            ($handler:ident, $query:expr) => {
+                struct $handler;
+                impl HandleRequest for $handler {
+                    const METHOD: &'static str = "GET";
+                    const PATH: &'static str = "/search";
+                    const QUERY: &'static [(&'static str, &'static str)] = $query;
+
+                    type ReqBody = ();
+                    type ResBody = ();
+                    type Decoder = BodyDecoder<NullDecoder>;
+                    type Encoder = NoBodyEncoder;
+                    type Reply = Reply<Self::ResBody>;
+
+                    fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                        Box::new(ok(Res::new(Status::Ok, ())))
+                    }
+                }
+            };
+        }
+        define_query_handler!(UserSearch, &[("type", "user")]);
+        define_query_handler!(RepoSearch, &[("type", "repo")]);
+        define_query_handler!(AnySearch, &[]);
+        define_query_handler!(DuplicatedSearch, &[("type", "repo")]);
+
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(UserSearch, Default::default()));
+        track_try_unwrap!(builder.register_handler(RepoSearch, Default::default()));
+        assert!(builder
+            .register_handler(DuplicatedSearch, Default::default())
+            .is_err());
+
+        let trie = builder.finish().trie;
+        assert!(trie
+            .dispatch("GET", &url("/search?type=user"), None)
+            .is_ok());
+        assert!(trie
+            .dispatch("GET", &url("/search?q=foo&type=repo"), None)
+            .is_ok());
+        assert_eq!(
+            trie.dispatch("GET", &url("/search?type=team"), None).err(),
+            Some(DispatchError::NotFound)
+        );
+        assert_eq!(
+            trie.dispatch("GET", &url("/search"), None).err(),
+            Some(DispatchError::NotFound)
+        );
+        assert_eq!(
+            trie.dispatch("PUT", &url("/search"), None).err(),
+            Some(DispatchError::MethodNotAllowed {
+                allowed: vec!["GET"]
+            })
+        );
+
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(UserSearch, Default::default()));
+        track_try_unwrap!(builder.register_handler(AnySearch, Default::default()));
+        let trie = builder.finish().trie;
+        assert!(trie
+            .dispatch("GET", &url("/search?type=team"), None)
+            .is_ok());
+        assert!(trie.dispatch("GET", &url("/search"), None).is_ok());
+    }
+
+    #[test]
+    fn segment_matcher_works() {
+        let mut builder = DispatcherBuilder::new();
+        builder.add_segment_matcher("uint", |s| s.parse::<u64>().is_ok());
+        builder.add_segment_matcher("lower", |s| s.chars().all(|c| c.is_ascii_lowercase()));
+        track_try_unwrap!(builder.register_handler(Handler8, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler9, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler10, Default::default()));
+        assert!(builder
+            .register_handler(Handler8, Default::default())
+            .is_err());
+
+        let trie = builder.finish().trie;
+        let (_, captures) = trie.dispatch("GET", &url("/items/10"), None).ok().unwrap();
+        assert_eq!(
+            captures.params,
+            vec![(Arc::from("id"), 1, Range { start: 7, end: 9 })]
+        );
+        let (_, captures) = trie.dispatch("GET", &url("/items/new"), None).ok().unwrap();
+        assert!(captures.params.is_empty());
+        assert!(trie
+            .dispatch("GET", &url("/items/foo/detail"), None)
+            .is_ok());
+        assert_eq!(
+            trie.dispatch("GET", &url("/items/foo"), None).err(),
+            Some(DispatchError::NotFound)
+        );
+        assert_eq!(
+            trie.dispatch("GET", &url("/items/-1"), None).err(),
+            Some(DispatchError::NotFound)
+        );
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn unknown_segment_matcher_works() {
+        let mut builder = DispatcherBuilder::new();
+        let e = builder
+            .register_handler(Handler11, Default::default())
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_segment_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler11, Default::default()));
+
+        let trie = builder.finish().trie;
+        assert!(trie.dispatch("GET", &url("/items/10"), None).is_ok());
+        assert!(trie.dispatch("GET", &url("/items/1a"), None).is_err());
+        assert!(trie.dispatch("GET", &url("/items/"), None).is_err());
+    }
+
+    #[test]
+    fn path_captures_works() {
+        let mut builder = DispatcherBuilder::new();
+        track_try_unwrap!(builder.register_handler(Handler1, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler3, Default::default()));
+        track_try_unwrap!(builder.register_handler(Handler4, Default::default()));
+
+        let trie = builder.finish().trie;
+        let (_, captures) = trie.dispatch("GET", &url("/foo/bar"), None).ok().unwrap();
+        assert_eq!(captures, PathCaptures::default());
+
+        let (_, captures) = trie
+            .dispatch("GET", &url("/aaa/012/bbb"), None)
+            .ok()
+            .unwrap();
+        assert_eq!(captures.wildcards, vec![(1, Range { start: 5, end: 8 })]);
+        assert_eq!(captures.rest, None);
+
+        let (_, captures) = trie
+            .dispatch("GET", &url("/111/222/333"), None)
+            .ok()
+            .unwrap();
+        assert!(captures.wildcards.is_empty());
+        assert_eq!(captures.rest, Some(5..12));
+
+        let (_, captures) = trie.dispatch("GET", &url("/111/"), None).ok().unwrap();
+        assert_eq!(captures.rest, Some(5..5));
+    }
+
+    #[test]
+    fn dispatch_error_handler_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.dispatch_error_handler(|_req, e| match *e {
+            DispatchError::NotFound => Some(Res::new(Status::NotFound, b"nothing".to_vec())),
+            _ => None,
+        });
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"PUT /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        assert_eq!(
+            String::from_utf8(conn.take_received()).unwrap(),
+            concat!(
+                "HTTP/1.1 405 Method Not Allowed\r\nConnection: close\r\nAllow: GET\r\n",
+                "Content-Length: 18\r\n\r\nMethod Not Allowed"
+            )
+        );
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /world HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        assert_eq!(
+            String::from_utf8(conn.take_received()).unwrap(),
+            "HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 7\r\n\r\nnothing"
+        );
+    }
+
+    #[test]
+    fn dispatch_error_variants_work() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.max_request_target_len(16);
+        builder.path_decoding(PathDecoding::new());
+        builder.dispatch_error_handler(|_req, e| {
+            Some(Res::new(e.status(), e.to_string().into_bytes()))
+        });
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello?0123456789").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 414);
+        assert_eq!(
+            res.body(),
+            b"The request target is too long (limit: 16 bytes)"
+        );
+        assert_eq!(client.metrics().dispatch_uri_too_long_errors(), 1);
+        assert_eq!(client.metrics().dispatch_request_errors(), 1);
+
+        let res = fibers_global::execute(client.get("/%FF").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(res.body(), b"The path cannot be decoded");
+        assert_eq!(client.metrics().parse_request_path_errors(), 1);
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+    }
+
+    #[test]
+    fn server_fallback_handler_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.fallback_handler(Hello).unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/foo/bar").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"hello");
+    }
+
+    #[test]
+    fn routes_works() {
+        struct Form;
+        impl HandleRequest for Form {
+            const METHOD: &'static str = "GET";
+            const METHODS: &'static [&'static str] = &["GET", "POST"];
+            const PATH: &'static str = "/forms/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "form".to_owned())))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_handler(Form).unwrap();
+        builder.fallback_handler(Hello).unwrap();
+        let routes = [("GET", "/hello"), ("GET", "/forms/*"), ("POST", "/forms/*")];
+        assert_eq!(builder.routes(), routes);
+
+        let server = builder.finish(fibers_global::handle());
+        assert_eq!(server.routes(), routes);
+    }
+
+    #[test]
+    fn wildcards_works() {
+        struct Wildcards;
+        impl HandleRequest for Wildcards {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/users/*/files/*/**";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let body = format!("{:?} {:?}", req.wildcards(), req.rest_of_path());
+                Box::new(ok(Res::new(Status::Ok, body)))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Wildcards).unwrap();
+        let client = builder.finish_test_client();
+
+        let res =
+            fibers_global::execute(client.get("/users/foo/files/a%20b/c/d.txt").unwrap()).unwrap();
+        assert_eq!(res.body(), br#"["foo", "a%20b"] Some("c/d.txt")"#);
+    }
+
+    #[test]
+    fn route_is_set_to_requests() {
+        struct Echo;
+        impl HandleRequest for Echo {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/echo/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let route = req.extensions().get::<Route>().cloned().unwrap();
+                let pattern = req.route_pattern().unwrap();
+                let body = format!("{}:{}:{}", route.id(), route, pattern);
+                Box::new(ok(Res::new(Status::Ok, body)))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_handler(Echo).unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/echo/foo").unwrap()).unwrap();
+        assert_eq!(res.body(), b"1:GET /echo/*:/echo/*");
     }
 }
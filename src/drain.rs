@@ -0,0 +1,254 @@
+use bytecodec::marker::Never;
+use fibers::sync::mpsc;
+use futures::{Async, Future, Poll, Stream};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Shared state of the drain mode of a server.
+#[derive(Debug, Default)]
+pub struct DrainState {
+    is_draining: AtomicBool,
+    next_watch_id: AtomicUsize,
+    watchers: Mutex<HashMap<usize, mpsc::Sender<()>>>,
+}
+impl DrainState {
+    pub fn is_draining(&self) -> bool {
+        self.is_draining.load(Ordering::SeqCst)
+    }
+
+    /// Starts draining, and wakes up the fibers that are watching this state.
+    ///
+    /// Returns `false` if it has already been started.
+    pub fn start(&self) -> bool {
+        if self.is_draining.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let watchers = self.watchers.lock().unwrap_or_else(|e| e.into_inner());
+        for tx in watchers.values() {
+            let _ = tx.send(());
+        }
+        true
+    }
+
+    pub fn watch(this: &Arc<Self>) -> DrainWatch {
+        let id = this.next_watch_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = mpsc::channel();
+        this.watchers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, tx);
+        DrainWatch {
+            id,
+            state: Arc::clone(this),
+            rx,
+        }
+    }
+}
+
+/// A future that completes when the server starts draining (i.e., shutting down).
+///
+/// Long-running handlers (e.g., long-polling or server-sent events) can use this
+/// to finish their responses so that the draining completes.
+///
+/// This can be obtained via `ServerBuilder::shutdown_signal`, `ServerHandle::shutdown_signal`
+/// or `req.extensions().get::<ShutdownSignal>()`.
+/// The future has to be polled on fibers.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{ServerBuilder, ShutdownSignal};
+/// use futures::Future;
+///
+/// let builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// let signal: ShutdownSignal = builder.shutdown_signal();
+/// assert!(!signal.is_shutting_down());
+///
+/// let server = builder.finish(fibers_global::handle());
+/// server.handle().drain();
+/// assert!(signal.is_shutting_down());
+/// fibers_global::execute(signal).unwrap();
+/// ```
+pub struct ShutdownSignal {
+    state: Arc<DrainState>,
+
+    // `Mutex` makes this `Sync`, so that this can be stored in `Extensions`.
+    watch: Option<Mutex<DrainWatch>>,
+}
+impl ShutdownSignal {
+    pub(crate) fn new(state: Arc<DrainState>) -> Self {
+        ShutdownSignal { state, watch: None }
+    }
+
+    /// Returns `true` if the server has started draining, otherwise `false`.
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.is_draining()
+    }
+}
+impl Future for ShutdownSignal {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.state.is_draining() {
+            return Ok(Async::Ready(()));
+        }
+        let state = &self.state;
+        let watch = self
+            .watch
+            .get_or_insert_with(|| Mutex::new(DrainState::watch(state)));
+        let watch = watch.get_mut().unwrap_or_else(|e| e.into_inner());
+        if watch.is_draining() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl Clone for ShutdownSignal {
+    fn clone(&self) -> Self {
+        ShutdownSignal::new(Arc::clone(&self.state))
+    }
+}
+impl fmt::Debug for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ShutdownSignal {{ is_shutting_down: {:?} }}",
+            self.is_shutting_down()
+        )
+    }
+}
+
+/// A watcher of `DrainState`.
+///
+/// The fiber that calls `is_draining` method is woken up when draining starts.
+#[derive(Debug)]
+pub struct DrainWatch {
+    id: usize,
+    state: Arc<DrainState>,
+    rx: mpsc::Receiver<()>,
+}
+impl DrainWatch {
+    pub fn is_draining(&mut self) -> bool {
+        // Polls the channel so that the current fiber is registered to be woken up.
+        while let Ok(Async::Ready(Some(()))) = self.rx.poll() {}
+        self.state.is_draining()
+    }
+}
+impl Drop for DrainWatch {
+    fn drop(&mut self) {
+        self.state
+            .watchers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn drain_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        let server = builder.finish(fibers_global::handle());
+        let handle = server.handle();
+        let (server, addr) = fibers_global::execute(server.local_addr()).unwrap();
+        assert!(server.listener_fd().is_some());
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            fibers_global::execute(server).unwrap();
+            tx.send(()).unwrap();
+        });
+
+        let req = b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let mut buf = [0; 1024];
+        let mut client0 = TcpStream::connect(addr).unwrap();
+        client0.write_all(req).unwrap();
+        assert!(client0.read(&mut buf).unwrap() > 0);
+
+        // The head of the second request is sent along with the first request,
+        // so the server has already started reading it when the first response arrives.
+        let mut client1 = TcpStream::connect(addr).unwrap();
+        client1
+            .write_all(&[&req[..], b"GET /hello HTTP/1.1\r\n"].concat())
+            .unwrap();
+        assert!(client1.read(&mut buf).unwrap() > 0);
+
+        handle.drain();
+        assert!(handle.is_draining());
+
+        // The idle connection is closed immediately.
+        assert_eq!(client0.read(&mut buf).unwrap(), 0);
+        assert!(rx.try_recv().is_err());
+
+        // The in-flight request is completed.
+        client1.write_all(b"Content-Length: 0\r\n\r\n").unwrap();
+        assert!(client1.read(&mut buf).unwrap() > 0);
+        assert!(buf.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert_eq!(client1.read(&mut buf).unwrap(), 0);
+
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn shutdown_signal_works() {
+        struct LongPoll;
+        impl HandleRequest for LongPoll {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/poll";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let signal = req.extensions().get::<ShutdownSignal>().unwrap().clone();
+                Box::new(signal.map(|()| Res::new(Status::Ok, "bye".to_owned())))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(LongPoll).unwrap();
+        let signal = builder.shutdown_signal();
+        let server = builder.finish(fibers_global::handle());
+        let handle = server.handle();
+        let (server, addr) = fibers_global::execute(server.local_addr()).unwrap();
+        thread::spawn(move || fibers_global::execute(server).unwrap());
+
+        let mut buf = [0; 1024];
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        client
+            .write_all(b"GET /poll HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert!(client.read(&mut buf).is_err());
+        assert!(!signal.is_shutting_down());
+
+        handle.drain();
+        assert!(signal.is_shutting_down());
+        client.set_read_timeout(None).unwrap();
+        let size = client.read(&mut buf).unwrap();
+        assert!(buf[..size].ends_with(b"\r\n\r\nbye"));
+        fibers_global::execute(signal).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use bytecodec::{self, ByteCount, Encode, Eos};
+use httpcodec::{BodyEncode, HeaderMut};
+use std::sync::{Arc, Mutex};
+
+/// A pool of response body encoders shared by the requests dispatched to a handler
+/// (see `HandlerOptions::encoder_pool`).
+#[derive(Debug)]
+pub struct EncoderPool<E> {
+    encoders: Mutex<Vec<E>>,
+    capacity: usize,
+}
+impl<E> EncoderPool<E> {
+    pub fn new(capacity: usize) -> Self {
+        EncoderPool {
+            encoders: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Takes an encoder from the pool.
+    pub fn take(&self) -> Option<E> {
+        self.lock().pop()
+    }
+
+    /// Returns `encoder` to the pool.
+    ///
+    /// If the pool is full, `encoder` is dropped.
+    pub fn put(&self, encoder: E) {
+        let mut encoders = self.lock();
+        if encoders.len() < self.capacity {
+            encoders.push(encoder);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<E>> {
+        self.encoders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An encoder that is returned to its pool when dropped.
+///
+/// The encoders that have not finished encoding (e.g., because the connection was closed
+/// in the middle of a response) are discarded instead.
+#[derive(Debug)]
+pub struct PooledEncoder<E: Encode> {
+    inner: Option<E>,
+    pool: Option<Arc<EncoderPool<E>>>,
+}
+impl<E: Encode> PooledEncoder<E> {
+    /// Makes a new `PooledEncoder` instance.
+    ///
+    /// If `pool` is `Some(..)`, the encoder is taken from the pool (`create` is called only if the pool is empty).
+    pub fn new<F>(pool: Option<Arc<EncoderPool<E>>>, create: F) -> Self
+    where
+        F: FnOnce() -> E,
+    {
+        let inner = pool.as_ref().and_then(|p| p.take()).unwrap_or_else(create);
+        PooledEncoder {
+            inner: Some(inner),
+            pool,
+        }
+    }
+
+    fn inner_ref(&self) -> &E {
+        self.inner.as_ref().expect("Never fails")
+    }
+
+    fn inner_mut(&mut self) -> &mut E {
+        self.inner.as_mut().expect("Never fails")
+    }
+}
+impl<E: Encode> Encode for PooledEncoder<E> {
+    type Item = E::Item;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner_mut().encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        track!(self.inner_mut().start_encoding(item))
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner_ref().is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner_ref().requiring_bytes()
+    }
+}
+impl<E: BodyEncode> BodyEncode for PooledEncoder<E> {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        track!(self.inner_ref().update_header(header))
+    }
+}
+impl<E: Encode> Drop for PooledEncoder<E> {
+    fn drop(&mut self) {
+        if let (Some(inner), Some(pool)) = (self.inner.take(), self.pool.as_ref()) {
+            if inner.is_idle() {
+                pool.put(inner);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, HandlerOptions, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use bytecodec::EncodeExt;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+
+    #[test]
+    fn encoder_pool_works() {
+        let pool = Arc::new(EncoderPool::new(1));
+
+        let mut encoder = PooledEncoder::new(Some(Arc::clone(&pool)), Utf8Encoder::new);
+        assert_eq!(
+            track_try_unwrap!(encoder.encode_into_bytes("foo")),
+            b"foo".to_vec()
+        );
+        drop(encoder);
+        assert!(pool.take().is_some());
+        assert!(pool.take().is_none());
+
+        // Encoders in the middle of encoding are discarded
+        let mut encoder = PooledEncoder::new(Some(Arc::clone(&pool)), Utf8Encoder::new);
+        track_try_unwrap!(encoder.start_encoding("bar"));
+        drop(encoder);
+        assert!(pool.take().is_none());
+
+        // Encoders exceeding the capacity are discarded
+        pool.put(Utf8Encoder::new());
+        pool.put(Utf8Encoder::new());
+        assert!(pool.take().is_some());
+        assert!(pool.take().is_none());
+    }
+
+    #[test]
+    fn handler_encoder_pool_works() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Greet;
+        impl HandleRequest for Greet {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/greet";
+
+            type ReqBody = ();
+            type ResBody = &'static str;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder<&'static str>>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "hello")))
+            }
+        }
+
+        struct CountingFactory(Arc<AtomicUsize>);
+        impl factory::Factory for CountingFactory {
+            type Item = BodyEncoder<Utf8Encoder<&'static str>>;
+
+            fn create(&self) -> Self::Item {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Default::default()
+            }
+        }
+
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        let options = HandlerOptions::new()
+            .default_decoder()
+            .encoder(CountingFactory(Arc::clone(&created)))
+            .encoder_pool(4);
+        builder.add_handler_with_options(Greet, options).unwrap();
+        let client = builder.finish_test_client();
+
+        for _ in 0..5 {
+            let res = fibers_global::execute(client.get("/greet").unwrap()).unwrap();
+            assert_eq!(res.status_code(), 200);
+            assert_eq!(res.body(), b"hello");
+        }
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,8 +1,5 @@
-use bytecodec;
-use std;
 use trackable::error::{ErrorKind as TrackableErrorKind, ErrorKindExt};
 use trackable::error::{Failure, TrackableError};
-use url;
 
 /// This crate specific `Error` type.
 #[derive(Debug, Clone, TrackableError)]
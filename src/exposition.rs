@@ -0,0 +1,651 @@
+//! Conversion of the metrics in the Prometheus text format into the other exposition formats
+//! (see `metrics::MetricsHandler`).
+use crate::header::{self, Accept};
+use crate::metrics;
+use crate::{Req, Res, Status};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt::Write as _;
+use std::io::Write as _;
+
+const TEXT_MEDIA_TYPE: &str = "text/plain";
+const OPENMETRICS_MEDIA_TYPE: &str = "application/openmetrics-text";
+#[cfg(feature = "prost")]
+const PROTOBUF_MEDIA_TYPE: &str = "application/vnd.google.protobuf";
+
+/// An exposition format of metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    #[cfg(feature = "prost")]
+    Protobuf,
+}
+impl Format {
+    /// Selects the format that has the highest quality value in the `Accept` header of the request.
+    ///
+    /// Ties are broken in the order of `Text`, `OpenMetrics` and `Protobuf`.
+    /// If the request has no (valid) `Accept` header or accepts none of the formats, `Text` is selected.
+    pub fn negotiate<T>(req: &Req<T>) -> Self {
+        let accept = match req.typed_header::<Accept>() {
+            Ok(Some(accept)) => accept,
+            _ => return Format::Text,
+        };
+        let mut selected = Format::Text;
+        let mut max_quality = accept.quality(TEXT_MEDIA_TYPE);
+        let candidates = [
+            (Format::OpenMetrics, OPENMETRICS_MEDIA_TYPE),
+            #[cfg(feature = "prost")]
+            (Format::Protobuf, PROTOBUF_MEDIA_TYPE),
+        ];
+        for &(format, media_type) in &candidates {
+            let quality = accept.quality(media_type);
+            if quality > max_quality {
+                selected = format;
+                max_quality = quality;
+            }
+        }
+        selected
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => "text/plain; version=0.0.4; charset=utf-8",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            #[cfg(feature = "prost")]
+            Format::Protobuf => {
+                "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited"
+            }
+        }
+    }
+
+    fn encode(self, text: String) -> Vec<u8> {
+        match self {
+            Format::Text => text.into_bytes(),
+            Format::OpenMetrics => to_openmetrics(&text).into_bytes(),
+            #[cfg(feature = "prost")]
+            Format::Protobuf => protobuf::encode(&parse(&text)),
+        }
+    }
+}
+
+/// Makes the response containing the given metrics in the Prometheus text format.
+///
+/// The metrics are converted into `format`, and compressed by gzip if `gzip` is `true`.
+pub fn response(text: String, format: Format, gzip: bool) -> Res<Vec<u8>> {
+    let mut body = format.encode(text);
+    if gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).expect("Never fails");
+        body = encoder.finish().expect("Never fails");
+    }
+    let mut res = Res::new(Status::Ok, body);
+    res.add_header("Content-Type", format.content_type())
+        .expect("Never fails");
+    res.add_header("Vary", "Accept, Accept-Encoding")
+        .expect("Never fails");
+    if gzip {
+        res.add_header("Content-Encoding", "gzip")
+            .expect("Never fails");
+    }
+    res
+}
+
+/// Returns `true` if the `Accept-Encoding` header of the request accepts gzip.
+pub fn accepts_gzip<T>(req: &Req<T>) -> bool {
+    req.header()
+        .get_field("Accept-Encoding")
+        .map_or(false, |v| header::encoding_quality(v, "gzip") > 0.0)
+}
+
+/// A metric family parsed from the text format.
+#[derive(Debug, Default, PartialEq)]
+struct Family {
+    name: String,
+    help: Option<String>,
+    kind: String,
+    samples: Vec<Sample>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: String,
+    timestamp_ms: Option<i64>,
+}
+
+/// Parses the metrics in the Prometheus text format.
+///
+/// Malformed lines are ignored.
+fn parse(text: &str) -> Vec<Family> {
+    let mut families: Vec<Family> = Vec::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut items = comment.trim_start().splitn(3, ' ');
+            let (keyword, name) = match (items.next(), items.next()) {
+                (Some(keyword), Some(name)) => (keyword, name),
+                _ => continue,
+            };
+            let rest = items.next().unwrap_or("").trim();
+            if keyword != "HELP" && keyword != "TYPE" {
+                continue;
+            }
+            if families.last().map_or(true, |f| f.name != name) {
+                families.push(Family {
+                    name: name.to_owned(),
+                    kind: "untyped".to_owned(),
+                    ..Family::default()
+                });
+            }
+            let family = families.last_mut().expect("Never fails");
+            if keyword == "HELP" {
+                family.help = Some(unescape(rest));
+            } else {
+                family.kind = rest.to_owned();
+            }
+            continue;
+        }
+
+        let sample = match parse_sample(line) {
+            None => continue,
+            Some(sample) => sample,
+        };
+        let belongs = families.last().map_or(false, |f| {
+            sample
+                .name
+                .strip_prefix(f.name.as_str())
+                .map_or(false, |suffix| {
+                    suffix.is_empty() || ["_bucket", "_sum", "_count"].contains(&suffix)
+                })
+        });
+        if !belongs {
+            families.push(Family {
+                name: sample.name.clone(),
+                kind: "untyped".to_owned(),
+                ..Family::default()
+            });
+        }
+        families
+            .last_mut()
+            .expect("Never fails")
+            .samples
+            .push(sample);
+    }
+    families
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = line[..name_end].to_owned();
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if let Some(s) = rest.strip_prefix('{') {
+        rest = s;
+        loop {
+            rest = rest.trim_start_matches([' ', ',']);
+            if let Some(s) = rest.strip_prefix('}') {
+                rest = s;
+                break;
+            }
+            let eq = rest.find('=')?;
+            let label_name = rest[..eq].trim().to_owned();
+            rest = rest[eq + 1..].trim_start().strip_prefix('"')?;
+            let mut value = String::new();
+            let mut chars = rest.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.push((label_name, value));
+            rest = &rest[end + 1..];
+        }
+    }
+    let mut items = rest.split_whitespace();
+    let value = items.next()?.to_owned();
+    let timestamp_ms = match items.next() {
+        None => None,
+        Some(t) => Some(t.parse().ok()?),
+    };
+    Some(Sample {
+        name,
+        labels,
+        value,
+        timestamp_ms,
+    })
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn escape(s: &str, quotes: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if quotes => escaped.push_str("\\\""),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Converts the metrics in the Prometheus text format into the OpenMetrics text format.
+fn to_openmetrics(text: &str) -> String {
+    let mut s = String::new();
+    for family in parse(text) {
+        // The name of a counter family does not have the `_total` suffix in OpenMetrics
+        let (name, kind) = match family.kind.as_str() {
+            "counter" => match family.name.strip_suffix("_total") {
+                Some(name) => (name, "counter"),
+                None => (family.name.as_str(), "unknown"),
+            },
+            "gauge" | "histogram" | "summary" => (family.name.as_str(), family.kind.as_str()),
+            _ => (family.name.as_str(), "unknown"),
+        };
+        let _ = writeln!(s, "# TYPE {} {}", name, kind);
+        if let Some(ref help) = family.help {
+            let _ = writeln!(s, "# HELP {} {}", name, escape(help, true));
+        }
+        for sample in &family.samples {
+            s.push_str(&sample.name);
+            if !sample.labels.is_empty() {
+                s.push('{');
+                for (i, (k, v)) in sample.labels.iter().enumerate() {
+                    if i != 0 {
+                        s.push(',');
+                    }
+                    let _ = write!(s, "{}=\"{}\"", k, escape(v, true));
+                }
+                s.push('}');
+            }
+            let _ = write!(s, " {}", sample.value);
+            if let Some(ts) = sample.timestamp_ms {
+                // Timestamps are in seconds in OpenMetrics
+                let _ = write!(s, " {}.{:03}", ts.div_euclid(1000), ts.rem_euclid(1000));
+            }
+            if let Some(e) = exemplar(sample) {
+                let _ = write!(
+                    s,
+                    " # {{trace_id=\"{}\"}} {} {:.3}",
+                    escape(e.trace_id.as_str(), true),
+                    e.value,
+                    e.timestamp
+                );
+            }
+            s.push('\n');
+        }
+    }
+    s.push_str("# EOF\n");
+    s
+}
+
+/// Returns the exemplar of `sample` if it is a bucket of a `request_duration_seconds` histogram
+/// of a handler (see `metrics::TraceId`).
+fn exemplar(sample: &Sample) -> Option<metrics::Exemplar> {
+    if sample.name != "fibers_http_server_handler_request_duration_seconds_bucket" {
+        return None;
+    }
+    let label = |name: &str| {
+        sample
+            .labels
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+    let upper_bound = label("le")?.parse().ok()?;
+    metrics::exemplar(label("method")?, label("path")?, upper_bound)
+}
+
+#[cfg(feature = "prost")]
+mod protobuf {
+    //! The messages of `io.prometheus.client` (i.e., `metrics.proto` of Prometheus).
+    use super::Family;
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct LabelPair {
+        #[prost(string, optional, tag = "1")]
+        pub name: Option<String>,
+        #[prost(string, optional, tag = "2")]
+        pub value: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum MetricType {
+        Counter = 0,
+        Gauge = 1,
+        Summary = 2,
+        Untyped = 3,
+        Histogram = 4,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Value {
+        #[prost(double, optional, tag = "1")]
+        pub value: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Quantile {
+        #[prost(double, optional, tag = "1")]
+        pub quantile: Option<f64>,
+        #[prost(double, optional, tag = "2")]
+        pub value: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Summary {
+        #[prost(uint64, optional, tag = "1")]
+        pub sample_count: Option<u64>,
+        #[prost(double, optional, tag = "2")]
+        pub sample_sum: Option<f64>,
+        #[prost(message, repeated, tag = "3")]
+        pub quantile: Vec<Quantile>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Bucket {
+        #[prost(uint64, optional, tag = "1")]
+        pub cumulative_count: Option<u64>,
+        #[prost(double, optional, tag = "2")]
+        pub upper_bound: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Histogram {
+        #[prost(uint64, optional, tag = "1")]
+        pub sample_count: Option<u64>,
+        #[prost(double, optional, tag = "2")]
+        pub sample_sum: Option<f64>,
+        #[prost(message, repeated, tag = "3")]
+        pub bucket: Vec<Bucket>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Metric {
+        #[prost(message, repeated, tag = "1")]
+        pub label: Vec<LabelPair>,
+        #[prost(message, optional, tag = "2")]
+        pub gauge: Option<Value>,
+        #[prost(message, optional, tag = "3")]
+        pub counter: Option<Value>,
+        #[prost(message, optional, tag = "4")]
+        pub summary: Option<Summary>,
+        #[prost(message, optional, tag = "5")]
+        pub untyped: Option<Value>,
+        #[prost(int64, optional, tag = "6")]
+        pub timestamp_ms: Option<i64>,
+        #[prost(message, optional, tag = "7")]
+        pub histogram: Option<Histogram>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct MetricFamily {
+        #[prost(string, optional, tag = "1")]
+        pub name: Option<String>,
+        #[prost(string, optional, tag = "2")]
+        pub help: Option<String>,
+        #[prost(enumeration = "MetricType", optional, tag = "3")]
+        pub r#type: Option<i32>,
+        #[prost(message, repeated, tag = "4")]
+        pub metric: Vec<Metric>,
+    }
+
+    /// Encodes the families into length-delimited `MetricFamily` messages.
+    pub fn encode(families: &[Family]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for family in families {
+            let message = convert(family);
+            message
+                .encode_length_delimited(&mut buf)
+                .expect("Never fails");
+        }
+        buf
+    }
+
+    fn convert(family: &Family) -> MetricFamily {
+        let kind = match family.kind.as_str() {
+            "counter" => MetricType::Counter,
+            "gauge" => MetricType::Gauge,
+            "summary" => MetricType::Summary,
+            "histogram" => MetricType::Histogram,
+            _ => MetricType::Untyped,
+        };
+
+        // The samples of a histogram (or summary) are grouped by the labels other than `le` (or `quantile`)
+        let mut metrics: Vec<(Vec<(String, String)>, Metric)> = Vec::new();
+        for sample in &family.samples {
+            let value = parse_value(&sample.value);
+            let suffix = &sample.name[family.name.len()..];
+            let mut bound = None;
+            let labels = sample
+                .labels
+                .iter()
+                .filter(|(k, v)| {
+                    let is_bound = match kind {
+                        MetricType::Histogram => k == "le",
+                        MetricType::Summary => k == "quantile",
+                        _ => false,
+                    };
+                    if is_bound {
+                        bound = Some(parse_value(v));
+                    }
+                    !is_bound
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            let i = match metrics.iter().position(|m| m.0 == labels) {
+                Some(i) if kind == MetricType::Histogram || kind == MetricType::Summary => i,
+                _ => {
+                    let metric = Metric {
+                        label: labels
+                            .iter()
+                            .map(|(k, v)| LabelPair {
+                                name: Some(k.clone()),
+                                value: Some(v.clone()),
+                            })
+                            .collect(),
+                        timestamp_ms: sample.timestamp_ms,
+                        ..Metric::default()
+                    };
+                    metrics.push((labels, metric));
+                    metrics.len() - 1
+                }
+            };
+            let metric = &mut metrics[i].1;
+            let value = Some(Value { value: Some(value) });
+            match kind {
+                MetricType::Counter => metric.counter = value,
+                MetricType::Gauge => metric.gauge = value,
+                MetricType::Untyped => metric.untyped = value,
+                MetricType::Histogram => {
+                    let histogram = metric.histogram.get_or_insert_with(Histogram::default);
+                    let value = value.and_then(|v| v.value).unwrap_or(0.0);
+                    match suffix {
+                        "_sum" => histogram.sample_sum = Some(value),
+                        "_count" => histogram.sample_count = Some(value as u64),
+                        // The `+Inf` bucket is implied by `sample_count`
+                        "_bucket" if bound.map_or(false, |b| b.is_finite()) => {
+                            histogram.bucket.push(Bucket {
+                                cumulative_count: Some(value as u64),
+                                upper_bound: bound,
+                            })
+                        }
+                        _ => {}
+                    }
+                }
+                MetricType::Summary => {
+                    let summary = metric.summary.get_or_insert_with(Summary::default);
+                    let value = value.and_then(|v| v.value).unwrap_or(0.0);
+                    match suffix {
+                        "_sum" => summary.sample_sum = Some(value),
+                        "_count" => summary.sample_count = Some(value as u64),
+                        "" => summary.quantile.push(Quantile {
+                            quantile: bound,
+                            value: Some(value),
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        MetricFamily {
+            name: Some(family.name.clone()),
+            help: family.help.clone(),
+            r#type: Some(kind as i32),
+            metric: metrics.into_iter().map(|m| m.1).collect(),
+        }
+    }
+
+    fn parse_value(s: &str) -> f64 {
+        match s {
+            "+Inf" | "Inf" => f64::INFINITY,
+            "-Inf" => f64::NEG_INFINITY,
+            _ => s.parse().unwrap_or(f64::NAN),
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::super::parse;
+        use super::*;
+
+        #[test]
+        fn encode_works() {
+            let text = r#"# HELP requests_total Number of requests
+# TYPE requests_total counter
+requests_total{status="200"} 3
+# TYPE duration_seconds histogram
+duration_seconds_bucket{le="0.1"} 1
+duration_seconds_bucket{le="+Inf"} 2
+duration_seconds_sum 0.5
+duration_seconds_count 2
+"#;
+            let mut buf = &encode(&parse(text))[..];
+
+            let family = MetricFamily::decode_length_delimited(&mut buf).unwrap();
+            assert_eq!(family.name.as_deref(), Some("requests_total"));
+            assert_eq!(family.help.as_deref(), Some("Number of requests"));
+            assert_eq!(family.r#type, Some(MetricType::Counter as i32));
+            assert_eq!(family.metric.len(), 1);
+            assert_eq!(family.metric[0].label[0].value.as_deref(), Some("200"));
+            assert_eq!(family.metric[0].counter.as_ref().unwrap().value, Some(3.0));
+
+            let family = MetricFamily::decode_length_delimited(&mut buf).unwrap();
+            assert_eq!(family.r#type, Some(MetricType::Histogram as i32));
+            let histogram = family.metric[0].histogram.as_ref().unwrap();
+            assert_eq!(histogram.sample_count, Some(2));
+            assert_eq!(histogram.sample_sum, Some(0.5));
+            assert_eq!(histogram.bucket.len(), 1);
+            assert_eq!(histogram.bucket[0].upper_bound, Some(0.1));
+            assert!(buf.is_empty());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metrics::MetricsHandler;
+    use crate::ServerBuilder;
+    use std::io::Read;
+
+    #[test]
+    fn to_openmetrics_works() {
+        let text = r#"# HELP requests_total Number of "requests"
+# TYPE requests_total counter
+requests_total{method="GET",path="/a\"b"} 3
+requests_total{method="PUT",path="/"} 1 1500
+# TYPE temperature gauge
+temperature 36.5
+untyped_metric 1
+"#;
+        assert_eq!(
+            to_openmetrics(text),
+            r#"# TYPE requests counter
+# HELP requests Number of \"requests\"
+requests_total{method="GET",path="/a\"b"} 3
+requests_total{method="PUT",path="/"} 1 1.500
+# TYPE temperature gauge
+temperature 36.5
+# TYPE untyped_metric unknown
+untyped_metric 1
+# EOF
+"#
+        );
+    }
+
+    #[test]
+    fn metrics_exposition_formats_work() {
+        use flate2::read::GzDecoder;
+        use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+        fn req(fields: &[(&str, &str)]) -> Request<Vec<u8>> {
+            let mut req = Request::new(
+                Method::new("GET").unwrap(),
+                RequestTarget::new("/metrics").unwrap(),
+                HttpVersion::V1_1,
+                Vec::new(),
+            );
+            for &(name, value) in fields {
+                req.header_mut()
+                    .add_field(HeaderField::new(name, value).unwrap());
+            }
+            req
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(MetricsHandler).unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.send(req(&[]))).unwrap();
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("text/plain; version=0.0.4; charset=utf-8")
+        );
+        assert!(!res.body().ends_with(b"# EOF\n"));
+
+        let accept = "application/openmetrics-text;version=1.0.0;q=0.9,text/plain;q=0.5";
+        let res = fibers_global::execute(
+            client.send(req(&[("Accept", accept), ("Accept-Encoding", "gzip")])),
+        )
+        .unwrap();
+        assert!(res
+            .header()
+            .get_field("Content-Type")
+            .unwrap()
+            .starts_with("application/openmetrics-text"));
+        assert_eq!(res.header().get_field("Content-Encoding"), Some("gzip"));
+
+        let mut body = String::new();
+        GzDecoder::new(&res.body()[..])
+            .read_to_string(&mut body)
+            .unwrap();
+        assert!(body.ends_with("# EOF\n"));
+    }
+}
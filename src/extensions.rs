@@ -0,0 +1,103 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A type map of values associated with a request or a server.
+///
+/// Each type can have at most one value in a map.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+impl Extensions {
+    /// Makes a new empty `Extensions` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a value into the map.
+    ///
+    /// If the map already has a value of the same type, the old value is returned.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    /// Returns a reference to the value of the type `T`.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+
+    /// Returns a mutable reference to the value of the type `T`.
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_mut())
+    }
+
+    /// Removes the value of the type `T` from the map, and returns it.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.downcast().ok())
+            .map(|v| *v)
+    }
+
+    /// Returns `true` if the map has a value of the type `T`, otherwise `false`.
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the number of values in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map has no values, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Removes all values from the map.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extensions {{ len: {} }}", self.map.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Tenant(&'static str);
+
+    #[test]
+    fn extensions_works() {
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+
+        assert_eq!(extensions.insert(Tenant("foo")), None);
+        assert_eq!(extensions.insert(10usize), None);
+        assert_eq!(extensions.insert(Tenant("bar")), Some(Tenant("foo")));
+        assert_eq!(extensions.len(), 2);
+
+        assert_eq!(extensions.get::<Tenant>(), Some(&Tenant("bar")));
+        *extensions.get_mut::<usize>().unwrap() += 1;
+        assert_eq!(extensions.get::<usize>(), Some(&11));
+        assert!(!extensions.contains::<String>());
+
+        assert_eq!(extensions.remove::<usize>(), Some(11));
+        assert_eq!(extensions.remove::<usize>(), None);
+        extensions.clear();
+        assert!(extensions.is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{Error, Result};
+use bytecodec::{self, ByteCount, Encode, Eos};
+use fibers::net::TcpStream;
+use httpcodec::{BodyEncode, HeaderField, HeaderMut};
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// A response body backed by a file.
+///
+/// If a handler uses `FileBodyEncoder` as the response body encoder,
+/// the content of the file is written to sockets by using `sendfile(2)` (on Linux),
+/// so that it does not pass through the userspace buffers.
+/// On the other platforms, or if `sendfile(2)` is not available, the ordinary reads and writes are used instead.
+#[derive(Debug)]
+pub struct FileBody {
+    file: File,
+    len: u64,
+}
+impl FileBody {
+    /// Opens the file at the given path as a response body.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = track!(File::open(path).map_err(Error::from))?;
+        track!(FileBody::new(file))
+    }
+
+    /// Makes a new `FileBody` instance.
+    ///
+    /// The whole content of the file (regardless of the current cursor position) is used as the body.
+    pub fn new(file: File) -> Result<Self> {
+        let len = track!(file.metadata().map_err(Error::from))?.len();
+        Ok(FileBody { file, len })
+    }
+
+    /// Returns the length of the body in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the body is empty, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference to the inner file.
+    pub fn file_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Takes ownership of the body, and returns the inner file.
+    pub fn into_file(self) -> File {
+        self.file
+    }
+
+    pub(crate) fn into_sender(self) -> FileSender {
+        FileSender {
+            file: self.file,
+            offset: 0,
+            len: self.len,
+        }
+    }
+}
+
+/// Response body encoder for `FileBody`.
+#[derive(Debug, Default)]
+pub struct FileBodyEncoder {
+    sender: Option<FileSender>,
+}
+impl FileBodyEncoder {
+    /// Makes a new `FileBodyEncoder` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Encode for FileBodyEncoder {
+    type Item = FileBody;
+
+    fn encode(&mut self, buf: &mut [u8], _eos: Eos) -> bytecodec::Result<usize> {
+        let size = if let Some(ref mut sender) = self.sender {
+            track!(sender.read(buf).map_err(bytecodec::Error::from))?
+        } else {
+            return Ok(0);
+        };
+        if self.sender.as_ref().is_some_and(FileSender::is_completed) {
+            self.sender = None;
+        }
+        Ok(size)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        track_assert!(self.is_idle(), bytecodec::ErrorKind::EncoderFull);
+        if !item.is_empty() {
+            self.sender = Some(item.into_sender());
+        }
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.sender.is_none()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(self.sender.as_ref().map_or(0, FileSender::remaining))
+    }
+}
+impl BodyEncode for FileBodyEncoder {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        let value = self
+            .requiring_bytes()
+            .to_u64()
+            .expect("Never fails")
+            .to_string();
+        header.add_field(track!(HeaderField::new("Content-Length", &value))?);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileSender {
+    file: File,
+    offset: u64,
+    len: u64,
+}
+impl FileSender {
+    const FALLBACK_CHUNK_SIZE: usize = 16 * 1024;
+
+    pub fn remaining(&self) -> u64 {
+        self.len - self.offset
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.offset == self.len
+    }
+
+    /// Writes the remaining part of the file to the given stream.
+    ///
+    /// This continues until the whole file is written or the stream would block,
+    /// and returns the number of written bytes.
+    pub fn send_to(&mut self, stream: &mut TcpStream) -> io::Result<usize> {
+        let mut written = 0;
+        while !self.is_completed() {
+            let size = match self.sendfile(stream) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // `TcpStream::write` registers the stream to the poller if it would block,
+                    // so that the current fiber is woken up when the stream becomes writable.
+                    let mut buf = [0; Self::FALLBACK_CHUNK_SIZE];
+                    let size = self.read_at(&mut buf)?;
+                    match stream.write(&buf[..size]) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        result => result?,
+                    }
+                }
+                result => result?,
+            };
+            if size == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "Failed to write the file",
+                ));
+            }
+            self.offset += size as u64;
+            written += size;
+        }
+        Ok(written)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sendfile(&mut self, stream: &mut TcpStream) -> io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+
+        let count = cmp::min(self.remaining(), isize::MAX as u64) as usize;
+        let mut offset = self.offset as libc::off_t;
+        let out_fd = stream.with_inner(|s| s.as_raw_fd());
+        let in_fd = self.file.as_raw_fd();
+        let size = unsafe { libc::sendfile(out_fd, in_fd, &mut offset, count) };
+        if size < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(size as usize)
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn sendfile(&mut self, _stream: &mut TcpStream) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::WouldBlock))
+    }
+
+    fn read_at(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len() as u64, self.remaining()) as usize;
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let size = self.file.read(&mut buf[..n])?;
+        if size == 0 && n != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The file has been truncated",
+            ));
+        }
+        Ok(size)
+    }
+}
+impl Read for FileSender {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.read_at(buf)?;
+        self.offset += size as u64;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::spawn_server;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::null::NullDecoder;
+    use bytecodec::EncodeExt;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::fs;
+    use std::net::TcpStream;
+
+    #[test]
+    fn file_body_encoder_works() {
+        let path = std::env::temp_dir().join(format!(
+            "fibers_http_server_file_body_encoder_{}",
+            std::process::id()
+        ));
+        fs::write(&path, b"hello").unwrap();
+
+        let body = track_try_unwrap!(FileBody::open(&path));
+        assert_eq!(body.len(), 5);
+
+        let mut encoder = FileBodyEncoder::new();
+        let bytes = track_try_unwrap!(encoder.encode_into_bytes(body));
+        assert_eq!(bytes, b"hello");
+        assert!(encoder.is_idle());
+
+        let body = track_try_unwrap!(FileBody::open(&path));
+        let mut encoder = BodyEncoder::new(FileBodyEncoder::new());
+        assert!(encoder.start_encoding(body).is_ok());
+        assert_eq!(encoder.requiring_bytes(), ByteCount::Finite(5));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_body_works() {
+        use std::fs;
+
+        let path = std::env::temp_dir().join(format!(
+            "fibers_http_server_file_body_{}",
+            std::process::id()
+        ));
+        let content = (0..300 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(&path, &content).unwrap();
+
+        struct File(std::path::PathBuf);
+        impl HandleRequest for File {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/file";
+
+            type ReqBody = ();
+            type ResBody = FileBody;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = FileBodyEncoder;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                let body = track_try_unwrap!(FileBody::open(&self.0));
+                Box::new(ok(Res::new(Status::Ok, body)))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(File(path.clone())).unwrap();
+        let addr = spawn_server(builder);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let head = b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 307200\r\n\r\n";
+        for _ in 0..2 {
+            client
+                .write_all(b"GET /file HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            let mut buf = vec![0; head.len() + content.len()];
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..head.len()], &head[..]);
+            assert!(buf[head.len()..] == content[..]);
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+use httpcodec::Header;
+use url::{Position, Url};
+
+/// Returns the base URL of the original request described by the forwarding headers of a request.
+///
+/// The `Forwarded` header ([RFC 7239]) takes precedence over the `X-Forwarded-Proto`,
+/// `X-Forwarded-Host` and `X-Forwarded-Port` headers.
+/// Since each proxy appends its own element to the headers, only the last element (i.e., the one
+/// added by the proxy connected to the server) is used.
+/// The parts not specified by the headers are taken from `default`.
+///
+/// If the request has no forwarding headers or they are malformed, this returns `None`.
+///
+/// [RFC 7239]: https://tools.ietf.org/html/rfc7239
+pub fn forwarded_base_url(header: &Header, default: &Url) -> Option<Url> {
+    let (proto, host) = from_forwarded(header).or_else(|| from_x_forwarded(header))?;
+    let proto = proto.unwrap_or_else(|| default.scheme().to_owned());
+    if !proto.eq_ignore_ascii_case("http") && !proto.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let host = match host {
+        Some(host) if is_valid_host(&host) => host,
+        Some(_) => return None,
+        None => default[Position::BeforeHost..Position::AfterPort].to_owned(),
+    };
+    Url::parse(&format!("{}://{}/", proto, host)).ok()
+}
+
+/// Returns the `proto` and `host` parameters of the last element of the `Forwarded` header.
+fn from_forwarded(header: &Header) -> Option<(Option<String>, Option<String>)> {
+    let element = last_value(header, "Forwarded")?;
+    let mut proto = None;
+    let mut host = None;
+    for pair in element
+        .split(';')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+    {
+        let mut iter = pair.splitn(2, '=');
+        let (name, value) = (iter.next()?, unquote(iter.next()?.trim()));
+        if name.eq_ignore_ascii_case("proto") {
+            proto = Some(value.to_owned());
+        } else if name.eq_ignore_ascii_case("host") {
+            host = Some(value.to_owned());
+        }
+    }
+    if proto.is_none() && host.is_none() {
+        None
+    } else {
+        Some((proto, host))
+    }
+}
+
+/// Returns the values of the `X-Forwarded-Proto` and `X-Forwarded-Host` (and `X-Forwarded-Port`) headers.
+fn from_x_forwarded(header: &Header) -> Option<(Option<String>, Option<String>)> {
+    let proto = last_value(header, "X-Forwarded-Proto").map(|v| v.to_owned());
+    let port = last_value(header, "X-Forwarded-Port");
+    let host = last_value(header, "X-Forwarded-Host").map(|host| match port {
+        Some(port) if !has_port(host) => format!("{}:{}", host, port),
+        _ => host.to_owned(),
+    });
+    if proto.is_none() && host.is_none() {
+        None
+    } else {
+        Some((proto, host))
+    }
+}
+
+/// Returns the last element of the comma-separated values of the header fields named `name`.
+fn last_value<'a>(header: &'a Header, name: &str) -> Option<&'a str> {
+    header
+        .fields()
+        .filter(|f| f.name().eq_ignore_ascii_case(name))
+        .flat_map(|f| f.value().split(','))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .last()
+}
+
+fn unquote(s: &str) -> &str {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+fn has_port(host: &str) -> bool {
+    if host.starts_with('[') {
+        host.contains("]:")
+    } else {
+        host.contains(':')
+    }
+}
+
+/// Returns `true` if `host` consists only of the characters allowed in a host and a port.
+///
+/// This prevents the headers from injecting userinfo, paths, etc. into the URL.
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._:[]".contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::{
+        BodyDecoder, BodyEncoder, HeaderField, HttpVersion, Method, Request, RequestTarget,
+    };
+
+    fn base_url(fields: &[(&str, &str)]) -> Option<String> {
+        let mut req = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        for &(name, value) in fields {
+            req.header_mut()
+                .add_field(HeaderField::new(name, value).unwrap());
+        }
+        let default = Url::parse("http://127.0.0.1:8080/").unwrap();
+        forwarded_base_url(&req.header(), &default).map(|url| url.to_string())
+    }
+
+    #[test]
+    fn forwarded_base_url_works() {
+        assert_eq!(base_url(&[]), None);
+        assert_eq!(
+            base_url(&[("Forwarded", "for=192.0.2.60;proto=https;host=example.com")]),
+            Some("https://example.com/".to_owned())
+        );
+        assert_eq!(
+            base_url(&[(
+                "Forwarded",
+                "proto=http;host=evil.com, for=10.0.0.1;proto=https;host=\"example.com:8443\""
+            )]),
+            Some("https://example.com:8443/".to_owned())
+        );
+        assert_eq!(
+            base_url(&[("Forwarded", "proto=https")]),
+            Some("https://127.0.0.1:8080/".to_owned())
+        );
+
+        assert_eq!(
+            base_url(&[
+                ("X-Forwarded-Proto", "https"),
+                ("X-Forwarded-Host", "example.com"),
+                ("X-Forwarded-Port", "8443"),
+            ]),
+            Some("https://example.com:8443/".to_owned())
+        );
+        assert_eq!(
+            base_url(&[("X-Forwarded-Proto", "http, https")]),
+            Some("https://127.0.0.1:8080/".to_owned())
+        );
+        assert_eq!(
+            base_url(&[
+                ("Forwarded", "proto=http;host=a.example.com"),
+                ("X-Forwarded-Host", "b.example.com"),
+            ]),
+            Some("http://a.example.com/".to_owned())
+        );
+
+        // Malformed
+        assert_eq!(base_url(&[("X-Forwarded-Proto", "ftp")]), None);
+        assert_eq!(base_url(&[("X-Forwarded-Host", "example.com/evil")]), None);
+        assert_eq!(base_url(&[("X-Forwarded-Host", "user@example.com")]), None);
+    }
+
+    #[test]
+    fn trusted_proxies_work() {
+        struct ShowUrl;
+        impl HandleRequest for ShowUrl {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/url";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, req.url().to_string())))
+            }
+        }
+
+        let req = b"GET /url?a=b HTTP/1.1\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: example.com\r\n\r\n";
+        for &(cidr, expected) in &[
+            ("127.0.0.0/8", "https://example.com/url?a=b"),
+            ("10.0.0.0/8", "http://127.0.0.1/url?a=b"),
+        ] {
+            let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+            builder.add_handler(ShowUrl).unwrap();
+            builder.trusted_proxies(Some(cidr.parse().unwrap()));
+            let mut sim = builder.finish_simulation(0);
+
+            let conn = sim.connect().unwrap();
+            conn.write(req);
+            sim.run().unwrap();
+            let received = String::from_utf8(conn.take_received()).unwrap();
+            assert!(received.ends_with(expected), "{}", received);
+        }
+    }
+
+    #[test]
+    fn base_url_works() {
+        struct ShowUrl;
+        impl HandleRequest for ShowUrl {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/url";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, req.url().to_string())))
+            }
+        }
+
+        struct Redirect;
+        impl HandleRequest for Redirect {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/old/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                assert!(req.redirect::<String>(Status::Ok, "/new").is_err());
+                let location = format!("../new/{}", req.wildcards()[0]);
+                Box::new(ok(req.redirect(Status::Found, &location).unwrap()))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(ShowUrl).unwrap();
+        builder.add_handler(Redirect).unwrap();
+        builder.base_url("https://example.com:8443/ignored?x=y".parse().unwrap());
+        builder.trusted_proxies(Some("127.0.0.0/8".parse().unwrap()));
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /url?a=b HTTP/1.1\r\nX-Forwarded-Host: evil.com\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.ends_with("https://example.com:8443/url?a=b"),
+            "{}",
+            received
+        );
+
+        conn.write(b"GET /old/foo HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 302 Found\r\n"),
+            "{}",
+            received
+        );
+        assert!(
+            received.contains("Location: https://example.com:8443/new/foo\r\n"),
+            "{}",
+            received
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use crate::bandwidth::BandwidthLimit;
+use crate::handler::{FlushMode, Middleware};
+use crate::header;
+use crate::{HandleRequest, HandlerOptions, Req, Res, Result, ServerBuilder, Status};
+use factory::{DefaultFactory, Factory};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A group of handlers that share a path prefix, options and middleware (see `ServerBuilder::group`).
+///
+/// The settings of the group are applied to the handlers added after they are made.
+/// This includes the decoder and encoder factories registered by `decoder_factory` and `encoder_factory`,
+/// which are used by `add_handler` instead of the default ones.
+/// If a handler is added with `HandlerOptions` that specify the same settings,
+/// the ones of the handler take precedence (the middleware of the group is invoked
+/// before the one of the handler).
+///
+/// Note that the handlers themselves are unaware of the prefix
+/// (e.g., the `path` label of `ServerBuilder::handler_metrics` is still `HandleRequest::PATH`).
+#[derive(Debug)]
+pub struct HandlerGroup<'a> {
+    builder: &'a mut ServerBuilder,
+    prefix: String,
+    options: GroupOptions,
+    factories: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+impl<'a> HandlerGroup<'a> {
+    pub(crate) fn new(builder: &'a mut ServerBuilder, prefix: &str) -> Self {
+        HandlerGroup {
+            builder,
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            options: GroupOptions::default(),
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Returns the path prefix of the group.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Limits the bandwidth for reading requests handled by the handlers of the group
+    /// (see `HandlerOptions::read_bandwidth_limit`).
+    pub fn read_bandwidth_limit(&mut self, limit: BandwidthLimit) -> &mut Self {
+        self.options.read_bandwidth_limit = Some(limit);
+        self
+    }
+
+    /// Limits the bandwidth for writing responses from the handlers of the group
+    /// (see `HandlerOptions::write_bandwidth_limit`).
+    pub fn write_bandwidth_limit(&mut self, limit: BandwidthLimit) -> &mut Self {
+        self.options.write_bandwidth_limit = Some(limit);
+        self
+    }
+
+    /// Specifies how the responses from the handlers of the group are flushed to sockets
+    /// (see `HandlerOptions::flush_mode`).
+    pub fn flush_mode(&mut self, mode: FlushMode) -> &mut Self {
+        self.options.flush_mode = mode;
+        self
+    }
+
+    /// Makes the handlers of the group reuse their response body encoders
+    /// (see `HandlerOptions::encoder_pool`).
+    pub fn encoder_pool(&mut self, capacity: usize) -> &mut Self {
+        self.options.encoder_pool_capacity = capacity;
+        self
+    }
+
+    /// Makes the handlers of the group whose decoder is `F::Item` use `factory`
+    /// instead of the default decoder factory.
+    ///
+    /// The factory is shared by the handlers added by `add_handler` after this call.
+    pub fn decoder_factory<F>(&mut self, factory: F) -> &mut Self
+    where
+        F: Factory + Send + Sync + 'static,
+        F::Item: 'static,
+    {
+        self.insert_factory(factory);
+        self
+    }
+
+    /// Makes the handlers of the group whose encoder is `F::Item` use `factory`
+    /// instead of the default encoder factory.
+    ///
+    /// The factory is shared by the handlers added by `add_handler` after this call.
+    pub fn encoder_factory<F>(&mut self, factory: F) -> &mut Self
+    where
+        F: Factory + Send + Sync + 'static,
+        F::Item: 'static,
+    {
+        self.insert_factory(factory);
+        self
+    }
+
+    /// Adds a header field to every response from the handlers of the group
+    /// (see `HandlerOptions::header`).
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a token, or `value` contains control characters (e.g., CR and LF) or
+    /// is longer than `header::MAX_FIELD_VALUE_LEN` bytes, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn header(&mut self, name: &str, value: &str) -> Result<&mut Self> {
+        track!(header::validate_field(name, value))?;
+        self.options
+            .headers
+            .push((name.to_owned(), value.to_owned()));
+        Ok(self)
+    }
+
+    /// Adds a function that is invoked with the head part of each request before the handlers
+    /// of the group (see `HandlerOptions::middleware`).
+    pub fn middleware<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&mut Req<()>) -> Option<Res<Vec<u8>>> + Send + Sync + 'static,
+    {
+        self.options.middleware.push(Middleware::new(f));
+        self
+    }
+
+    /// Requires the requests to the handlers of the group to be authenticated.
+    ///
+    /// If `f` returns `false` for the head part of a request, `401 Unauthorized` is returned
+    /// with the `WWW-Authenticate: {challenge}` header (e.g., `Bearer realm="api"`).
+    ///
+    /// This is a shorthand of the middleware that performs the check.
+    ///
+    /// # Errors
+    ///
+    /// If `challenge` is not a valid header field value, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn require_auth<F>(&mut self, challenge: &str, f: F) -> Result<&mut Self>
+    where
+        F: Fn(&Req<()>) -> bool + Send + Sync + 'static,
+    {
+        track!(header::validate_field("WWW-Authenticate", challenge))?;
+        let challenge = challenge.to_owned();
+        Ok(self.middleware(move |req| {
+            if f(req) {
+                return None;
+            }
+            let status = Status::Unauthorized;
+            let mut res = Res::new(status, status.reason_phrase().as_bytes().to_owned());
+            res.add_header("WWW-Authenticate", &challenge)
+                .expect("Never fails");
+            Some(res)
+        }))
+    }
+
+    /// Adds a HTTP request handler to the group.
+    ///
+    /// The handler uses the decoder and encoder factories of the group if they are registered
+    /// for its decoder and encoder types (see `decoder_factory` and `encoder_factory`).
+    ///
+    /// # Errors
+    ///
+    /// If the prefixed path and method of the handler conflicts with the already registered
+    /// handlers, an `ErrorKind::InvalidInput` error will be returned
+    /// (see also `ServerBuilder::add_handler`).
+    pub fn add_handler<H>(&mut self, handler: H) -> Result<&mut Self>
+    where
+        H: HandleRequest,
+        H::Decoder: Default,
+        H::Encoder: Default,
+    {
+        let options = HandlerOptions::new()
+            .decoder(self.factory::<H::Decoder>())
+            .encoder(self.factory::<H::Encoder>());
+        self.add_handler_with_options(handler, options)
+    }
+
+    /// Adds a HTTP request handler to the group with the given options.
+    ///
+    /// # Errors
+    ///
+    /// If the prefixed path and method of the handler conflicts with the already registered
+    /// handlers, an `ErrorKind::InvalidInput` error will be returned
+    /// (see also `ServerBuilder::add_handler`).
+    pub fn add_handler_with_options<H, D, E>(
+        &mut self,
+        handler: H,
+        options: HandlerOptions<H, D, E>,
+    ) -> Result<&mut Self>
+    where
+        H: HandleRequest,
+        D: Factory<Item = H::Decoder> + Send + Sync + 'static,
+        E: Factory<Item = H::Encoder> + Send + Sync + 'static,
+    {
+        let pattern = Arc::from(format!("{}{}", self.prefix, H::PATH));
+        let options = options.inherit(&self.options);
+        track!(self.builder.add_handler_at(handler, options, pattern))?;
+        Ok(self)
+    }
+
+    fn insert_factory<F>(&mut self, factory: F)
+    where
+        F: Factory + Send + Sync + 'static,
+        F::Item: 'static,
+    {
+        let factory: SharedFactory<F::Item> = SharedFactory(Arc::new(factory));
+        self.factories
+            .insert(TypeId::of::<F::Item>(), Box::new(factory));
+    }
+
+    /// Returns the factory registered for `T`, or the default one.
+    fn factory<T>(&self) -> SharedFactory<T>
+    where
+        T: Default + 'static,
+    {
+        self.factories
+            .get(&TypeId::of::<T>())
+            .and_then(|f| f.downcast_ref::<SharedFactory<T>>())
+            .cloned()
+            .unwrap_or_else(|| SharedFactory(Arc::new(DefaultFactory::<T>::new())))
+    }
+}
+
+/// A decoder or encoder factory shared by the handlers of a group.
+struct SharedFactory<T>(Arc<dyn Factory<Item = T> + Send + Sync>);
+impl<T> Factory for SharedFactory<T> {
+    type Item = T;
+
+    fn create(&self) -> Self::Item {
+        self.0.create()
+    }
+}
+impl<T> Clone for SharedFactory<T> {
+    fn clone(&self) -> Self {
+        SharedFactory(Arc::clone(&self.0))
+    }
+}
+impl<T> fmt::Debug for SharedFactory<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedFactory(_)")
+    }
+}
+
+/// The settings of a handler group applied to the `HandlerOptions` of its handlers.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct GroupOptions {
+    pub read_bandwidth_limit: Option<BandwidthLimit>,
+    pub write_bandwidth_limit: Option<BandwidthLimit>,
+    pub flush_mode: FlushMode,
+    pub encoder_pool_capacity: usize,
+    pub headers: Vec<(String, String)>,
+    pub middleware: Vec<Middleware>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use bytecodec::bytes::Utf8Encoder;
+    use httpcodec::BodyEncoder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn handler_group_works() {
+        use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+        fn req(authorization: Option<&str>) -> Request<Vec<u8>> {
+            let mut req = Request::new(
+                Method::new("GET").unwrap(),
+                RequestTarget::new("/api/hello").unwrap(),
+                HttpVersion::V1_1,
+                Vec::new(),
+            );
+            if let Some(value) = authorization {
+                req.header_mut()
+                    .add_field(HeaderField::new("Authorization", value).unwrap());
+            }
+            req
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        {
+            let mut group = builder.group("/api/");
+            group.header("X-Api-Version", "1").unwrap();
+            group
+                .require_auth("Bearer", |req| {
+                    req.header().get_field("Authorization") == Some("Bearer secret")
+                })
+                .unwrap();
+            assert!(group.header("X-Api-Version", "1\r\n").is_err());
+            assert!(group.require_auth("Bearer\n", |_| true).is_err());
+            group.add_handler(Hello).unwrap();
+        }
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.header().get_field("X-Api-Version"), None);
+
+        let res = fibers_global::execute(client.send(req(None))).unwrap();
+        assert_eq!(res.status_code(), 401);
+        assert_eq!(res.header().get_field("WWW-Authenticate"), Some("Bearer"));
+
+        let res = fibers_global::execute(client.send(req(Some("Bearer secret")))).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"hello");
+        assert_eq!(res.header().get_field("X-Api-Version"), Some("1"));
+    }
+
+    #[test]
+    fn group_factories_work() {
+        struct CountingFactory(Arc<AtomicUsize>);
+        impl Factory for CountingFactory {
+            type Item = BodyEncoder<Utf8Encoder>;
+
+            fn create(&self) -> Self::Item {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                BodyEncoder::default()
+            }
+        }
+
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder
+            .group("/api")
+            .encoder_factory(CountingFactory(Arc::clone(&created)))
+            .add_handler(Hello)
+            .unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/api/hello").unwrap()).unwrap();
+        assert_eq!(res.body(), b"hello");
+        assert!(created.load(Ordering::SeqCst) > 0);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::drain::{DrainState, ShutdownSignal};
+use crate::head_limits::HeadLimits;
+use slog::{Drain, Level, Logger, OwnedKVList, Record};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// A handle for updating the options of a running `Server`.
+///
+/// The updated options are applied to new connections immediately,
+/// and to existing connections when they start reading the next requests.
+///
+/// This is created via `Server::handle` method.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    logger: Logger,
+    options: Arc<ReloadableOptions>,
+    drain: Arc<DrainState>,
+}
+impl ServerHandle {
+    pub(crate) fn new(
+        logger: Logger,
+        options: Arc<ReloadableOptions>,
+        drain: Arc<DrainState>,
+    ) -> Self {
+        ServerHandle {
+            logger,
+            options,
+            drain,
+        }
+    }
+
+    /// Starts draining the server.
+    ///
+    /// The draining server closes its listening socket, and stops reading new requests:
+    /// idle keep-alive connections are closed immediately, and the other connections are closed
+    /// after writing the responses of the current requests.
+    /// The `Server` future completes when all the connections have been closed.
+    pub fn drain(&self) {
+        if self.drain.start() {
+            info!(self.logger, "Draining requested");
+        }
+    }
+
+    /// Returns `true` if the server is draining, otherwise `false`.
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_draining()
+    }
+
+    /// Returns a future that completes when the server starts draining.
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        ShutdownSignal::new(Arc::clone(&self.drain))
+    }
+
+    /// Updates the timeout for receiving the head part of a request.
+    ///
+    /// See also `ServerBuilder::read_request_head_timeout`.
+    pub fn set_read_request_head_timeout(&self, timeout: Option<Duration>) {
+        self.update("read_request_head_timeout", &timeout, |v| {
+            v.read_request_head_timeout = timeout;
+        });
+    }
+
+    /// Updates the timeout for receiving the body part of a request.
+    ///
+    /// See also `ServerBuilder::read_request_body_timeout`.
+    pub fn set_read_request_body_timeout(&self, timeout: Option<Duration>) {
+        self.update("read_request_body_timeout", &timeout, |v| {
+            v.read_request_body_timeout = timeout;
+        });
+    }
+
+    /// Updates the timeout for writing a response to the client.
+    ///
+    /// See also `ServerBuilder::write_response_timeout`.
+    pub fn set_write_response_timeout(&self, timeout: Option<Duration>) {
+        self.update("write_response_timeout", &timeout, |v| {
+            v.write_response_timeout = timeout;
+        });
+    }
+
+    /// Updates the maximum size of the request line of a request.
+    ///
+    /// See also `ServerBuilder::max_request_line_size`.
+    pub fn set_max_request_line_size(&self, size: usize) {
+        self.update("max_request_line_size", &size, |v| {
+            v.head_limits.max_request_line_size = size;
+        });
+    }
+
+    /// Updates the maximum number of header fields of a request.
+    ///
+    /// See also `ServerBuilder::max_header_count`.
+    pub fn set_max_header_count(&self, n: usize) {
+        self.update("max_header_count", &n, |v| {
+            v.head_limits.max_header_count = n;
+        });
+    }
+
+    /// Updates the maximum size of a header field of a request.
+    ///
+    /// See also `ServerBuilder::max_header_field_size`.
+    pub fn set_max_header_field_size(&self, size: usize) {
+        self.update("max_header_field_size", &size, |v| {
+            v.head_limits.max_header_field_size = size;
+        });
+    }
+
+    /// Updates the level of the log messages emitted by the server.
+    ///
+    /// The messages less severe than `level` are discarded.
+    pub fn set_log_level(&self, level: Level) {
+        self.options
+            .log_level
+            .store(level.as_usize(), Ordering::SeqCst);
+        info!(self.logger, "Server option updated"; "name" => "log_level", "value" => ?level);
+    }
+
+    fn update<F>(&self, name: &'static str, value: &dyn fmt::Debug, f: F)
+    where
+        F: FnOnce(&mut ReloadableValues),
+    {
+        f(&mut self.options.lock());
+        self.options.version.fetch_add(1, Ordering::SeqCst);
+        info!(self.logger, "Server option updated"; "name" => name, "value" => ?value);
+    }
+}
+
+/// The options that can be updated via `ServerHandle`.
+#[derive(Debug)]
+pub struct ReloadableOptions {
+    version: AtomicUsize,
+    values: Mutex<ReloadableValues>,
+    log_level: Arc<AtomicUsize>,
+}
+impl ReloadableOptions {
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    pub fn values(&self) -> ReloadableValues {
+        self.lock().clone()
+    }
+
+    pub fn values_mut(&mut self) -> &mut ReloadableValues {
+        self.values.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wraps `logger` so that the messages are filtered by the log level of the options.
+    pub fn filter_logger(&self, logger: Logger) -> Logger {
+        let drain = LogLevelFilter {
+            inner: logger,
+            level: Arc::clone(&self.log_level),
+        };
+        Logger::root(drain, o!())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ReloadableValues> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+impl Default for ReloadableOptions {
+    fn default() -> Self {
+        ReloadableOptions {
+            version: AtomicUsize::new(0),
+            values: Mutex::default(),
+            log_level: Arc::new(AtomicUsize::new(Level::Trace.as_usize())),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ReloadableValues {
+    pub read_request_head_timeout: Option<Duration>,
+    pub read_request_body_timeout: Option<Duration>,
+    pub write_response_timeout: Option<Duration>,
+    pub head_limits: HeadLimits,
+}
+
+#[derive(Debug)]
+struct LogLevelFilter {
+    inner: Logger,
+    level: Arc<AtomicUsize>,
+}
+impl Drain for LogLevelFilter {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        let level = Level::from_usize(self.level.load(Ordering::SeqCst)).unwrap_or(Level::Trace);
+        if record.level().is_at_least(level) {
+            Drain::log(&self.inner, record, values)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+    use slog::Discard;
+
+    #[test]
+    fn server_handle_works() {
+        let options = Arc::new(ReloadableOptions::default());
+        let handle = ServerHandle::new(
+            Logger::root(Discard, o!()),
+            Arc::clone(&options),
+            Arc::default(),
+        );
+        assert_eq!(options.version(), 0);
+
+        handle.set_read_request_head_timeout(Some(Duration::from_secs(1)));
+        handle.set_max_header_count(10);
+        assert_eq!(options.version(), 2);
+
+        let values = options.values();
+        assert_eq!(
+            values.read_request_head_timeout,
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(values.read_request_body_timeout, None);
+        assert_eq!(values.head_limits.max_header_count, 10);
+
+        handle.set_log_level(Level::Warning);
+        assert_eq!(
+            options.log_level.load(Ordering::SeqCst),
+            Level::Warning.as_usize()
+        );
+    }
+
+    #[test]
+    fn set_max_header_count_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        let server = builder.finish(fibers_global::handle());
+
+        let req = || {
+            let mut req = httpcodec::Request::new(
+                httpcodec::Method::new("GET").unwrap(),
+                httpcodec::RequestTarget::new("/hello").unwrap(),
+                httpcodec::HttpVersion::V1_1,
+                Vec::new(),
+            );
+            req.header_mut()
+                .add_field(httpcodec::HeaderField::new("Foo", "bar").unwrap());
+            req
+        };
+        let res = fibers_global::execute(server.call(req())).unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        server.handle().set_max_header_count(1);
+        let res = fibers_global::execute(server.call(req())).unwrap();
+        assert_eq!(res.status_code(), 431);
+    }
+
+    #[test]
+    fn server_options_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder
+            .write_buffer_size(1024)
+            .max_active_connections(10)
+            .read_request_head_timeout(Duration::from_secs(3));
+        let server = builder.finish(fibers_global::handle());
+
+        let options = server.options();
+        assert_eq!(options.read_buffer_size(), 8192);
+        assert_eq!(options.write_buffer_size(), 1024);
+        assert_eq!(options.max_active_connections(), 10);
+        assert_eq!(options.max_pending_connections(), 1024);
+        assert_eq!(
+            options.read_request_head_timeout(),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(options.write_response_timeout(), None);
+
+        server.handle().set_max_header_count(5);
+        assert_eq!(server.options().max_header_count(), 5);
+        assert_eq!(options.max_header_count(), usize::MAX);
+    }
+}
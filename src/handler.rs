@@ -1,11 +1,23 @@
-use crate::response::ResEncoder;
-use crate::{Error, Req, Res, Result};
+use crate::bandwidth::BandwidthLimit;
+use crate::body_stream::{BodyStream, BodyStreamDecoder};
+use crate::decompression::{self, BodyDecompressor, ContentCoding};
+use crate::encoder_pool::{EncoderPool, PooledEncoder};
+use crate::file::{FileBody, FileBodyEncoder};
+use crate::group::GroupOptions;
+use crate::header::{self, Connection, ContentLength};
+use crate::response::{self, BodylessEncoder, ResEncoder};
+use crate::server::ResponseHook;
+use crate::static_files::{StaticBody, StaticBodyEncoder};
+use crate::{Error, Priority, Req, Res, Result, Route, Status};
+use bytecodec::bytes::{BytesEncoder, Utf8Encoder};
 use bytecodec::io::{IoDecodeExt, ReadBuf};
 use bytecodec::marker::Never;
-use bytecodec::{Decode, EncodeExt};
+use bytecodec::{self, Decode, DecodeExt, Encode};
 use factory::{DefaultFactory, Factory};
-use futures::{self, Future, Poll};
-use httpcodec::{BodyDecode, BodyEncode, ResponseEncoder};
+use futures::future::Either;
+use futures::{self, Async, Future, Poll};
+use httpcodec::{BodyDecode, BodyEncode, BodyEncoder, ResponseEncoder};
+use std::any::{Any, TypeId};
 use std::fmt;
 use std::marker::PhantomData;
 use std::sync::Arc;
@@ -15,13 +27,45 @@ pub trait HandleRequest: Sized + Send + Sync + 'static {
     /// The method that the handler can handle.
     const METHOD: &'static str;
 
+    /// The methods that the handler can handle.
+    ///
+    /// Override this to register the handler for multiple methods at once.
+    /// Note that if this is overridden, `METHOD` is only used to identify
+    /// the handler (e.g., by per-route rate limiting).
+    ///
+    /// The default value is `&[Self::METHOD]`.
+    const METHODS: &'static [&'static str] = &[Self::METHOD];
+
     /// The request path that the handler can handle.
     ///
     /// `*` and `**` in the path have the special meanings as follows:
     /// - `*` matches any path segment (i.e., regarded as a wildcard)
     /// - `**` matches all remaining parts of a path
+    /// - `<name:matcher>` matches a path segment accepted by `matcher`, which is either the name of
+    ///   a predicate registered via `ServerBuilder::segment_matcher` or a regular expression
+    ///   (e.g., `/items/<id:[0-9]+>`; regular expressions require the `regex` feature).
+    ///   The matched segment can be retrieved via `Req::path_param`.
     const PATH: &'static str;
 
+    /// The priority class of the requests handled by the handler.
+    ///
+    /// This is used by the load shedding (see `ServerBuilder::load_shedding`).
+    ///
+    /// The default value is `Priority::Normal`.
+    const PRIORITY: Priority = Priority::Normal;
+
+    /// The query parameters that the requests handled by the handler must have.
+    ///
+    /// If this is not empty, the handler is selected only for the requests whose query strings
+    /// contain all of the `(name, value)` pairs (the pairs are compared after being percent-decoded).
+    /// This allows for registering multiple handlers for the same method and path,
+    /// e.g., `GET /search?type=user` and `GET /search?type=repo`.
+    /// If none of such handlers match a request, the handler having no query constraints
+    /// for the method and path (if any) is selected.
+    ///
+    /// The default value is `&[]`.
+    const QUERY: &'static [(&'static str, &'static str)] = &[];
+
     /// The type of the request bodies.
     type ReqBody: Send + 'static;
 
@@ -29,6 +73,9 @@ pub trait HandleRequest: Sized + Send + Sync + 'static {
     type ResBody: Send + 'static;
 
     /// Request body decoder.
+    ///
+    /// If this is `BodyStreamDecoder`, `handle_request` is invoked as soon as the head part
+    /// of a request has been received, and the body is passed as a `BodyStream`.
     type Decoder: BodyDecode<Item = Self::ReqBody> + Send + 'static;
 
     /// Response body encoder.
@@ -66,6 +113,14 @@ pub struct HandlerOptions<H, D, E> {
     _handler: PhantomData<H>,
     decoder_factory: D,
     encoder_factory: E,
+    upload_progress: Option<UploadProgressFactory>,
+    read_bandwidth_limit: Option<BandwidthLimit>,
+    write_bandwidth_limit: Option<BandwidthLimit>,
+    flush_mode: FlushMode,
+    encoder_pool_capacity: usize,
+    headers: Vec<(String, String)>,
+    middleware: Vec<Middleware>,
+    error: Option<Error>,
 }
 impl<H> HandlerOptions<H, (), ()> {
     /// Makes a new `HandlerOptions` instance.
@@ -74,6 +129,14 @@ impl<H> HandlerOptions<H, (), ()> {
             _handler: PhantomData,
             decoder_factory: (),
             encoder_factory: (),
+            upload_progress: None,
+            read_bandwidth_limit: None,
+            write_bandwidth_limit: None,
+            flush_mode: FlushMode::Auto,
+            encoder_pool_capacity: 0,
+            headers: Vec::new(),
+            middleware: Vec::new(),
+            error: None,
         }
     }
 }
@@ -90,6 +153,14 @@ where
             _handler: self._handler,
             decoder_factory,
             encoder_factory: self.encoder_factory,
+            upload_progress: self.upload_progress,
+            read_bandwidth_limit: self.read_bandwidth_limit,
+            write_bandwidth_limit: self.write_bandwidth_limit,
+            flush_mode: self.flush_mode,
+            encoder_pool_capacity: self.encoder_pool_capacity,
+            headers: self.headers,
+            middleware: self.middleware,
+            error: self.error,
         }
     }
 
@@ -110,6 +181,14 @@ where
             _handler: self._handler,
             decoder_factory: self.decoder_factory,
             encoder_factory,
+            upload_progress: self.upload_progress,
+            read_bandwidth_limit: self.read_bandwidth_limit,
+            write_bandwidth_limit: self.write_bandwidth_limit,
+            flush_mode: self.flush_mode,
+            encoder_pool_capacity: self.encoder_pool_capacity,
+            headers: self.headers,
+            middleware: self.middleware,
+            error: self.error,
         }
     }
 
@@ -120,6 +199,149 @@ where
     {
         self.encoder(Default::default())
     }
+
+    /// Reinterprets the options as the ones for `G` (e.g., `WithMetrics<H>`).
+    ///
+    /// `G` has to use the same decoder and encoder as `H`.
+    pub(crate) fn cast<G>(self) -> HandlerOptions<G, D, E> {
+        HandlerOptions {
+            _handler: PhantomData,
+            decoder_factory: self.decoder_factory,
+            encoder_factory: self.encoder_factory,
+            upload_progress: self.upload_progress,
+            read_bandwidth_limit: self.read_bandwidth_limit,
+            write_bandwidth_limit: self.write_bandwidth_limit,
+            flush_mode: self.flush_mode,
+            encoder_pool_capacity: self.encoder_pool_capacity,
+            headers: self.headers,
+            middleware: self.middleware,
+            error: self.error,
+        }
+    }
+
+    /// Specifies the function that observes the progress of reading request bodies.
+    ///
+    /// `f` is called with the head part of each request handled by the handler,
+    /// and the returned function is called with the number of bytes of the body received so far
+    /// and the expected total size (i.e., the value of the `Content-Length` header) every time
+    /// a part of the body is read from the connection.
+    /// Note that the received bytes are counted as they are transferred
+    /// (e.g., including the chunk headers of chunked bodies).
+    ///
+    /// This can be used to expose the progress of uploads to clients via a separate channel.
+    pub fn upload_progress<F, G>(mut self, f: F) -> Self
+    where
+        F: Fn(&Req<()>) -> G + Send + Sync + 'static,
+        G: FnMut(u64, Option<u64>) + Send + 'static,
+    {
+        let factory = move |req: &Req<()>| -> UploadProgressFn { Box::new(f(req)) };
+        self.upload_progress = Some(UploadProgressFactory(Arc::new(factory)));
+        self
+    }
+
+    /// Limits the bandwidth for reading requests handled by the handler.
+    ///
+    /// This overrides the server-wide limit (see `ServerBuilder::read_bandwidth_limit`)
+    /// while the body of a request is being read.
+    pub fn read_bandwidth_limit(mut self, limit: BandwidthLimit) -> Self {
+        self.read_bandwidth_limit = Some(limit);
+        self
+    }
+
+    /// Limits the bandwidth for writing responses from the handler.
+    ///
+    /// This overrides the server-wide limit (see `ServerBuilder::write_bandwidth_limit`).
+    pub fn write_bandwidth_limit(mut self, limit: BandwidthLimit) -> Self {
+        self.write_bandwidth_limit = Some(limit);
+        self
+    }
+
+    /// Specifies how the responses from the handler are flushed to sockets.
+    ///
+    /// The default value is `FlushMode::Auto`.
+    pub fn flush_mode(mut self, mode: FlushMode) -> Self {
+        self.flush_mode = mode;
+        self
+    }
+
+    /// Makes the handler reuse the response body encoders across requests.
+    ///
+    /// The encoders that have finished encoding responses are kept in a pool shared by
+    /// all connections (up to `capacity` encoders), and are used for the subsequent requests
+    /// instead of the ones created by the encoder factory.
+    /// This reduces the allocator pressure if the encoders are costly to create
+    /// (e.g., they own large buffers). Note that the encoders must be able to encode
+    /// another item once the previous one has been encoded.
+    ///
+    /// The default value is `0` (i.e., the pooling is disabled).
+    pub fn encoder_pool(mut self, capacity: usize) -> Self {
+        self.encoder_pool_capacity = capacity;
+        self
+    }
+
+    /// Adds a header field to every response from the handler (e.g., `Cache-Control: no-store`).
+    ///
+    /// The field is not added if the response already has a field of the same name
+    /// (i.e., the handler can override it).
+    ///
+    /// If `name` is not a token, or `value` contains control characters (e.g., CR and LF) or
+    /// is longer than `header::MAX_FIELD_VALUE_LEN` bytes, adding the handler with the options
+    /// will fail with an `ErrorKind::InvalidInput` error.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        if self.error.is_none() {
+            match track!(header::validate_field(name, value)) {
+                Err(e) => self.error = Some(e),
+                Ok(()) => self.headers.push((name.to_owned(), value.to_owned())),
+            }
+        }
+        self
+    }
+
+    /// Adds a function that is invoked with the head part of each request before the handler.
+    ///
+    /// The function can modify the request (e.g., insert extensions), or reject it by returning
+    /// a response. In the latter case, the response is sent instead of invoking the handler
+    /// and the connection is closed (the body of the request is not read).
+    ///
+    /// The functions are invoked in the order of addition, after the server-wide checks
+    /// (e.g., `ServerBuilder::rate_limit` and `ServerBuilder::csrf_protection`).
+    pub fn middleware<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut Req<()>) -> Option<Res<Vec<u8>>> + Send + Sync + 'static,
+    {
+        self.middleware.push(Middleware::new(f));
+        self
+    }
+
+    /// Returns the error occurred while building the options (if any).
+    pub(crate) fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Applies the settings of a handler group that are not specified by the options themselves.
+    pub(crate) fn inherit(mut self, group: &GroupOptions) -> Self {
+        if self.read_bandwidth_limit.is_none() {
+            self.read_bandwidth_limit = group.read_bandwidth_limit;
+        }
+        if self.write_bandwidth_limit.is_none() {
+            self.write_bandwidth_limit = group.write_bandwidth_limit;
+        }
+        if self.flush_mode == FlushMode::Auto {
+            self.flush_mode = group.flush_mode;
+        }
+        if self.encoder_pool_capacity == 0 {
+            self.encoder_pool_capacity = group.encoder_pool_capacity;
+        }
+        // The fields of the handler precede (i.e., override) the ones of the group
+        self.headers.extend(group.headers.iter().cloned());
+        self.middleware = group
+            .middleware
+            .iter()
+            .cloned()
+            .chain(self.middleware)
+            .collect();
+        self
+    }
 }
 impl<H> Default for HandlerOptions<H, DefaultFactory<H::Decoder>, DefaultFactory<H::Encoder>>
 where
@@ -132,35 +354,220 @@ where
     }
 }
 
+/// How the responses from a handler are flushed to sockets (see `HandlerOptions::flush_mode`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    /// The buffered bytes of responses are written to sockets when the connection gets around to it
+    /// (e.g., after the current part of a response has been encoded or the write buffer is full).
+    #[default]
+    Auto,
+
+    /// The buffered bytes are written to the socket as soon as each part of a response is encoded.
+    ///
+    /// This is suitable for small, latency-sensitive responses and streaming responses.
+    Immediate,
+
+    /// The socket is corked while a response is being written,
+    /// so that many tiny writes are coalesced into full-sized segments.
+    ///
+    /// The socket is uncorked (i.e., the remaining bytes are sent) when the response has been written.
+    /// This uses `TCP_CORK`, and is equivalent to `Auto` on the platforms other than Linux.
+    Cork,
+}
+
+type MiddlewareFn = dyn Fn(&mut Req<()>) -> Option<Res<Vec<u8>>> + Send + Sync + 'static;
+
+#[derive(Clone)]
+pub(crate) struct Middleware(Arc<MiddlewareFn>);
+impl Middleware {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&mut Req<()>) -> Option<Res<Vec<u8>>> + Send + Sync + 'static,
+    {
+        Middleware(Arc::new(f))
+    }
+}
+impl fmt::Debug for Middleware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Middleware(_)")
+    }
+}
+
+type UploadProgressFn = Box<dyn FnMut(u64, Option<u64>) + Send + 'static>;
+
+#[derive(Clone)]
+struct UploadProgressFactory(Arc<dyn Fn(&Req<()>) -> UploadProgressFn + Send + Sync + 'static>);
+impl fmt::Debug for UploadProgressFactory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UploadProgressFactory(_)")
+    }
+}
+
+struct UploadProgress {
+    callback: UploadProgressFn,
+    received: u64,
+    expected: Option<u64>,
+}
+impl UploadProgress {
+    fn new(factory: &UploadProgressFactory, req: &Req<()>) -> Self {
+        let expected = req
+            .typed_header::<ContentLength>()
+            .ok()
+            .and_then(|x| x)
+            .map(|x| x.0);
+        UploadProgress {
+            callback: (factory.0)(req),
+            received: 0,
+            expected,
+        }
+    }
+
+    fn update(&mut self, size: usize) {
+        if size > 0 {
+            self.received += size as u64;
+            (self.callback)(self.received, self.expected);
+        }
+    }
+}
+
 pub trait HandleInput {
-    fn init(&mut self, req: Req<()>) -> Result<()>;
+    fn priority(&self) -> Priority;
+
+    /// Invokes the middleware specified by `HandlerOptions` (and handler groups).
+    ///
+    /// Returns the response if the request has been rejected.
+    fn apply_middleware(&self, req: &mut Req<()>) -> Option<Res<Vec<u8>>>;
+
+    /// Returns the bandwidth limits for reading and writing specified by `HandlerOptions`.
+    fn bandwidth_limits(&self) -> (Option<BandwidthLimit>, Option<BandwidthLimit>);
+
+    /// Returns the flush mode specified by `HandlerOptions`.
+    fn flush_mode(&self) -> FlushMode;
+
+    fn init(
+        &mut self,
+        req: Req<()>,
+        max_decompressed_size: Option<usize>,
+        max_drained_size: usize,
+    ) -> Result<()>;
 
     fn handle_input(&mut self, buf: &mut ReadBuf<Vec<u8>>) -> Result<Option<BoxReply>>;
 
     fn is_closed(&self) -> bool;
+
+    /// Returns the number of bytes of the request body that have to be skipped
+    /// before reading the next request.
+    ///
+    /// This is non-zero if the handler responded without reading the body
+    /// (e.g., by `HandleRequest::handle_request_head`).
+    fn unread_body_len(&self) -> u64;
+
+    /// Returns `true` if the handler cannot consume more input until the current task is notified.
+    fn is_blocked(&self) -> bool;
+
+    /// Resets the handler so that it can handle another request.
+    fn reset(&mut self);
 }
 
-struct InputHandler<H: HandleRequest> {
-    req_handler: Arc<H>,
+/// The part of a handler shared by all of its instances.
+struct SharedHandler<H: HandleRequest, D, E> {
+    req_handler: H,
+    options: HandlerOptions<H, D, E>,
+    encoder_pool: Option<Arc<EncoderPool<H::Encoder>>>,
+    headers: Arc<Vec<(String, String)>>,
+}
+impl<H, D, E> SharedHandler<H, D, E>
+where
+    H: HandleRequest,
+    E: Factory<Item = H::Encoder>,
+{
+    fn create_encoder(&self) -> PooledEncoder<H::Encoder> {
+        PooledEncoder::new(self.encoder_pool.clone(), || {
+            self.options.encoder_factory.create()
+        })
+    }
+}
+
+struct InputHandler<H: HandleRequest, D, E> {
+    shared: Arc<SharedHandler<H, D, E>>,
     req_head: Option<Req<()>>,
     res: Option<Res<H::ResBody>>,
     decoder: H::Decoder,
-    encoder: Option<H::Encoder>,
+    decompressor: Option<BodyDecompressor>,
+    encoder: Option<PooledEncoder<H::Encoder>>,
     is_closed: bool,
+    keep_alive: bool,
+    unread_body_len: u64,
+    res_fields: Vec<(String, String)>,
+    default_res_fields: Arc<Vec<(String, String)>>,
+    response_hook: Option<(Route, ResponseHook)>,
+    is_head: bool,
+    streaming_reply: Option<H::Reply>,
+    is_blocked: bool,
+    upload_progress: Option<UploadProgress>,
 }
-impl<H: HandleRequest> HandleInput for InputHandler<H> {
-    fn init(&mut self, req: Req<()>) -> Result<()> {
-        if let Some(res) = self.req_handler.handle_request_head(&req) {
+impl<H, D, E> HandleInput for InputHandler<H, D, E>
+where
+    H: HandleRequest,
+    D: Factory<Item = H::Decoder>,
+    E: Factory<Item = H::Encoder>,
+{
+    fn priority(&self) -> Priority {
+        H::PRIORITY
+    }
+
+    fn apply_middleware(&self, req: &mut Req<()>) -> Option<Res<Vec<u8>>> {
+        self.shared
+            .options
+            .middleware
+            .iter()
+            .find_map(|middleware| (middleware.0)(req))
+    }
+
+    fn bandwidth_limits(&self) -> (Option<BandwidthLimit>, Option<BandwidthLimit>) {
+        let options = &self.shared.options;
+        (options.read_bandwidth_limit, options.write_bandwidth_limit)
+    }
+
+    fn flush_mode(&self) -> FlushMode {
+        self.shared.options.flush_mode
+    }
+
+    fn init(
+        &mut self,
+        mut req: Req<()>,
+        max_decompressed_size: Option<usize>,
+        max_drained_size: usize,
+    ) -> Result<()> {
+        self.keep_alive = header::is_keep_alive(req.version(), &req.header());
+        self.res_fields = req.take_res_fields();
+        self.default_res_fields = Arc::clone(req.default_res_fields());
+        self.response_hook = match (req.extensions().get::<Route>(), req.response_hook()) {
+            (Some(route), Some(hook)) => Some((route.clone(), hook.clone())),
+            _ => None,
+        };
+        self.is_head = req.original_method() == "HEAD";
+        self.upload_progress = self
+            .shared
+            .options
+            .upload_progress
+            .as_ref()
+            .map(|f| UploadProgress::new(f, &req));
+        let drainable_body_len = drainable_body_len(&req, max_drained_size);
+        if let Some(res) = self.shared.req_handler.handle_request_head(&req) {
             self.res = Some(res);
-            self.is_closed = true;
-        } else if let Err(e) = self.decoder.initialize(&req.header()) {
+            self.skip_body(drainable_body_len);
+        } else if let Err(e) = self.initialize_decoder(&req, max_decompressed_size) {
             let e = track!(Error::from(e));
-            if let Some(res) = self.req_handler.handle_decoding_error(req, &e) {
+            if let Some(res) = self.shared.req_handler.handle_decoding_error(req, &e) {
                 self.res = Some(res);
-                self.is_closed = true;
+                self.skip_body(drainable_body_len);
             } else {
                 return Err(e);
             }
+        } else if let Some(stream) = self.take_body_stream() {
+            let req = req.map_body(|()| into_req_body::<H>(stream));
+            self.streaming_reply = Some(self.shared.req_handler.handle_request(req));
         } else {
             self.req_head = Some(req);
         }
@@ -170,21 +577,27 @@ impl<H: HandleRequest> HandleInput for InputHandler<H> {
     fn handle_input(&mut self, buf: &mut ReadBuf<Vec<u8>>) -> Result<Option<BoxReply>> {
         if let Some(res) = self.res.take() {
             let encoder = self.encoder.take().expect("Never fails");
-            return Ok(Some(BoxReply::new::<_, H>(futures::finished(res), encoder)));
+            let close = self.is_closed();
+            let reply = futures::finished(res);
+            let fields = std::mem::take(&mut self.res_fields);
+            let post_process = self.post_process();
+            return Ok(Some(BoxReply::new::<_, H>(
+                reply,
+                encoder,
+                close,
+                fields,
+                post_process,
+            )));
+        }
+        if let Some(reply) = self.streaming_reply.take() {
+            return track!(self.handle_streaming_input(buf, reply));
         }
 
-        let result = self.decoder.decode_from_read_buf(buf).and_then(|()| {
-            if self.decoder.is_idle() {
-                self.decoder.finish_decoding().map(Some)
-            } else {
-                Ok(None)
-            }
-        });
-        match result {
+        match self.decode_body(buf) {
             Err(e) => {
                 let e = track!(Error::from(e));
                 let req = self.req_head.take().expect("Never fails");
-                if let Some(res) = self.req_handler.handle_decoding_error(req, &e) {
+                if let Some(res) = self.shared.req_handler.handle_decoding_error(req, &e) {
                     self.is_closed = true;
                     self.res = Some(res);
                     self.handle_input(buf)
@@ -199,30 +612,253 @@ impl<H: HandleRequest> HandleInput for InputHandler<H> {
                     .take()
                     .expect("Never fails")
                     .map_body(|()| body);
-                let reply = self.req_handler.handle_request(req);
+                let reply = self.shared.req_handler.handle_request(req);
                 let encoder = self.encoder.take().expect("Never fails");
-                Ok(Some(BoxReply::new::<_, H>(reply, encoder)))
+                let fields = std::mem::take(&mut self.res_fields);
+                Ok(Some(BoxReply::new::<_, H>(
+                    reply,
+                    encoder,
+                    self.is_closed(),
+                    fields,
+                    self.post_process(),
+                )))
             }
         }
     }
 
     fn is_closed(&self) -> bool {
-        self.is_closed
+        self.is_closed || !self.keep_alive
+    }
+
+    fn unread_body_len(&self) -> u64 {
+        self.unread_body_len
+    }
+
+    fn is_blocked(&self) -> bool {
+        self.is_blocked
+    }
+
+    fn reset(&mut self) {
+        self.req_head = None;
+        self.res = None;
+        self.decoder = self.shared.options.decoder_factory.create();
+        self.decompressor = None;
+        if self.encoder.is_none() {
+            self.encoder = Some(self.shared.create_encoder());
+        }
+        self.is_closed = false;
+        self.keep_alive = true;
+        self.unread_body_len = 0;
+        self.res_fields.clear();
+        self.streaming_reply = None;
+        self.is_blocked = false;
+        self.upload_progress = None;
     }
 }
 
-pub struct RequestHandlerInstance(Box<dyn HandleInput + Send + 'static>);
+impl<H, D, E> InputHandler<H, D, E>
+where
+    H: HandleRequest,
+    D: Factory<Item = H::Decoder>,
+    E: Factory<Item = H::Encoder>,
+{
+    fn new(shared: Arc<SharedHandler<H, D, E>>) -> Self {
+        InputHandler {
+            req_head: None,
+            res: None,
+            decoder: shared.options.decoder_factory.create(),
+            decompressor: None,
+            encoder: Some(shared.create_encoder()),
+            is_closed: false,
+            keep_alive: true,
+            unread_body_len: 0,
+            res_fields: Vec::new(),
+            default_res_fields: Arc::default(),
+            response_hook: None,
+            is_head: false,
+            streaming_reply: None,
+            is_blocked: false,
+            upload_progress: None,
+            shared,
+        }
+    }
+
+    fn post_process(&self) -> PostProcess {
+        PostProcess {
+            handler_fields: Arc::clone(&self.shared.headers),
+            server_fields: Arc::clone(&self.default_res_fields),
+            hook: self.response_hook.clone(),
+            is_head: self.is_head,
+        }
+    }
+
+    /// Arranges for the request body to be skipped, because the response is made without reading it.
+    ///
+    /// If the body cannot be drained (i.e., `len` is `None`), the connection will be closed.
+    fn skip_body(&mut self, len: Option<u64>) {
+        if let Some(len) = len {
+            self.unread_body_len = len;
+        } else {
+            self.is_closed = true;
+        }
+    }
+
+    /// Initializes the body decoder.
+    ///
+    /// If the body of the request is compressed and the decompression is enabled,
+    /// the decoder will be fed the decompressed body (see `ServerBuilder::request_decompression`).
+    fn initialize_decoder(
+        &mut self,
+        req: &Req<()>,
+        max_decompressed_size: Option<usize>,
+    ) -> bytecodec::Result<()> {
+        let header = req.header();
+        let coding = max_decompressed_size.and_then(|_| ContentCoding::from_header(&header));
+        if let (Some(coding), Some(max_size)) = (coding, max_decompressed_size) {
+            let mut decompressor = BodyDecompressor::new(coding, max_size);
+            track!(decompressor.initialize(&header))?;
+            let decompressed = decompression::decompressed_header(&header);
+            track!(self.decoder.initialize(&decompressed.header()))?;
+            self.decompressor = Some(decompressor);
+            Ok(())
+        } else {
+            track!(self.decoder.initialize(&header))
+        }
+    }
+
+    /// Returns the stream of the request body if the handler uses `BodyStreamDecoder`.
+    fn take_body_stream(&mut self) -> Option<BodyStream> {
+        let any: &mut dyn Any = &mut self.decoder;
+        any.downcast_mut::<BodyStreamDecoder>()
+            .and_then(|d| d.take_stream())
+    }
+
+    /// Feeds the request body to the handler that has already been invoked with a `BodyStream`.
+    ///
+    /// The reply is polled while the body is being read, so that the handler can consume the stream.
+    /// If the reply completes before the whole body is received, the connection will be closed.
+    fn handle_streaming_input(
+        &mut self,
+        buf: &mut ReadBuf<Vec<u8>>,
+        mut reply: H::Reply,
+    ) -> Result<Option<BoxReply>> {
+        self.is_blocked = false;
+        let reply = loop {
+            let is_body_received = track!(self.decode_body(buf).map_err(Error::from))?.is_some();
+            match reply.poll().expect("Never fails") {
+                Async::Ready(res) => {
+                    self.is_closed |= !is_body_received;
+                    break Either::A(futures::finished(res));
+                }
+                Async::NotReady if is_body_received => break Either::B(reply),
+                Async::NotReady => {}
+            }
+            if self.is_body_stream_full() {
+                self.is_blocked = true;
+            } else if !buf.is_empty() {
+                // The handler has made room for the remaining bytes
+                continue;
+            }
+            self.streaming_reply = Some(reply);
+            return Ok(None);
+        };
+        let encoder = self.encoder.take().expect("Never fails");
+        let fields = std::mem::take(&mut self.res_fields);
+        Ok(Some(BoxReply::new::<_, H>(
+            reply,
+            encoder,
+            self.is_closed(),
+            fields,
+            self.post_process(),
+        )))
+    }
+
+    fn is_body_stream_full(&self) -> bool {
+        let any: &dyn Any = &self.decoder;
+        any.downcast_ref::<BodyStreamDecoder>()
+            .map_or(false, |d| d.is_full())
+    }
+
+    fn decode_body(&mut self, buf: &mut ReadBuf<Vec<u8>>) -> bytecodec::Result<Option<H::ReqBody>> {
+        let before = buf.len();
+        let result = self.decode_body_from_read_buf(buf);
+        if let Some(ref mut progress) = self.upload_progress {
+            progress.update(before - buf.len());
+        }
+        result
+    }
+
+    fn decode_body_from_read_buf(
+        &mut self,
+        buf: &mut ReadBuf<Vec<u8>>,
+    ) -> bytecodec::Result<Option<H::ReqBody>> {
+        if let Some(ref mut decompressor) = self.decompressor {
+            track!(decompressor.decode_from_read_buf(buf))?;
+            if !decompressor.is_idle() {
+                return Ok(None);
+            }
+            let body = track!(decompressor.finish_decoding())?;
+            track!(self.decoder.decode_from_bytes(&body)).map(Some)
+        } else {
+            track!(self.decoder.decode_from_read_buf(buf))?;
+            if self.decoder.is_idle() {
+                track!(self.decoder.finish_decoding()).map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+pub struct RequestHandlerInstance {
+    handler: Box<dyn HandleInput + Send + 'static>,
+    factory: RequestHandlerFactory,
+}
 impl HandleInput for RequestHandlerInstance {
-    fn init(&mut self, req: Req<()>) -> Result<()> {
-        self.0.init(req)
+    fn priority(&self) -> Priority {
+        self.handler.priority()
+    }
+
+    fn apply_middleware(&self, req: &mut Req<()>) -> Option<Res<Vec<u8>>> {
+        self.handler.apply_middleware(req)
+    }
+
+    fn bandwidth_limits(&self) -> (Option<BandwidthLimit>, Option<BandwidthLimit>) {
+        self.handler.bandwidth_limits()
+    }
+
+    fn flush_mode(&self) -> FlushMode {
+        self.handler.flush_mode()
+    }
+
+    fn init(
+        &mut self,
+        req: Req<()>,
+        max_decompressed_size: Option<usize>,
+        max_drained_size: usize,
+    ) -> Result<()> {
+        self.handler
+            .init(req, max_decompressed_size, max_drained_size)
     }
 
     fn handle_input(&mut self, buf: &mut ReadBuf<Vec<u8>>) -> Result<Option<BoxReply>> {
-        self.0.handle_input(buf)
+        self.handler.handle_input(buf)
     }
 
     fn is_closed(&self) -> bool {
-        self.0.is_closed()
+        self.handler.is_closed()
+    }
+
+    fn unread_body_len(&self) -> u64 {
+        self.handler.unread_body_len()
+    }
+
+    fn is_blocked(&self) -> bool {
+        self.handler.is_blocked()
+    }
+
+    fn reset(&mut self) {
+        self.handler.reset()
     }
 }
 impl fmt::Debug for RequestHandlerInstance {
@@ -231,8 +867,11 @@ impl fmt::Debug for RequestHandlerInstance {
     }
 }
 
+type BoxHandleInput = Box<dyn HandleInput + Send + 'static>;
+
+#[derive(Clone)]
 pub struct RequestHandlerFactory {
-    inner: Box<dyn Fn() -> RequestHandlerInstance + Send + Sync + 'static>,
+    inner: Arc<dyn Fn() -> BoxHandleInput + Send + Sync + 'static>,
 }
 impl RequestHandlerFactory {
     pub fn new<H, D, E>(req_handler: H, options: HandlerOptions<H, D, E>) -> Self
@@ -241,23 +880,50 @@ impl RequestHandlerFactory {
         D: Factory<Item = H::Decoder> + Send + Sync + 'static,
         E: Factory<Item = H::Encoder> + Send + Sync + 'static,
     {
-        let req_handler = Arc::new(req_handler);
-        let f = move || {
-            let handler = InputHandler {
-                req_handler: Arc::clone(&req_handler),
-                req_head: None,
-                res: None,
-                decoder: options.decoder_factory.create(),
-                encoder: Some(options.encoder_factory.create()),
-                is_closed: false,
-            };
-            RequestHandlerInstance(Box::new(handler))
+        let encoder_pool = if options.encoder_pool_capacity > 0 {
+            Some(Arc::new(EncoderPool::new(options.encoder_pool_capacity)))
+        } else {
+            None
         };
-        RequestHandlerFactory { inner: Box::new(f) }
+        let headers = Arc::new(options.headers.clone());
+        let shared = Arc::new(SharedHandler {
+            req_handler,
+            options,
+            encoder_pool,
+            headers,
+        });
+        let f = move || -> BoxHandleInput { Box::new(InputHandler::new(Arc::clone(&shared))) };
+        RequestHandlerFactory { inner: Arc::new(f) }
     }
 
     pub fn create(&self) -> RequestHandlerInstance {
-        (self.inner)()
+        RequestHandlerInstance {
+            handler: (self.inner)(),
+            factory: self.clone(),
+        }
+    }
+
+    /// Returns `cached` after resetting it if it has been created by this factory,
+    /// otherwise creates a new instance.
+    ///
+    /// This saves the allocation of a handler instance for each request on keep-alive connections.
+    pub fn create_or_reuse(
+        &self,
+        cached: Option<RequestHandlerInstance>,
+    ) -> RequestHandlerInstance {
+        match cached {
+            Some(mut instance) if instance.factory.is_same(self) => {
+                instance.reset();
+                instance
+            }
+            _ => self.create(),
+        }
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        let this = Arc::as_ptr(&self.inner) as *const ();
+        let other = Arc::as_ptr(&other.inner) as *const ();
+        std::ptr::eq(this, other)
     }
 }
 impl fmt::Debug for RequestHandlerFactory {
@@ -269,19 +935,41 @@ impl fmt::Debug for RequestHandlerFactory {
 /// An alias of the typical `Future` that can be used as the result of `HandleRequest::handle_request` method.
 pub type Reply<T> = Box<dyn Future<Item = Res<T>, Error = Never> + Send + 'static>;
 
-pub struct BoxReply(Box<dyn Future<Item = ResEncoder, Error = Never> + Send + 'static>);
+/// An alias of the encoder for `String` response bodies (e.g., the ones made by `Res::html` method).
+///
+/// The responses encoded by this are written to sockets directly.
+pub type TextEncoder = BodyEncoder<Utf8Encoder<String>>;
+
+pub struct BoxReply(BoxReplyInner);
 impl BoxReply {
-    fn new<F, H>(reply: F, encoder: H::Encoder) -> Self
+    fn new<F, H>(
+        mut reply: F,
+        encoder: PooledEncoder<H::Encoder>,
+        close: bool,
+        res_fields: Vec<(String, String)>,
+        post_process: PostProcess,
+    ) -> Self
     where
         F: Future<Item = Res<H::ResBody>, Error = Never> + Send + 'static,
         H: HandleRequest,
     {
-        let future = reply.and_then(move |res| {
-            let body_encoder = Box::new(encoder);
-            let encoder = ResponseEncoder::new(body_encoder).last(res.0);
-            futures::finished(ResEncoder::new(encoder))
-        });
-        BoxReply(Box::new(future))
+        // The replies that have already completed need not be boxed
+        if let Ok(Async::Ready(res)) = reply.poll() {
+            let encoder = into_res_encoder::<H>(res, encoder, close, &res_fields, &post_process);
+            return BoxReply(BoxReplyInner::Ready(Some(encoder)));
+        }
+        let future = reply
+            .map(move |res| into_res_encoder::<H>(res, encoder, close, &res_fields, &post_process));
+        BoxReply(BoxReplyInner::Pending(Box::new(future)))
+    }
+
+    /// Takes the response encoder if the reply has already completed.
+    pub fn take_ready(&mut self) -> Option<ResEncoder> {
+        if let BoxReplyInner::Ready(ref mut encoder) = self.0 {
+            encoder.take()
+        } else {
+            None
+        }
     }
 }
 impl Future for BoxReply {
@@ -289,7 +977,12 @@ impl Future for BoxReply {
     type Error = Never;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.0.poll()
+        match self.0 {
+            BoxReplyInner::Pending(ref mut f) => f.poll(),
+            BoxReplyInner::Ready(ref mut encoder) => Ok(Async::Ready(
+                encoder.take().expect("Cannot poll BoxReply twice"),
+            )),
+        }
     }
 }
 impl fmt::Debug for BoxReply {
@@ -297,3 +990,337 @@ impl fmt::Debug for BoxReply {
         write!(f, "BoxReply(_)")
     }
 }
+
+enum BoxReplyInner {
+    Pending(Box<dyn Future<Item = ResEncoder, Error = Never> + Send + 'static>),
+    Ready(Option<ResEncoder>),
+}
+
+/// The processing applied to a response before it is encoded.
+struct PostProcess {
+    // The header fields added to the response unless it already has the fields of the same names
+    // (see `HandlerOptions::header` and `ServerBuilder::default_header`).
+    handler_fields: Arc<Vec<(String, String)>>,
+    server_fields: Arc<Vec<(String, String)>>,
+
+    // See `ServerBuilder::response_hook`.
+    hook: Option<(Route, ResponseHook)>,
+
+    // If `true`, the body of the response is not sent.
+    is_head: bool,
+}
+
+/// Converts `res` into a `ResEncoder` after adding the header fields that the server is responsible for.
+fn into_res_encoder<H: HandleRequest>(
+    mut res: Res<H::ResBody>,
+    encoder: PooledEncoder<H::Encoder>,
+    close: bool,
+    res_fields: &[(String, String)],
+    post_process: &PostProcess,
+) -> ResEncoder {
+    for (name, value) in res_fields {
+        // The fields are discarded if they are invalid (see `Req::add_res_field`).
+        let _ = res.add_header(name, value);
+    }
+    // The fields of the handler take precedence over the ones of the server.
+    let defaults = post_process
+        .handler_fields
+        .iter()
+        .chain(post_process.server_fields.iter());
+    for (name, value) in defaults {
+        if res.header().get_field(name).is_none() {
+            let _ = res.add_header(name, value);
+        }
+    }
+    if let Some((ref route, ref hook)) = post_process.hook {
+        let (head, body) = res.0.take_body();
+        let mut head = Res(head);
+        hook.call(route.clone(), &mut head);
+        res = Res(head.0.map_body(|()| body));
+    }
+    let close = set_connection_header(&mut res, close);
+    let status_code = res.status_code();
+    let mut res_encoder = if response::is_bodiless_status(status_code) {
+        // Neither the body nor the framing headers are sent, even if the handler set them.
+        let (head, body) = res.0.take_body();
+        let mut head = Res(head);
+        head.remove_header("Content-Length");
+        head.remove_header("Transfer-Encoding");
+        let encoder = ResponseEncoder::new(BodylessEncoder::new(encoder, false));
+        start_res_encoder(encoder, Res(head.0.map_body(|()| body)))
+    } else if post_process.is_head {
+        let encoder = ResponseEncoder::new(BodylessEncoder::new(encoder, true));
+        start_res_encoder(encoder, res)
+    } else {
+        match into_direct_res_encoder::<H>(res) {
+            Ok(encoder) => encoder,
+            Err(res) => start_res_encoder(ResponseEncoder::new(encoder), res),
+        }
+    };
+    if close {
+        res_encoder.close_connection();
+    }
+    res_encoder
+}
+
+fn start_res_encoder<E>(mut encoder: ResponseEncoder<E>, res: Res<E::Item>) -> ResEncoder
+where
+    E: BodyEncode + Send + 'static,
+{
+    let status_code = res.status_code();
+    match track!(encoder.start_encoding(res.0)) {
+        Ok(()) => ResEncoder::from_started(status_code, encoder),
+        Err(_) => ResEncoder::error(Status::InternalServerError),
+    }
+}
+
+/// Returns the length of the body of `req` if the body can be skipped without closing the connection.
+///
+/// Chunked bodies and the bodies larger than `max_drained_size` bytes cannot be skipped.
+fn drainable_body_len(req: &Req<()>, max_drained_size: usize) -> Option<u64> {
+    if req.header().get_field("Transfer-Encoding").is_some() {
+        return None;
+    }
+    let len = match req.typed_header::<ContentLength>() {
+        Ok(Some(ContentLength(len))) => len,
+        Ok(None) => 0,
+        Err(_) => return None,
+    };
+    if len <= max_drained_size as u64 {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Converts `stream` into the request body type of `H`.
+///
+/// This must be called only if the decoder of `H` is `BodyStreamDecoder`.
+fn into_req_body<H: HandleRequest>(stream: BodyStream) -> H::ReqBody {
+    let mut body = Some(stream);
+    let any: &mut dyn Any = &mut body;
+    any.downcast_mut::<Option<H::ReqBody>>()
+        .and_then(|x| x.take())
+        .expect("Never fails")
+}
+
+/// Adds the `Connection` header to the response unless the handler has already added it,
+/// and returns whether the connection should be closed after the response is written.
+///
+/// If the server closes the connection anyway (i.e., `close` is `true`),
+/// the field added by the handler is replaced with `Connection: close`.
+fn set_connection_header<T>(res: &mut Res<T>, close: bool) -> bool {
+    if close {
+        res.remove_header("Connection");
+        res.header_mut().add_field(Connection::Close);
+        true
+    } else if res.header().get_field("Connection").is_some() {
+        header::has_connection_option(&res.header(), "close")
+    } else {
+        res.header_mut().add_field(Connection::KeepAlive);
+        false
+    }
+}
+
+/// Makes a `ResEncoder` that can write the given response to sockets directly
+/// if the response body is encoded by a plain bytes (or UTF-8 string) encoder, `FileBodyEncoder` or `StaticBodyEncoder`.
+fn into_direct_res_encoder<H: HandleRequest>(
+    res: Res<H::ResBody>,
+) -> std::result::Result<ResEncoder, Res<H::ResBody>> {
+    let encoder = TypeId::of::<H::Encoder>();
+    if encoder != TypeId::of::<BodyEncoder<BytesEncoder<Vec<u8>>>>()
+        && encoder != TypeId::of::<BodyEncoder<Utf8Encoder<String>>>()
+        && encoder != TypeId::of::<FileBodyEncoder>()
+        && encoder != TypeId::of::<StaticBodyEncoder>()
+    {
+        return Err(res);
+    }
+
+    let (head, body) = res.0.take_body();
+    let mut body = Some(body);
+    let encoder = {
+        let any: &mut dyn Any = &mut body;
+        if let Some(x) = any.downcast_mut::<Option<Vec<u8>>>() {
+            x.take()
+                .map(|x| ResEncoder::with_bytes_body(head.map_body(|()| x)))
+        } else if let Some(x) = any.downcast_mut::<Option<String>>() {
+            x.take()
+                .map(|x| ResEncoder::with_bytes_body(head.map_body(|()| x.into_bytes())))
+        } else if let Some(x) = any.downcast_mut::<Option<FileBody>>() {
+            x.take()
+                .map(|x| ResEncoder::with_file_body(head.map_body(|()| x)))
+        } else if let Some(x) = any.downcast_mut::<Option<StaticBody>>() {
+            x.take().map(|x| match x {
+                StaticBody::File(x) => ResEncoder::with_file_body(head.map_body(|()| x)),
+                StaticBody::Bytes(x) => ResEncoder::with_bytes_body(head.map_body(|()| x)),
+            })
+        } else {
+            unreachable!()
+        }
+    };
+    Ok(encoder.expect("Never fails"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::{ErrorKind, ServerBuilder};
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::BodyDecoder;
+    use std::sync::Mutex;
+
+    #[test]
+    fn set_connection_header_works() {
+        let mut res = Res::new(Status::Ok, ());
+        assert!(!set_connection_header(&mut res, false));
+        assert_eq!(res.header().get_field("Connection"), Some("keep-alive"));
+
+        let mut res = Res::new(Status::Ok, ());
+        res.add_header("Connection", "close").unwrap();
+        assert!(set_connection_header(&mut res, false));
+        assert_eq!(res.header().get_field("Connection"), Some("close"));
+
+        // The server-side close overrides the field added by the handler
+        let mut res = Res::new(Status::Ok, ());
+        res.add_header("Connection", "keep-alive").unwrap();
+        assert!(set_connection_header(&mut res, true));
+        assert_eq!(res.header().get_field("Connection"), Some("close"));
+        assert_eq!(res.header().fields().count(), 1);
+    }
+
+    #[test]
+    fn upload_progress_works() {
+        use bytecodec::bytes::RemainingBytesDecoder;
+        use httpcodec::{HttpVersion, Method, Request, RequestTarget};
+
+        struct Upload;
+        impl HandleRequest for Upload {
+            const METHOD: &'static str = "PUT";
+            const PATH: &'static str = "/upload";
+
+            type ReqBody = Vec<u8>;
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<RemainingBytesDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, Vec::new())))
+            }
+        }
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let options = HandlerOptions::default().upload_progress({
+            let progress = Arc::clone(&progress);
+            move |req: &Req<()>| {
+                assert_eq!(req.url().path(), "/upload");
+                let progress = Arc::clone(&progress);
+                move |received, expected| progress.lock().unwrap().push((received, expected))
+            }
+        });
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler_with_options(Upload, options).unwrap();
+        let client = builder.finish_test_client();
+
+        let req = Request::new(
+            Method::new("PUT").unwrap(),
+            RequestTarget::new("/upload").unwrap(),
+            HttpVersion::V1_1,
+            vec![0; 200 * 1024],
+        );
+        let res = fibers_global::execute(client.send(req)).unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        let progress = progress.lock().unwrap();
+        assert!(!progress.is_empty());
+        assert!(progress.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(progress.last(), Some(&(200 * 1024, Some(200 * 1024))));
+    }
+
+    #[test]
+    fn flush_mode_works() {
+        for &mode in &[FlushMode::Auto, FlushMode::Immediate, FlushMode::Cork] {
+            let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+            let options = HandlerOptions::default().flush_mode(mode);
+            builder.add_handler_with_options(Hello, options).unwrap();
+            let client = builder.finish_test_client();
+
+            for _ in 0..3 {
+                let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+                assert_eq!(res.status_code(), 200);
+                assert_eq!(res.body(), b"hello");
+            }
+        }
+    }
+
+    #[test]
+    fn handler_headers_work() {
+        struct Login;
+        impl HandleRequest for Login {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/login/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let mut res = Res::new(Status::Ok, "hello".to_owned());
+                if req.wildcards()[0] == "cached" {
+                    res.add_header("Cache-Control", "max-age=60").unwrap();
+                }
+                Box::new(ok(res))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        let options = HandlerOptions::default()
+            .header("Cache-Control", "no-store")
+            .header("X-Frame-Options", "DENY");
+        builder.add_handler_with_options(Login, options).unwrap();
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /login/foo HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.contains("Cache-Control: no-store\r\n"),
+            "{}",
+            received
+        );
+        assert!(
+            received.contains("X-Frame-Options: DENY\r\n"),
+            "{}",
+            received
+        );
+
+        conn.write(b"GET /login/cached HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.contains("Cache-Control: max-age=60\r\n"),
+            "{}",
+            received
+        );
+        assert!(!received.contains("no-store"), "{}", received);
+        assert!(
+            received.contains("X-Frame-Options: DENY\r\n"),
+            "{}",
+            received
+        );
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        let options = HandlerOptions::default().header("X-Frame-Options", "DENY\r\n");
+        let e = builder
+            .add_handler_with_options(Login, options)
+            .err()
+            .unwrap();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(builder.routes().is_empty());
+    }
+}
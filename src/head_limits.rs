@@ -0,0 +1,353 @@
+use crate::Status;
+use bytecodec::{self, ByteCount, Decode, Eos};
+use httpcodec::DecodeOptions;
+use std::fmt;
+
+/// Limits applied to the head part of each request.
+#[derive(Debug, Clone)]
+pub struct HeadLimits {
+    pub max_request_line_size: usize,
+    pub max_header_count: usize,
+    pub max_header_field_size: usize,
+    pub strict: bool,
+}
+impl Default for HeadLimits {
+    fn default() -> Self {
+        HeadLimits {
+            max_request_line_size: DecodeOptions::DEFAULT_MAX_START_LINE_SIZE,
+            max_header_count: usize::MAX,
+            max_header_field_size: DecodeOptions::DEFAULT_MAX_HEADER_SIZE,
+            strict: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadLimitViolation {
+    RequestLineTooLong,
+    TooManyHeaders,
+    HeaderFieldTooLarge,
+    HeaderSectionTooLarge,
+    ObsFold,
+    BareCr,
+}
+impl HeadLimitViolation {
+    pub fn status(self) -> Status {
+        match self {
+            HeadLimitViolation::RequestLineTooLong => Status::UriTooLong,
+            HeadLimitViolation::TooManyHeaders
+            | HeadLimitViolation::HeaderFieldTooLarge
+            | HeadLimitViolation::HeaderSectionTooLarge => Status::RequestHeaderFieldsTooLarge,
+            HeadLimitViolation::ObsFold | HeadLimitViolation::BareCr => Status::BadRequest,
+        }
+    }
+}
+impl fmt::Display for HeadLimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeadLimitViolation::RequestLineTooLong => write!(f, "Too long request line"),
+            HeadLimitViolation::TooManyHeaders => write!(f, "Too many header fields"),
+            HeadLimitViolation::HeaderFieldTooLarge => write!(f, "Too large header field"),
+            HeadLimitViolation::HeaderSectionTooLarge => write!(f, "Too large header section"),
+            HeadLimitViolation::ObsFold => write!(f, "Obsolete line folding"),
+            HeadLimitViolation::BareCr => write!(f, "Bare CR"),
+        }
+    }
+}
+
+/// A decoder that checks the head part of a request against `HeadLimits`
+/// before the inner decoder consumes the bytes.
+#[derive(Debug)]
+pub struct HeadLimitsDecoder<D> {
+    inner: D,
+    limits: HeadLimits,
+    decode_options: DecodeOptions,
+    scanner: Scanner,
+    violation: Option<HeadLimitViolation>,
+}
+impl<D: Decode> HeadLimitsDecoder<D> {
+    /// Makes a new `HeadLimitsDecoder` instance.
+    ///
+    /// The limits of `decode_options` (i.e., the ones used by the inner decoder) are also checked,
+    /// so that the requests exceeding them are answered with the appropriate statuses.
+    pub fn new(inner: D, limits: HeadLimits, decode_options: DecodeOptions) -> Self {
+        HeadLimitsDecoder {
+            inner,
+            limits,
+            decode_options,
+            scanner: Scanner::default(),
+            violation: None,
+        }
+    }
+
+    /// Replaces the limits with the given ones.
+    ///
+    /// This must be called only between requests.
+    pub fn set_limits(&mut self, limits: HeadLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the violation that caused the last decoding error.
+    pub fn violation(&self) -> Option<HeadLimitViolation> {
+        self.violation
+    }
+}
+impl<D: Decode> Decode for HeadLimitsDecoder<D> {
+    type Item = D::Item;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        if let Err(violation) = self.scanner.scan(buf, &self.limits, &self.decode_options) {
+            self.violation = Some(violation);
+            track_panic!(bytecodec::ErrorKind::InvalidInput, "{}", violation);
+        }
+        let size = track!(self.inner.decode(buf, eos))?;
+        self.scanner.consume(size);
+        Ok(size)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        let item = track!(self.inner.finish_decoding())?;
+        self.scanner = Scanner::default();
+        self.violation = None;
+        Ok(item)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Scanner {
+    // The number of bytes that have been scanned but not consumed by the inner decoder yet.
+    scanned_ahead: usize,
+    lines: usize,
+    line_len: usize,
+    start_line_bytes: usize,
+    header_bytes: usize,
+    prev_cr: bool,
+    is_completed: bool,
+}
+impl Scanner {
+    fn scan(
+        &mut self,
+        buf: &[u8],
+        limits: &HeadLimits,
+        decode_options: &DecodeOptions,
+    ) -> Result<(), HeadLimitViolation> {
+        for &b in buf.iter().skip(self.scanned_ahead) {
+            if self.is_completed {
+                break;
+            }
+            self.scanned_ahead += 1;
+
+            // Raw sizes including CRLFs (i.e., the ones limited by `DecodeOptions`)
+            if self.lines == 0 {
+                self.start_line_bytes += 1;
+                if self.start_line_bytes > decode_options.max_start_line_size {
+                    return Err(HeadLimitViolation::RequestLineTooLong);
+                }
+            } else {
+                self.header_bytes += 1;
+                if self.header_bytes > decode_options.max_header_size {
+                    return Err(HeadLimitViolation::HeaderSectionTooLarge);
+                }
+            }
+            if self.prev_cr {
+                self.prev_cr = false;
+                if b != b'\n' {
+                    if limits.strict {
+                        return Err(HeadLimitViolation::BareCr);
+                    }
+                    self.line_len += 1;
+                }
+            }
+            match b {
+                b'\r' => {
+                    self.prev_cr = true;
+                }
+                b'\n' => {
+                    if self.line_len == 0 && self.lines > 0 {
+                        self.is_completed = true;
+                    } else if self.line_len > 0 {
+                        self.lines += 1;
+                        if self.lines - 1 > limits.max_header_count {
+                            return Err(HeadLimitViolation::TooManyHeaders);
+                        }
+                    }
+                    self.line_len = 0;
+                }
+                _ => {
+                    let is_header = self.lines > 0;
+                    if is_header && self.line_len == 0 && (b == b' ' || b == b'\t') && limits.strict
+                    {
+                        return Err(HeadLimitViolation::ObsFold);
+                    }
+                    self.line_len += 1;
+                    if !is_header && self.line_len > limits.max_request_line_size {
+                        return Err(HeadLimitViolation::RequestLineTooLong);
+                    }
+                    if is_header && self.line_len > limits.max_header_field_size {
+                        return Err(HeadLimitViolation::HeaderFieldTooLarge);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, size: usize) {
+        self.scanned_ahead = self.scanned_ahead.saturating_sub(size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+
+    fn scan(head: &str, limits: &HeadLimits) -> Result<(), HeadLimitViolation> {
+        let decode_options = DecodeOptions {
+            max_start_line_size: 64,
+            max_header_size: 64,
+        };
+        let mut scanner = Scanner::default();
+        for chunk in head.as_bytes().chunks(3) {
+            scanner.scan(chunk, limits, &decode_options)?;
+            scanner.consume(chunk.len());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn head_limits_work() {
+        let limits = HeadLimits {
+            max_request_line_size: 16,
+            max_header_count: 2,
+            max_header_field_size: 8,
+            strict: true,
+        };
+        assert_eq!(
+            scan("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n", &limits),
+            Ok(())
+        );
+        assert_eq!(
+            scan("GET /foo/bar HTTP/1.1\r\n\r\n", &limits),
+            Err(HeadLimitViolation::RequestLineTooLong)
+        );
+        assert_eq!(
+            scan("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", &limits),
+            Err(HeadLimitViolation::TooManyHeaders)
+        );
+        assert_eq!(
+            scan("GET / HTTP/1.1\r\nA: 123456\r\n\r\n", &limits),
+            Err(HeadLimitViolation::HeaderFieldTooLarge)
+        );
+        assert_eq!(
+            scan("GET / HTTP/1.1\r\nA: 1\r\n 2\r\n\r\n", &limits),
+            Err(HeadLimitViolation::ObsFold)
+        );
+        assert_eq!(
+            scan("GET / HTTP/1.1\r\nA: 1\r2\r\n\r\n", &limits),
+            Err(HeadLimitViolation::BareCr)
+        );
+
+        let limits = HeadLimits::default();
+        assert_eq!(
+            scan(
+                &format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(50)),
+                &limits
+            ),
+            Err(HeadLimitViolation::RequestLineTooLong)
+        );
+        assert_eq!(
+            scan(
+                &format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(60)),
+                &limits
+            ),
+            Err(HeadLimitViolation::HeaderSectionTooLarge)
+        );
+        assert_eq!(
+            scan("GET / HTTP/1.1\r\nA: 1\r\n 2\r\n\r\n", &limits),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn scanner_stops_at_end_of_head() {
+        let limits = HeadLimits {
+            max_header_count: 0,
+            ..HeadLimits::default()
+        };
+        let mut scanner = Scanner::default();
+        let buf = b"GET / HTTP/1.1\r\n\r\nA: 1\r\n";
+        let decode_options = DecodeOptions::default();
+        assert_eq!(scanner.scan(buf, &limits, &decode_options), Ok(()));
+        assert_eq!(scanner.scanned_ahead, 18);
+    }
+
+    #[test]
+    fn server_head_limits_work() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.max_request_line_size(32).max_header_count(1);
+        let mut sim = builder.finish_simulation(0);
+
+        for (req, status) in [
+            (
+                "GET /hello?foo=barbazqux HTTP/1.1\r\n\r\n",
+                "414 URI Too Long",
+            ),
+            (
+                "GET /hello HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n",
+                "431 Request Header Fields Too Large",
+            ),
+        ] {
+            let conn = sim.connect().unwrap();
+            conn.write(req.as_bytes());
+            sim.run().unwrap();
+
+            let received = String::from_utf8(conn.take_received()).unwrap();
+            assert!(received.starts_with(&format!("HTTP/1.1 {}\r\n", status)));
+            assert!(conn.is_closed());
+        }
+        assert_eq!(sim.metrics().request_line_too_long_errors(), 1);
+        assert_eq!(sim.metrics().too_many_headers_errors(), 1);
+        assert_eq!(sim.metrics().read_request_head_errors(), 0);
+    }
+
+    #[test]
+    fn oversized_head_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.decode_options(httpcodec::DecodeOptions {
+            max_start_line_size: 32,
+            max_header_size: 32,
+        });
+        let mut sim = builder.finish_simulation(0);
+
+        for (req, status) in [
+            (
+                "GET /hello?foo=barbazqux HTTP/1.1\r\n\r\n",
+                "414 URI Too Long",
+            ),
+            (
+                "GET /hello HTTP/1.1\r\nFoo: barbazquxbarbazquxbarbazqux\r\n\r\n",
+                "431 Request Header Fields Too Large",
+            ),
+        ] {
+            let conn = sim.connect().unwrap();
+            conn.write(req.as_bytes());
+            sim.run().unwrap();
+
+            let received = String::from_utf8(conn.take_received()).unwrap();
+            assert!(received.starts_with(&format!("HTTP/1.1 {}\r\n", status)));
+            assert!(conn.is_closed());
+        }
+    }
+}
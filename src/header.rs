@@ -1,11 +1,754 @@
-use httpcodec::HeaderField;
+//! Typed HTTP header fields.
+//!
+//! The types in this module can be retrieved from requests and responses via
+//! `Req::typed_header` and `Res::typed_header`, and can be added to responses via `Res::add_typed_header`.
+use crate::{ErrorKind, Result};
+use httpcodec::{Header, HeaderField, HttpVersion};
+use std::fmt;
+use trackable::error::ErrorKindExt;
+
+/// A header field that can be converted from/to a string value.
+pub trait TypedHeader: Sized + fmt::Display {
+    /// The name of the header field.
+    const NAME: &'static str;
+
+    /// Parses the value of the header field.
+    ///
+    /// # Errors
+    ///
+    /// If the value is malformed, an `ErrorKind::InvalidInput` error will be returned.
+    fn parse(value: &str) -> Result<Self>;
+}
+
+pub(crate) fn get<H: TypedHeader>(header: &Header) -> Result<Option<H>> {
+    match header.get_field(H::NAME) {
+        None => Ok(None),
+        Some(value) => track!(H::parse(value.trim())).map(Some),
+    }
+}
+
+/// The maximum length of the values of the header fields added to responses.
+pub const MAX_FIELD_VALUE_LEN: usize = 8 * 1024;
+
+/// Validates a header field.
+///
+/// Unlike `HeaderField::new`, this allows spaces and horizontal tabs in the value (RFC 7230 section 3.2).
+/// Control characters (including CR and LF) are rejected, and so are values longer than `MAX_FIELD_VALUE_LEN`.
+pub(crate) fn validate_field(name: &str, value: &str) -> Result<()> {
+    track_assert!(is_token(name), ErrorKind::InvalidInput; name);
+    track_assert!(
+        value.len() <= MAX_FIELD_VALUE_LEN,
+        ErrorKind::InvalidInput;
+        name, value.len()
+    );
+    track_assert!(
+        value.bytes().all(|b| is_vchar(b) || b == b' ' || b == b'\t'),
+        ErrorKind::InvalidInput;
+        name, value
+    );
+    Ok(())
+}
+
+/// `Content-Type` header field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    media_type: String,
+    params: Vec<(String, String)>,
+}
+impl ContentType {
+    /// Makes a new `ContentType` instance.
+    ///
+    /// `media_type` is a string like `application/json` (the letters are converted to lowercase).
+    ///
+    /// # Errors
+    ///
+    /// If `media_type` is not a valid media type, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn new(media_type: &str) -> Result<Self> {
+        let media_type = track!(parse_media_type(media_type, false))?;
+        Ok(ContentType {
+            media_type,
+            params: Vec::new(),
+        })
+    }
+
+    /// Adds a parameter (e.g., `charset=utf-8`).
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a token or `value` contains invalid characters,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn param(mut self, name: &str, value: &str) -> Result<Self> {
+        track_assert!(is_token(name), ErrorKind::InvalidInput; name);
+        track_assert!(is_param_value(value), ErrorKind::InvalidInput; value);
+        self.params
+            .push((name.to_ascii_lowercase(), value.to_owned()));
+        Ok(self)
+    }
+
+    /// Returns the media type (e.g., `text/html`) without the parameters.
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// Returns the value of the parameter that has the given name.
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|p| p.0.eq_ignore_ascii_case(name))
+            .map(|p| p.1.as_str())
+    }
+
+    /// Returns the value of the `charset` parameter.
+    pub fn charset(&self) -> Option<&str> {
+        self.get_param("charset")
+    }
+}
+impl TypedHeader for ContentType {
+    const NAME: &'static str = "Content-Type";
+
+    fn parse(value: &str) -> Result<Self> {
+        let mut items = value.split(';');
+        let mut this = track!(ContentType::new(items.next().unwrap_or("")))?;
+        for item in items {
+            let (name, value) = track!(parse_param(item))?;
+            this = track!(this.param(name, value))?;
+        }
+        Ok(this)
+    }
+}
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.media_type)?;
+        for (name, value) in &self.params {
+            write!(f, "; {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// `Content-Length` header field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContentLength(pub u64);
+impl TypedHeader for ContentLength {
+    const NAME: &'static str = "Content-Length";
+
+    fn parse(value: &str) -> Result<Self> {
+        track_assert!(
+            !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()),
+            ErrorKind::InvalidInput;
+            value
+        );
+        let n = track_assert_some!(value.parse().ok(), ErrorKind::InvalidInput; value);
+        Ok(ContentLength(n))
+    }
+}
+impl fmt::Display for ContentLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// `Accept` header field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accept {
+    ranges: Vec<MediaRange>,
+}
+impl Accept {
+    /// Makes a new `Accept` instance.
+    pub fn new(ranges: Vec<MediaRange>) -> Self {
+        Accept { ranges }
+    }
+
+    /// Returns the media ranges in the order of appearance.
+    pub fn media_ranges(&self) -> &[MediaRange] {
+        &self.ranges
+    }
+
+    /// Returns the quality value of the given media type (e.g., `application/json`).
+    ///
+    /// The most specific range matching the media type is used.
+    /// If no range matches, `0.0` is returned.
+    pub fn quality(&self, media_type: &str) -> f32 {
+        self.ranges
+            .iter()
+            .filter(|r| r.matches(media_type))
+            .max_by_key(|r| r.specificity())
+            .map_or(0.0, |r| r.quality())
+    }
+}
+impl TypedHeader for Accept {
+    const NAME: &'static str = "Accept";
+
+    fn parse(value: &str) -> Result<Self> {
+        let ranges = value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| track!(MediaRange::parse(s)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Accept { ranges })
+    }
+}
+impl fmt::Display for Accept {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, r) in self.ranges.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+/// A media range in the `Accept` header field (e.g., `text/*;q=0.5`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    media_type: String,
+    quality: f32,
+}
+impl MediaRange {
+    /// Makes a new `MediaRange` instance.
+    ///
+    /// # Errors
+    ///
+    /// If `media_type` is not a valid media range or `quality` is not in the range of `0.0..=1.0`,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn new(media_type: &str, quality: f32) -> Result<Self> {
+        let media_type = track!(parse_media_type(media_type, true))?;
+        track_assert!(
+            (0.0..=1.0).contains(&quality),
+            ErrorKind::InvalidInput,
+            "quality={}",
+            quality
+        );
+        Ok(MediaRange {
+            media_type,
+            quality,
+        })
+    }
+
+    /// Returns the media type of the range (e.g., `text/*`).
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// Returns the quality value of the range.
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+
+    /// Returns `true` if the range matches the given media type, otherwise `false`.
+    pub fn matches(&self, media_type: &str) -> bool {
+        let media_type = media_type.split(';').next().unwrap_or("").trim();
+        let (ty, subty) = split_media_type(&self.media_type);
+        let (other_ty, other_subty) = split_media_type(media_type);
+        (ty == "*" || ty.eq_ignore_ascii_case(other_ty))
+            && (subty == "*" || subty.eq_ignore_ascii_case(other_subty))
+    }
+
+    fn specificity(&self) -> u8 {
+        match split_media_type(&self.media_type) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ => 2,
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let mut items = s.split(';');
+        let media_type = items.next().unwrap_or("");
+        let mut quality = 1.0;
+        for item in items {
+            let (name, value) = track!(parse_param(item))?;
+            if name.eq_ignore_ascii_case("q") {
+                quality = track_assert_some!(value.parse().ok(), ErrorKind::InvalidInput; s);
+            }
+        }
+        track!(MediaRange::new(media_type, quality))
+    }
+}
+impl fmt::Display for MediaRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.media_type)?;
+        if self.quality < 1.0 {
+            write!(f, ";q={}", self.quality)?;
+        }
+        Ok(())
+    }
+}
+
+/// `Authorization` header field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authorization {
+    scheme: String,
+    credentials: String,
+}
+impl Authorization {
+    /// Makes a new `Authorization` instance.
+    ///
+    /// # Errors
+    ///
+    /// If `scheme` is not a token or `credentials` contains invalid characters,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn new(scheme: &str, credentials: &str) -> Result<Self> {
+        track_assert!(is_token(scheme), ErrorKind::InvalidInput; scheme);
+        track_assert!(
+            credentials.bytes().all(|b| is_vchar(b) || b == b' '),
+            ErrorKind::InvalidInput
+        );
+        Ok(Authorization {
+            scheme: scheme.to_owned(),
+            credentials: credentials.trim().to_owned(),
+        })
+    }
+
+    /// Makes a new `Authorization` instance that has the `Bearer` scheme.
+    ///
+    /// # Errors
+    ///
+    /// If `token` contains invalid characters, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn bearer(token: &str) -> Result<Self> {
+        track!(Authorization::new("Bearer", token))
+    }
+
+    /// Returns the authentication scheme (e.g., `Basic`).
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Returns the credentials following the scheme.
+    pub fn credentials(&self) -> &str {
+        &self.credentials
+    }
+
+    /// Returns the token if the scheme is `Bearer` (case-insensitive).
+    pub fn bearer_token(&self) -> Option<&str> {
+        if self.scheme.eq_ignore_ascii_case("bearer") {
+            Some(&self.credentials)
+        } else {
+            None
+        }
+    }
+}
+impl TypedHeader for Authorization {
+    const NAME: &'static str = "Authorization";
+
+    fn parse(value: &str) -> Result<Self> {
+        let (scheme, credentials) = match value.find(' ') {
+            None => (value, ""),
+            Some(i) => (&value[..i], &value[i + 1..]),
+        };
+        track!(Authorization::new(scheme, credentials))
+    }
+}
+impl fmt::Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.credentials.is_empty() {
+            write!(f, "{}", self.scheme)
+        } else {
+            write!(f, "{} {}", self.scheme, self.credentials)
+        }
+    }
+}
+
+/// `Cache-Control` header field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    directives: Vec<(String, Option<String>)>,
+}
+impl CacheControl {
+    /// Makes a new `CacheControl` instance that has no directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directive (e.g., `no-cache` or `max-age=60`).
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a token or `value` contains invalid characters,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn directive(mut self, name: &str, value: Option<&str>) -> Result<Self> {
+        track_assert!(is_token(name), ErrorKind::InvalidInput; name);
+        if let Some(value) = value {
+            track_assert!(is_param_value(value), ErrorKind::InvalidInput; value);
+        }
+        self.directives
+            .push((name.to_ascii_lowercase(), value.map(ToOwned::to_owned)));
+        Ok(self)
+    }
+
+    /// Returns the directives in the order of appearance.
+    pub fn directives(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.directives
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    /// Returns `true` if the directive that has the given name exists, otherwise `false`.
+    pub fn has(&self, name: &str) -> bool {
+        self.directives
+            .iter()
+            .any(|d| d.0.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the value of the `max-age` directive.
+    pub fn max_age(&self) -> Option<u64> {
+        self.directives
+            .iter()
+            .find(|d| d.0 == "max-age")
+            .and_then(|d| d.1.as_ref())
+            .and_then(|v| v.trim_matches('"').parse().ok())
+    }
+
+    /// Returns `true` if the `no-cache` directive exists, otherwise `false`.
+    pub fn no_cache(&self) -> bool {
+        self.has("no-cache")
+    }
+
+    /// Returns `true` if the `no-store` directive exists, otherwise `false`.
+    pub fn no_store(&self) -> bool {
+        self.has("no-store")
+    }
+}
+impl TypedHeader for CacheControl {
+    const NAME: &'static str = "Cache-Control";
+
+    fn parse(value: &str) -> Result<Self> {
+        let mut this = CacheControl::new();
+        for item in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            this = match item.find('=') {
+                None => track!(this.directive(item, None))?,
+                Some(i) => track!(this.directive(item[..i].trim(), Some(item[i + 1..].trim())))?,
+            };
+        }
+        Ok(this)
+    }
+}
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, value)) in self.directives.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", name)?;
+            if let Some(value) = value {
+                write!(f, "={}", value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `Host` header field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Host {
+    host: String,
+    port: Option<u16>,
+}
+impl Host {
+    /// Makes a new `Host` instance.
+    ///
+    /// # Errors
+    ///
+    /// If `host` is not a valid host name or IP address, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn new(host: &str, port: Option<u16>) -> Result<Self> {
+        let url = format!("http://{}/", host);
+        let parsed = track_assert_some!(url::Url::parse(&url).ok(), ErrorKind::InvalidInput; host);
+        track_assert!(
+            parsed.port().is_none() && parsed.path() == "/",
+            ErrorKind::InvalidInput;
+            host
+        );
+        Ok(Host {
+            host: host.to_owned(),
+            port,
+        })
+    }
+
+    /// Returns the host part (e.g., `example.com` or `[::1]`).
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the port part.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+impl TypedHeader for Host {
+    const NAME: &'static str = "Host";
+
+    fn parse(value: &str) -> Result<Self> {
+        let (host, port) = match value.rfind(':') {
+            Some(i) if !value[i..].contains(']') => {
+                let port =
+                    track_assert_some!(value[i + 1..].parse().ok(), ErrorKind::InvalidInput; value);
+                (&value[..i], Some(port))
+            }
+            _ => (value, None),
+        };
+        track!(Host::new(host, port))
+    }
+}
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// `User-Agent` header field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserAgent(String);
+impl UserAgent {
+    /// Makes a new `UserAgent` instance.
+    ///
+    /// # Errors
+    ///
+    /// If `value` contains invalid characters, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn new(value: &str) -> Result<Self> {
+        track!(validate_field(Self::NAME, value))?;
+        Ok(UserAgent(value.to_owned()))
+    }
+
+    /// Returns the value of the field.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+impl TypedHeader for UserAgent {
+    const NAME: &'static str = "User-Agent";
+
+    fn parse(value: &str) -> Result<Self> {
+        track!(UserAgent::new(value))
+    }
+}
+impl fmt::Display for UserAgent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 #[derive(Debug)]
-pub enum Connection {
+pub(crate) enum Connection {
     Close,
+    KeepAlive,
 }
 impl From<Connection> for HeaderField<'static, 'static> {
-    fn from(_: Connection) -> Self {
-        unsafe { HeaderField::new_unchecked("Connection", "close") }
+    fn from(f: Connection) -> Self {
+        let value = match f {
+            Connection::Close => "close",
+            Connection::KeepAlive => "keep-alive",
+        };
+        unsafe { HeaderField::new_unchecked("Connection", value) }
+    }
+}
+
+/// The cause of the errors returned by `check_content_type`.
+///
+/// If the initialization of a request body decoder fails with this cause,
+/// the server responds with `415 Unsupported Media Type`.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    not(any(feature = "prost", feature = "msgpack", feature = "cbor")),
+    allow(dead_code)
+)]
+pub(crate) struct UnsupportedMediaType(Option<String>);
+impl fmt::Display for UnsupportedMediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => write!(f, "No Content-Type header"),
+            Some(ref t) => write!(f, "Unsupported Content-Type: {:?}", t),
+        }
+    }
+}
+impl std::error::Error for UnsupportedMediaType {}
+
+/// Checks that the media type of the `Content-Type` header is one of `media_types`.
+#[cfg_attr(
+    not(any(feature = "prost", feature = "msgpack", feature = "cbor")),
+    allow(dead_code)
+)]
+pub(crate) fn check_content_type(header: &Header, media_types: &[&str]) -> bytecodec::Result<()> {
+    let content_type = get::<ContentType>(header).ok().and_then(|t| t);
+    if content_type.is_some_and(|t| media_types.contains(&t.media_type())) {
+        Ok(())
+    } else {
+        let actual = header.get_field(ContentType::NAME).map(str::to_owned);
+        Err(track!(bytecodec::Error::from(
+            bytecodec::ErrorKind::InvalidInput.cause(UnsupportedMediaType(actual))
+        )))
+    }
+}
+
+/// Returns `true` if the `Connection` header fields contain the given option (case-insensitive).
+pub(crate) fn has_connection_option(header: &Header, option: &str) -> bool {
+    header
+        .fields()
+        .filter(|f| f.name().eq_ignore_ascii_case("Connection"))
+        .flat_map(|f| f.value().split(','))
+        .any(|o| o.trim().eq_ignore_ascii_case(option))
+}
+
+/// Returns `true` if the client wants to keep the connection alive after receiving the response.
+///
+/// HTTP/1.1 connections are persistent unless the `close` option is specified,
+/// while HTTP/1.0 ones are persistent only if the `keep-alive` option is specified.
+pub(crate) fn is_keep_alive(version: HttpVersion, header: &Header) -> bool {
+    match version {
+        HttpVersion::V1_0 => has_connection_option(header, "keep-alive"),
+        HttpVersion::V1_1 => !has_connection_option(header, "close"),
+    }
+}
+
+/// Returns the quality value of `coding` in the given `Accept-Encoding` header field.
+pub(crate) fn encoding_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params
+            .map(str::trim)
+            .find(|p| p.starts_with("q=") || p.starts_with("Q="))
+            .map_or(Some(1.0), |p| p[2..].trim().parse().ok())
+            .unwrap_or(0.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return quality;
+        } else if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+fn parse_media_type(s: &str, allow_wildcard: bool) -> Result<String> {
+    let s = s.trim();
+    let (ty, subty) = split_media_type(s);
+    track_assert!(is_token(ty) && is_token(subty), ErrorKind::InvalidInput; s);
+    track_assert!(
+        allow_wildcard || (ty != "*" && subty != "*"),
+        ErrorKind::InvalidInput;
+        s
+    );
+    track_assert!(ty != "*" || subty == "*", ErrorKind::InvalidInput; s);
+    Ok(s.to_ascii_lowercase())
+}
+
+fn split_media_type(s: &str) -> (&str, &str) {
+    match s.find('/') {
+        None => (s, ""),
+        Some(i) => (&s[..i], &s[i + 1..]),
+    }
+}
+
+fn parse_param(s: &str) -> Result<(&str, &str)> {
+    let i = track_assert_some!(s.find('='), ErrorKind::InvalidInput; s);
+    Ok((s[..i].trim(), s[i + 1..].trim()))
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn is_param_value(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| is_vchar(b) && b != b';' && b != b',')
+}
+
+fn is_vchar(b: u8) -> bool {
+    (0x21..=0x7e).contains(&b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_type_works() {
+        let h = track_try_unwrap!(ContentType::parse("Text/HTML; charset=UTF-8"));
+        assert_eq!(h.media_type(), "text/html");
+        assert_eq!(h.charset(), Some("UTF-8"));
+        assert_eq!(h.to_string(), "text/html; charset=UTF-8");
+
+        assert!(ContentType::parse("text").is_err());
+        assert!(ContentType::parse("text/*").is_err());
+        assert!(ContentType::parse("text/html; charset").is_err());
+    }
+
+    #[test]
+    fn content_length_works() {
+        assert_eq!(
+            track_try_unwrap!(ContentLength::parse("10")),
+            ContentLength(10)
+        );
+        assert!(ContentLength::parse("+10").is_err());
+        assert!(ContentLength::parse("").is_err());
+    }
+
+    #[test]
+    fn accept_works() {
+        let h = track_try_unwrap!(Accept::parse(
+            "text/html, text/*;q=0.5, application/json;q=0.8, */*;q=0.1"
+        ));
+        assert_eq!(h.media_ranges().len(), 4);
+        assert_eq!(h.quality("text/html"), 1.0);
+        assert_eq!(h.quality("text/plain"), 0.5);
+        assert_eq!(h.quality("application/json"), 0.8);
+        assert_eq!(h.quality("image/png"), 0.1);
+        assert_eq!(
+            h.to_string(),
+            "text/html, text/*;q=0.5, application/json;q=0.8, */*;q=0.1"
+        );
+
+        assert!(Accept::parse("*/html").is_err());
+        assert!(Accept::parse("text/html;q=2").is_err());
+    }
+
+    #[test]
+    fn authorization_works() {
+        let h = track_try_unwrap!(Authorization::parse("Bearer abc.def"));
+        assert_eq!(h.scheme(), "Bearer");
+        assert_eq!(h.bearer_token(), Some("abc.def"));
+
+        let h = track_try_unwrap!(Authorization::parse("Basic Zm9vOmJhcg=="));
+        assert_eq!(h.credentials(), "Zm9vOmJhcg==");
+        assert_eq!(h.bearer_token(), None);
+        assert_eq!(h.to_string(), "Basic Zm9vOmJhcg==");
+    }
+
+    #[test]
+    fn cache_control_works() {
+        let h = track_try_unwrap!(CacheControl::parse("no-cache, max-age=60"));
+        assert!(h.no_cache());
+        assert!(!h.no_store());
+        assert_eq!(h.max_age(), Some(60));
+        assert_eq!(h.to_string(), "no-cache, max-age=60");
+    }
+
+    #[test]
+    fn host_works() {
+        let h = track_try_unwrap!(Host::parse("example.com:8080"));
+        assert_eq!(h.host(), "example.com");
+        assert_eq!(h.port(), Some(8080));
+
+        let h = track_try_unwrap!(Host::parse("[::1]"));
+        assert_eq!(h.host(), "[::1]");
+        assert_eq!(h.port(), None);
+
+        assert!(Host::parse("example.com:foo").is_err());
+        assert!(Host::parse("example.com/foo").is_err());
+    }
+
+    #[test]
+    fn user_agent_works() {
+        let h = track_try_unwrap!(UserAgent::parse("curl/7.64.1"));
+        assert_eq!(h.as_str(), "curl/7.64.1");
+        assert!(UserAgent::parse("foo\r\nbar").is_err());
     }
 }
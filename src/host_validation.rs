@@ -0,0 +1,200 @@
+use crate::Status;
+use httpcodec::{Header, HttpVersion};
+
+/// Configuration of the validation of the `Host` header.
+///
+/// Requests are validated as follows:
+/// - An `HTTP/1.1` request has to have exactly one `Host` header (RFC 7230, Section 5.4),
+///   and an `HTTP/1.0` request may have at most one
+/// - The value of the header has to be a well-formed `host[:port]`
+/// - If one or more hosts are allowed by `allow_host`, the header has to match one of them
+///
+/// Requests violating the first two rules are responded with `400 Bad Request`,
+/// and the ones violating the last rule are responded with `421 Misdirected Request`.
+#[derive(Debug, Clone, Default)]
+pub struct HostValidation {
+    allowed: Vec<AllowedHost>,
+}
+impl HostValidation {
+    /// Makes a new `HostValidation` instance.
+    ///
+    /// By default, any well-formed host is allowed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a host to the allowlist.
+    ///
+    /// If `host` does not have a port (e.g., `example.com`), it matches any port.
+    /// A leading `*.` (e.g., `*.example.com`) matches any subdomain but not the domain itself.
+    /// Hosts are compared case-insensitively.
+    pub fn allow_host(mut self, host: &str) -> Self {
+        let (host, port) = split_port(host).unwrap_or((host, None));
+        self.allowed.push(AllowedHost {
+            host: host.to_ascii_lowercase(),
+            port: port.map(|p| p.to_owned()),
+        });
+        self
+    }
+
+    /// Checks the `Host` header of a request.
+    ///
+    /// Returns the status of the error response if the request should be rejected.
+    pub(crate) fn check(&self, header: &Header, version: HttpVersion) -> Option<Status> {
+        let mut fields = header
+            .fields()
+            .filter(|f| f.name().eq_ignore_ascii_case("Host"));
+        let value = match (fields.next(), fields.next()) {
+            (Some(f), None) => f.value().trim(),
+            (None, None) if version == HttpVersion::V1_0 => return None,
+            _ => return Some(Status::BadRequest),
+        };
+        let (host, port) = match split_port(value) {
+            Some((host, port)) if is_valid_host(host) => (host, port),
+            _ => return Some(Status::BadRequest),
+        };
+        if self.allowed.is_empty() || self.allowed.iter().any(|a| a.matches(host, port)) {
+            None
+        } else {
+            Some(Status::MisdirectedRequest)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AllowedHost {
+    host: String,
+    port: Option<String>,
+}
+impl AllowedHost {
+    fn matches(&self, host: &str, port: Option<&str>) -> bool {
+        if self.port.is_some() && self.port.as_deref() != port {
+            return false;
+        }
+        if let Some(domain) = self.host.strip_prefix("*.") {
+            let host = host.to_ascii_lowercase();
+            host.len() > domain.len() + 1
+                && host.ends_with(domain)
+                && host[..host.len() - domain.len()].ends_with('.')
+        } else {
+            self.host.eq_ignore_ascii_case(host)
+        }
+    }
+}
+
+/// Splits `host[:port]` into the host and the port.
+///
+/// Returns `None` if the port is not a valid port number.
+fn split_port(s: &str) -> Option<(&str, Option<&str>)> {
+    let i = match s.rfind(':') {
+        Some(i) if !s[i..].contains(']') => i,
+        _ => return Some((s, None)),
+    };
+    let port = &s[i + 1..];
+    if port.is_empty() || port.parse::<u16>().is_err() {
+        return None;
+    }
+    Some((&s[..i], Some(port)))
+}
+
+/// Returns `true` if `host` is a well-formed `reg-name` (a domain name or an IPv4 address)
+/// or `IP-literal` (an IPv6 address enclosed in brackets).
+fn is_valid_host(host: &str) -> bool {
+    if host.starts_with('[') && host.ends_with(']') {
+        return host[1..host.len() - 1]
+            .parse::<std::net::Ipv6Addr>()
+            .is_ok();
+    }
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+    use httpcodec::{HeaderField, Method, Request, RequestTarget};
+
+    fn check(validation: &HostValidation, version: HttpVersion, hosts: &[&str]) -> Option<u16> {
+        let mut req = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            version,
+            (),
+        );
+        for host in hosts {
+            req.header_mut()
+                .add_field(HeaderField::new("Host", host).unwrap());
+        }
+        validation.check(&req.header(), version).map(|s| s.code())
+    }
+
+    #[test]
+    fn host_validation_works() {
+        let v = HostValidation::new();
+        assert_eq!(check(&v, HttpVersion::V1_1, &["example.com"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &["127.0.0.1:8080"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &["[::1]:8080"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_0, &[]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &[]), Some(400));
+        assert_eq!(check(&v, HttpVersion::V1_1, &["a.com", "b.com"]), Some(400));
+        assert_eq!(check(&v, HttpVersion::V1_1, &["example.com:x"]), Some(400));
+        assert_eq!(
+            check(&v, HttpVersion::V1_1, &["example.com/evil"]),
+            Some(400)
+        );
+        assert_eq!(
+            check(&v, HttpVersion::V1_1, &["user@example.com"]),
+            Some(400)
+        );
+        assert_eq!(check(&v, HttpVersion::V1_1, &["[::x]"]), Some(400));
+
+        let v = HostValidation::new()
+            .allow_host("example.com")
+            .allow_host("*.example.org")
+            .allow_host("localhost:8080");
+        assert_eq!(check(&v, HttpVersion::V1_1, &["Example.COM"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &["example.com:443"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &["www.example.org"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &["localhost:8080"]), None);
+        assert_eq!(check(&v, HttpVersion::V1_1, &["example.org"]), Some(421));
+        assert_eq!(check(&v, HttpVersion::V1_1, &["badexample.org"]), Some(421));
+        assert_eq!(check(&v, HttpVersion::V1_1, &["localhost"]), Some(421));
+        assert_eq!(check(&v, HttpVersion::V1_1, &["evil.com"]), Some(421));
+    }
+
+    #[test]
+    fn server_host_validation_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Hello).unwrap();
+        builder.host_validation(HostValidation::new().allow_host("example.com"));
+        let mut sim = builder.finish_simulation(0);
+
+        for &(req, expected) in &[
+            (
+                &b"GET /hello HTTP/1.1\r\nHost: example.com\r\n\r\n"[..],
+                "200 OK",
+            ),
+            (&b"GET /hello HTTP/1.0\r\n\r\n"[..], "200 OK"),
+            (&b"GET /hello HTTP/1.1\r\n\r\n"[..], "400 Bad Request"),
+            (
+                &b"GET /hello HTTP/1.1\r\nHost: example.com\r\nHost: evil.com\r\n\r\n"[..],
+                "400 Bad Request",
+            ),
+            (
+                &b"GET /hello HTTP/1.1\r\nHost: evil.com\r\n\r\n"[..],
+                "421 Misdirected Request",
+            ),
+        ] {
+            let conn = sim.connect().unwrap();
+            conn.write(req);
+            sim.run().unwrap();
+            let received = String::from_utf8(conn.take_received()).unwrap();
+            assert!(received.contains(expected), "{}", received);
+        }
+        assert_eq!(sim.metrics().host_rejected_requests(), 3);
+    }
+}
@@ -55,33 +55,140 @@
 //! let size = client.read(&mut buf).unwrap();
 //! assert_eq!(
 //!     &buf[..size],
-//!     b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".as_ref()
+//!     b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\nhello".as_ref()
 //! );
 //! ```
+//!
+//! # TLS
+//!
+//! This crate only speaks plaintext HTTP/1.1 and does not have a TLS layer.
+//! To serve HTTPS, terminate TLS at a reverse proxy in front of the server,
+//! and register the proxy with `ServerBuilder::trusted_proxies` so that `Req::url` reflects
+//! the original scheme and host.
+//!
+//! Client certificate (mTLS) authentication has to be done by the proxy as well.
+//! If the handlers need the identity of a client, make the proxy forward it in a request header
+//! and make sure that the server is only reachable through the proxy.
+//!
+//! Likewise, ALPN is negotiated by the proxy. Since the server only implements HTTP/1.1,
+//! the proxy has to talk to it with `http/1.1` whatever protocol was negotiated with the clients.
+//!
+//! Certificates for multiple host names (SNI) are also selected by the proxy.
+//! When one server serves several hosts, the handlers can tell them apart by the `Host` header
+//! (see `ServerBuilder::host_validation` for rejecting unknown hosts).
 #![warn(missing_docs)]
 #[macro_use]
 extern crate slog;
 #[macro_use]
 extern crate trackable;
 
+pub use access_log::{AccessLogEntry, AccessLogFormat, AccessLogSink, AccessLogWriter, JsonLines};
+#[cfg(feature = "async")]
+pub use async_handler::{reply_into_future, AsyncHandler, HandleRequestAsync};
+pub use bandwidth::BandwidthLimit;
+pub use blocking::{BlockingEndpoint, BlockingHandler, DEFAULT_BLOCKING_QUEUE_CAPACITY};
+pub use body_stream::{BodyStream, BodyStreamDecoder};
+pub use cancellation::CancellationToken;
+pub use cidr::Cidr;
+pub use clock::{Clock, ManualClock, SharedClock, Sleep, SystemClock};
+pub use csrf::{CsrfProtection, CsrfToken};
+pub use debug::DebugHandler;
+pub use dispatcher::{DispatchError, Route, RouteConflict, ShadowedRoute};
+pub use drain::ShutdownSignal;
 pub use error::{Error, ErrorKind};
-pub use handler::{HandleRequest, HandlerOptions, Reply};
+pub use extensions::Extensions;
+pub use file::{FileBody, FileBodyEncoder};
+pub use group::HandlerGroup;
+pub use handle::ServerHandle;
+pub use handler::{FlushMode, HandleRequest, HandlerOptions, Reply, TextEncoder};
+pub use host_validation::HostValidation;
+pub use load_shedding::{LoadShedding, Priority};
+pub use negotiation::{
+    Negotiate, NegotiateReply, Negotiated, NegotiatingEncoder, NegotiatingEncoderFactory,
+};
+pub use observer::{ConnectionObserver, ConnectionPhase, RequestTraffic};
+pub use options::EffectiveOptions;
+pub use path_decoding::PathDecoding;
+pub use problem::{IntoProblem, ProblemDetails};
+pub use push::MetricsPush;
+pub use rate_limit::RateLimit;
 pub use request::Req;
-pub use response::Res;
+pub use response::{Res, ResBuilder};
+pub use retry::RetryPolicy;
+pub use sampling::{RequestSampler, SampledExchange};
 pub use server::{Server, ServerBuilder};
-pub use status::Status;
+pub use static_files::{StaticBody, StaticBodyEncoder, StaticEtag, StaticFiles, StaticMount};
+pub use status::{CustomStatus, Status};
+pub use temp_file::{SpooledBody, TempFile, TempFileDecoder};
+pub use thread_pool::{ThreadPoolReply, WithThreadPool};
+pub use trace::TracePolicy;
+pub use try_handler::{TryHandleRequest, TryHandler};
+pub use tunnel::{ConnectReply, HandleConnect};
+pub use warmup::ReadinessGate;
 
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "http")]
+pub mod compat;
+pub mod header;
 pub mod metrics;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "prost")]
+pub mod protobuf;
+pub mod reply;
+pub mod testing;
 
+mod access_log;
+#[cfg(feature = "async")]
+mod async_handler;
+mod bandwidth;
+mod blocking;
+mod body_stream;
+mod cancellation;
+mod cidr;
+mod clock;
+#[cfg(any(feature = "prost", feature = "msgpack", feature = "cbor"))]
+mod codec;
 mod connection;
+mod csrf;
+mod debug;
+mod decompression;
 mod dispatcher;
+mod drain;
+mod encoder_pool;
 mod error;
+mod exposition;
+mod extensions;
+mod file;
+mod forwarded;
+mod group;
+mod handle;
 mod handler;
-mod header;
+mod head_limits;
+mod host_validation;
+mod load_shedding;
+mod negotiation;
+mod observer;
+mod options;
+mod path_decoding;
+mod problem;
+mod push;
+mod rate_limit;
 mod request;
 mod response;
+mod retry;
+mod sampling;
 mod server;
+mod smuggling;
+mod static_files;
 mod status;
+mod temp_file;
+mod thread_pool;
+mod trace;
+mod try_handler;
+mod tunnel;
+mod warmup;
 
 /// This crate specific `Result` type.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -99,7 +206,7 @@ mod test {
 
     use super::*;
 
-    struct Hello;
+    pub(crate) struct Hello;
     impl HandleRequest for Hello {
         const METHOD: &'static str = "GET";
         const PATH: &'static str = "/hello";
@@ -115,6 +222,25 @@ mod test {
         }
     }
 
+    pub(crate) fn spawn_server(builder: ServerBuilder) -> std::net::SocketAddr {
+        let server = builder.finish(fibers_global::handle());
+        let (server, addr) = fibers_global::execute(server.local_addr()).unwrap();
+        thread::spawn(move || {
+            fibers_global::execute(server).unwrap();
+        });
+        addr
+    }
+
+    pub(crate) fn wait_until<F: FnMut() -> bool>(mut f: F) {
+        for _ in 0..100 {
+            if f() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Timed out");
+    }
+
     #[test]
     fn it_works() {
         let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
@@ -136,7 +262,43 @@ mod test {
         let size = client.read(&mut buf).unwrap();
         assert_eq!(
             &buf[..size],
-            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".as_ref()
+            b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\nhello".as_ref()
         );
     }
+
+    #[test]
+    fn extensions_work() {
+        struct Greeting(&'static str);
+        struct Name(String);
+
+        struct Greet;
+        impl HandleRequest for Greet {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/greet";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let greeting = req.state::<Greeting>().map_or("?", |g| g.0);
+                let name = req.extensions().get::<Name>().map_or("?", |n| &n.0);
+                Box::new(ok(Res::new(Status::Ok, format!("{}, {}", greeting, name))))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Greet).unwrap();
+        builder.state(Greeting("Hello"));
+        builder.request_hook(|req| {
+            let name = req.url().query().unwrap_or("world").to_owned();
+            req.extensions_mut().insert(Name(name));
+        });
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/greet?alice").unwrap()).unwrap();
+        assert_eq!(res.body(), b"Hello, alice");
+    }
 }
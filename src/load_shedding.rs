@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The priority class of requests.
+///
+/// The priority of a request is determined by `HandleRequest::PRIORITY` of the handler of the request.
+/// When the server is overloaded, lower priority requests are shed first (see `LoadShedding`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Requests that are shed first.
+    Low,
+
+    /// The default priority.
+    #[default]
+    Normal,
+
+    /// Requests that are shed after `Low` and `Normal` ones.
+    High,
+
+    /// Requests that are never shed (e.g., health checks and metrics scrapes).
+    Critical,
+}
+impl Priority {
+    fn index(self) -> usize {
+        match self {
+            Priority::Low => 0,
+            Priority::Normal => 1,
+            Priority::High => 2,
+            Priority::Critical => panic!("Critical requests are never shed"),
+        }
+    }
+}
+
+/// Configuration of the load shedding.
+///
+/// If the number of in-flight requests (i.e., the requests dispatched to handlers but not yet responded)
+/// or the number of active connections exceeds the threshold of the priority of a request,
+/// the request is rejected with the `503 Service Unavailable` response.
+///
+/// The effective threshold of a priority is the minimum of the ones of the priority and the higher priorities,
+/// so lower priority requests are always shed first.
+/// `Priority::Critical` requests are always admitted.
+#[derive(Debug, Clone)]
+pub struct LoadShedding {
+    max_in_flight_requests: [usize; 3],
+    max_active_connections: [usize; 3],
+}
+impl LoadShedding {
+    /// Makes a new `LoadShedding` instance that has no thresholds.
+    pub fn new() -> Self {
+        LoadShedding {
+            max_in_flight_requests: [usize::MAX; 3],
+            max_active_connections: [usize::MAX; 3],
+        }
+    }
+
+    /// Sets the maximum number of in-flight requests up to which requests of `priority` are admitted.
+    ///
+    /// # Panics
+    ///
+    /// If `priority` is `Priority::Critical`, this method will panic.
+    pub fn max_in_flight_requests(mut self, priority: Priority, n: usize) -> Self {
+        self.max_in_flight_requests[priority.index()] = n;
+        self
+    }
+
+    /// Sets the maximum number of active connections up to which requests of `priority` are admitted.
+    ///
+    /// # Panics
+    ///
+    /// If `priority` is `Priority::Critical`, this method will panic.
+    pub fn max_active_connections(mut self, priority: Priority, n: usize) -> Self {
+        self.max_active_connections[priority.index()] = n;
+        self
+    }
+
+    fn threshold(limits: &[usize; 3], priority: Priority) -> usize {
+        limits[priority.index()..]
+            .iter()
+            .cloned()
+            .min()
+            .expect("Never fails")
+    }
+}
+impl Default for LoadShedding {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadShedder {
+    config: LoadShedding,
+    in_flight_requests: Arc<AtomicUsize>,
+    active_connections: Arc<AtomicUsize>,
+}
+impl LoadShedder {
+    pub fn new(config: LoadShedding, active_connections: Arc<AtomicUsize>) -> Self {
+        LoadShedder {
+            config,
+            in_flight_requests: Arc::default(),
+            active_connections,
+        }
+    }
+
+    /// Decides whether to admit a request of the given priority.
+    ///
+    /// If the request is admitted, it is counted as in-flight until the returned guard is dropped.
+    pub fn admit(&self, priority: Priority) -> Option<InFlightRequest> {
+        if priority != Priority::Critical {
+            let in_flight = self.in_flight_requests.load(Ordering::SeqCst);
+            let active = self.active_connections.load(Ordering::SeqCst);
+            if in_flight >= LoadShedding::threshold(&self.config.max_in_flight_requests, priority)
+                || active > LoadShedding::threshold(&self.config.max_active_connections, priority)
+            {
+                return None;
+            }
+        }
+        self.in_flight_requests.fetch_add(1, Ordering::SeqCst);
+        Some(InFlightRequest(Arc::clone(&self.in_flight_requests)))
+    }
+}
+
+#[derive(Debug)]
+pub struct InFlightRequest(Arc<AtomicUsize>);
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use futures::Future;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::sync::Mutex;
+
+    #[test]
+    fn load_shedder_works() {
+        let config = LoadShedding::new()
+            .max_in_flight_requests(Priority::Low, 1)
+            .max_in_flight_requests(Priority::Normal, 2)
+            .max_active_connections(Priority::High, 3);
+        let active_connections = Arc::new(AtomicUsize::new(1));
+        let shedder = LoadShedder::new(config, Arc::clone(&active_connections));
+
+        let a = shedder.admit(Priority::Low);
+        assert!(a.is_some());
+        assert!(shedder.admit(Priority::Low).is_none());
+
+        let b = shedder.admit(Priority::Normal);
+        assert!(b.is_some());
+        assert!(shedder.admit(Priority::Normal).is_none());
+        assert!(shedder.admit(Priority::High).is_some());
+
+        drop(a);
+        assert!(shedder.admit(Priority::Low).is_none());
+        drop(b);
+        assert!(shedder.admit(Priority::Low).is_some());
+
+        active_connections.store(4, Ordering::SeqCst);
+        assert!(shedder.admit(Priority::Low).is_none());
+        assert!(shedder.admit(Priority::High).is_none());
+        assert!(shedder.admit(Priority::Critical).is_some());
+    }
+
+    #[test]
+    fn load_shedding_works() {
+        use futures::sync::oneshot;
+
+        struct LongPoll(Mutex<Option<oneshot::Receiver<()>>>);
+        impl HandleRequest for LongPoll {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/poll";
+            const PRIORITY: Priority = Priority::Low;
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                let done = self.0.lock().unwrap().take().unwrap();
+                Box::new(done.then(|_| Ok(Res::new(Status::Ok, "bye".to_owned()))))
+            }
+        }
+
+        struct Health;
+        impl HandleRequest for Health {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/health";
+            const PRIORITY: Priority = Priority::Critical;
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "ok".to_owned())))
+            }
+        }
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder
+            .add_handler(LongPoll(Mutex::new(Some(done_rx))))
+            .unwrap();
+        builder.add_handler(Health).unwrap();
+        builder.add_handler(Hello).unwrap();
+        builder.load_shedding(
+            LoadShedding::new()
+                .max_in_flight_requests(Priority::Low, 1)
+                .max_in_flight_requests(Priority::Normal, 1),
+        );
+        let mut sim = builder.finish_simulation(0);
+
+        let poller = sim.connect().unwrap();
+        poller.write(b"GET /poll HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        assert!(poller.take_received().is_empty());
+
+        let mut request = |path: &str| {
+            let conn = sim.connect().unwrap();
+            conn.write(format!("GET {} HTTP/1.1\r\nContent-Length: 0\r\n\r\n", path).as_bytes());
+            sim.run().unwrap();
+            String::from_utf8(conn.take_received()).unwrap()
+        };
+        assert!(request("/poll").starts_with("HTTP/1.1 503 "));
+        assert!(request("/hello").starts_with("HTTP/1.1 503 "));
+        assert!(request("/health").starts_with("HTTP/1.1 200 "));
+        assert_eq!(sim.metrics().shed_requests(), 2);
+
+        done_tx.send(()).unwrap();
+        sim.run().unwrap();
+        let received = String::from_utf8(poller.take_received()).unwrap();
+        assert!(received.ends_with("\r\n\r\nbye"));
+    }
+}
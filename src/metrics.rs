@@ -1,9 +1,13 @@
 //! [Prometheus][prometheus] metrics.
 //!
 //! [prometheus]: https://prometheus.io/
-use crate::{Error, HandleRequest, Req, Res, Status};
+use crate::clock::{Clock, SharedClock};
+use crate::exposition;
+use crate::head_limits::HeadLimitViolation;
+use crate::smuggling::SmugglingViolation;
+use crate::{DispatchError, Error, HandleRequest, Priority, Req, Res, Route, Status};
 use atomic_immut::AtomicImmut;
-use bytecodec::bytes::Utf8Encoder;
+use bytecodec::bytes::BytesEncoder;
 use bytecodec::marker::Never;
 use bytecodec::null::NullDecoder;
 use fibers::sync::oneshot;
@@ -11,24 +15,63 @@ use futures::{Async, Future, Poll};
 use httpcodec::{BodyDecoder, BodyEncoder};
 use prometrics;
 use prometrics::bucket::Bucket;
-use prometrics::metrics::{Counter, Histogram, HistogramBuilder, MetricBuilder};
-use std::collections::HashMap;
+use prometrics::metrics::{Counter, Gauge, Histogram, HistogramBuilder, MetricBuilder};
+use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// HTTP server metrics.
 #[derive(Debug, Clone)]
 pub struct ServerMetrics {
     pub(crate) connected_tcp_clients: Counter,
     pub(crate) disconnected_tcp_clients: Counter,
+    pub(crate) rejected_tcp_clients: Counter,
+    pub(crate) pending_connections: Gauge,
+    pub(crate) accept_pauses: Counter,
+    pub(crate) bind_errors: Counter,
+    pub(crate) accept_errors: Counter,
+    pub(crate) fd_exhaustion_errors: Counter,
     pub(crate) read_request_head_errors: Counter,
+    pub(crate) request_line_too_long_errors: Counter,
+    pub(crate) too_many_headers_errors: Counter,
+    pub(crate) header_field_too_large_errors: Counter,
+    pub(crate) header_section_too_large_errors: Counter,
+    pub(crate) obs_fold_errors: Counter,
+    pub(crate) bare_cr_errors: Counter,
+    pub(crate) content_length_with_transfer_encoding_errors: Counter,
+    pub(crate) invalid_content_length_errors: Counter,
+    pub(crate) invalid_transfer_encoding_errors: Counter,
+    pub(crate) invalid_chunk_errors: Counter,
     pub(crate) parse_request_path_errors: Counter,
-    pub(crate) dispatch_request_errors: Counter,
+    pub(crate) dispatch_not_found_errors: Counter,
+    pub(crate) dispatch_method_not_allowed_errors: Counter,
+    pub(crate) dispatch_uri_too_long_errors: Counter,
     pub(crate) initialize_handler_errors: Counter,
     pub(crate) decode_request_body_errors: Counter,
+    pub(crate) decompress_request_body_errors: Counter,
     pub(crate) write_response_errors: Counter,
+    pub(crate) read_request_head_timeouts: Counter,
+    pub(crate) read_request_body_timeouts: Counter,
+    pub(crate) write_response_timeouts: Counter,
+    pub(crate) throttled_requests: Counter,
+    pub(crate) csrf_rejected_requests: Counter,
+    pub(crate) host_rejected_requests: Counter,
+    pub(crate) shed_requests: Counter,
+    pub(crate) warmup_rejected_requests: Counter,
+    pub(crate) client_aborted_reads: Counter,
+    pub(crate) client_aborted_writes: Counter,
+    pub(crate) cancelled_replies: Counter,
+    pub(crate) read_buffer_high_watermark: Gauge,
+    pub(crate) write_buffer_high_watermark: Gauge,
+    pub(crate) read_buffer_full_stalls: Counter,
+    pub(crate) write_buffer_full_stalls: Counter,
+    read_buffer_peak_fill_bytes: Histogram,
+    write_buffer_peak_fill_bytes: Histogram,
+    slow_requests: Arc<AtomicImmut<HashMap<(&'static str, Arc<str>), Counter>>>,
+    time_to_first_byte_seconds: Arc<AtomicImmut<HashMap<(&'static str, Arc<str>), Histogram>>>,
+    builder: Arc<Mutex<MetricBuilder>>,
 }
 impl ServerMetrics {
     /// Number of connected TCP clients.
@@ -45,15 +88,136 @@ impl ServerMetrics {
         self.disconnected_tcp_clients.value() as u64
     }
 
+    /// Number of TCP clients rejected by the access control lists.
+    ///
+    /// Metric: `fibers_http_server_rejected_tcp_clients_total <COUNTER>`
+    pub fn rejected_tcp_clients(&self) -> u64 {
+        self.rejected_tcp_clients.value() as u64
+    }
+
+    /// Number of accepted connections waiting to be spawned.
+    ///
+    /// Metric: `fibers_http_server_pending_connections <GAUGE>`
+    pub fn pending_connections(&self) -> usize {
+        self.pending_connections.value() as usize
+    }
+
+    /// Number of times the server paused accepting new connections because it was busy.
+    ///
+    /// Metric: `fibers_http_server_accept_pauses_total <COUNTER>`
+    pub fn accept_pauses(&self) -> u64 {
+        self.accept_pauses.value() as u64
+    }
+
+    /// Number of failures to bind the listening socket.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="bind" } <COUNTER>`
+    pub fn bind_errors(&self) -> u64 {
+        self.bind_errors.value() as u64
+    }
+
+    /// Number of errors occurred while accepting connections.
+    ///
+    /// Note that this does not include `fd_exhaustion_errors`.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="accept" } <COUNTER>`
+    pub fn accept_errors(&self) -> u64 {
+        self.accept_errors.value() as u64
+    }
+
+    /// Number of times accepting a connection failed because the process or the system
+    /// ran out of file descriptors (i.e., `EMFILE` or `ENFILE`).
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="accept", reason="fd_exhaustion" } <COUNTER>`
+    pub fn fd_exhaustion_errors(&self) -> u64 {
+        self.fd_exhaustion_errors.value() as u64
+    }
+
     /// Number of errors occurred while reading the head part of requests.
     ///
+    /// Note that this does not include the errors caused by the limits of request heads
+    /// (e.g., `request_line_too_long_errors`).
+    ///
     /// Metric: `fibers_http_server_errors_total { phase="read_request_head" } <COUNTER>`
     pub fn read_request_head_errors(&self) -> u64 {
         self.read_request_head_errors.value() as u64
     }
 
+    /// Number of request lines exceeding the size limit.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="request_line_too_long" } <COUNTER>`
+    pub fn request_line_too_long_errors(&self) -> u64 {
+        self.request_line_too_long_errors.value() as u64
+    }
+
+    /// Number of request heads exceeding the header count limit.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="too_many_headers" } <COUNTER>`
+    pub fn too_many_headers_errors(&self) -> u64 {
+        self.too_many_headers_errors.value() as u64
+    }
+
+    /// Number of header fields exceeding the size limit.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="header_field_too_large" } <COUNTER>`
+    pub fn header_field_too_large_errors(&self) -> u64 {
+        self.header_field_too_large_errors.value() as u64
+    }
+
+    /// Number of request heads exceeding the header size limit of `DecodeOptions`.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="header_section_too_large" } <COUNTER>`
+    pub fn header_section_too_large_errors(&self) -> u64 {
+        self.header_section_too_large_errors.value() as u64
+    }
+
+    /// Number of obsolete line foldings rejected by the strict parsing mode.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="obs_fold" } <COUNTER>`
+    pub fn obs_fold_errors(&self) -> u64 {
+        self.obs_fold_errors.value() as u64
+    }
+
+    /// Number of bare CRs rejected by the strict parsing mode.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="bare_cr" } <COUNTER>`
+    pub fn bare_cr_errors(&self) -> u64 {
+        self.bare_cr_errors.value() as u64
+    }
+
+    /// Number of requests having both `Content-Length` and `Transfer-Encoding` headers
+    /// rejected by the request smuggling protection.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="content_length_with_transfer_encoding" } <COUNTER>`
+    pub fn content_length_with_transfer_encoding_errors(&self) -> u64 {
+        self.content_length_with_transfer_encoding_errors.value() as u64
+    }
+
+    /// Number of conflicting or malformed `Content-Length` headers rejected by the request smuggling protection.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="invalid_content_length" } <COUNTER>`
+    pub fn invalid_content_length_errors(&self) -> u64 {
+        self.invalid_content_length_errors.value() as u64
+    }
+
+    /// Number of `Transfer-Encoding` headers not ending with `chunked` rejected by the request smuggling protection.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="read_request_head", reason="invalid_transfer_encoding" } <COUNTER>`
+    pub fn invalid_transfer_encoding_errors(&self) -> u64 {
+        self.invalid_transfer_encoding_errors.value() as u64
+    }
+
+    /// Number of malformed chunks (e.g., invalid chunk extensions) rejected by the request smuggling protection.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="decode_request_body", reason="invalid_chunk" } <COUNTER>`
+    pub fn invalid_chunk_errors(&self) -> u64 {
+        self.invalid_chunk_errors.value() as u64
+    }
+
     /// Number of errors occurred while parsing the path of requests.
     ///
+    /// This includes the requests rejected with `DispatchError::BadTarget`.
+    ///
     /// Metric: `fibers_http_server_errors_total { phase="parse_request_path" } <COUNTER>`
     pub fn parse_request_path_errors(&self) -> u64 {
         self.parse_request_path_errors.value() as u64
@@ -61,9 +225,33 @@ impl ServerMetrics {
 
     /// Number of errors occurred while dispatcing requests.
     ///
-    /// Metric: `fibers_http_server_errors_total { phase="dispatch_request" } <COUNTER>`
+    /// This is the sum of `dispatch_not_found_errors`, `dispatch_method_not_allowed_errors`
+    /// and `dispatch_uri_too_long_errors`.
     pub fn dispatch_request_errors(&self) -> u64 {
-        self.dispatch_request_errors.value() as u64
+        self.dispatch_not_found_errors()
+            + self.dispatch_method_not_allowed_errors()
+            + self.dispatch_uri_too_long_errors()
+    }
+
+    /// Number of requests that did not match any handler paths.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="dispatch_request", reason="not_found" } <COUNTER>`
+    pub fn dispatch_not_found_errors(&self) -> u64 {
+        self.dispatch_not_found_errors.value() as u64
+    }
+
+    /// Number of requests whose methods were not allowed by the handlers of the paths.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="dispatch_request", reason="method_not_allowed" } <COUNTER>`
+    pub fn dispatch_method_not_allowed_errors(&self) -> u64 {
+        self.dispatch_method_not_allowed_errors.value() as u64
+    }
+
+    /// Number of requests whose targets exceeded `ServerBuilder::max_request_target_len`.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="dispatch_request", reason="uri_too_long" } <COUNTER>`
+    pub fn dispatch_uri_too_long_errors(&self) -> u64 {
+        self.dispatch_uri_too_long_errors.value() as u64
     }
 
     /// Number of errors occurred while initializing request handlers.
@@ -80,6 +268,13 @@ impl ServerMetrics {
         self.decode_request_body_errors.value() as u64
     }
 
+    /// Number of errors occurred while decompressing request bodies.
+    ///
+    /// Metric: `fibers_http_server_errors_total { phase="decompress_request_body" } <COUNTER>`
+    pub fn decompress_request_body_errors(&self) -> u64 {
+        self.decompress_request_body_errors.value() as u64
+    }
+
     /// Number of errors occurred while writing responses to sockets.
     ///
     /// Metric: `fibers_http_server_errors_total { phase="write_response" } <COUNTER>`
@@ -87,6 +282,170 @@ impl ServerMetrics {
         self.write_response_errors.value() as u64
     }
 
+    /// Number of timeouts occurred while reading the head part of requests.
+    ///
+    /// Metric: `fibers_http_server_timeouts_total { phase="read_request_head" } <COUNTER>`
+    pub fn read_request_head_timeouts(&self) -> u64 {
+        self.read_request_head_timeouts.value() as u64
+    }
+
+    /// Number of timeouts occurred while reading the body part of requests.
+    ///
+    /// Metric: `fibers_http_server_timeouts_total { phase="read_request_body" } <COUNTER>`
+    pub fn read_request_body_timeouts(&self) -> u64 {
+        self.read_request_body_timeouts.value() as u64
+    }
+
+    /// Number of timeouts occurred while writing responses to sockets.
+    ///
+    /// Metric: `fibers_http_server_timeouts_total { phase="write_response" } <COUNTER>`
+    pub fn write_response_timeouts(&self) -> u64 {
+        self.write_response_timeouts.value() as u64
+    }
+
+    /// Number of requests rejected by the rate limiter.
+    ///
+    /// Metric: `fibers_http_server_throttled_requests_total <COUNTER>`
+    pub fn throttled_requests(&self) -> u64 {
+        self.throttled_requests.value() as u64
+    }
+
+    /// Number of requests rejected by the load shedding.
+    ///
+    /// Metric: `fibers_http_server_shed_requests_total <COUNTER>`
+    pub fn shed_requests(&self) -> u64 {
+        self.shed_requests.value() as u64
+    }
+
+    /// Number of requests rejected during the warmup.
+    ///
+    /// Metric: `fibers_http_server_warmup_rejected_requests_total <COUNTER>`
+    pub fn warmup_rejected_requests(&self) -> u64 {
+        self.warmup_rejected_requests.value() as u64
+    }
+
+    /// Number of requests rejected by the CSRF protection.
+    ///
+    /// Metric: `fibers_http_server_csrf_rejected_requests_total <COUNTER>`
+    pub fn csrf_rejected_requests(&self) -> u64 {
+        self.csrf_rejected_requests.value() as u64
+    }
+
+    /// Number of requests rejected by the validation of the `Host` header.
+    ///
+    /// Metric: `fibers_http_server_host_rejected_requests_total <COUNTER>`
+    pub fn host_rejected_requests(&self) -> u64 {
+        self.host_rejected_requests.value() as u64
+    }
+
+    /// Number of requests aborted by clients (e.g., `ECONNRESET`) while the requests were being read.
+    ///
+    /// Note that the connections reset by clients between requests are not counted.
+    ///
+    /// Metric: `fibers_http_server_client_aborted_requests_total { phase="read" } <COUNTER>`
+    pub fn client_aborted_reads(&self) -> u64 {
+        self.client_aborted_reads.value() as u64
+    }
+
+    /// Number of requests aborted by clients (e.g., `EPIPE`) while the responses were being written.
+    ///
+    /// Metric: `fibers_http_server_client_aborted_requests_total { phase="write" } <COUNTER>`
+    pub fn client_aborted_writes(&self) -> u64 {
+        self.client_aborted_writes.value() as u64
+    }
+
+    /// Number of requests cancelled because the connections were closed while the handlers
+    /// were computing the responses.
+    ///
+    /// Metric: `fibers_http_server_cancelled_replies_total <COUNTER>`
+    pub fn cancelled_replies(&self) -> u64 {
+        self.cancelled_replies.value() as u64
+    }
+
+    /// The largest size of the read buffers of connections in bytes.
+    ///
+    /// Metric: `fibers_http_server_buffer_high_watermark_bytes { kind="read" } <GAUGE>`
+    pub fn read_buffer_high_watermark(&self) -> usize {
+        self.read_buffer_high_watermark.value() as usize
+    }
+
+    /// The largest size of the write buffers of connections in bytes.
+    ///
+    /// Metric: `fibers_http_server_buffer_high_watermark_bytes { kind="write" } <GAUGE>`
+    pub fn write_buffer_high_watermark(&self) -> usize {
+        self.write_buffer_high_watermark.value() as usize
+    }
+
+    /// Number of times the read buffer of a connection became full and could not be grown
+    /// (see `ServerBuilder::max_read_buffer_size`).
+    ///
+    /// While the buffer is full, no more bytes are read from the socket until the handler consumes some of them.
+    ///
+    /// Metric: `fibers_http_server_buffer_full_stalls_total { kind="read" } <COUNTER>`
+    pub fn read_buffer_full_stalls(&self) -> u64 {
+        self.read_buffer_full_stalls.value() as u64
+    }
+
+    /// Number of times the write buffer of a connection became full and could not be grown
+    /// (see `ServerBuilder::max_write_buffer_size`).
+    ///
+    /// While the buffer is full, no more bytes of the response are encoded until the socket becomes writable.
+    ///
+    /// Metric: `fibers_http_server_buffer_full_stalls_total { kind="write" } <COUNTER>`
+    pub fn write_buffer_full_stalls(&self) -> u64 {
+        self.write_buffer_full_stalls.value() as u64
+    }
+
+    /// Histogram bucket of the largest number of bytes held by the read buffer of each connection.
+    ///
+    /// This is observed when a connection is closed.
+    ///
+    /// Metric: `fibers_http_server_buffer_peak_fill_bytes_bucket { kind="read", le="..." } <COUNTER>`
+    pub fn read_buffer_peak_fill_bytes_buckets(&self) -> &[Bucket] {
+        self.read_buffer_peak_fill_bytes.buckets()
+    }
+
+    /// Histogram bucket of the largest number of bytes held by the write buffer of each connection.
+    ///
+    /// This is observed when a connection is closed.
+    ///
+    /// Metric: `fibers_http_server_buffer_peak_fill_bytes_bucket { kind="write", le="..." } <COUNTER>`
+    pub fn write_buffer_peak_fill_bytes_buckets(&self) -> &[Bucket] {
+        self.write_buffer_peak_fill_bytes.buckets()
+    }
+
+    /// Number of requests that took longer than the threshold set by `ServerBuilder::slow_request_threshold`.
+    ///
+    /// `method` and `path` are the ones of the handler (i.e., `HandleRequest::METHOD` and `HandleRequest::PATH`).
+    ///
+    /// Metric: `fibers_http_server_slow_requests_total { method="...", path="..." } <COUNTER>`
+    pub fn slow_requests(&self, method: &str, path: &str) -> Option<u64> {
+        self.slow_requests
+            .load()
+            .iter()
+            .find(|(k, _)| k.0 == method && &*k.1 == path)
+            .map(|(_, c)| c.value() as u64)
+    }
+
+    /// Histogram of the time from when the head part of a request has been read
+    /// to when the first byte of the response has been flushed to the socket.
+    ///
+    /// Unlike `HandlerMetrics::request_duration_seconds_buckets`, this contains the time elapsed
+    /// for reading the request body and for waiting for the socket to become writable.
+    ///
+    /// `method` and `path` are the ones of the handler (i.e., `HandleRequest::METHOD` and `HandleRequest::PATH`).
+    /// The error responses returned before requests are dispatched to handlers are not observed.
+    ///
+    /// Metric: `fibers_http_server_time_to_first_byte_seconds_bucket
+    /// { le="...", method="...", path="..." } <COUNTER>`
+    pub fn time_to_first_byte_seconds(&self, method: &str, path: &str) -> Option<Histogram> {
+        self.time_to_first_byte_seconds
+            .load()
+            .iter()
+            .find(|(k, _)| k.0 == method && &*k.1 == path)
+            .map(|(_, h)| h.clone())
+    }
+
     pub(crate) fn new(mut builder: MetricBuilder) -> Self {
         builder.namespace("fibers_http_server");
         ServerMetrics {
@@ -100,22 +459,141 @@ impl ServerMetrics {
                 .help("Number of disconnected TCP clients")
                 .finish()
                 .expect("Never fails"),
+            rejected_tcp_clients: builder
+                .counter("rejected_tcp_clients_total")
+                .help("Number of TCP clients rejected by the access control lists")
+                .finish()
+                .expect("Never fails"),
+            pending_connections: builder
+                .gauge("pending_connections")
+                .help("Number of accepted connections waiting to be spawned")
+                .finish()
+                .expect("Never fails"),
+            accept_pauses: builder
+                .counter("accept_pauses_total")
+                .help("Number of times the server paused accepting new connections")
+                .finish()
+                .expect("Never fails"),
+            bind_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "bind")
+                .finish()
+                .expect("Never fails"),
+            accept_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "accept")
+                .finish()
+                .expect("Never fails"),
+            fd_exhaustion_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "accept")
+                .label("reason", "fd_exhaustion")
+                .finish()
+                .expect("Never fails"),
             read_request_head_errors: builder
                 .counter("errors_total")
                 .help("Number of errors")
                 .label("phase", "read_request_head")
                 .finish()
                 .expect("Never fails"),
+            request_line_too_long_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "request_line_too_long")
+                .finish()
+                .expect("Never fails"),
+            too_many_headers_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "too_many_headers")
+                .finish()
+                .expect("Never fails"),
+            header_field_too_large_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "header_field_too_large")
+                .finish()
+                .expect("Never fails"),
+            header_section_too_large_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "header_section_too_large")
+                .finish()
+                .expect("Never fails"),
+            obs_fold_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "obs_fold")
+                .finish()
+                .expect("Never fails"),
+            bare_cr_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "bare_cr")
+                .finish()
+                .expect("Never fails"),
+            content_length_with_transfer_encoding_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "content_length_with_transfer_encoding")
+                .finish()
+                .expect("Never fails"),
+            invalid_content_length_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "invalid_content_length")
+                .finish()
+                .expect("Never fails"),
+            invalid_transfer_encoding_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "read_request_head")
+                .label("reason", "invalid_transfer_encoding")
+                .finish()
+                .expect("Never fails"),
+            invalid_chunk_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "decode_request_body")
+                .label("reason", "invalid_chunk")
+                .finish()
+                .expect("Never fails"),
             parse_request_path_errors: builder
                 .counter("errors_total")
                 .help("Number of errors")
                 .label("phase", "parse_request_path")
                 .finish()
                 .expect("Never fails"),
-            dispatch_request_errors: builder
+            dispatch_not_found_errors: builder
                 .counter("errors_total")
                 .help("Number of errors")
                 .label("phase", "dispatch_request")
+                .label("reason", "not_found")
+                .finish()
+                .expect("Never fails"),
+            dispatch_method_not_allowed_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "dispatch_request")
+                .label("reason", "method_not_allowed")
+                .finish()
+                .expect("Never fails"),
+            dispatch_uri_too_long_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "dispatch_request")
+                .label("reason", "uri_too_long")
                 .finish()
                 .expect("Never fails"),
             initialize_handler_errors: builder
@@ -130,44 +608,297 @@ impl ServerMetrics {
                 .label("phase", "decode_request_body")
                 .finish()
                 .expect("Never fails"),
+            decompress_request_body_errors: builder
+                .counter("errors_total")
+                .help("Number of errors")
+                .label("phase", "decompress_request_body")
+                .finish()
+                .expect("Never fails"),
             write_response_errors: builder
                 .counter("errors_total")
                 .help("Number of errors")
                 .label("phase", "write_response")
                 .finish()
                 .expect("Never fails"),
+            read_request_head_timeouts: builder
+                .counter("timeouts_total")
+                .help("Number of timeouts")
+                .label("phase", "read_request_head")
+                .finish()
+                .expect("Never fails"),
+            read_request_body_timeouts: builder
+                .counter("timeouts_total")
+                .help("Number of timeouts")
+                .label("phase", "read_request_body")
+                .finish()
+                .expect("Never fails"),
+            write_response_timeouts: builder
+                .counter("timeouts_total")
+                .help("Number of timeouts")
+                .label("phase", "write_response")
+                .finish()
+                .expect("Never fails"),
+            throttled_requests: builder
+                .counter("throttled_requests_total")
+                .help("Number of requests rejected by the rate limiter")
+                .finish()
+                .expect("Never fails"),
+            csrf_rejected_requests: builder
+                .counter("csrf_rejected_requests_total")
+                .help("Number of requests rejected by the CSRF protection")
+                .finish()
+                .expect("Never fails"),
+            host_rejected_requests: builder
+                .counter("host_rejected_requests_total")
+                .help("Number of requests rejected by the validation of the `Host` header")
+                .finish()
+                .expect("Never fails"),
+            shed_requests: builder
+                .counter("shed_requests_total")
+                .help("Number of requests rejected by the load shedding")
+                .finish()
+                .expect("Never fails"),
+            warmup_rejected_requests: builder
+                .counter("warmup_rejected_requests_total")
+                .help("Number of requests rejected during the warmup")
+                .finish()
+                .expect("Never fails"),
+            client_aborted_reads: builder
+                .counter("client_aborted_requests_total")
+                .help("Number of requests aborted by clients")
+                .label("phase", "read")
+                .finish()
+                .expect("Never fails"),
+            client_aborted_writes: builder
+                .counter("client_aborted_requests_total")
+                .help("Number of requests aborted by clients")
+                .label("phase", "write")
+                .finish()
+                .expect("Never fails"),
+            cancelled_replies: builder
+                .counter("cancelled_replies_total")
+                .help(
+                    "Number of requests cancelled while the handlers were computing the responses",
+                )
+                .finish()
+                .expect("Never fails"),
+            read_buffer_high_watermark: builder
+                .gauge("buffer_high_watermark_bytes")
+                .help("The largest size of the buffers of connections")
+                .label("kind", "read")
+                .finish()
+                .expect("Never fails"),
+            write_buffer_high_watermark: builder
+                .gauge("buffer_high_watermark_bytes")
+                .help("The largest size of the buffers of connections")
+                .label("kind", "write")
+                .finish()
+                .expect("Never fails"),
+            read_buffer_full_stalls: builder
+                .counter("buffer_full_stalls_total")
+                .help("Number of times the buffers of connections became full")
+                .label("kind", "read")
+                .finish()
+                .expect("Never fails"),
+            write_buffer_full_stalls: builder
+                .counter("buffer_full_stalls_total")
+                .help("Number of times the buffers of connections became full")
+                .label("kind", "write")
+                .finish()
+                .expect("Never fails"),
+            read_buffer_peak_fill_bytes: BucketConfig::buffer_bytes()
+                .prepare_histogram(
+                    builder
+                        .histogram("buffer_peak_fill_bytes")
+                        .help("The largest number of bytes held by the buffer of each connection")
+                        .label("kind", "read"),
+                )
+                .finish()
+                .expect("Never fails"),
+            write_buffer_peak_fill_bytes: BucketConfig::buffer_bytes()
+                .prepare_histogram(
+                    builder
+                        .histogram("buffer_peak_fill_bytes")
+                        .help("The largest number of bytes held by the buffer of each connection")
+                        .label("kind", "write"),
+                )
+                .finish()
+                .expect("Never fails"),
+            slow_requests: Default::default(),
+            time_to_first_byte_seconds: Default::default(),
+            builder: Arc::new(Mutex::new(builder)),
+        }
+    }
+
+    pub(crate) fn update_buffer_high_watermarks(
+        &self,
+        read_buf_size: usize,
+        write_buf_size: usize,
+    ) {
+        if self.read_buffer_high_watermark.value() < read_buf_size as f64 {
+            self.read_buffer_high_watermark.set(read_buf_size as f64);
+        }
+        if self.write_buffer_high_watermark.value() < write_buf_size as f64 {
+            self.write_buffer_high_watermark.set(write_buf_size as f64);
+        }
+    }
+
+    pub(crate) fn observe_buffer_peak_fills(&self, read: usize, write: usize) {
+        self.read_buffer_peak_fill_bytes.observe(read as f64);
+        self.write_buffer_peak_fill_bytes.observe(write as f64);
+    }
+
+    pub(crate) fn increment_head_limit_violation(&self, violation: HeadLimitViolation) {
+        match violation {
+            HeadLimitViolation::RequestLineTooLong => self.request_line_too_long_errors.increment(),
+            HeadLimitViolation::TooManyHeaders => self.too_many_headers_errors.increment(),
+            HeadLimitViolation::HeaderFieldTooLarge => {
+                self.header_field_too_large_errors.increment()
+            }
+            HeadLimitViolation::HeaderSectionTooLarge => {
+                self.header_section_too_large_errors.increment()
+            }
+            HeadLimitViolation::ObsFold => self.obs_fold_errors.increment(),
+            HeadLimitViolation::BareCr => self.bare_cr_errors.increment(),
+        }
+    }
+
+    pub(crate) fn increment_smuggling_violation(&self, violation: SmugglingViolation) {
+        match violation {
+            SmugglingViolation::ContentLengthWithTransferEncoding => self
+                .content_length_with_transfer_encoding_errors
+                .increment(),
+            SmugglingViolation::InvalidContentLength => {
+                self.invalid_content_length_errors.increment()
+            }
+            SmugglingViolation::InvalidTransferEncoding => {
+                self.invalid_transfer_encoding_errors.increment()
+            }
+            SmugglingViolation::InvalidChunk => self.invalid_chunk_errors.increment(),
+        }
+    }
+
+    pub(crate) fn increment_slow_request(&self, route: &Route) {
+        let route = route.key();
+        if self
+            .slow_requests
+            .load()
+            .get(&route)
+            .map(|c| c.increment())
+            .is_none()
+        {
+            if let Ok(builder) = self.builder.try_lock() {
+                let counter = builder
+                    .counter("slow_requests_total")
+                    .help("Number of requests that took longer than the threshold")
+                    .label("method", route.0)
+                    .label("path", &route.1)
+                    .finish()
+                    .expect("Never fails");
+                self.slow_requests.update(|old| {
+                    let mut new = old.clone();
+                    new.insert(route.clone(), counter.clone());
+                    new
+                });
+            }
+            if let Some(c) = self.slow_requests.load().get(&route) {
+                c.increment()
+            }
+        }
+    }
+
+    pub(crate) fn observe_time_to_first_byte(&self, route: &Route, elapsed: Duration) {
+        let route = route.key();
+        let elapsed = prometrics::timestamp::duration_to_seconds(elapsed);
+        if self
+            .time_to_first_byte_seconds
+            .load()
+            .get(&route)
+            .map(|h| h.observe(elapsed))
+            .is_none()
+        {
+            if let Ok(builder) = self.builder.try_lock() {
+                let histogram = BucketConfig::default()
+                    .prepare_histogram(
+                        builder
+                            .histogram("time_to_first_byte_seconds")
+                            .help("Time to the first byte of responses")
+                            .label("method", route.0)
+                            .label("path", &route.1),
+                    )
+                    .finish()
+                    .expect("Never fails");
+                self.time_to_first_byte_seconds.update(|old| {
+                    let mut new = old.clone();
+                    new.insert(route.clone(), histogram.clone());
+                    new
+                });
+            }
+            if let Some(h) = self.time_to_first_byte_seconds.load().get(&route) {
+                h.observe(elapsed)
+            }
+        }
+    }
+
+    pub(crate) fn increment_dispatch_error(&self, error: &DispatchError) {
+        match *error {
+            DispatchError::NotFound => self.dispatch_not_found_errors.increment(),
+            DispatchError::MethodNotAllowed { .. } => {
+                self.dispatch_method_not_allowed_errors.increment()
+            }
+            DispatchError::UriTooLong { .. } => self.dispatch_uri_too_long_errors.increment(),
+            DispatchError::BadTarget => self.parse_request_path_errors.increment(),
         }
     }
 }
 
 /// A handler for exposing [prometheus] metrics.
 ///
+/// The exposition format is selected by the `Accept` header of each request:
+/// - `text/plain`: the Prometheus text format (the default)
+/// - `application/openmetrics-text`: the [OpenMetrics] text format
+/// - `application/vnd.google.protobuf`: the length-delimited `io.prometheus.client.MetricFamily` messages
+///   (available only if the `prost` feature is enabled)
+///
+/// If the `Accept-Encoding` header accepts `gzip`, the response body is compressed.
+///
+/// The requests to this handler are never shed by the load shedding (i.e., `Priority::Critical`).
+///
 /// [prometheus]: https://prometheus.io/
+/// [OpenMetrics]: https://openmetrics.io/
 #[derive(Debug)]
 pub struct MetricsHandler;
 impl HandleRequest for MetricsHandler {
     const METHOD: &'static str = "GET";
     const PATH: &'static str = "/metrics";
+    const PRIORITY: Priority = Priority::Critical;
 
     type ReqBody = ();
-    type ResBody = String;
+    type ResBody = Vec<u8>;
     type Decoder = BodyDecoder<NullDecoder>;
-    type Encoder = BodyEncoder<Utf8Encoder>;
+    type Encoder = BodyEncoder<BytesEncoder>;
     type Reply = Box<dyn Future<Item = Res<Self::ResBody>, Error = Never> + Send + 'static>;
 
-    fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let format = exposition::Format::negotiate(&req);
+        let gzip = exposition::accepts_gzip(&req);
         let (tx, rx) = oneshot::channel();
         thread::spawn(move || {
             let res = match prometrics::default_gatherer().lock() {
-                Err(e) => Res::new(Status::InternalServerError, e.to_string()),
+                Err(e) => Res::new(Status::InternalServerError, e.to_string().into_bytes()),
                 Ok(mut gatherer) => {
                     let metrics = gatherer.gather().to_text();
-                    Res::new(Status::Ok, metrics)
+                    exposition::response(metrics, format, gzip)
                 }
             };
             let _ = tx.send(res);
         });
-        Box::new(rx.or_else(|e| Ok(Res::new(Status::InternalServerError, e.to_string()))))
+        Box::new(rx.or_else(|e| {
+            Ok(Res::new(
+                Status::InternalServerError,
+                e.to_string().into_bytes(),
+            ))
+        }))
     }
 }
 
@@ -184,6 +915,7 @@ impl<H: HandleRequest> WithMetrics<H> {
     }
 
     /// Makes a new `WithMetrics` instance with the given `MetricBuilder`.
+    #[allow(clippy::self_named_constructors)]
     pub fn with_metrics(inner: H, metric_builder: MetricBuilder) -> Self {
         Self::with_metrics_and_bucket_config(inner, metric_builder, BucketConfig::default())
     }
@@ -205,7 +937,10 @@ impl<H: HandleRequest> WithMetrics<H> {
 }
 impl<H: HandleRequest> HandleRequest for WithMetrics<H> {
     const METHOD: &'static str = H::METHOD;
+    const METHODS: &'static [&'static str] = H::METHODS;
     const PATH: &'static str = H::PATH;
+    const PRIORITY: Priority = H::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = H::QUERY;
 
     type ReqBody = H::ReqBody;
     type ResBody = H::ResBody;
@@ -214,7 +949,18 @@ impl<H: HandleRequest> HandleRequest for WithMetrics<H> {
     type Reply = Time<H>;
 
     fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
-        Time::new(self.inner.handle_request(req), self.metrics.clone())
+        let clock = req
+            .extensions()
+            .get::<SharedClock>()
+            .cloned()
+            .unwrap_or_default();
+        let trace_id = req.extensions().get::<TraceId>().cloned();
+        Time::new(
+            self.inner.handle_request(req),
+            self.metrics.clone(),
+            clock,
+            trace_id,
+        )
     }
 
     fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
@@ -240,14 +986,23 @@ pub struct Time<H: HandleRequest> {
     future: H::Reply,
     start: Instant,
     metrics: HandlerMetrics,
+    clock: SharedClock,
+    trace_id: Option<TraceId>,
     _handler: PhantomData<H>,
 }
 impl<H: HandleRequest> Time<H> {
-    fn new(future: H::Reply, metrics: HandlerMetrics) -> Self {
+    fn new(
+        future: H::Reply,
+        metrics: HandlerMetrics,
+        clock: SharedClock,
+        trace_id: Option<TraceId>,
+    ) -> Self {
         Time {
             future,
-            start: Instant::now(),
+            start: clock.now(),
             metrics,
+            clock,
+            trace_id,
             _handler: PhantomData,
         }
     }
@@ -258,8 +1013,12 @@ impl<H: HandleRequest> Future for Time<H> {
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         if let Ok(Async::Ready(res)) = self.future.poll() {
-            let elapsed = prometrics::timestamp::duration_to_seconds(self.start.elapsed());
+            let elapsed = self.clock.now().duration_since(self.start);
+            let elapsed = prometrics::timestamp::duration_to_seconds(elapsed);
             self.metrics.request_duration_seconds.observe(elapsed);
+            if let Some(trace_id) = self.trace_id.take() {
+                self.metrics.record_exemplar(trace_id, elapsed);
+            }
             self.metrics.increment_status(res.status_code());
             Ok(Async::Ready(res))
         } else {
@@ -271,6 +1030,8 @@ impl<H: HandleRequest> Future for Time<H> {
 /// HTTP handler metrics.
 #[derive(Debug, Clone)]
 pub struct HandlerMetrics {
+    method: &'static str,
+    path: &'static str,
     requests: Arc<AtomicImmut<HashMap<u16, Counter>>>,
     request_duration_seconds: Histogram,
     builder: Arc<Mutex<MetricBuilder>>,
@@ -303,6 +1064,8 @@ impl HandlerMetrics {
             .label("method", H::METHOD)
             .label("path", H::PATH);
         HandlerMetrics {
+            method: H::METHOD,
+            path: H::PATH,
             requests: Default::default(),
             request_duration_seconds: bucket_config
                 .prepare_histogram(
@@ -342,9 +1105,112 @@ impl HandlerMetrics {
             }
         }
     }
+
+    fn record_exemplar(&self, trace_id: TraceId, value: f64) {
+        let upper_bound = self
+            .request_duration_seconds
+            .buckets()
+            .iter()
+            .map(|b| b.upper_bound())
+            .find(|&upper_bound| value <= upper_bound)
+            .unwrap_or(f64::INFINITY);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(prometrics::timestamp::duration_to_seconds)
+            .unwrap_or(0.0);
+        let exemplar = Exemplar {
+            trace_id,
+            value,
+            timestamp,
+        };
+        if let Ok(mut exemplars) = EXEMPLARS.lock() {
+            exemplars.insert((self.method, self.path, upper_bound.to_bits()), exemplar);
+        }
+    }
+}
+
+/// The trace ID of a request.
+///
+/// If a request has this value in its extensions (e.g., inserted by the function set by
+/// `ServerBuilder::request_hook`), `WithMetrics` attaches the ID to the bucket of
+/// the `request_duration_seconds` histogram that the duration of the request is observed in.
+/// These exemplars (the latest one for each bucket) are exposed by `MetricsHandler`
+/// only in the OpenMetrics format.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::metrics::TraceId;
+/// use fibers_http_server::ServerBuilder;
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.handler_metrics(true).request_hook(|req| {
+///     let trace_id = req
+///         .header()
+///         .get_field("traceparent")
+///         .and_then(TraceId::from_traceparent);
+///     if let Some(trace_id) = trace_id {
+///         req.extensions_mut().insert(trace_id);
+///     }
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceId(String);
+impl TraceId {
+    /// Makes a new `TraceId` instance.
+    pub fn new(id: &str) -> Self {
+        TraceId(id.to_owned())
+    }
+
+    /// Extracts the trace ID from the value of a `traceparent` header ([W3C Trace Context]).
+    ///
+    /// Returns `None` if the value is malformed.
+    ///
+    /// [W3C Trace Context]: https://www.w3.org/TR/trace-context/
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let mut fields = value.trim().split('-');
+        let is_hex =
+            |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+        if !is_hex(fields.next()?, 2) {
+            return None;
+        }
+        let trace_id = fields.next()?;
+        if !is_hex(trace_id, 32) || trace_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+        Some(TraceId(trace_id.to_ascii_lowercase()))
+    }
+
+    /// Returns the ID as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// An exemplar of the `request_duration_seconds` histograms.
+#[derive(Debug, Clone)]
+pub(crate) struct Exemplar {
+    pub trace_id: TraceId,
+    pub value: f64,
+    pub timestamp: f64,
+}
+
+// The latest exemplars keyed by the method, path and upper bound (in bits) of each bucket.
+static EXEMPLARS: Mutex<BTreeMap<(&'static str, &'static str, u64), Exemplar>> =
+    Mutex::new(BTreeMap::new());
+
+/// Returns the latest exemplar of the bucket of the `request_duration_seconds` histogram
+/// of the handler for `method` and `path`.
+pub(crate) fn exemplar(method: &str, path: &str, upper_bound: f64) -> Option<Exemplar> {
+    let exemplars = EXEMPLARS.lock().ok()?;
+    let exemplars: &BTreeMap<(&str, &str, u64), Exemplar> = &exemplars;
+    exemplars
+        .get(&(method, path, upper_bound.to_bits()))
+        .cloned()
 }
 
 /// Bucket configuration. Holds an increasing sequence of upper_bound.
+#[derive(Debug, Clone)]
 pub struct BucketConfig(Vec<f64>);
 
 impl Default for BucketConfig {
@@ -368,6 +1234,12 @@ impl BucketConfig {
         }
         Self(upper_bounds)
     }
+    // The buckets of the histograms of the numbers of bytes held by buffers.
+    fn buffer_bytes() -> Self {
+        let upper_bounds = (8..=20).step_by(2).map(|n| f64::from(1u32 << n)).collect();
+        Self::new(upper_bounds)
+    }
+
     // Build a histogram using this BucketConfig.
     fn prepare_histogram<'a>(
         &self,
@@ -383,6 +1255,10 @@ impl BucketConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::Hello;
+    use crate::{Reply, ServerBuilder};
+    use bytecodec::bytes::Utf8Encoder;
+    use futures::future::ok;
 
     #[test]
     fn bucket_config_new_succeeds() {
@@ -400,4 +1276,213 @@ mod tests {
         ];
         let _ = BucketConfig::new(upper_bounds);
     }
+
+    #[test]
+    fn trace_id_from_traceparent_works() {
+        let id =
+            TraceId::from_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01");
+        assert_eq!(
+            id.as_ref().map(|id| id.as_str()),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+
+        assert_eq!(TraceId::from_traceparent(""), None);
+        assert_eq!(TraceId::from_traceparent("00-4bf92f35"), None);
+        assert_eq!(
+            TraceId::from_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+    }
+
+    #[test]
+    fn slow_request_threshold_works() {
+        struct Slow;
+        impl HandleRequest for Slow {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/slow";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                let timeout = fibers::time::timer::timeout(Duration::from_millis(100));
+                Box::new(timeout.then(|_| Ok(Res::new(Status::Ok, "zzz".to_owned()))))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Slow).unwrap();
+        builder.add_handler(Hello).unwrap();
+        builder.slow_request_threshold(Duration::from_millis(50));
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.body(), b"hello");
+        assert_eq!(client.metrics().slow_requests("GET", "/hello"), None);
+
+        for _ in 0..2 {
+            let res = fibers_global::execute(client.get("/slow").unwrap()).unwrap();
+            assert_eq!(res.body(), b"zzz");
+        }
+        assert_eq!(client.metrics().slow_requests("GET", "/slow"), Some(2));
+    }
+
+    #[test]
+    fn time_to_first_byte_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        let client = builder.finish_test_client();
+        assert!(client
+            .metrics()
+            .time_to_first_byte_seconds("GET", "/hello")
+            .is_none());
+
+        for _ in 0..2 {
+            let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+            assert_eq!(res.body(), b"hello");
+        }
+        let histogram = client
+            .metrics()
+            .time_to_first_byte_seconds("GET", "/hello")
+            .unwrap();
+        assert_eq!(histogram.count(), 2);
+
+        // The requests that are not dispatched are not observed
+        let res = fibers_global::execute(client.get("/world").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 404);
+        assert!(client
+            .metrics()
+            .time_to_first_byte_seconds("GET", "/world")
+            .is_none());
+    }
+
+    #[test]
+    fn buffer_usage_metrics_work() {
+        struct Large;
+        impl HandleRequest for Large {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/large";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "a".repeat(64 * 1024))))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Large).unwrap();
+        builder.write_buffer_size(1024).max_write_buffer_size(1024);
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/large").unwrap()).unwrap();
+        assert_eq!(res.body().len(), 64 * 1024);
+        assert!(client.metrics().write_buffer_full_stalls() > 0);
+        assert_eq!(client.metrics().read_buffer_full_stalls(), 0);
+
+        // The peak fill levels are observed when the connection is closed
+        let buckets = client.metrics().write_buffer_peak_fill_bytes_buckets();
+        assert!(buckets.iter().any(|b| b.count() > 0));
+        let buckets = client.metrics().read_buffer_peak_fill_bytes_buckets();
+        assert!(buckets.iter().any(|b| b.count() > 0));
+    }
+
+    #[test]
+    fn handler_metrics_works() {
+        let mut metrics = prometrics::metrics::MetricBuilder::new();
+        metrics.label("test", "handler_metrics_works");
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.metrics(metrics).handler_metrics(true);
+        builder.add_handler(Hello).unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        let text = prometrics::default_gatherer()
+            .lock()
+            .unwrap()
+            .gather()
+            .to_text();
+        assert!(text.lines().any(|line| {
+            line.starts_with("fibers_http_server_handler_requests_total{")
+                && line.contains(r#"test="handler_metrics_works""#)
+                && line.contains(r#"path="/hello""#)
+                && line.contains(r#"status="200""#)
+                && line.ends_with(" 1")
+        }));
+    }
+
+    #[test]
+    fn exemplars_work() {
+        use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+        fn req(path: &str, field: (&str, &str)) -> Request<Vec<u8>> {
+            let mut req = Request::new(
+                Method::new("GET").unwrap(),
+                RequestTarget::new(path).unwrap(),
+                HttpVersion::V1_1,
+                Vec::new(),
+            );
+            req.header_mut()
+                .add_field(HeaderField::new(field.0, field.1).unwrap());
+            req
+        }
+
+        struct Traced;
+        impl HandleRequest for Traced {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/traced";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "traced".to_owned())))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.handler_metrics(true).request_hook(|req| {
+            let trace_id = req
+                .header()
+                .get_field("traceparent")
+                .and_then(TraceId::from_traceparent);
+            if let Some(trace_id) = trace_id {
+                req.extensions_mut().insert(trace_id);
+            }
+        });
+        builder.add_handler(Traced).unwrap();
+        builder.add_handler(MetricsHandler).unwrap();
+        let client = builder.finish_test_client();
+
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let res = fibers_global::execute(client.send(req("/traced", ("traceparent", traceparent))))
+            .unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        let accept = "application/openmetrics-text";
+        let res = fibers_global::execute(client.send(req("/metrics", ("Accept", accept)))).unwrap();
+        let body = String::from_utf8(res.body().clone()).unwrap();
+        let exemplars = body
+            .lines()
+            .filter(|line| {
+                line.starts_with("fibers_http_server_handler_request_duration_seconds_bucket{")
+                    && line.contains(r#"path="/traced""#)
+                    && line.contains(r#" # {trace_id="4bf92f3577b34da6a3ce929d0e0e4736"} "#)
+            })
+            .count();
+        assert_eq!(exemplars, 1);
+    }
 }
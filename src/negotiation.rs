@@ -0,0 +1,389 @@
+use crate::header::{Accept, ContentType, TypedHeader};
+use crate::{Error, HandleRequest, Priority, Req, Res, Result, Status};
+use bytecodec::marker::Never;
+use bytecodec::{self, ByteCount, Encode, Eos};
+use factory::Factory;
+use futures::{Async, Future, Poll};
+use httpcodec::{BodyEncode, HeaderField, HeaderMut};
+use std::fmt;
+use std::sync::Arc;
+
+type BoxBodyEncoder<T> = Box<dyn BodyEncode<Item = T> + Send + 'static>;
+type EncoderFactoryFn<T> = Arc<dyn Fn() -> BoxBodyEncoder<T> + Send + Sync + 'static>;
+
+/// A handler that selects the response body encoder based on the `Accept` header of each request.
+///
+/// Encoders are registered with their media types via `encoder` method.
+/// For each request, the registered media type that has the highest quality value in
+/// the `Accept` header is selected (ties are broken by the registration order),
+/// and it is set to the `Content-Type` header of the response
+/// unless the inner handler has already set the header.
+/// If the request has no (valid) `Accept` header, the first registered encoder is used.
+/// If no registered media type is acceptable, the `406 Not Acceptable` response is returned
+/// without invoking the inner handler.
+///
+/// Note that the encoder factory returned by `encoder_factory` method has to be
+/// specified to the `HandlerOptions` of the handler.
+///
+/// # Examples
+///
+/// ```
+/// use bytecodec::bytes::BytesEncoder;
+/// use fibers_http_server::{HandlerOptions, Negotiate, ServerBuilder};
+/// use fibers_http_server::metrics::MetricsHandler;
+/// use factory::DefaultFactory;
+/// use httpcodec::BodyEncoder;
+///
+/// # fn main() -> fibers_http_server::Result<()> {
+/// let handler = Negotiate::new(MetricsHandler)
+///     .encoder("text/plain", DefaultFactory::<BodyEncoder<BytesEncoder>>::new())?
+///     .encoder("text/html", DefaultFactory::<BodyEncoder<BytesEncoder>>::new())?;
+/// let options = HandlerOptions::new()
+///     .default_decoder()
+///     .encoder(handler.encoder_factory());
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.add_handler_with_options(handler, options)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Negotiate<H: HandleRequest> {
+    inner: H,
+    entries: Vec<(ContentType, EncoderFactoryFn<H::ResBody>)>,
+}
+impl<H: HandleRequest> Negotiate<H> {
+    /// Makes a new `Negotiate` instance that has no encoders.
+    pub fn new(inner: H) -> Self {
+        Negotiate {
+            inner,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Registers an encoder factory for the given media type (e.g., `application/json`).
+    ///
+    /// # Errors
+    ///
+    /// If `media_type` is not a valid media type, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn encoder<F, E>(mut self, media_type: &str, factory: F) -> Result<Self>
+    where
+        F: Factory<Item = E> + Send + Sync + 'static,
+        E: BodyEncode<Item = H::ResBody> + Send + 'static,
+    {
+        let content_type = track!(ContentType::parse(media_type))?;
+        let f = move || -> BoxBodyEncoder<H::ResBody> { Box::new(factory.create()) };
+        self.entries.push((content_type, Arc::new(f)));
+        Ok(self)
+    }
+
+    /// Returns the factory of the encoders registered to the handler.
+    pub fn encoder_factory(&self) -> NegotiatingEncoderFactory<H::ResBody> {
+        NegotiatingEncoderFactory {
+            factories: self.entries.iter().map(|e| Arc::clone(&e.1)).collect(),
+        }
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn inner_ref(&self) -> &H {
+        &self.inner
+    }
+
+    fn select<T>(&self, req: &Req<T>) -> Option<usize> {
+        let accept = match req.typed_header::<Accept>() {
+            Ok(Some(accept)) => accept,
+            _ => return (!self.entries.is_empty()).then_some(0),
+        };
+        let mut selected = None;
+        let mut max_quality = 0.0;
+        for (i, (content_type, _)) in self.entries.iter().enumerate() {
+            let quality = accept.quality(content_type.media_type());
+            if quality > max_quality {
+                selected = Some(i);
+                max_quality = quality;
+            }
+        }
+        selected
+    }
+
+    fn negotiated(&self, index: usize, res: Res<H::ResBody>) -> Res<Negotiated<H::ResBody>> {
+        negotiated(res, index, &self.entries[index].0)
+    }
+}
+impl<H: HandleRequest> HandleRequest for Negotiate<H> {
+    const METHOD: &'static str = H::METHOD;
+    const METHODS: &'static [&'static str] = H::METHODS;
+    const PATH: &'static str = H::PATH;
+    const PRIORITY: Priority = H::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = H::QUERY;
+
+    type ReqBody = H::ReqBody;
+    type ResBody = Negotiated<H::ResBody>;
+    type Decoder = H::Decoder;
+    type Encoder = NegotiatingEncoder<H::ResBody>;
+    type Reply = NegotiateReply<H>;
+
+    fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
+        if let Some(index) = self.select(req) {
+            self.inner
+                .handle_request_head(req)
+                .map(|res| self.negotiated(index, res))
+        } else {
+            Some(Res::new(Status::NotAcceptable, Negotiated::NotAcceptable))
+        }
+    }
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let index = self.select(&req).expect("Never fails");
+        NegotiateReply {
+            inner: self.inner.handle_request(req),
+            index,
+            content_type: self.entries[index].0.clone(),
+        }
+    }
+
+    fn handle_decoding_error(&self, req: Req<()>, error: &Error) -> Option<Res<Self::ResBody>> {
+        let index = self.select(&req)?;
+        self.inner
+            .handle_decoding_error(req, error)
+            .map(|res| self.negotiated(index, res))
+    }
+}
+impl<H: HandleRequest + fmt::Debug> fmt::Debug for Negotiate<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let media_types = self
+            .entries
+            .iter()
+            .map(|e| e.0.to_string())
+            .collect::<Vec<_>>();
+        f.debug_struct("Negotiate")
+            .field("inner", &self.inner)
+            .field("media_types", &media_types)
+            .finish()
+    }
+}
+
+/// `Future` that represents the reply from a handler wrapped by `Negotiate`.
+pub struct NegotiateReply<H: HandleRequest> {
+    inner: H::Reply,
+    index: usize,
+    content_type: ContentType,
+}
+impl<H: HandleRequest> Future for NegotiateReply<H> {
+    type Item = Res<Negotiated<H::ResBody>>;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(res) = self.inner.poll()? {
+            Ok(Async::Ready(negotiated(
+                res,
+                self.index,
+                &self.content_type,
+            )))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl<H: HandleRequest> fmt::Debug for NegotiateReply<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "NegotiateReply {{ index: {}, content_type: {:?}, .. }}",
+            self.index, self.content_type
+        )
+    }
+}
+
+fn negotiated<T>(res: Res<T>, index: usize, content_type: &ContentType) -> Res<Negotiated<T>> {
+    let mut res = Res(res.0.map_body(|body| Negotiated::Selected { index, body }));
+    if res.header().get_field(ContentType::NAME).is_none() {
+        res.add_typed_header(content_type).expect("Never fails");
+    }
+    res
+}
+
+/// A response body of a handler wrapped by `Negotiate`.
+#[derive(Debug)]
+pub enum Negotiated<T> {
+    /// The body that will be encoded by the encoder at the given index.
+    Selected {
+        /// The index of the selected encoder.
+        index: usize,
+
+        /// The body of the response.
+        body: T,
+    },
+
+    /// No registered media types are acceptable (i.e., the body is empty).
+    NotAcceptable,
+}
+
+/// Response body encoder used by `Negotiate`.
+pub struct NegotiatingEncoder<T> {
+    encoders: Vec<BoxBodyEncoder<T>>,
+    current: Option<usize>,
+}
+impl<T> Encode for NegotiatingEncoder<T> {
+    type Item = Negotiated<T>;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        if let Some(i) = self.current {
+            track!(self.encoders[i].encode(buf, eos))
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        track_assert!(self.is_idle(), bytecodec::ErrorKind::EncoderFull);
+        match item {
+            Negotiated::Selected { index, body } => {
+                let encoder = track_assert_some!(
+                    self.encoders.get_mut(index),
+                    bytecodec::ErrorKind::InvalidInput;
+                    index
+                );
+                track!(encoder.start_encoding(body))?;
+                self.current = Some(index);
+            }
+            Negotiated::NotAcceptable => {
+                self.current = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.current.is_none_or(|i| self.encoders[i].is_idle())
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.current
+            .map_or(ByteCount::Finite(0), |i| self.encoders[i].requiring_bytes())
+    }
+}
+impl<T> BodyEncode for NegotiatingEncoder<T> {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        if let Some(i) = self.current {
+            track!(self.encoders[i].update_header(header))
+        } else {
+            header.add_field(HeaderField::new("Content-Length", "0").expect("Never fails"));
+            Ok(())
+        }
+    }
+}
+impl<T> fmt::Debug for NegotiatingEncoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "NegotiatingEncoder {{ encoders.len: {}, current: {:?} }}",
+            self.encoders.len(),
+            self.current
+        )
+    }
+}
+
+/// Factory of `NegotiatingEncoder`.
+pub struct NegotiatingEncoderFactory<T> {
+    factories: Vec<EncoderFactoryFn<T>>,
+}
+impl<T> Factory for NegotiatingEncoderFactory<T> {
+    type Item = NegotiatingEncoder<T>;
+
+    fn create(&self) -> Self::Item {
+        NegotiatingEncoder {
+            encoders: self.factories.iter().map(|f| f()).collect(),
+            current: None,
+        }
+    }
+}
+impl<T> fmt::Debug for NegotiatingEncoderFactory<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "NegotiatingEncoderFactory {{ factories.len: {} }}",
+            self.factories.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use bytecodec::EncodeExt;
+    use factory::DefaultFactory;
+    use httpcodec::{BodyDecoder, BodyEncoder, HttpVersion, Method, Request, RequestTarget};
+    use url::Url;
+
+    struct Hello;
+    impl HandleRequest for Hello {
+        const METHOD: &'static str = "GET";
+        const PATH: &'static str = "/";
+
+        type ReqBody = ();
+        type ResBody = String;
+        type Decoder = BodyDecoder<NullDecoder>;
+        type Encoder = BodyEncoder<Utf8Encoder>;
+        type Reply = futures::future::FutureResult<Res<String>, Never>;
+
+        fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+            futures::finished(Res::new(Status::Ok, "hello".to_owned()))
+        }
+    }
+
+    fn req(accept: Option<&str>) -> Req<()> {
+        let mut inner = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        if let Some(accept) = accept {
+            inner
+                .header_mut()
+                .add_field(HeaderField::new("Accept", accept).unwrap());
+        }
+        let base_url = Url::parse("http://localhost/").unwrap();
+        track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()))
+    }
+
+    fn handler() -> Negotiate<Hello> {
+        let factory = || DefaultFactory::<BodyEncoder<Utf8Encoder>>::new();
+        let handler = track_try_unwrap!(Negotiate::new(Hello).encoder("text/plain", factory()));
+        track_try_unwrap!(handler.encoder("text/html", factory()))
+    }
+
+    #[test]
+    fn negotiate_works() {
+        let handler = handler();
+        for (accept, expected) in [
+            (None, "text/plain"),
+            (Some("text/html"), "text/html"),
+            (Some("text/*;q=0.5,text/html"), "text/html"),
+            (Some("*/*"), "text/plain"),
+        ] {
+            assert!(handler.handle_request_head(&req(accept)).is_none());
+            let res = handler.handle_request(req(accept)).wait().unwrap();
+            assert_eq!(res.status_code(), 200);
+            assert_eq!(res.header().get_field("Content-Type"), Some(expected));
+
+            let mut encoder = handler.encoder_factory().create();
+            let bytes = track_try_unwrap!(encoder.encode_into_bytes(res.0.into_body()));
+            assert_eq!(bytes, b"hello");
+        }
+    }
+
+    #[test]
+    fn not_acceptable_works() {
+        let handler = handler();
+        let res = handler.handle_request_head(&req(Some("application/json")));
+        let res = res.expect("406 response");
+        assert_eq!(res.status_code(), 406);
+
+        let mut encoder = handler.encoder_factory().create();
+        let bytes = track_try_unwrap!(encoder.encode_into_bytes(res.0.into_body()));
+        assert!(bytes.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::Route;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// `ConnectionObserver` allows for observing the traffic of each request processed by connections.
+///
+/// This can be used for bandwidth accounting, per-tenant quotas, etc.
+pub trait ConnectionObserver: Send + Sync + 'static {
+    /// Called when the whole response to a request has been encoded.
+    ///
+    /// Note that this is also called for error responses generated by the server itself.
+    fn on_request_completed(&self, traffic: &RequestTraffic);
+}
+
+/// The phase of a connection.
+///
+/// This is passed to the function set by `ServerBuilder::on_connection_error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionPhase {
+    /// Waiting for the next request (i.e., no bytes of the next request have been received).
+    Idle,
+
+    /// Reading the head part of a request.
+    ReadRequestHead,
+
+    /// Reading the body part of a request.
+    ReadRequestBody,
+
+    /// Waiting for the reply of a handler.
+    HandleRequest,
+
+    /// Writing a response.
+    WriteResponse,
+}
+
+/// Traffic statistics of a request.
+#[derive(Debug, Clone)]
+pub struct RequestTraffic {
+    pub(crate) connection_id: u64,
+    pub(crate) peer_addr: SocketAddr,
+    pub(crate) method: Option<String>,
+    pub(crate) path: Option<String>,
+    pub(crate) route: Option<Route>,
+    pub(crate) bytes_read: u64,
+    pub(crate) bytes_written: u64,
+}
+impl RequestTraffic {
+    pub(crate) fn new(connection_id: u64, peer_addr: SocketAddr) -> Self {
+        RequestTraffic {
+            connection_id,
+            peer_addr,
+            method: None,
+            path: None,
+            route: None,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    /// Returns the ID of the connection that the request was received on.
+    ///
+    /// The IDs are assigned to connections in ascending order by the server.
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// Returns the address of the client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Returns the method of the request.
+    ///
+    /// If the head part of the request could not be decoded, this returns `None`.
+    pub fn method(&self) -> Option<&str> {
+        self.method.as_deref()
+    }
+
+    /// Returns the path of the request.
+    ///
+    /// If the head part of the request could not be decoded, this returns `None`.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Returns the route that the request was dispatched to.
+    ///
+    /// Unlike `path`, this can be used as a label without unbounded cardinality.
+    /// If the request was not dispatched to any handlers, this returns `None`.
+    pub fn route(&self) -> Option<&Route> {
+        self.route.as_ref()
+    }
+
+    /// Returns the number of bytes of the request consumed by the server.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of bytes of the response produced by the server.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+}
+
+#[derive(Clone)]
+pub struct SharedObserver(Arc<dyn ConnectionObserver>);
+impl SharedObserver {
+    pub fn new<O: ConnectionObserver>(observer: O) -> Self {
+        SharedObserver(Arc::new(observer))
+    }
+
+    pub fn on_request_completed(&self, traffic: &RequestTraffic) {
+        self.0.on_request_completed(traffic);
+    }
+}
+impl fmt::Debug for SharedObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedObserver(_)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+    use std::sync::Mutex;
+
+    #[test]
+    fn connection_observer_works() {
+        #[derive(Clone, Default)]
+        struct Observer(Arc<Mutex<Vec<RequestTraffic>>>);
+        impl ConnectionObserver for Observer {
+            fn on_request_completed(&self, traffic: &RequestTraffic) {
+                self.0.lock().unwrap().push(traffic.clone());
+            }
+        }
+
+        let observer = Observer::default();
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.connection_observer(observer.clone());
+        let mut sim = builder.finish_simulation(0);
+
+        let req = b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let conn = sim.connect().unwrap();
+        conn.write(req);
+        sim.run().unwrap();
+        let received = conn.take_received();
+
+        let traffics = observer.0.lock().unwrap();
+        assert_eq!(traffics.len(), 1);
+        assert_eq!(traffics[0].peer_addr(), conn.peer_addr());
+        assert_eq!(traffics[0].method(), Some("GET"));
+        assert_eq!(traffics[0].path(), Some("/hello"));
+        assert_eq!(traffics[0].route().map(|r| r.path()), Some("/hello"));
+        assert_eq!(traffics[0].bytes_read(), req.len() as u64);
+        assert_eq!(traffics[0].bytes_written(), received.len() as u64);
+    }
+
+    #[test]
+    fn connection_ids_work() {
+        #[derive(Clone, Default)]
+        struct Observer(Arc<Mutex<Vec<RequestTraffic>>>);
+        impl ConnectionObserver for Observer {
+            fn on_request_completed(&self, traffic: &RequestTraffic) {
+                self.0.lock().unwrap().push(traffic.clone());
+            }
+        }
+
+        let observer = Observer::default();
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.connection_observer(observer.clone());
+        let mut sim = builder.finish_simulation(0);
+
+        let req = b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let first = sim.connect().unwrap();
+        first.write(req);
+        first.write(req);
+        sim.run().unwrap();
+
+        let second = sim.connect().unwrap();
+        second.write(req);
+        sim.run().unwrap();
+
+        let traffics = observer.0.lock().unwrap();
+        assert_eq!(traffics.len(), 3);
+        assert_eq!(traffics[0].connection_id(), traffics[1].connection_id());
+        assert!(traffics[0].connection_id() < traffics[2].connection_id());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::handle::{ReloadableOptions, ReloadableValues};
+use crate::server::ServerOptions;
+use httpcodec::DecodeOptions;
+use std::time::Duration;
+
+/// A snapshot of the effective options of a server.
+///
+/// This is created via `Server::options` method.
+/// The options updated via `ServerHandle` are reflected in the snapshots taken after the update.
+#[derive(Debug, Clone)]
+pub struct EffectiveOptions {
+    read_buffer_size: usize,
+    write_buffer_size: usize,
+    max_read_buffer_size: usize,
+    max_write_buffer_size: usize,
+    vectored_write_threshold: usize,
+    write_high_watermark: usize,
+    max_decompressed_request_body_size: Option<usize>,
+    slow_request_threshold: Option<Duration>,
+    decode_options: DecodeOptions,
+    values: ReloadableValues,
+    max_pending_connections: usize,
+    max_active_connections: usize,
+}
+impl EffectiveOptions {
+    pub(crate) fn new(
+        options: &ServerOptions,
+        max_pending_connections: usize,
+        max_active_connections: usize,
+    ) -> Self {
+        EffectiveOptions {
+            read_buffer_size: options.read_buffer_size,
+            write_buffer_size: options.write_buffer_size,
+            max_read_buffer_size: options.max_read_buffer_size,
+            max_write_buffer_size: options.max_write_buffer_size,
+            vectored_write_threshold: options.vectored_write_threshold,
+            write_high_watermark: options.write_high_watermark,
+            max_decompressed_request_body_size: options.max_decompressed_request_body_size,
+            slow_request_threshold: options.slow_request_threshold,
+            decode_options: options.decode_options.clone(),
+            values: options.reloadable.values(),
+            max_pending_connections,
+            max_active_connections,
+        }
+    }
+
+    /// Returns a copy of this snapshot that reflects the current values of `reloadable`.
+    pub(crate) fn reload(&self, reloadable: &ReloadableOptions) -> Self {
+        let mut options = self.clone();
+        options.values = reloadable.values();
+        options
+    }
+
+    /// Returns the initial size of the read buffer of a connection.
+    pub fn read_buffer_size(&self) -> usize {
+        self.read_buffer_size
+    }
+
+    /// Returns the initial size of the write buffer of a connection.
+    pub fn write_buffer_size(&self) -> usize {
+        self.write_buffer_size
+    }
+
+    /// Returns the maximum size up to which the read buffer of a connection can grow.
+    pub fn max_read_buffer_size(&self) -> usize {
+        self.max_read_buffer_size
+    }
+
+    /// Returns the maximum size up to which the write buffer of a connection can grow.
+    pub fn max_write_buffer_size(&self) -> usize {
+        self.max_write_buffer_size
+    }
+
+    /// Returns the minimum size of response bodies that are written to sockets directly.
+    pub fn vectored_write_threshold(&self) -> usize {
+        self.vectored_write_threshold
+    }
+
+    /// Returns the maximum number of response bytes that a connection writes without yielding.
+    pub fn write_high_watermark(&self) -> usize {
+        self.write_high_watermark
+    }
+
+    /// Returns the maximum size of decompressed request bodies.
+    ///
+    /// `None` means that the decompression of request bodies is disabled.
+    pub fn max_decompressed_request_body_size(&self) -> Option<usize> {
+        self.max_decompressed_request_body_size
+    }
+
+    /// Returns the threshold above which completed requests are regarded as slow.
+    pub fn slow_request_threshold(&self) -> Option<Duration> {
+        self.slow_request_threshold
+    }
+
+    /// Returns the options of the request decoder.
+    pub fn decode_options(&self) -> &DecodeOptions {
+        &self.decode_options
+    }
+
+    /// Returns the timeout for receiving the head part of a request.
+    pub fn read_request_head_timeout(&self) -> Option<Duration> {
+        self.values.read_request_head_timeout
+    }
+
+    /// Returns the timeout for receiving the body part of a request.
+    pub fn read_request_body_timeout(&self) -> Option<Duration> {
+        self.values.read_request_body_timeout
+    }
+
+    /// Returns the timeout for writing a response to the client.
+    pub fn write_response_timeout(&self) -> Option<Duration> {
+        self.values.write_response_timeout
+    }
+
+    /// Returns the maximum size of the request line of a request.
+    pub fn max_request_line_size(&self) -> usize {
+        self.values.head_limits.max_request_line_size
+    }
+
+    /// Returns the maximum number of header fields of a request.
+    pub fn max_header_count(&self) -> usize {
+        self.values.head_limits.max_header_count
+    }
+
+    /// Returns the maximum size of each header field of a request.
+    pub fn max_header_field_size(&self) -> usize {
+        self.values.head_limits.max_header_field_size
+    }
+
+    /// Returns `true` if the strict parsing mode is enabled, otherwise `false`.
+    pub fn strict_parsing(&self) -> bool {
+        self.values.head_limits.strict
+    }
+
+    /// Returns the maximum number of accepted connections waiting to be spawned.
+    pub fn max_pending_connections(&self) -> usize {
+        self.max_pending_connections
+    }
+
+    /// Returns the maximum number of connections that are being handled concurrently.
+    pub fn max_active_connections(&self) -> usize {
+        self.max_active_connections
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{ErrorKind, Result};
+
+/// Options for decoding the path segments of requests before dispatching them.
+///
+/// If this is enabled via `ServerBuilder::path_decoding`, the percent-encoded segments of the paths of requests
+/// are decoded, and the decoded segments are compared with the paths of handlers
+/// (e.g., the request for `/caf%C3%A9` is dispatched to the handler for `/café`).
+/// The decoded segments can be retrieved via `Req::decoded_path_segments`.
+///
+/// The requests having segments that are not valid UTF-8 strings after decoding
+/// are rejected with `400 Bad Request`.
+#[derive(Debug, Clone, Default)]
+pub struct PathDecoding {
+    allow_encoded_slash: bool,
+    allow_encoded_nul: bool,
+}
+impl PathDecoding {
+    /// Makes a new `PathDecoding` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to accept encoded slashes (`%2F`) in path segments.
+    ///
+    /// If accepted, a decoded segment can contain `/` (i.e., it never matches the paths of handlers
+    /// except for wildcards).
+    ///
+    /// By default, the requests having encoded slashes are rejected with `400 Bad Request`.
+    pub fn allow_encoded_slash(mut self, allowed: bool) -> Self {
+        self.allow_encoded_slash = allowed;
+        self
+    }
+
+    /// Sets whether to accept encoded NUL characters (`%00`) in path segments.
+    ///
+    /// By default, the requests having encoded NUL characters are rejected with `400 Bad Request`.
+    pub fn allow_encoded_nul(mut self, allowed: bool) -> Self {
+        self.allow_encoded_nul = allowed;
+        self
+    }
+
+    /// Returns `true` if `segment` can be produced by decoding a segment of a request path.
+    pub(crate) fn can_match(&self, segment: &str) -> bool {
+        segment != "."
+            && segment != ".."
+            && (self.allow_encoded_slash || !segment.contains('/'))
+            && (self.allow_encoded_nul || !segment.contains('\0'))
+    }
+
+    pub(crate) fn decode<'a, I>(&self, segments: I) -> Result<Vec<String>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut decoded = Vec::new();
+        for segment in segments {
+            let s = track_assert_some!(percent_decode(segment), ErrorKind::InvalidInput; segment);
+            track_assert!(
+                self.allow_encoded_slash || !s.contains('/'),
+                ErrorKind::InvalidInput,
+                "Encoded slash"; segment
+            );
+            track_assert!(
+                self.allow_encoded_nul || !s.contains('\0'),
+                ErrorKind::InvalidInput,
+                "Encoded NUL"; segment
+            );
+            decoded.push(s);
+        }
+        Ok(decoded)
+    }
+}
+
+/// Decodes the percent-encoded string.
+///
+/// Returns `None` if `s` contains malformed percent-encodings or the decoded bytes are not a valid UTF-8 string.
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hi = (iter.next()? as char).to_digit(16)?;
+            let lo = (iter.next()? as char).to_digit(16)?;
+            bytes.push((hi * 16 + lo) as u8);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+
+    #[test]
+    fn path_decoding_works() {
+        let decoding = PathDecoding::new();
+        assert_eq!(
+            decoding
+                .decode(["caf%C3%A9", "a%20b", "c"].iter().copied())
+                .unwrap(),
+            ["café", "a b", "c"]
+        );
+        assert!(decoding.decode(["a%2Fb"].iter().copied()).is_err());
+        assert!(decoding.decode(["a%00b"].iter().copied()).is_err());
+        assert!(decoding.decode(["%FF"].iter().copied()).is_err());
+        assert!(decoding.decode(["%2"].iter().copied()).is_err());
+
+        let decoding = PathDecoding::new()
+            .allow_encoded_slash(true)
+            .allow_encoded_nul(true);
+        assert_eq!(
+            decoding.decode(["a%2Fb", "%00"].iter().copied()).unwrap(),
+            ["a/b", "\0"]
+        );
+    }
+
+    #[test]
+    fn server_path_decoding_works() {
+        struct Cafe;
+        impl HandleRequest for Cafe {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/café/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let body = format!("{:?}", req.wildcards());
+                Box::new(ok(Res::new(Status::Ok, body)))
+            }
+        }
+
+        // Disabled
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Cafe).unwrap();
+        let client = builder.finish_test_client();
+        let res = fibers_global::execute(client.get("/caf%C3%A9/a%20b").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 404);
+
+        // Enabled
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Cafe).unwrap();
+        builder.path_decoding(PathDecoding::new());
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/caf%C3%A9/a%20b").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), br#"["a b"]"#);
+
+        let res = fibers_global::execute(client.get("/caf%C3%A9/a%2Fb").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 400);
+
+        let res = fibers_global::execute(client.get("/caf%C3%A9/%00").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 400);
+
+        let res = fibers_global::execute(client.get("/caf%C3%A9/%FF").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(client.metrics().parse_request_path_errors(), 3);
+    }
+}
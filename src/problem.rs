@@ -0,0 +1,225 @@
+use crate::reply::{IntoRes, IntoStatus};
+use crate::{Error, Res, Status};
+use httpcodec::HeaderField;
+use std::fmt::Write;
+
+pub(crate) const CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem details for HTTP APIs ([RFC 7807]).
+///
+/// This can be converted into an `application/problem+json` response by `Res::problem`.
+///
+/// [RFC 7807]: https://tools.ietf.org/html/rfc7807
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{ProblemDetails, Res, Status};
+///
+/// let problem = ProblemDetails::new(Status::Forbidden)
+///     .type_uri("https://example.com/probs/out-of-credit")
+///     .title("You do not have enough credit.")
+///     .detail("Your current balance is 30, but that costs 50.")
+///     .instance("/account/12345/msgs/abc");
+///
+/// let res = Res::problem(&problem);
+/// assert_eq!(res.status_code(), 403);
+/// assert_eq!(
+///     res.header().get_field("Content-Type"),
+///     Some("application/problem+json")
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProblemDetails {
+    status: Status,
+    type_uri: String,
+    title: String,
+    detail: Option<String>,
+    instance: Option<String>,
+}
+impl ProblemDetails {
+    /// Makes a new `ProblemDetails` instance.
+    ///
+    /// The type is initially set to `about:blank` and the title is the reason phrase of `status`.
+    pub fn new(status: Status) -> Self {
+        ProblemDetails {
+            status,
+            type_uri: "about:blank".to_owned(),
+            title: status.reason_phrase().to_owned(),
+            detail: None,
+            instance: None,
+        }
+    }
+
+    /// Sets the URI reference that identifies the problem type (the `type` member).
+    pub fn type_uri<T: Into<String>>(mut self, type_uri: T) -> Self {
+        self.type_uri = type_uri.into();
+        self
+    }
+
+    /// Sets the short, human-readable summary of the problem type (the `title` member).
+    pub fn title<T: Into<String>>(mut self, title: T) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Sets the human-readable explanation specific to this occurrence of the problem
+    /// (the `detail` member).
+    pub fn detail<T: Into<String>>(mut self, detail: T) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Sets the URI reference that identifies the specific occurrence of the problem
+    /// (the `instance` member).
+    pub fn instance<T: Into<String>>(mut self, instance: T) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Returns the status of the problem.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns the problem type.
+    pub fn get_type_uri(&self) -> &str {
+        &self.type_uri
+    }
+
+    /// Returns the title of the problem.
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    /// Returns the detail of the problem.
+    pub fn get_detail(&self) -> Option<&str> {
+        self.detail.as_ref().map(|x| x.as_str())
+    }
+
+    /// Returns the instance of the problem.
+    pub fn get_instance(&self) -> Option<&str> {
+        self.instance.as_ref().map(|x| x.as_str())
+    }
+
+    /// Serializes the problem into a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\"type\":");
+        push_json_string(&mut json, &self.type_uri);
+        json.push_str(",\"title\":");
+        push_json_string(&mut json, &self.title);
+        let _ = write!(json, ",\"status\":{}", self.status.code());
+        if let Some(ref detail) = self.detail {
+            json.push_str(",\"detail\":");
+            push_json_string(&mut json, detail);
+        }
+        if let Some(ref instance) = self.instance {
+            json.push_str(",\"instance\":");
+            push_json_string(&mut json, instance);
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// This trait allows for converting an error into a problem document.
+///
+/// Handlers whose errors implement this trait can render the errors as
+/// `application/problem+json` responses via `TryHandler::with_problem_details`.
+pub trait IntoProblem {
+    /// Converts `self` into a `ProblemDetails`.
+    fn into_problem(self) -> ProblemDetails;
+}
+impl IntoProblem for ProblemDetails {
+    fn into_problem(self) -> ProblemDetails {
+        self
+    }
+}
+impl IntoProblem for Status {
+    fn into_problem(self) -> ProblemDetails {
+        ProblemDetails::new(self)
+    }
+}
+impl IntoProblem for Error {
+    /// The status is decided in the same manner as `IntoStatus::into_status`.
+    ///
+    /// Note that the error message is not included in the resulting problem,
+    /// because it may contain internal information.
+    fn into_problem(self) -> ProblemDetails {
+        ProblemDetails::new(self.into_status())
+    }
+}
+
+impl<T: From<String>> IntoRes<T> for ProblemDetails {
+    fn into_res(self) -> Res<T> {
+        let mut res = Res::new(self.status, T::from(self.to_json()));
+        let field = unsafe { HeaderField::new_unchecked("Content-Type", CONTENT_TYPE) };
+        res.header_mut().add_field(field);
+        res
+    }
+}
+
+fn push_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn problem_details_works() {
+        let problem = ProblemDetails::new(Status::NotFound);
+        assert_eq!(
+            problem.to_json(),
+            r#"{"type":"about:blank","title":"Not Found","status":404}"#
+        );
+
+        let problem = ProblemDetails::new(Status::BadRequest)
+            .type_uri("https://example.com/probs/invalid")
+            .title("Invalid \"name\"")
+            .detail("line1\nline2\u{1}")
+            .instance("/users/1");
+        let res = Res::problem(&problem);
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/problem+json")
+        );
+        assert_eq!(
+            res.body(),
+            concat!(
+                r#"{"type":"https://example.com/probs/invalid","#,
+                r#""title":"Invalid \"name\"","status":400,"#,
+                r#""detail":"line1\nline2\u0001","instance":"/users/1"}"#
+            )
+        );
+
+        let problem = Error::from(ErrorKind::Other).into_problem();
+        assert_eq!(problem.status(), Status::InternalServerError);
+        assert_eq!(problem.get_detail(), None);
+
+        let res: Res<Vec<u8>> = problem.into_res();
+        assert_eq!(res.status_code(), 500);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/problem+json")
+        );
+    }
+}
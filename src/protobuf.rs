@@ -0,0 +1,195 @@
+//! Request/response body codecs for [Protocol Buffers] messages.
+//!
+//! This module is available only if the `prost` feature is enabled.
+//!
+//! [Protocol Buffers]: https://protobuf.dev/
+use crate::codec::{RawBodyDecoder, RawBodyEncoder};
+use bytecodec::{self, ByteCount, Decode, Encode, Eos};
+use httpcodec::{BodyDecode, BodyEncode, Header, HeaderMut};
+use prost::Message;
+use std::convert::Infallible;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// The media types accepted by `ProtobufDecoder`.
+pub const ACCEPTABLE_MEDIA_TYPES: &[&str] = &["application/x-protobuf", "application/protobuf"];
+
+/// The media type set by `ProtobufEncoder`.
+pub const MEDIA_TYPE: &str = "application/x-protobuf";
+
+/// A request body decoder for Protocol Buffers messages.
+///
+/// If the media type of the `Content-Type` header of a request is not one of `ACCEPTABLE_MEDIA_TYPES`,
+/// the `415 Unsupported Media Type` response will be returned.
+/// If the body is not a valid message, the `400 Bad Request` response will be returned.
+pub struct ProtobufDecoder<T> {
+    inner: RawBodyDecoder,
+    _message: PhantomData<fn() -> T>,
+}
+impl<T: Message + Default> ProtobufDecoder<T> {
+    /// Makes a new `ProtobufDecoder` instance.
+    pub fn new() -> Self {
+        ProtobufDecoder {
+            inner: RawBodyDecoder::new(ACCEPTABLE_MEDIA_TYPES),
+            _message: PhantomData,
+        }
+    }
+}
+impl<T: Message + Default> Decode for ProtobufDecoder<T> {
+    type Item = T;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        track!(self.inner.finish_decoding(|bytes| T::decode(bytes)))
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl<T: Message + Default> BodyDecode for ProtobufDecoder<T> {
+    fn initialize(&mut self, header: &Header) -> bytecodec::Result<()> {
+        track!(self.inner.initialize(header))
+    }
+}
+impl<T: Message + Default> Default for ProtobufDecoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> fmt::Debug for ProtobufDecoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProtobufDecoder {{ .. }}")
+    }
+}
+
+/// A response body encoder for Protocol Buffers messages.
+///
+/// The `Content-Type` header of a response is set to `MEDIA_TYPE`
+/// unless the handler has already set the header.
+pub struct ProtobufEncoder<T> {
+    inner: RawBodyEncoder,
+    _message: PhantomData<fn(T)>,
+}
+impl<T: Message> ProtobufEncoder<T> {
+    /// Makes a new `ProtobufEncoder` instance.
+    pub fn new() -> Self {
+        ProtobufEncoder {
+            inner: RawBodyEncoder::new(MEDIA_TYPE),
+            _message: PhantomData,
+        }
+    }
+}
+impl<T: Message> Encode for ProtobufEncoder<T> {
+    type Item = T;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.encode(buf, eos))
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        track!(self
+            .inner
+            .start_encoding(item, |m| Ok::<_, Infallible>(m.encode_to_vec())))
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl<T: Message> BodyEncode for ProtobufEncoder<T> {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        track!(self.inner.update_header(header))
+    }
+}
+impl<T: Message> Default for ProtobufEncoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> fmt::Debug for ProtobufEncoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProtobufEncoder {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use futures::future::ok;
+    use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Greeting {
+        #[prost(string, tag = "1")]
+        name: String,
+    }
+
+    struct Greet;
+    impl HandleRequest for Greet {
+        const METHOD: &'static str = "POST";
+        const PATH: &'static str = "/greet";
+
+        type ReqBody = Greeting;
+        type ResBody = Greeting;
+        type Decoder = ProtobufDecoder<Greeting>;
+        type Encoder = ProtobufEncoder<Greeting>;
+        type Reply = Reply<Self::ResBody>;
+
+        fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+            let name = format!("Hello {}", req.into_body().name);
+            Box::new(ok(Res::new(Status::Ok, Greeting { name })))
+        }
+    }
+
+    fn req(content_type: &str, body: Vec<u8>) -> Request<Vec<u8>> {
+        let mut req = Request::new(
+            Method::new("POST").unwrap(),
+            RequestTarget::new("/greet").unwrap(),
+            HttpVersion::V1_1,
+            body,
+        );
+        req.header_mut()
+            .add_field(HeaderField::new("Content-Type", content_type).unwrap());
+        req
+    }
+
+    #[test]
+    fn protobuf_codec_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Greet).unwrap();
+        let client = builder.finish_test_client();
+
+        let body = Greeting {
+            name: "foo".to_owned(),
+        }
+        .encode_to_vec();
+        let res = fibers_global::execute(client.send(req("application/x-protobuf", body))).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/x-protobuf")
+        );
+        assert_eq!(Greeting::decode(&res.body()[..]).unwrap().name, "Hello foo");
+
+        let body = Greeting::default().encode_to_vec();
+        let res = fibers_global::execute(client.send(req("application/json", body))).unwrap();
+        assert_eq!(res.status_code(), 415);
+
+        let res =
+            fibers_global::execute(client.send(req("application/protobuf", vec![0xFF]))).unwrap();
+        assert_eq!(res.status_code(), 400);
+    }
+}
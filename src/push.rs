@@ -0,0 +1,275 @@
+use crate::clock::{Clock, SharedClock};
+use crate::{Error, ErrorKind, Result};
+use fibers::sync::oneshot;
+use futures::future::{loop_fn, ok, Either, Loop};
+use futures::Future;
+use slog::Logger;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Periodic export of the gathered metrics (see `ServerBuilder::metrics_push`).
+///
+/// This is useful for the environments where the metrics cannot be scraped
+/// via `metrics::MetricsHandler` (e.g., the server is behind a NAT).
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{MetricsPush, ServerBuilder};
+/// use std::time::Duration;
+///
+/// let push = MetricsPush::gateway("127.0.0.1:9091".parse().unwrap(), "my_job")
+///     .grouping_label("instance", "server-1")
+///     .interval(Duration::from_secs(30));
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.metrics_push(push);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MetricsPush {
+    target: PushTarget,
+    interval: Duration,
+    timeout: Duration,
+}
+impl MetricsPush {
+    /// Makes a new `MetricsPush` instance that pushes the metrics to the Prometheus [Pushgateway]
+    /// listening on `addr`.
+    ///
+    /// The metrics are pushed in the text format by `PUT /metrics/job/{job}`
+    /// (i.e., all the metrics of the grouping key are replaced).
+    ///
+    /// [Pushgateway]: https://github.com/prometheus/pushgateway
+    pub fn gateway(addr: SocketAddr, job: &str) -> Self {
+        Self::new(PushTarget::Gateway {
+            addr,
+            grouping_key: vec![("job".to_owned(), job.to_owned())],
+        })
+    }
+
+    /// Makes a new `MetricsPush` instance that invokes `f` with the metrics in the text format.
+    ///
+    /// `f` is invoked on a thread other than the ones of the server, so it may block.
+    pub fn callback<F>(f: F) -> Self
+    where
+        F: Fn(String) + Send + Sync + 'static,
+    {
+        Self::new(PushTarget::Callback(Arc::new(f)))
+    }
+
+    /// Adds a label to the grouping key (e.g., `instance`) of the metrics pushed to the Pushgateway.
+    ///
+    /// This has no effect on the instances made by `callback`.
+    pub fn grouping_label(mut self, name: &str, value: &str) -> Self {
+        if let PushTarget::Gateway {
+            ref mut grouping_key,
+            ..
+        } = self.target
+        {
+            grouping_key.push((name.to_owned(), value.to_owned()));
+        }
+        self
+    }
+
+    /// Sets the interval between pushes.
+    ///
+    /// The default value is `15` seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the timeout of each push to the Pushgateway.
+    ///
+    /// The default value is `10` seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn new(target: PushTarget) -> Self {
+        MetricsPush {
+            target,
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Returns the future that pushes the metrics periodically until `is_alive` becomes `false`.
+    pub(crate) fn into_task(
+        self,
+        logger: Logger,
+        clock: SharedClock,
+        is_alive: Arc<AtomicBool>,
+    ) -> impl Future<Item = (), Error = ()> + Send + 'static {
+        loop_fn(self, move |this| {
+            let logger = logger.clone();
+            let is_alive = Arc::clone(&is_alive);
+            clock.sleep(this.interval).then(move |_| {
+                if !is_alive.load(Ordering::SeqCst) {
+                    return Either::A(ok(Loop::Break(())));
+                }
+                let (tx, rx) = oneshot::channel();
+                let push = this.clone();
+                thread::spawn(move || {
+                    let _ = tx.send(push.push_once());
+                });
+                Either::B(rx.then(move |result| {
+                    match result {
+                        Err(e) => warn!(logger, "Cannot push metrics: {}", e),
+                        Ok(Err(e)) => warn!(logger, "Cannot push metrics: {}", e),
+                        Ok(Ok(())) => debug!(logger, "Pushed metrics"),
+                    }
+                    Ok(Loop::Continue(this))
+                }))
+            })
+        })
+    }
+
+    fn push_once(&self) -> Result<()> {
+        let metrics = match prometrics::default_gatherer().lock() {
+            Err(e) => track_panic!(ErrorKind::Other, "{}", e),
+            Ok(mut gatherer) => gatherer.gather().to_text(),
+        };
+        match self.target {
+            PushTarget::Callback(ref f) => {
+                f(metrics);
+                Ok(())
+            }
+            PushTarget::Gateway {
+                addr,
+                ref grouping_key,
+            } => track!(self.put(addr, grouping_key, &metrics)),
+        }
+    }
+
+    fn put(&self, addr: SocketAddr, grouping_key: &[(String, String)], body: &str) -> Result<()> {
+        let mut path = "/metrics".to_owned();
+        for (name, value) in grouping_key {
+            // The values are encoded in base64 so that they can contain slashes
+            path.push_str(&format!("/{}@base64/{}", name, base64url(value)));
+        }
+        let req = format!(
+            "PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            addr,
+            body.len(),
+            body
+        );
+
+        let mut stream =
+            track!(TcpStream::connect_timeout(&addr, self.timeout).map_err(Error::from))?;
+        track!(stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(Error::from))?;
+        track!(stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(Error::from))?;
+        track!(stream.write_all(req.as_bytes()).map_err(Error::from))?;
+
+        let mut res = Vec::new();
+        track!(stream.read_to_end(&mut res).map_err(Error::from))?;
+        let res = String::from_utf8_lossy(&res);
+        let status = res.split(' ').nth(1).unwrap_or("");
+        track_assert!(
+            status.starts_with('2'),
+            ErrorKind::Other,
+            "Unexpected response from the Pushgateway: {:?}",
+            res.lines().next().unwrap_or("")
+        );
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum PushTarget {
+    Gateway {
+        addr: SocketAddr,
+        grouping_key: Vec<(String, String)>,
+    },
+    Callback(Arc<dyn Fn(String) + Send + Sync + 'static>),
+}
+impl fmt::Debug for PushTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PushTarget::Gateway {
+                addr,
+                ref grouping_key,
+            } => write!(
+                f,
+                "Gateway {{ addr: {}, grouping_key: {:?} }}",
+                addr, grouping_key
+            ),
+            PushTarget::Callback(_) => write!(f, "Callback(_)"),
+        }
+    }
+}
+
+/// Encodes `s` in the URL-safe base64 (an empty string is encoded as `=` as the Pushgateway requires).
+fn base64url(s: &str) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    if s.is_empty() {
+        return "=".to_owned();
+    }
+    let mut encoded = String::new();
+    for chunk in s.as_bytes().chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (u32::from(b) << (16 - 8 * i)));
+        for i in 0..=chunk.len() {
+            encoded.push(TABLE[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+        }
+        for _ in chunk.len()..3 {
+            encoded.push('=');
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+    use std::sync::Mutex;
+
+    #[test]
+    fn base64url_works() {
+        assert_eq!(base64url(""), "=");
+        assert_eq!(base64url("f"), "Zg==");
+        assert_eq!(base64url("fo"), "Zm8=");
+        assert_eq!(base64url("foo"), "Zm9v");
+        assert_eq!(base64url("/path/to?"), "L3BhdGgvdG8_");
+    }
+
+    #[test]
+    fn metrics_push_works() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.metrics_push(
+            MetricsPush::callback(move |metrics| {
+                let _ = tx.lock().unwrap().send(metrics);
+            })
+            .interval(Duration::from_millis(50)),
+        );
+        let server = builder.finish(fibers_global::handle());
+
+        let metrics = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(metrics.contains("fibers_http_server_"));
+
+        // The task stops when the server is dropped
+        drop(server);
+        let error = (0..100)
+            .find_map(|_| rx.recv_timeout(Duration::from_secs(5)).err())
+            .unwrap();
+        assert_eq!(error, std::sync::mpsc::RecvTimeoutError::Disconnected);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Configuration of the per-client rate limiting.
+///
+/// Requests are throttled by using the token bucket algorithm keyed by the IP address of the client
+/// (and optionally by the route of the handler).
+/// If a request exceeds the limit, the `429 Too Many Requests` response will be returned.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    requests_per_second: f64,
+    burst: u32,
+    per_route: bool,
+}
+impl RateLimit {
+    /// Makes a new `RateLimit` instance.
+    ///
+    /// `requests_per_second` is the rate at which tokens are refilled,
+    /// and `burst` is the capacity of each bucket.
+    ///
+    /// # Panics
+    ///
+    /// If `requests_per_second` is not positive or `burst` is zero, this function will panic.
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        assert!(
+            requests_per_second > 0.0,
+            "requests_per_second must be positive: {}",
+            requests_per_second
+        );
+        assert!(burst > 0, "burst must be positive");
+        RateLimit {
+            requests_per_second,
+            burst,
+            per_route: false,
+        }
+    }
+
+    /// Specifies that buckets are also keyed by the route (i.e., the method and path) of handlers.
+    pub fn per_route(mut self) -> Self {
+        self.per_route = true;
+        self
+    }
+}
+
+type BucketKey = (IpAddr, Option<(&'static str, Arc<str>)>);
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimit,
+    buckets: Arc<Mutex<Buckets>>,
+}
+impl RateLimiter {
+    const PURGE_INTERVAL: u64 = 1024;
+
+    pub fn new(config: RateLimit) -> Self {
+        RateLimiter {
+            config,
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+        }
+    }
+
+    /// Tries to take a token from the bucket associated with the given client and route.
+    ///
+    /// If the bucket is empty, the duration to wait before retrying is returned as `Err`.
+    pub fn acquire(
+        &self,
+        client: IpAddr,
+        route: (&'static str, Arc<str>),
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let route = if self.config.per_route {
+            Some(route)
+        } else {
+            None
+        };
+        let key = (client, route);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        buckets.checks += 1;
+        if buckets.checks.is_multiple_of(Self::PURGE_INTERVAL) {
+            let config = &self.config;
+            buckets
+                .entries
+                .retain(|_, b| b.available_tokens(config, now) < f64::from(config.burst));
+        }
+
+        let config = &self.config;
+        let bucket = buckets.entries.entry(key).or_insert_with(|| Bucket {
+            tokens: f64::from(config.burst),
+            last_refill: now,
+        });
+        bucket.tokens = bucket.available_tokens(config, now);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / config.requests_per_second;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    entries: HashMap<BucketKey, Bucket>,
+    checks: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+impl Bucket {
+    fn available_tokens(&self, config: &RateLimit, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        let tokens = self.tokens + elapsed * config.requests_per_second;
+        tokens.min(f64::from(config.burst))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+
+    #[test]
+    fn rate_limiter_works() {
+        let limiter = RateLimiter::new(RateLimit::new(1.0, 2));
+        let now = Instant::now();
+        let client0 = IpAddr::from([127, 0, 0, 1]);
+        let client1 = IpAddr::from([127, 0, 0, 2]);
+
+        assert!(limiter
+            .acquire(client0, ("GET", Arc::from("/foo")), now)
+            .is_ok());
+        assert!(limiter
+            .acquire(client0, ("GET", Arc::from("/bar")), now)
+            .is_ok());
+        assert!(limiter
+            .acquire(client0, ("GET", Arc::from("/foo")), now)
+            .is_err());
+        assert!(limiter
+            .acquire(client1, ("GET", Arc::from("/foo")), now)
+            .is_ok());
+    }
+
+    #[test]
+    fn per_route_rate_limiter_works() {
+        let limiter = RateLimiter::new(RateLimit::new(1.0, 1).per_route());
+        let now = Instant::now();
+        let client = IpAddr::from([127, 0, 0, 1]);
+
+        assert!(limiter
+            .acquire(client, ("GET", Arc::from("/foo")), now)
+            .is_ok());
+        assert!(limiter
+            .acquire(client, ("GET", Arc::from("/bar")), now)
+            .is_ok());
+        assert!(limiter
+            .acquire(client, ("PUT", Arc::from("/foo")), now)
+            .is_ok());
+
+        let wait = limiter
+            .acquire(client, ("GET", Arc::from("/foo")), now)
+            .err()
+            .unwrap();
+        assert!(wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn rate_limit_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.rate_limit(RateLimit::new(0.1, 1));
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        assert_eq!(
+            String::from_utf8(conn.take_received()).unwrap(),
+            "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\nhello"
+        );
+
+        conn.write(b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(received.contains("Retry-After: 10\r\n"), "{}", received);
+    }
+}
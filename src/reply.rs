@@ -0,0 +1,137 @@
+//! Helpers for making `Reply` instances.
+//!
+//! # Examples
+//!
+//! ```
+//! use fibers_http_server::{reply, Reply, Res, Status};
+//!
+//! let _: Reply<&str> = reply::ok(Res::new(Status::Ok, "hello"));
+//! let _: Reply<String> = reply::lazy(|| Res::new(Status::Ok, "hello".to_owned()));
+//! ```
+use crate::{Error, ErrorKind, Reply, Res, Status};
+use bytecodec::marker::Never;
+use futures::{self, Future};
+
+/// This trait allows for converting an error into the status of the corresponding error response.
+pub trait IntoStatus {
+    /// Converts `self` into a `Status`.
+    fn into_status(self) -> Status;
+}
+impl IntoStatus for Status {
+    fn into_status(self) -> Status {
+        self
+    }
+}
+impl IntoStatus for Error {
+    /// `ErrorKind::InvalidInput` is converted into `Status::BadRequest`,
+    /// and the others are converted into `Status::InternalServerError`.
+    fn into_status(self) -> Status {
+        match *self.kind() {
+            ErrorKind::InvalidInput => Status::BadRequest,
+            ErrorKind::Other => Status::InternalServerError,
+        }
+    }
+}
+
+/// This trait allows for converting an error into an error response.
+///
+/// This is implemented for all `IntoStatus` types, and the resulting responses have
+/// the reason phrases of the statuses as the bodies.
+pub trait IntoRes<T> {
+    /// Converts `self` into a `Res<T>`.
+    fn into_res(self) -> Res<T>;
+}
+impl<T, E> IntoRes<T> for E
+where
+    T: From<&'static str>,
+    E: IntoStatus,
+{
+    fn into_res(self) -> Res<T> {
+        let status = self.into_status();
+        Res::new(status, T::from(status.reason_phrase()))
+    }
+}
+
+/// Makes a `Reply` that immediately returns the given response.
+pub fn ok<T>(res: Res<T>) -> Reply<T>
+where
+    T: Send + 'static,
+{
+    Box::new(futures::finished(res))
+}
+
+/// Makes a `Reply` from the given future.
+pub fn from_future<F, T>(future: F) -> Reply<T>
+where
+    F: Future<Item = Res<T>, Error = Never> + Send + 'static,
+{
+    Box::new(future)
+}
+
+/// Makes a `Reply` that calls `f` when it is polled for the first time.
+pub fn lazy<F, T>(f: F) -> Reply<T>
+where
+    F: FnOnce() -> Res<T> + Send + 'static,
+    T: Send + 'static,
+{
+    Box::new(futures::lazy(move || Ok(f())))
+}
+
+/// Makes a `Reply` from the given result.
+///
+/// If `result` is an error, it is converted into a response by `IntoRes::into_res`.
+pub fn from_result<T, E>(result: Result<Res<T>, E>) -> Reply<T>
+where
+    T: Send + 'static,
+    E: IntoRes<T>,
+{
+    ok(result.unwrap_or_else(IntoRes::into_res))
+}
+
+/// Makes a `Reply` from the given fallible future.
+///
+/// If `future` fails, the error is converted into a response in the same manner as `from_result`.
+pub fn try_future<F, T>(future: F) -> Reply<T>
+where
+    F: Future<Item = Res<T>> + Send + 'static,
+    F::Error: IntoRes<T>,
+    T: Send + 'static,
+{
+    Box::new(future.or_else(|e| Ok(e.into_res())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wait<T>(reply: Reply<T>) -> Res<T> {
+        reply.wait().unwrap_or_else(|_| unreachable!())
+    }
+
+    #[test]
+    fn reply_helpers_work() {
+        let res = wait(ok(Res::new(Status::Ok, "foo")));
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(*res.body(), "foo");
+
+        let res = wait(lazy(|| Res::new(Status::Created, "bar")));
+        assert_eq!(res.status_code(), 201);
+
+        let res = wait(from_future(futures::finished(Res::new(Status::Ok, ()))));
+        assert_eq!(res.status_code(), 200);
+
+        let result: Result<_, Status> = Err(Status::NotFound);
+        let res = wait(from_result::<String, _>(result));
+        assert_eq!(res.status_code(), 404);
+        assert_eq!(res.body(), "Not Found");
+
+        let future = futures::failed::<Res<Vec<u8>>, _>(Error::from(ErrorKind::InvalidInput));
+        let res = wait(try_future(future));
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(res.body(), b"Bad Request");
+
+        let future = futures::failed::<Res<Vec<u8>>, _>(Error::from(ErrorKind::Other));
+        let res = wait(try_future(future));
+        assert_eq!(res.status_code(), 500);
+    }
+}
@@ -1,6 +1,14 @@
-use crate::{Error, ErrorKind, Result};
-use httpcodec::{Header, HttpVersion, Request};
+use crate::extensions::Extensions;
+use crate::header::{self, TypedHeader};
+use crate::path_decoding::PathDecoding;
+use crate::server::ResponseHook;
+use crate::{Error, ErrorKind, Res, Result, Route, Status};
+use httpcodec::{Header, HttpVersion, Method, Request};
+use slog::{Discard, Logger};
 use std::fmt;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
 use url::Url;
 
 /// HTTP request.
@@ -8,30 +16,193 @@ use url::Url;
 pub struct Req<T> {
     inner: Request<T>,
     url: Url,
+    peer_addr: SocketAddr,
+    overridden_method: Option<String>,
+    extensions: Extensions,
+    state: Arc<Extensions>,
+    logger: Logger,
+    res_fields: Vec<(String, String)>,
+    default_res_fields: Arc<Vec<(String, String)>>,
+    response_hook: Option<ResponseHook>,
+    captures: PathCaptures,
+    decoded_segments: Option<Vec<String>>,
 }
 impl<T> Req<T> {
     /// Returns the method of the request.
+    ///
+    /// If the method has been overridden by the `X-HTTP-Method-Override` header
+    /// (see `ServerBuilder::method_override`), the overriding method is returned.
     pub fn method(&self) -> &str {
+        self.overridden_method
+            .as_deref()
+            .unwrap_or_else(|| self.original_method())
+    }
+
+    /// Returns the method in the request line of the request.
+    pub fn original_method(&self) -> &str {
         self.inner.method().as_str()
     }
 
     /// Returns the URL of the request.
     ///
-    /// Note that the peer address of the client socket is used as the host and port of the URL.
+    /// The scheme, host and port of the URL are taken from the canonical base URL of the server
+    /// (see `ServerBuilder::base_url`) if it is set.
+    /// Otherwise, they are reconstructed from the forwarding headers of trusted proxies
+    /// (see `ServerBuilder::trusted_proxies`) or derived from the local address of the server socket.
     pub fn url(&self) -> &Url {
         &self.url
     }
 
+    /// Makes a redirect response to `location`.
+    ///
+    /// `location` is resolved against the URL of the request (see `Req::url`),
+    /// so the `Location` header of the response always contains an absolute URL.
+    ///
+    /// # Errors
+    ///
+    /// If `status` is not a redirection status (i.e., `3xx`) or `location` cannot be resolved,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn redirect<B: Default>(&self, status: Status, location: &str) -> Result<Res<B>> {
+        let code = status.code();
+        track_assert!(
+            (300..400).contains(&code),
+            ErrorKind::InvalidInput,
+            "status={}",
+            code
+        );
+        let url = track!(
+            self.url.join(location).map_err(Error::from),
+            "location={:?}",
+            location
+        )?;
+        let mut res = Res::new(status, B::default());
+        track!(res.add_header("Location", url.as_str()))?;
+        Ok(res)
+    }
+
+    /// Returns the percent-decoded segments of the path of the request.
+    ///
+    /// If the path decoding is disabled (see `ServerBuilder::path_decoding`), this returns `None`.
+    pub fn decoded_path_segments(&self) -> Option<&[String]> {
+        self.decoded_segments.as_deref()
+    }
+
+    /// Returns the path segments matched by the `*` wildcards in the path of the handler.
+    ///
+    /// The segments are ordered by their positions in the path, and are percent-encoded as `Url::path`
+    /// unless the path decoding is enabled (see `ServerBuilder::path_decoding`).
+    /// For example, if the path of the handler is `/users/*/posts/*`,
+    /// the wildcards of the request for `/users/foo/posts/10` are `["foo", "10"]`.
+    pub fn wildcards(&self) -> Vec<&str> {
+        let path = self.url.path();
+        self.captures
+            .wildcards
+            .iter()
+            .map(|(i, r)| match self.decoded_segments {
+                None => &path[r.clone()],
+                Some(ref segments) => segments[*i].as_str(),
+            })
+            .collect()
+    }
+
+    /// Returns the path segment matched by the `<name:matcher>` segment in the path of the handler.
+    ///
+    /// The segment is percent-encoded as `Url::path` unless the path decoding is enabled
+    /// (see `ServerBuilder::path_decoding`).
+    /// For example, if the path of the handler is `/items/<id:[0-9]+>`,
+    /// the `id` parameter of the request for `/items/10` is `Some("10")`.
+    ///
+    /// If the path of the handler does not have such a segment, this returns `None`.
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        let path = self.url.path();
+        self.captures
+            .params
+            .iter()
+            .find(|p| &*p.0 == name)
+            .map(|(_, i, r)| match self.decoded_segments {
+                None => &path[r.clone()],
+                Some(ref segments) => segments[*i].as_str(),
+            })
+    }
+
+    /// Returns the part of the path matched by the trailing `**` in the path of the handler.
+    ///
+    /// The part is percent-encoded as `Url::path` (even if the path decoding is enabled)
+    /// and does not have a leading slash.
+    /// For example, if the path of the handler is `/static/**`,
+    /// the rest of the path of the request for `/static/css/main.css` is `Some("css/main.css")`.
+    ///
+    /// If the path of the handler does not end with `**`, this returns `None`.
+    pub fn rest_of_path(&self) -> Option<&str> {
+        let path = self.url.path();
+        self.captures.rest.clone().map(|r| &path[r])
+    }
+
+    /// Returns the address of the client that sent the request.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
     /// Returns the HTTP version of the request.
     pub fn version(&self) -> HttpVersion {
         self.inner.http_version()
     }
 
     /// Returns the header of the response.
-    pub fn header(&self) -> Header {
+    pub fn header(&self) -> Header<'_> {
         self.inner.header()
     }
 
+    /// Returns the typed header field `H` of the request.
+    ///
+    /// If the field does not exist, this returns `Ok(None)`.
+    ///
+    /// # Errors
+    ///
+    /// If the value of the field is malformed, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn typed_header<H: TypedHeader>(&self) -> Result<Option<H>> {
+        track!(header::get(&self.inner.header()))
+    }
+
+    /// Returns a reference to the extensions of the request.
+    ///
+    /// Extensions are used to pass values (e.g., authentication principals) from
+    /// request hooks and handler wrappers to handlers.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns a mutable reference to the extensions of the request.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns a reference to the server-level shared value of the type `S`.
+    ///
+    /// Shared values can be registered via `ServerBuilder::state` method.
+    pub fn state<S: Send + Sync + 'static>(&self) -> Option<&S> {
+        self.state.get()
+    }
+
+    /// Returns the path pattern of the route that the request was dispatched to (e.g., `/users/*`).
+    ///
+    /// Unlike the path of the request, the number of distinct patterns is bounded by the number of
+    /// registered handlers, so this is suitable for labeling metrics and logs.
+    ///
+    /// This returns `None` until the request is dispatched (e.g., in request hooks).
+    pub fn route_pattern(&self) -> Option<&str> {
+        self.extensions.get::<Route>().map(|r| r.path())
+    }
+
+    /// Returns the logger for the request.
+    ///
+    /// The logger is a child of the server's logger, and has the method, path, route pattern and
+    /// ID of the request as the key-value pairs.
+    /// The ID is taken from the `X-Request-Id` header if it exists, otherwise generated by the server.
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
     /// Returns a reference to the body of the response.
     pub fn body(&self) -> &T {
         self.inner.body()
@@ -48,6 +219,16 @@ impl<T> Req<T> {
         let req = Req {
             inner,
             url: self.url,
+            peer_addr: self.peer_addr,
+            overridden_method: self.overridden_method,
+            extensions: self.extensions,
+            state: self.state,
+            logger: self.logger,
+            res_fields: self.res_fields,
+            default_res_fields: self.default_res_fields,
+            response_hook: self.response_hook,
+            captures: self.captures,
+            decoded_segments: self.decoded_segments,
         };
         (req, body)
     }
@@ -60,10 +241,20 @@ impl<T> Req<T> {
         Req {
             inner,
             url: self.url,
+            peer_addr: self.peer_addr,
+            overridden_method: self.overridden_method,
+            extensions: self.extensions,
+            state: self.state,
+            logger: self.logger,
+            res_fields: self.res_fields,
+            default_res_fields: self.default_res_fields,
+            response_hook: self.response_hook,
+            captures: self.captures,
+            decoded_segments: self.decoded_segments,
         }
     }
 
-    pub(crate) fn new(inner: Request<T>, base_url: &Url) -> Result<Self> {
+    pub(crate) fn new(inner: Request<T>, base_url: &Url, peer_addr: SocketAddr) -> Result<Self> {
         track_assert!(
             inner.request_target().as_str().starts_with('/'),
             ErrorKind::InvalidInput,
@@ -78,9 +269,102 @@ impl<T> Req<T> {
             "path={:?}",
             inner.request_target()
         )?;
-        Ok(Req { inner, url })
+        Ok(Req {
+            inner,
+            url,
+            peer_addr,
+            overridden_method: None,
+            extensions: Extensions::new(),
+            state: Arc::default(),
+            logger: Logger::root(Discard, o!()),
+            res_fields: Vec::new(),
+            default_res_fields: Arc::default(),
+            response_hook: None,
+            captures: PathCaptures::default(),
+            decoded_segments: None,
+        })
+    }
+
+    /// Returns the request target in the request line (i.e., the undecoded path and query).
+    pub(crate) fn request_target(&self) -> &str {
+        self.inner.request_target().as_str()
+    }
+
+    /// Returns the underlying request as received (i.e., without the overridden method).
+    pub(crate) fn as_request(&self) -> &Request<T> {
+        &self.inner
+    }
+
+    pub(crate) fn apply_method_override(&mut self) {
+        if self.original_method() != "POST" {
+            return;
+        }
+        if let Some(method) = self.inner.header().get_field("X-HTTP-Method-Override") {
+            if Method::new(method).is_ok() {
+                self.overridden_method = Some(method.to_owned());
+            }
+        }
+    }
+
+    pub(crate) fn set_state(&mut self, state: Arc<Extensions>) {
+        self.state = state;
+    }
+
+    pub(crate) fn decode_path(&mut self, decoding: &PathDecoding) -> Result<()> {
+        let segments = self.url.path_segments().expect("Never fails");
+        self.decoded_segments = Some(track!(decoding.decode(segments))?);
+        Ok(())
+    }
+
+    pub(crate) fn set_captures(&mut self, captures: PathCaptures) {
+        self.captures = captures;
+    }
+
+    pub(crate) fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// Adds a header field that will be added to the response to the request.
+    ///
+    /// The field is validated by `Res::add_header` when it is added to the response,
+    /// and it is discarded if it is invalid.
+    pub(crate) fn add_res_field(&mut self, name: &str, value: &str) {
+        self.res_fields.push((name.to_owned(), value.to_owned()));
+    }
+
+    pub(crate) fn take_res_fields(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.res_fields)
+    }
+
+    /// Sets the header fields that will be added to the response unless it has the fields of the same names
+    /// (see `ServerBuilder::default_header`).
+    pub(crate) fn set_default_res_fields(&mut self, fields: Arc<Vec<(String, String)>>) {
+        self.default_res_fields = fields;
+    }
+
+    pub(crate) fn default_res_fields(&self) -> &Arc<Vec<(String, String)>> {
+        &self.default_res_fields
+    }
+
+    /// Sets the function that will be invoked with the response (see `ServerBuilder::response_hook`).
+    pub(crate) fn set_response_hook(&mut self, hook: Option<ResponseHook>) {
+        self.response_hook = hook;
+    }
+
+    pub(crate) fn response_hook(&self) -> Option<&ResponseHook> {
+        self.response_hook.as_ref()
     }
 }
+/// The ranges of the path of a request matched by the wildcards of the path of a handler.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct PathCaptures {
+    /// The indices of the segments and their byte ranges in `Url::path`.
+    pub wildcards: Vec<(usize, Range<usize>)>,
+    pub rest: Option<Range<usize>>,
+    /// The names, indices and byte ranges of the segments matched by `<name:matcher>` segments.
+    pub params: Vec<(Arc<str>, usize, Range<usize>)>,
+}
+
 impl<T: fmt::Display> fmt::Display for Req<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.inner.fmt(f)
@@ -1,13 +1,22 @@
-use crate::header;
+use crate::dispatcher::DispatchError;
+use crate::file::{FileBody, FileSender};
+use crate::header::{self, TypedHeader};
+use crate::problem::{self, ProblemDetails};
 use crate::status::Status;
-use bytecodec::bytes::Utf8Encoder;
+use crate::{Error, Result};
+use bytecodec::bytes::{BytesEncoder, Utf8Encoder};
 use bytecodec::marker::Never;
 use bytecodec::{self, ByteCount, Encode, EncodeExt, Eos};
+use fibers::net::TcpStream;
 use httpcodec::{
-    BodyEncoder, Header, HeaderMut, HttpVersion, ReasonPhrase, Response, ResponseEncoder,
-    StatusCode,
+    BodyEncode, BodyEncoder, Header, HeaderField, HeaderMut, HttpVersion, ReasonPhrase, Response,
+    ResponseEncoder, StatusCode,
 };
+use iovec::IoVec;
+use std::cmp;
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
 
 /// HTTP response.
 ///
@@ -39,15 +48,65 @@ impl<T> Res<T> {
     }
 
     /// Returns the header of the response.
-    pub fn header(&self) -> Header {
+    pub fn header(&self) -> Header<'_> {
         self.0.header()
     }
 
     /// Returns the mutable header of the response.
-    pub fn header_mut(&mut self) -> HeaderMut {
+    ///
+    /// Note that the fields added via the returned header are not validated by this crate.
+    /// Use `add_header` for adding fields whose values come from untrusted sources.
+    pub fn header_mut(&mut self) -> HeaderMut<'_> {
         self.0.header_mut()
     }
 
+    /// Returns the typed header field `H` of the response.
+    ///
+    /// If the field does not exist, this returns `Ok(None)`.
+    ///
+    /// # Errors
+    ///
+    /// If the value of the field is malformed, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn typed_header<H: TypedHeader>(&self) -> Result<Option<H>> {
+        track!(header::get(&self.0.header()))
+    }
+
+    /// Adds the typed header field `H` to the response.
+    ///
+    /// # Errors
+    ///
+    /// If the formatted value of the field is not a valid header value,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn add_typed_header<H: TypedHeader>(&mut self, field: &H) -> Result<()> {
+        track!(self.add_header(H::NAME, &field.to_string()))
+    }
+
+    /// Adds a header field to the response.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a token, or `value` contains control characters (e.g., CR and LF) or
+    /// is longer than `header::MAX_FIELD_VALUE_LEN` bytes,
+    /// an `ErrorKind::InvalidInput` error will be returned and the response is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fibers_http_server::{Res, Status};
+    ///
+    /// let mut res = Res::new(Status::Ok, ());
+    /// assert!(res.add_header("X-Foo", "bar").is_ok());
+    /// assert!(res.add_header("X-Foo", "bar\r\nSet-Cookie: a=b").is_err());
+    /// assert_eq!(res.header().get_field("X-Foo"), Some("bar"));
+    /// assert_eq!(res.header().get_field("Set-Cookie"), None);
+    /// ```
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<()> {
+        track!(header::validate_field(name, value))?;
+        let field = unsafe { HeaderField::new_unchecked(name, value) };
+        self.0.header_mut().add_field(field);
+        Ok(())
+    }
+
     /// Returns a reference to the body of the response.
     pub fn body(&self) -> &T {
         self.0.body()
@@ -58,6 +117,122 @@ impl<T> Res<T> {
         self.0.body_mut()
     }
 }
+impl Res<()> {
+    /// Makes a new `ResBuilder` instance for building a response that has the given status.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fibers_http_server::{Res, Status};
+    ///
+    /// # fn main() -> fibers_http_server::Result<()> {
+    /// let res = Res::builder(Status::Ok)
+    ///     .header("X-Foo", "bar")
+    ///     .content_type("application/json")
+    ///     .body(b"{}".to_vec())?;
+    /// assert_eq!(res.header().get_field("X-Foo"), Some("bar"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder(status: Status) -> ResBuilder {
+        ResBuilder::new(status)
+    }
+
+    /// Replaces the status of the response.
+    ///
+    /// The header fields are kept as they are.
+    pub fn set_status(&mut self, status: Status) {
+        self.rebuild(status.code(), status.reason_phrase(), None);
+    }
+
+    /// Removes the header fields named `name` (case-insensitively),
+    /// and returns the number of the removed fields.
+    pub fn remove_header(&mut self, name: &str) -> usize {
+        let before = self.0.header().fields().count();
+        let status_code = self.0.status_code().as_u16();
+        let reason_phrase = self.0.reason_phrase().as_str().to_owned();
+        self.rebuild(status_code, &reason_phrase, Some(name));
+        before - self.0.header().fields().count()
+    }
+
+    /// Replaces the inner response with the one that has the given status line and
+    /// the header fields except for the ones named `excluded`.
+    fn rebuild(&mut self, status_code: u16, reason_phrase: &str, excluded: Option<&str>) {
+        let mut inner = unsafe {
+            Response::new(
+                self.0.http_version(),
+                StatusCode::new_unchecked(status_code),
+                ReasonPhrase::new_unchecked(reason_phrase),
+                (),
+            )
+        };
+        for field in self.0.header().fields() {
+            if excluded.map_or(false, |name| field.name().eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            let field = unsafe { HeaderField::new_unchecked(field.name(), field.value()) };
+            inner.header_mut().add_field(field);
+        }
+        self.0 = inner;
+    }
+}
+impl Res<String> {
+    /// Makes a new `Res` instance that has the given HTML body.
+    ///
+    /// The `Content-Type` header of the response is set to `text/html; charset=utf-8`.
+    ///
+    /// Such responses can be encoded by `TextEncoder`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fibers_http_server::{Res, Status};
+    ///
+    /// let res = Res::html(Status::Ok, "<p>hello</p>");
+    /// assert_eq!(res.header().get_field("Content-Type"), Some("text/html; charset=utf-8"));
+    /// ```
+    pub fn html<B: Into<String>>(status: Status, body: B) -> Self {
+        Self::with_content_type(status, "text/html; charset=utf-8", body.into())
+    }
+
+    /// Makes a new `Res` instance that has the given plain text body.
+    ///
+    /// The `Content-Type` header of the response is set to `text/plain; charset=utf-8`.
+    ///
+    /// Such responses can be encoded by `TextEncoder`.
+    pub fn text<B: Into<String>>(status: Status, body: B) -> Self {
+        Self::with_content_type(status, "text/plain; charset=utf-8", body.into())
+    }
+
+    /// Makes a new `Res` instance that has the given (already serialized) JSON body.
+    ///
+    /// The `Content-Type` header of the response is set to `application/json`
+    /// (JSON texts are always encoded in UTF-8, so the `charset` parameter is not added).
+    ///
+    /// Such responses can be encoded by `TextEncoder`.
+    pub fn json<B: Into<String>>(status: Status, body: B) -> Self {
+        Self::with_content_type(status, "application/json", body.into())
+    }
+
+    /// Makes a new `Res` instance that has the given problem document ([RFC 7807]) as the body.
+    ///
+    /// The status of the response is that of `problem`, and
+    /// the `Content-Type` header of the response is set to `application/problem+json`.
+    ///
+    /// Such responses can be encoded by `TextEncoder`.
+    ///
+    /// [RFC 7807]: https://tools.ietf.org/html/rfc7807
+    pub fn problem(problem: &ProblemDetails) -> Self {
+        Self::with_content_type(problem.status(), problem::CONTENT_TYPE, problem.to_json())
+    }
+
+    fn with_content_type(status: Status, content_type: &'static str, body: String) -> Self {
+        let mut res = Res::new(status, body);
+        let field = unsafe { HeaderField::new_unchecked("Content-Type", content_type) };
+        res.header_mut().add_field(field);
+        res
+    }
+}
 impl<T: fmt::Display> fmt::Display for Res<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -69,21 +244,265 @@ impl<T> From<Response<T>> for Res<T> {
     }
 }
 
-pub struct ResEncoder(Box<dyn Encode<Item = Never> + Send + 'static>);
+/// Builder for `Res`.
+///
+/// Errors occurred while building are reported by `body` method.
+#[derive(Debug)]
+pub struct ResBuilder {
+    status: Status,
+    fields: Vec<(String, String)>,
+    error: Option<Error>,
+}
+impl ResBuilder {
+    /// Makes a new `ResBuilder` instance.
+    pub fn new(status: Status) -> Self {
+        ResBuilder {
+            status,
+            fields: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Adds a header field.
+    ///
+    /// If `name` is not a token, or `value` contains control characters (e.g., CR and LF) or
+    /// is longer than `header::MAX_FIELD_VALUE_LEN` bytes, the succeeding `body` method call will fail.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        if self.error.is_none() {
+            match track!(header::validate_field(name, value)) {
+                Err(e) => self.error = Some(e),
+                Ok(()) => self.fields.push((name.to_owned(), value.to_owned())),
+            }
+        }
+        self
+    }
+
+    /// Adds a typed header field.
+    pub fn typed_header<H: TypedHeader>(self, field: &H) -> Self {
+        self.header(H::NAME, &field.to_string())
+    }
+
+    /// Adds the `Content-Type` header field.
+    ///
+    /// If `content_type` is not a valid media type, the succeeding `body` method call will fail.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        if self.error.is_none() {
+            match track!(header::ContentType::parse(content_type)) {
+                Err(e) => self.error = Some(e),
+                Ok(field) => return self.typed_header(&field),
+            }
+        }
+        self
+    }
+
+    /// Builds a `Res` instance that has the given body.
+    ///
+    /// # Errors
+    ///
+    /// If any of the previous method calls failed, the error will be returned.
+    pub fn body<T>(self, body: T) -> Result<Res<T>> {
+        if let Some(e) = self.error {
+            return Err(track!(e));
+        }
+        let mut res = Res::new(self.status, body);
+        for (name, value) in &self.fields {
+            track!(res.add_header(name, value))?;
+        }
+        Ok(res)
+    }
+}
+
+/// The maximum size of the responses that are encoded into bytes at once (see `ResEncoder::from_started`).
+pub const SMALL_RESPONSE_SIZE: usize = 4096;
+
+pub struct ResEncoder {
+    inner: ResEncoderInner,
+    status_code: u16,
+    close: bool,
+}
 impl ResEncoder {
-    pub fn new<E>(inner: E) -> Self
+    pub fn new<E>(status_code: u16, inner: E) -> Self
     where
         E: Encode<Item = Never> + Send + 'static,
     {
-        ResEncoder(Box::new(inner))
+        Self::from_inner(status_code, ResEncoderInner::Encoder(Box::new(inner)))
+    }
+
+    /// Makes a new `ResEncoder` instance for the response that has an owned bytes body.
+    ///
+    /// The body of such a response can be written to sockets directly (see `write_to` method).
+    pub fn with_bytes_body(res: Response<Vec<u8>>) -> Self {
+        let status_code = res.status_code().as_u16();
+        let (head, body) = res.take_body();
+        let head = ResponseEncoder::new(ContentLength(body.len() as u64))
+            .encode_into_bytes(head)
+            .expect("Never fails");
+        Self::from_inner(
+            status_code,
+            ResEncoderInner::Bytes(BytesRes {
+                head,
+                body,
+                offset: 0,
+            }),
+        )
+    }
+
+    /// Makes a new `ResEncoder` instance from an encoder that has started encoding a response.
+    ///
+    /// If the remaining part of the response is `SMALL_RESPONSE_SIZE` bytes or shorter,
+    /// it is encoded into bytes at once (so the encoder need not be boxed),
+    /// and the bytes will be copied into the write buffer in one step.
+    pub fn from_started<E>(status_code: u16, mut encoder: E) -> Self
+    where
+        E: Encode + Send + 'static,
+    {
+        match encoder.requiring_bytes() {
+            ByteCount::Finite(n) if n <= SMALL_RESPONSE_SIZE as u64 => {
+                let mut bytes = vec![0; n as usize];
+                match track!(encode_all(&mut encoder, &mut bytes)) {
+                    Ok(size) => {
+                        bytes.truncate(size);
+                        Self::with_encoded_bytes(status_code, bytes)
+                    }
+                    Err(_) => Self::error(Status::InternalServerError),
+                }
+            }
+            _ => Self::new(status_code, Started(encoder)),
+        }
+    }
+
+    /// Makes a new `ResEncoder` instance for the response that has already been encoded into `bytes`.
+    fn with_encoded_bytes(status_code: u16, bytes: Vec<u8>) -> Self {
+        Self::from_inner(
+            status_code,
+            ResEncoderInner::Bytes(BytesRes {
+                head: bytes,
+                body: Vec::new(),
+                offset: 0,
+            }),
+        )
+    }
+
+    /// Makes a new `ResEncoder` instance for the response that has a file body.
+    ///
+    /// The body of such a response is written to sockets directly (see `write_to` method).
+    pub fn with_file_body(res: Response<FileBody>) -> Self {
+        let status_code = res.status_code().as_u16();
+        let (head, body) = res.take_body();
+        let head = ResponseEncoder::new(ContentLength(body.len()))
+            .encode_into_bytes(head)
+            .expect("Never fails");
+        Self::from_inner(
+            status_code,
+            ResEncoderInner::File(FileRes {
+                head,
+                offset: 0,
+                sender: body.into_sender(),
+            }),
+        )
+    }
+
+    /// Returns the status code of the response.
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    /// Marks that the connection should be closed after the response is written.
+    pub fn close_connection(&mut self) {
+        self.close = true;
+    }
+
+    /// Returns `true` if the connection should be closed after the response is written, otherwise `false`.
+    pub fn closes_connection(&self) -> bool {
+        self.close
+    }
+
+    /// Returns `true` if the response should be written to sockets directly rather than via the write buffer.
+    ///
+    /// Responses that have an owned bytes body of `threshold` bytes or larger and
+    /// responses that have a file body satisfy this condition.
+    pub fn prefers_direct_write(&self, threshold: usize) -> bool {
+        match self.inner {
+            ResEncoderInner::Encoder(_) => false,
+            ResEncoderInner::Bytes(ref x) => x.body.len() >= threshold,
+            ResEncoderInner::File(_) => true,
+        }
+    }
+
+    /// Writes the remaining part of the response to the given stream directly.
+    ///
+    /// This continues until the whole response is written or the stream would block,
+    /// and returns the number of written bytes.
+    ///
+    /// # Panics
+    ///
+    /// If the response has neither an owned bytes body nor a file body, this method will panic.
+    pub fn write_to(&mut self, stream: &mut TcpStream) -> io::Result<usize> {
+        match self.inner {
+            ResEncoderInner::Encoder(_) => panic!("The response cannot be written directly"),
+            ResEncoderInner::Bytes(ref mut x) => x.write_to(stream),
+            ResEncoderInner::File(ref mut x) => x.write_to(stream),
+        }
     }
 
     pub fn error(status: Status) -> Self {
+        Self::error_with_res(Self::error_res(status))
+    }
+
+    pub fn dispatch_error(error: &DispatchError) -> Self {
+        let mut res = Self::error_res(error.status());
+        if let DispatchError::MethodNotAllowed { ref allowed } = *error {
+            let allowed = allowed.join(", ");
+            let field = HeaderField::new("Allow", &allowed).expect("Never fails");
+            res.header_mut().add_field(field);
+        }
+        Self::error_with_res(res)
+    }
+
+    pub fn too_many_requests(retry_after: Duration) -> Self {
+        Self::error_with_retry_after(Status::TooManyRequests, retry_after)
+    }
+
+    pub fn service_unavailable(retry_after: Duration) -> Self {
+        Self::error_with_retry_after(Status::ServiceUnavailable, retry_after)
+    }
+
+    fn error_with_retry_after(status: Status, retry_after: Duration) -> Self {
+        let mut res = Self::error_res(status);
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        let secs = secs.to_string();
+        let field = HeaderField::new("Retry-After", &secs).expect("Never fails");
+        res.header_mut().add_field(field);
+        Self::error_with_res(res)
+    }
+
+    pub fn custom_error(mut res: Res<Vec<u8>>) -> Self {
+        res.header_mut().add_field(header::Connection::Close);
+        let encoder = ResponseEncoder::new(BodyEncoder::new(BytesEncoder::new()));
+        let mut this = Self::new(res.status_code(), encoder.last(res.0));
+        this.close_connection();
+        this
+    }
+
+    fn error_res(status: Status) -> Res<&'static str> {
         let mut res = Res::new(status, status.reason_phrase());
         res.header_mut().add_field(header::Connection::Close);
+        res
+    }
 
+    fn error_with_res(res: Res<&'static str>) -> Self {
         let encoder = ResponseEncoder::new(BodyEncoder::new(Utf8Encoder::new()));
-        ResEncoder(Box::new(encoder.last(res.0)))
+        let mut this = Self::new(res.status_code(), encoder.last(res.0));
+        this.close_connection();
+        this
+    }
+
+    fn from_inner(status_code: u16, inner: ResEncoderInner) -> Self {
+        ResEncoder {
+            inner,
+            status_code,
+            close: false,
+        }
     }
 }
 impl fmt::Debug for ResEncoder {
@@ -95,7 +514,81 @@ impl Encode for ResEncoder {
     type Item = Never;
 
     fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
-        self.0.encode(buf, eos)
+        match self.inner {
+            ResEncoderInner::Encoder(ref mut x) => x.encode(buf, eos),
+            ResEncoderInner::Bytes(ref mut x) => {
+                let mut size = 0;
+                while size < buf.len() && !x.is_completed() {
+                    let (head, body) = x.remaining();
+                    let src = if head.is_empty() { body } else { head };
+                    let n = cmp::min(src.len(), buf.len() - size);
+                    buf[size..][..n].copy_from_slice(&src[..n]);
+                    x.advance(n);
+                    size += n;
+                }
+                Ok(size)
+            }
+            ResEncoderInner::File(ref mut x) => {
+                let mut size = cmp::min(x.head.len() - x.offset, buf.len());
+                buf[..size].copy_from_slice(&x.head[x.offset..][..size]);
+                x.offset += size;
+                if x.offset == x.head.len() {
+                    size += track!(x
+                        .sender
+                        .read(&mut buf[size..])
+                        .map_err(bytecodec::Error::from))?;
+                }
+                Ok(size)
+            }
+        }
+    }
+
+    fn start_encoding(&mut self, _item: Self::Item) -> bytecodec::Result<()> {
+        unreachable!()
+    }
+
+    fn is_idle(&self) -> bool {
+        match self.inner {
+            ResEncoderInner::Encoder(ref x) => x.is_idle(),
+            ResEncoderInner::Bytes(ref x) => x.is_completed(),
+            ResEncoderInner::File(ref x) => x.offset == x.head.len() && x.sender.is_completed(),
+        }
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        match self.inner {
+            ResEncoderInner::Encoder(ref x) => x.requiring_bytes(),
+            ResEncoderInner::Bytes(ref x) => {
+                let (head, body) = x.remaining();
+                ByteCount::Finite((head.len() + body.len()) as u64)
+            }
+            ResEncoderInner::File(ref x) => {
+                ByteCount::Finite((x.head.len() - x.offset) as u64 + x.sender.remaining())
+            }
+        }
+    }
+}
+
+/// Encodes the remaining part of the item being encoded by `encoder` into `buf`.
+///
+/// Returns the number of bytes written to `buf`.
+fn encode_all<E: Encode>(encoder: &mut E, buf: &mut [u8]) -> bytecodec::Result<usize> {
+    let mut size = 0;
+    while !encoder.is_idle() {
+        let n = track!(encoder.encode(&mut buf[size..], Eos::new(false)))?;
+        track_assert_ne!(n, 0, bytecodec::ErrorKind::InconsistentState);
+        size += n;
+    }
+    Ok(size)
+}
+
+/// An encoder that has already started encoding an item.
+struct Started<E>(E);
+impl<E: Encode> Encode for Started<E> {
+    type Item = Never;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.0.encode(buf, eos))
     }
 
     fn start_encoding(&mut self, _item: Self::Item) -> bytecodec::Result<()> {
@@ -110,3 +603,451 @@ impl Encode for ResEncoder {
         self.0.requiring_bytes()
     }
 }
+
+enum ResEncoderInner {
+    Encoder(Box<dyn Encode<Item = Never> + Send + 'static>),
+    Bytes(BytesRes),
+    File(FileRes),
+}
+
+struct BytesRes {
+    head: Vec<u8>,
+    body: Vec<u8>,
+    offset: usize,
+}
+impl BytesRes {
+    fn remaining(&self) -> (&[u8], &[u8]) {
+        if self.offset < self.head.len() {
+            (&self.head[self.offset..], &self.body[..])
+        } else {
+            (&[], &self.body[self.offset - self.head.len()..])
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.offset += n;
+    }
+
+    fn is_completed(&self) -> bool {
+        self.offset == self.head.len() + self.body.len()
+    }
+
+    fn write_to(&mut self, stream: &mut TcpStream) -> io::Result<usize> {
+        let mut written = 0;
+        while !self.is_completed() {
+            let (head, body) = self.remaining();
+            let result = stream.with_inner(|s| {
+                let bufs = [head, body];
+                let bufs = bufs
+                    .iter()
+                    .filter_map(|b| IoVec::from_bytes(b))
+                    .collect::<Vec<_>>();
+                s.write_bufs(&bufs)
+            });
+            let size = match result {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // `TcpStream::write` registers the stream to the poller if it would block,
+                    // so that the current fiber is woken up when the stream becomes writable.
+                    let buf = if head.is_empty() { body } else { head };
+                    match stream.write(buf) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        result => result?,
+                    }
+                }
+                result => result?,
+            };
+            if size == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "Failed to write the response",
+                ));
+            }
+            self.advance(size);
+            written += size;
+        }
+        Ok(written)
+    }
+}
+
+struct FileRes {
+    head: Vec<u8>,
+    offset: usize,
+    sender: FileSender,
+}
+impl FileRes {
+    fn write_to(&mut self, stream: &mut TcpStream) -> io::Result<usize> {
+        let mut written = 0;
+        while self.offset < self.head.len() {
+            match stream.write(&self.head[self.offset..]) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(written),
+                Err(e) => return Err(e),
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Failed to write the response",
+                    ))
+                }
+                Ok(size) => {
+                    self.offset += size;
+                    written += size;
+                }
+            }
+        }
+        Ok(written + self.sender.send_to(stream)?)
+    }
+}
+
+/// A body encoder that only adds the `Content-Length` header.
+///
+/// This is used for encoding the head part of a response whose body is written separately.
+struct ContentLength(u64);
+impl Encode for ContentLength {
+    type Item = ();
+
+    fn encode(&mut self, _buf: &mut [u8], _eos: Eos) -> bytecodec::Result<usize> {
+        Ok(0)
+    }
+
+    fn start_encoding(&mut self, _item: Self::Item) -> bytecodec::Result<()> {
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        true
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(0)
+    }
+}
+impl BodyEncode for ContentLength {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        let value = self.0.to_string();
+        header.add_field(track!(HeaderField::new("Content-Length", &value))?);
+        Ok(())
+    }
+}
+
+/// A body encoder that discards the body of a response that must not have one
+/// (i.e., the responses to `HEAD` requests and the ones with `204` or `304` status).
+///
+/// If `keep_framing` is `true`, the header fields added by the inner encoder (e.g., `Content-Length`) are kept,
+/// so that they describe the body that would be sent for the corresponding `GET` request.
+pub struct BodylessEncoder<E> {
+    inner: E,
+    keep_framing: bool,
+}
+impl<E> BodylessEncoder<E> {
+    pub fn new(inner: E, keep_framing: bool) -> Self {
+        BodylessEncoder {
+            inner,
+            keep_framing,
+        }
+    }
+}
+impl<E: Encode> Encode for BodylessEncoder<E> {
+    type Item = E::Item;
+
+    fn encode(&mut self, _buf: &mut [u8], _eos: Eos) -> bytecodec::Result<usize> {
+        Ok(0)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        // The inner encoder has to know the body to update the header.
+        track!(self.inner.start_encoding(item))
+    }
+
+    fn is_idle(&self) -> bool {
+        true
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Finite(0)
+    }
+}
+impl<E: BodyEncode> BodyEncode for BodylessEncoder<E> {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        if self.keep_framing {
+            track!(self.inner.update_header(header))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if the responses that have the given status code must not have a body
+/// (RFC 7230, Section 3.3.3).
+pub fn is_bodiless_status(status_code: u16) -> bool {
+    (100..200).contains(&status_code) || status_code == 204 || status_code == 304
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, ServerBuilder};
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::BodyDecoder;
+
+    #[test]
+    fn bytes_body_encoding_works() {
+        let res = || Res::new(Status::Ok, b"hello".to_vec()).0;
+        let expected = ResponseEncoder::new(BodyEncoder::new(BytesEncoder::new()))
+            .encode_into_bytes(res())
+            .unwrap();
+
+        let mut encoder = ResEncoder::with_bytes_body(res());
+        assert!(encoder.prefers_direct_write(5));
+        assert!(!encoder.prefers_direct_write(6));
+        assert_eq!(
+            encoder.requiring_bytes(),
+            ByteCount::Finite(expected.len() as u64)
+        );
+
+        let mut buf = vec![0; expected.len() + 10];
+        let mut size = 0;
+        while !encoder.is_idle() {
+            size += encoder
+                .encode(&mut buf[size..][..3], Eos::new(false))
+                .unwrap();
+        }
+        assert_eq!(&buf[..size], &expected[..]);
+    }
+
+    #[test]
+    fn small_response_encoding_works() {
+        fn encode(mut encoder: ResEncoder) -> Vec<u8> {
+            let mut buf = vec![0; SMALL_RESPONSE_SIZE * 2];
+            let mut size = 0;
+            while !encoder.is_idle() {
+                size += encoder.encode(&mut buf[size..], Eos::new(false)).unwrap();
+            }
+            buf.truncate(size);
+            buf
+        }
+
+        for &len in &[5, SMALL_RESPONSE_SIZE] {
+            let res = || Res::new(Status::Ok, "a".repeat(len)).0;
+            let expected = ResponseEncoder::new(BodyEncoder::new(Utf8Encoder::new()))
+                .encode_into_bytes(res())
+                .unwrap();
+
+            let mut encoder = ResponseEncoder::new(BodyEncoder::new(Utf8Encoder::new()));
+            encoder.start_encoding(res()).unwrap();
+            let encoder = ResEncoder::from_started(200, encoder);
+            let is_small = expected.len() <= SMALL_RESPONSE_SIZE;
+            assert_eq!(matches!(encoder.inner, ResEncoderInner::Bytes(_)), is_small);
+            assert_eq!(encode(encoder), expected);
+        }
+    }
+
+    #[test]
+    fn typed_header_works() {
+        use crate::header::{CacheControl, ContentType};
+
+        let mut res = Res::new(Status::Ok, ());
+        let content_type = track_try_unwrap!(ContentType::new("application/json"));
+        let content_type = track_try_unwrap!(content_type.param("charset", "utf-8"));
+        track_try_unwrap!(res.add_typed_header(&content_type));
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+        assert_eq!(
+            track_try_unwrap!(res.typed_header::<ContentType>()),
+            Some(content_type)
+        );
+        assert_eq!(track_try_unwrap!(res.typed_header::<CacheControl>()), None);
+    }
+
+    #[test]
+    fn res_builder_works() {
+        let res = Res::builder(Status::Created)
+            .header("X-Foo", "bar baz")
+            .content_type("application/json; charset=utf-8")
+            .body(());
+        let res = track_try_unwrap!(res);
+        assert_eq!(res.status_code(), 201);
+        assert_eq!(res.header().get_field("X-Foo"), Some("bar baz"));
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+
+        assert!(Res::builder(Status::Ok)
+            .header("X Foo", "bar")
+            .body(())
+            .is_err());
+        assert!(Res::builder(Status::Ok)
+            .header("X-Foo", "bar\r\n")
+            .body(())
+            .is_err());
+        assert!(Res::builder(Status::Ok)
+            .content_type("json")
+            .body(())
+            .is_err());
+        assert!(Res::builder(Status::Ok)
+            .header("X-Foo", &"a".repeat(header::MAX_FIELD_VALUE_LEN + 1))
+            .body(())
+            .is_err());
+    }
+
+    #[test]
+    fn add_header_works() {
+        let mut res = Res::new(Status::Ok, ());
+        track_try_unwrap!(res.add_header("X-Foo", "bar\tbaz"));
+        assert_eq!(res.header().get_field("X-Foo"), Some("bar\tbaz"));
+
+        assert!(res.add_header("X-Bar", "a\r\nSet-Cookie: x=y").is_err());
+        assert!(res.add_header("X-Bar", "a\nb").is_err());
+        assert!(res.add_header("X-Bar", "a\0b").is_err());
+        assert!(res.add_header("X-Bar: a\r\nX-Baz", "b").is_err());
+        assert!(res
+            .add_header("X-Bar", &"a".repeat(header::MAX_FIELD_VALUE_LEN + 1))
+            .is_err());
+        assert!(res.header().get_field("X-Bar").is_none());
+        assert!(res.header().get_field("Set-Cookie").is_none());
+    }
+
+    #[test]
+    fn custom_status_error_works() {
+        let status = track_try_unwrap!(Status::custom(599, "Vendor Error"));
+        let mut encoder = ResEncoder::error(status);
+        let mut buf = vec![0; 1024];
+        let mut size = 0;
+        while !encoder.is_idle() {
+            size += track_try_unwrap!(encoder.encode(&mut buf[size..], Eos::new(false)));
+        }
+        assert!(buf[..size].starts_with(b"HTTP/1.1 599 Vendor Error\r\n"));
+        assert!(encoder.closes_connection());
+    }
+
+    #[test]
+    fn content_type_shortcuts_work() {
+        let res = Res::html(Status::Ok, "<p>hello</p>");
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(res.body(), "<p>hello</p>");
+
+        let res = Res::text(Status::NotFound, "not found".to_owned());
+        assert_eq!(res.status_code(), 404);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+
+        let res = Res::json(Status::Ok, r#"{"foo": 1}"#);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn bodyless_encoding_works() {
+        let res = || Res::new(Status::Ok, b"hello".to_vec()).0;
+
+        let mut encoder = ResponseEncoder::new(BodylessEncoder::new(
+            BodyEncoder::new(BytesEncoder::new()),
+            true,
+        ));
+        let bytes = encoder.encode_into_bytes(res()).unwrap();
+        assert_eq!(
+            bytes,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".to_vec()
+        );
+
+        let mut encoder = ResponseEncoder::new(BodylessEncoder::new(
+            BodyEncoder::new(BytesEncoder::new()),
+            false,
+        ));
+        let bytes = encoder.encode_into_bytes(res()).unwrap();
+        assert_eq!(bytes, b"HTTP/1.1 200 OK\r\n\r\n".to_vec());
+
+        assert!(is_bodiless_status(100));
+        assert!(is_bodiless_status(204));
+        assert!(is_bodiless_status(304));
+        assert!(!is_bodiless_status(200));
+        assert!(!is_bodiless_status(404));
+    }
+
+    #[test]
+    fn bodiless_responses_work() {
+        struct WithStatus;
+        impl HandleRequest for WithStatus {
+            const METHOD: &'static str = "GET";
+            const METHODS: &'static [&'static str] = &["GET", "HEAD"];
+            const PATH: &'static str = "/status/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let status = match req.wildcards()[0] {
+                    "204" => Status::NoContent,
+                    "304" => Status::NotModified,
+                    _ => Status::Ok,
+                };
+                let mut res = Res::new(status, "hello".to_owned());
+                if status == Status::NoContent {
+                    res.add_header("Content-Length", "5").unwrap();
+                }
+                Box::new(ok(res))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(WithStatus).unwrap();
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"HEAD /status/200 HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+        assert!(received.contains("Content-Length: 5\r\n"), "{}", received);
+        assert!(received.ends_with("\r\n\r\n"), "{}", received);
+
+        conn.write(b"GET /status/204 HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "{}",
+            received
+        );
+        assert!(!received.contains("Content-Length"), "{}", received);
+        assert!(received.ends_with("\r\n\r\n"), "{}", received);
+
+        conn.write(b"GET /status/304 HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+            "{}",
+            received
+        );
+        assert!(!received.contains("Content-Length"), "{}", received);
+        assert!(received.ends_with("\r\n\r\n"), "{}", received);
+
+        // The connection is still usable
+        conn.write(b"GET /status/200 HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.ends_with("\r\n\r\nhello"), "{}", received);
+        assert!(!conn.is_closed());
+
+        let client = {
+            let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+            builder.add_handler(WithStatus).unwrap();
+            builder.finish_test_client()
+        };
+        let res = fibers_global::execute(client.get("/status/204").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 204);
+        assert!(res.body().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use std::cmp;
+use std::time::Duration;
+
+/// A policy of retrying failed operations with exponential backoff.
+///
+/// This is used for binding the listening socket (see `ServerBuilder::bind_retry`) and
+/// accepting connections (see `ServerBuilder::accept_retry`).
+///
+/// The `n`-th retry is made after `initial_backoff * 2^(n - 1)` (capped at `max_backoff`) has elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_retries: Option<usize>,
+}
+impl RetryPolicy {
+    /// Makes a new `RetryPolicy` instance.
+    ///
+    /// The defaults are:
+    /// - `initial_backoff`: 100 milliseconds
+    /// - `max_backoff`: 5 seconds
+    /// - `max_retries`: unlimited
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound of the delays between retries.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum number of consecutive retries.
+    ///
+    /// If the operation still fails after that, the error is returned.
+    pub fn max_retries(mut self, n: usize) -> Self {
+        self.max_retries = Some(n);
+        self
+    }
+
+    /// Returns the same policy without the limit of the number of retries.
+    pub(crate) fn unlimited(mut self) -> Self {
+        self.max_retries = None;
+        self
+    }
+
+    /// Returns the delay before the next retry, given the number of the retries made so far.
+    ///
+    /// Returns `None` if no more retries should be made.
+    pub(crate) fn backoff(&self, retries: usize) -> Option<Duration> {
+        if self.max_retries.map_or(false, |max| retries >= max) {
+            return None;
+        }
+        let factor = 1u32.checked_shl(retries as u32).unwrap_or(u32::MAX);
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff);
+        Some(cmp::min(backoff, self.max_backoff))
+    }
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_retries: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_works() {
+        let policy = RetryPolicy::new()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(500))
+            .max_retries(5);
+        assert_eq!(policy.backoff(0), Some(Duration::from_millis(100)));
+        assert_eq!(policy.backoff(1), Some(Duration::from_millis(200)));
+        assert_eq!(policy.backoff(2), Some(Duration::from_millis(400)));
+        assert_eq!(policy.backoff(3), Some(Duration::from_millis(500)));
+        assert_eq!(policy.backoff(4), Some(Duration::from_millis(500)));
+        assert_eq!(policy.backoff(5), None);
+
+        let policy = RetryPolicy::new();
+        assert_eq!(policy.backoff(100), Some(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::{Req, Route};
+use bytecodec::io::{IoDecodeExt, ReadBuf};
+use bytecodec::marker::Never;
+use bytecodec::{self, ByteCount, Decode, Encode, Eos};
+use httpcodec::HttpVersion;
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+type PredicateFn = dyn Fn(&Req<()>) -> bool + Send + Sync + 'static;
+type CallbackFn = dyn Fn(SampledExchange) + Send + Sync + 'static;
+
+/// A sampler that captures the requests and the responses exchanged with clients for debugging
+/// (see `ServerBuilder::request_sampler`).
+///
+/// A request is sampled if it satisfies the predicate (see `predicate`) or it is chosen randomly
+/// at the sampling rate (see `rate`). The captured exchange is passed to the callback
+/// when the whole response has been encoded.
+///
+/// Note that the exchanges are captured as they are transferred
+/// (e.g., including the credentials in the header and the chunk headers of chunked bodies).
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{RequestSampler, ServerBuilder};
+///
+/// let sampler = RequestSampler::new(|exchange| {
+///     eprintln!("{}", String::from_utf8_lossy(exchange.request_head()));
+///     eprintln!("{}", String::from_utf8_lossy(exchange.response_head()));
+/// })
+/// .rate(0.001)
+/// .predicate(|req| req.header().get_field("X-Debug").is_some())
+/// .body_limit(1024);
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.request_sampler(sampler);
+/// ```
+#[derive(Clone)]
+pub struct RequestSampler {
+    rate: f64,
+    predicate: Option<Arc<PredicateFn>>,
+    body_limit: usize,
+    callback: Arc<CallbackFn>,
+    requests: Arc<AtomicU64>,
+    random: RandomState,
+}
+impl RequestSampler {
+    /// Makes a new `RequestSampler` instance that passes the sampled exchanges to `f`.
+    ///
+    /// `f` is invoked on the threads running the server, so it should not block for long.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(SampledExchange) + Send + Sync + 'static,
+    {
+        RequestSampler {
+            rate: 0.0,
+            predicate: None,
+            body_limit: 0,
+            callback: Arc::new(f),
+            requests: Arc::default(),
+            random: RandomState::new(),
+        }
+    }
+
+    /// Sets the fraction of the requests that are sampled randomly (between `0.0` and `1.0`).
+    ///
+    /// The default value is `0.0`.
+    pub fn rate(mut self, rate: f64) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// Sets the predicate that selects the requests to be sampled regardless of the sampling rate.
+    ///
+    /// The predicate receives the head part of each request after the function set by
+    /// `ServerBuilder::request_hook` has been invoked.
+    pub fn predicate<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Req<()>) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the maximum number of bytes captured from each of the request and response bodies.
+    ///
+    /// The default value is `0` (i.e., only the head parts are captured).
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Starts capturing the exchange if `req` should be sampled.
+    pub(crate) fn start(&self, req: &Req<()>) -> Option<Sample> {
+        let sampled = self.predicate.as_ref().map_or(false, |f| f(req)) || self.is_chosen();
+        if !sampled {
+            return None;
+        }
+
+        let version = match req.version() {
+            HttpVersion::V1_0 => "HTTP/1.0",
+            HttpVersion::V1_1 => "HTTP/1.1",
+        };
+        let mut head = format!(
+            "{} {} {}\r\n",
+            req.original_method(),
+            req.request_target(),
+            version
+        );
+        for field in req.header().fields() {
+            head.push_str(&format!("{}: {}\r\n", field.name(), field.value()));
+        }
+        head.push_str("\r\n");
+
+        Some(Sample {
+            exchange: SampledExchange {
+                peer_addr: req.peer_addr(),
+                route: None,
+                request_head: head.into_bytes(),
+                request_body: Vec::new(),
+                response_head: Vec::new(),
+                response_body: Vec::new(),
+            },
+            body_limit: self.body_limit,
+            is_response_head_completed: false,
+            callback: Arc::clone(&self.callback),
+        })
+    }
+
+    fn is_chosen(&self) -> bool {
+        if self.rate <= 0.0 {
+            return false;
+        }
+        if self.rate >= 1.0 {
+            return true;
+        }
+        let count = self.requests.fetch_add(1, Ordering::SeqCst);
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(count);
+        (hasher.finish() as f64) < self.rate * (u64::MAX as f64)
+    }
+}
+impl fmt::Debug for RequestSampler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "RequestSampler {{ rate: {}, body_limit: {}, .. }}",
+            self.rate, self.body_limit
+        )
+    }
+}
+
+/// A request and its response captured by `RequestSampler`.
+#[derive(Debug, Clone)]
+pub struct SampledExchange {
+    peer_addr: SocketAddr,
+    route: Option<Route>,
+    request_head: Vec<u8>,
+    request_body: Vec<u8>,
+    response_head: Vec<u8>,
+    response_body: Vec<u8>,
+}
+impl SampledExchange {
+    /// Returns the address of the client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Returns the route that the request was dispatched to.
+    ///
+    /// If the request was not dispatched to any handlers, this returns `None`.
+    pub fn route(&self) -> Option<&Route> {
+        self.route.as_ref()
+    }
+
+    /// Returns the head part of the request (i.e., the request line and the header).
+    pub fn request_head(&self) -> &[u8] {
+        &self.request_head
+    }
+
+    /// Returns the first bytes of the request body (up to `RequestSampler::body_limit` bytes).
+    pub fn request_body(&self) -> &[u8] {
+        &self.request_body
+    }
+
+    /// Returns the head part of the response (i.e., the status line and the header).
+    pub fn response_head(&self) -> &[u8] {
+        &self.response_head
+    }
+
+    /// Returns the first bytes of the response body (up to `RequestSampler::body_limit` bytes).
+    pub fn response_body(&self) -> &[u8] {
+        &self.response_body
+    }
+}
+
+/// An exchange being captured.
+pub(crate) struct Sample {
+    exchange: SampledExchange,
+    body_limit: usize,
+    is_response_head_completed: bool,
+    callback: Arc<CallbackFn>,
+}
+impl Sample {
+    pub fn set_route(&mut self, route: Route) {
+        self.exchange.route = Some(route);
+    }
+
+    /// Returns a copy of the bytes in `buf` that may be consumed as a part of the request body.
+    pub fn peek_request_body(&self, buf: &mut ReadBuf<Vec<u8>>) -> Vec<u8> {
+        let limit = self.body_limit - self.exchange.request_body.len();
+        let mut peeked = Vec::new();
+        if limit > 0 {
+            let _ = Peek(&mut peeked, limit).decode_from_read_buf(buf);
+        }
+        peeked
+    }
+
+    /// Captures the first `consumed` bytes of the ones returned by `peek_request_body`.
+    pub fn capture_request_body(&mut self, peeked: &[u8], consumed: usize) {
+        let size = cmp::min(peeked.len(), consumed);
+        self.exchange
+            .request_body
+            .extend_from_slice(&peeked[..size]);
+    }
+
+    fn capture_response(&mut self, mut bytes: &[u8]) {
+        if !self.is_response_head_completed {
+            let head = &mut self.exchange.response_head;
+            let mut size = bytes.len();
+            for (i, &b) in bytes.iter().enumerate() {
+                head.push(b);
+                if head.ends_with(b"\r\n\r\n") {
+                    self.is_response_head_completed = true;
+                    size = i + 1;
+                    break;
+                }
+            }
+            bytes = &bytes[size..];
+        }
+        let limit = self.body_limit - self.exchange.response_body.len();
+        let size = cmp::min(bytes.len(), limit);
+        self.exchange
+            .response_body
+            .extend_from_slice(&bytes[..size]);
+    }
+
+    /// Passes the captured exchange to the callback.
+    pub fn finish(self) {
+        (self.callback)(self.exchange);
+    }
+}
+impl fmt::Debug for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sample {{ exchange: {:?}, .. }}", self.exchange)
+    }
+}
+
+/// An encoder that captures the bytes of the response encoded by the inner encoder.
+pub(crate) struct Tee<'a, E> {
+    inner: &'a mut E,
+    sample: &'a mut Sample,
+}
+impl<'a, E> Tee<'a, E> {
+    pub fn new(inner: &'a mut E, sample: &'a mut Sample) -> Self {
+        Tee { inner, sample }
+    }
+}
+impl<'a, E: Encode<Item = Never>> Encode for Tee<'a, E> {
+    type Item = Never;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        let size = track!(self.inner.encode(buf, eos))?;
+        self.sample.capture_response(&buf[..size]);
+        Ok(size)
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        match item {}
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+
+/// A decoder that copies the given bytes without consuming them.
+struct Peek<'a>(&'a mut Vec<u8>, usize);
+impl<'a> Decode for Peek<'a> {
+    type Item = ();
+
+    fn decode(&mut self, buf: &[u8], _eos: Eos) -> bytecodec::Result<usize> {
+        let size = cmp::min(buf.len(), self.1);
+        self.0.extend_from_slice(&buf[..size]);
+        Ok(0)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        true
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Res, ServerBuilder, Status};
+    use bytecodec::bytes::BytesEncoder;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::sync::Mutex;
+
+    #[test]
+    fn capture_response_works() {
+        let captured = Arc::new(Mutex::new(None));
+        let captured_clone = Arc::clone(&captured);
+        let mut sample = Sample {
+            exchange: SampledExchange {
+                peer_addr: ([127, 0, 0, 1], 50000).into(),
+                route: None,
+                request_head: Vec::new(),
+                request_body: Vec::new(),
+                response_head: Vec::new(),
+                response_body: Vec::new(),
+            },
+            body_limit: 4,
+            is_response_head_completed: false,
+            callback: Arc::new(move |exchange| {
+                *captured_clone.lock().unwrap() = Some(exchange);
+            }),
+        };
+        sample.capture_response(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r");
+        sample.capture_response(b"\n\r\nhello");
+        sample.capture_response(b" world");
+        sample.finish();
+
+        let exchange = captured.lock().unwrap().take().unwrap();
+        assert_eq!(
+            exchange.response_head(),
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n"[..]
+        );
+        assert_eq!(exchange.response_body(), b"hell");
+    }
+
+    #[test]
+    fn request_sampler_works() {
+        use bytecodec::bytes::RemainingBytesDecoder;
+        use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+        struct Echo;
+        impl HandleRequest for Echo {
+            const METHOD: &'static str = "POST";
+            const PATH: &'static str = "/echo";
+
+            type ReqBody = Vec<u8>;
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<RemainingBytesDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, req.into_body())))
+            }
+        }
+
+        fn req(debug: bool) -> Request<Vec<u8>> {
+            let mut req = Request::new(
+                Method::new("POST").unwrap(),
+                RequestTarget::new("/echo?x=1").unwrap(),
+                HttpVersion::V1_1,
+                b"hello world".to_vec(),
+            );
+            if debug {
+                req.header_mut()
+                    .add_field(HeaderField::new("X-Debug", "1").unwrap());
+            }
+            req
+        }
+
+        let exchanges = Arc::new(Mutex::new(Vec::new()));
+        let sampler = {
+            let exchanges = Arc::clone(&exchanges);
+            RequestSampler::new(move |exchange| exchanges.lock().unwrap().push(exchange))
+                .predicate(|req| req.header().get_field("X-Debug").is_some())
+                .body_limit(5)
+        };
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.request_sampler(sampler);
+        builder.add_handler(Echo).unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.send(req(false))).unwrap();
+        assert_eq!(res.body(), b"hello world");
+        assert!(exchanges.lock().unwrap().is_empty());
+
+        let res = fibers_global::execute(client.send(req(true))).unwrap();
+        assert_eq!(res.body(), b"hello world");
+
+        let exchanges = exchanges.lock().unwrap();
+        assert_eq!(exchanges.len(), 1);
+        let exchange = &exchanges[0];
+        assert_eq!(exchange.route().map(|r| r.path()), Some("/echo"));
+        let request_head = String::from_utf8_lossy(exchange.request_head());
+        assert!(request_head.starts_with("POST /echo?x=1 HTTP/1.1\r\n"));
+        assert!(request_head.contains("X-Debug: 1\r\n"));
+        assert!(request_head.ends_with("\r\n\r\n"));
+        assert_eq!(exchange.request_body(), b"hello");
+        assert!(exchange.response_head().starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(exchange.response_head().ends_with(b"\r\n\r\n"));
+        assert_eq!(exchange.response_body(), b"hello");
+    }
+}
@@ -1,42 +1,137 @@
+use crate::access_log::SharedAccessLog;
+use crate::cidr::AccessControl;
+use crate::clock::{ManualClock, SharedClock, Sleep};
 use crate::connection::Connection;
-use crate::dispatcher::{Dispatcher, DispatcherBuilder};
-use crate::metrics::ServerMetrics;
-use crate::{Error, HandleRequest, HandlerOptions, Result};
+use crate::debug::{ConnectionRegistry, DebugState};
+use crate::dispatcher::{DispatchErrorHandler, Dispatcher, DispatcherBuilder};
+use crate::drain::{DrainState, DrainWatch, ShutdownSignal};
+use crate::extensions::Extensions;
+use crate::group::HandlerGroup;
+use crate::handle::{ReloadableOptions, ReloadableValues, ServerHandle};
+use crate::header;
+use crate::load_shedding::LoadShedder;
+use crate::metrics::{BucketConfig, ServerMetrics, WithMetrics};
+use crate::observer::{ConnectionPhase, SharedObserver};
+use crate::options::EffectiveOptions;
+use crate::rate_limit::RateLimiter;
+use crate::testing::{Simulation, TestClient, TestReply};
+use crate::tunnel::ConnectHandler;
+use crate::{
+    AccessLogSink, BandwidthLimit, Cidr, Clock, ConnectionObserver, CsrfProtection, DispatchError,
+    Error, ErrorKind, HandleConnect, HandleRequest, HandlerOptions, HostValidation, LoadShedding,
+    MetricsPush, PathDecoding, RateLimit, ReadinessGate, Req, RequestSampler, Res, Result,
+    RetryPolicy, Route, ShadowedRoute, TracePolicy,
+};
 use factory::Factory;
 use fibers::net::futures::{Connected, TcpListenerBind};
 use fibers::net::streams::Incoming;
-use fibers::net::TcpListener;
+use fibers::net::{TcpListener, TcpStream};
+use fibers::sync::mpsc;
 use fibers::{self, BoxSpawn, Spawn};
-use futures::future::{loop_fn, ok, Either, Loop};
+use futures::future::{err, loop_fn, ok, Either, Loop};
 use futures::{Async, Future, Poll, Stream};
-use httpcodec::DecodeOptions;
+use httpcodec::{DecodeOptions, Request};
 use prometrics::metrics::MetricBuilder;
 use slog::{Discard, Logger};
+use std::fmt;
+use std::fs::File;
+use std::io;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
+use url::Url;
 
 /// HTTP server builder.
 #[derive(Debug)]
 pub struct ServerBuilder {
     bind_addr: SocketAddr,
+    extra_bind_addrs: Vec<SocketAddr>,
     logger: Logger,
     metrics: MetricBuilder,
+    handler_metrics: Option<BucketConfig>,
     dispatcher: DispatcherBuilder,
+    access_control: AccessControl,
+    max_pending_connections: usize,
+    max_active_connections: usize,
+    bind_retry: Option<RetryPolicy>,
+    partial_bind: bool,
+    accept_retry: RetryPolicy,
+    reserve_fd: bool,
+    listen_backlog: Option<i32>,
+    metrics_push: Option<MetricsPush>,
     options: ServerOptions,
 }
 impl ServerBuilder {
     /// Makes a new `ServerBuilder` instance.
+    ///
+    /// If `bind_addr` is the unspecified IPv6 address (`[::]`), whether IPv4 clients are also
+    /// accepted is decided by the OS default of the `IPV6_V6ONLY` option (e.g., the
+    /// `net.ipv6.bindv6only` sysctl on Linux). The option cannot be set by this crate,
+    /// because `fibers` creates and binds the listening sockets by itself.
+    /// To accept only IPv4 or only IPv6 clients, bind to a specific address instead.
     pub fn new(bind_addr: SocketAddr) -> Self {
         ServerBuilder {
             bind_addr,
+            extra_bind_addrs: Vec::new(),
             logger: Logger::root(Discard, o!()),
             metrics: MetricBuilder::default(),
+            handler_metrics: None,
             dispatcher: DispatcherBuilder::new(),
+            access_control: AccessControl::default(),
+            max_pending_connections: 1024,
+            max_active_connections: usize::MAX,
+            bind_retry: None,
+            partial_bind: false,
+            accept_retry: RetryPolicy::default(),
+            reserve_fd: false,
+            listen_backlog: None,
+            metrics_push: None,
             options: ServerOptions {
                 read_buffer_size: 8192,
                 write_buffer_size: 8192,
+                max_read_buffer_size: 8192,
+                max_write_buffer_size: 8192,
+                vectored_write_threshold: 64 * 1024,
+                write_high_watermark: 1024 * 1024,
+                max_decompressed_request_body_size: None,
+                max_drained_request_body_size: 64 * 1024,
+                slow_request_threshold: None,
+                path_decoding: None,
                 decode_options: DecodeOptions::default(),
+                dispatch_error_handler: None,
+                rate_limiter: None,
+                read_bandwidth_limit: None,
+                write_bandwidth_limit: None,
+                load_shedder: None,
+                readiness_gate: None,
+                clock: SharedClock::default(),
+                debug_connections: None,
+                csrf_protection: None,
+                host_validation: None,
+                smuggling_protection: false,
+                trace_policy: TracePolicy::default(),
+                connect_handler: None,
+                connection_observer: None,
+                access_log: None,
+                request_sampler: None,
+                request_hook: None,
+                response_hook: None,
+                connection_error_hook: None,
+                method_override: false,
+                trusted_proxies: Vec::new(),
+                base_url: None,
+                default_headers: Arc::default(),
+                state: Arc::default(),
+                request_ids: Arc::default(),
+                connection_ids: Arc::default(),
+                reloadable: Arc::default(),
+                drain: Arc::default(),
+                active_connections: Arc::default(),
+                socket: SocketOptions::default(),
             },
         }
     }
@@ -46,7 +141,8 @@ impl ServerBuilder {
     /// # Errors
     ///
     /// If the path and method of the handler conflicts with the already registered handlers,
-    /// an `ErrorKind::InvalidInput` error will be returned.
+    /// an `ErrorKind::InvalidInput` error will be returned (see also `override_routes`).
+    /// The cause of the error is a `RouteConflict` that names the conflicting paths.
     pub fn add_handler<H>(&mut self, handler: H) -> Result<&mut Self>
     where
         H: HandleRequest,
@@ -61,7 +157,11 @@ impl ServerBuilder {
     /// # Errors
     ///
     /// If the path and method of the handler conflicts with the already registered handlers,
-    /// an `ErrorKind::InvalidInput` error will be returned.
+    /// an `ErrorKind::InvalidInput` error will be returned (see also `override_routes`).
+    /// The cause of the error is a `RouteConflict` that names the conflicting paths.
+    ///
+    /// If `options` has an invalid header field (see `HandlerOptions::header`),
+    /// an `ErrorKind::InvalidInput` error will also be returned.
     pub fn add_handler_with_options<H, D, E>(
         &mut self,
         handler: H,
@@ -72,7 +172,171 @@ impl ServerBuilder {
         D: Factory<Item = H::Decoder> + Send + Sync + 'static,
         E: Factory<Item = H::Encoder> + Send + Sync + 'static,
     {
-        track!(self.dispatcher.register_handler(handler, options))?;
+        track!(self.add_handler_at(handler, options, Arc::from(H::PATH)))?;
+        Ok(self)
+    }
+
+    /// Makes a group of handlers that share the path prefix, options and middleware.
+    ///
+    /// The handlers added to the group are served at `prefix` followed by their paths
+    /// (e.g., `/users/*` added to the group of `/api/v1` is served at `/api/v1/users/*`).
+    /// The trailing slashes of `prefix` are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bytecodec::bytes::Utf8Encoder;
+    /// use bytecodec::null::NullDecoder;
+    /// use fibers_http_server::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    /// use futures::future::ok;
+    /// use httpcodec::{BodyDecoder, BodyEncoder};
+    ///
+    /// struct ListUsers;
+    /// impl HandleRequest for ListUsers {
+    ///     const METHOD: &'static str = "GET";
+    ///     const PATH: &'static str = "/users";
+    ///
+    ///     type ReqBody = ();
+    ///     type ResBody = String;
+    ///     type Decoder = BodyDecoder<NullDecoder>;
+    ///     type Encoder = BodyEncoder<Utf8Encoder>;
+    ///     type Reply = Reply<Self::ResBody>;
+    ///
+    ///     fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+    ///         Box::new(ok(Res::new(Status::Ok, "[]".to_owned())))
+    ///     }
+    /// }
+    ///
+    /// # fn main() -> fibers_http_server::Result<()> {
+    /// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+    /// let mut group = builder.group("/api/v1");
+    /// group
+    ///     .header("Cache-Control", "no-store")?
+    ///     .require_auth("Bearer", |req| req.header().get_field("Authorization").is_some())?;
+    /// group.add_handler(ListUsers)?; // Served at `/api/v1/users`
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn group(&mut self, prefix: &str) -> HandlerGroup<'_> {
+        HandlerGroup::new(self, prefix)
+    }
+
+    /// Adds a HTTP request handler served at `pattern` instead of `HandleRequest::PATH`.
+    pub(crate) fn add_handler_at<H, D, E>(
+        &mut self,
+        handler: H,
+        options: HandlerOptions<H, D, E>,
+        pattern: Arc<str>,
+    ) -> Result<()>
+    where
+        H: HandleRequest,
+        D: Factory<Item = H::Decoder> + Send + Sync + 'static,
+        E: Factory<Item = H::Encoder> + Send + Sync + 'static,
+    {
+        if let Some(ref bucket_config) = self.handler_metrics {
+            let handler = WithMetrics::with_metrics_and_bucket_config(
+                handler,
+                self.metrics.clone(),
+                bucket_config.clone(),
+            );
+            track!(self
+                .dispatcher
+                .register_handler_at(handler, options.cast(), pattern))?;
+        } else {
+            track!(self
+                .dispatcher
+                .register_handler_at(handler, options, pattern))?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether the handlers added after this call replace the already added handlers
+    /// of the same method and path (e.g., to override the `/metrics` handler added by a library).
+    ///
+    /// The handlers are regarded as the same only if their `QUERY` constraints are also the same.
+    /// Note that the paths that conflict in other ways (e.g., `/foo/*` and `/foo/bar`) are still rejected.
+    ///
+    /// The default value is `false` (i.e., adding such a handler results in an `ErrorKind::InvalidInput` error).
+    pub fn override_routes(&mut self, enabled: bool) -> &mut Self {
+        self.dispatcher.set_override_routes(enabled);
+        self
+    }
+
+    /// Registers the predicate that can be referred by `<name:matcher>` segments of the paths of handlers.
+    ///
+    /// For example, after `builder.segment_matcher("uint", |s| s.parse::<u64>().is_ok())`,
+    /// the handler for `/items/<id:uint>` only receives the requests having numeric IDs
+    /// (the others are rejected with `404 Not Found` by the router).
+    /// The predicate receives the segments percent-decoded if the path decoding is enabled.
+    ///
+    /// Note that predicates must be registered before the handlers referring them are added.
+    pub fn segment_matcher<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.dispatcher.add_segment_matcher(name, f);
+        self
+    }
+
+    /// Returns the `(method, path)` pairs of the handlers added so far, in the order of registration.
+    ///
+    /// A handler having multiple `METHODS` appears once per method,
+    /// and handlers that differ only in their `QUERY` constraints appear separately.
+    /// The paths are the patterns specified by `HandleRequest::PATH` (e.g., `/users/*`).
+    /// Note that the fallback handler is not included.
+    pub fn routes(&self) -> Vec<(&'static str, &str)> {
+        self.dispatcher.routes()
+    }
+
+    /// Returns the routes of the handlers added so far that can never be dispatched.
+    ///
+    /// For example, a path containing `..` segments is unreachable
+    /// because such segments are removed from the paths of requests before dispatching.
+    ///
+    /// Note that the result depends on the path decoding option (see `ServerBuilder::path_decoding`).
+    pub fn check_routes(&self) -> Vec<ShadowedRoute> {
+        self.dispatcher.check(self.options.path_decoding.as_ref())
+    }
+
+    /// Sets the handler for the requests whose paths do not match any handlers added by `add_handler`.
+    ///
+    /// This is useful for single page applications (e.g., serving `index.html` for all paths)
+    /// and catch-all proxies. The `PATH` of the handler is ignored,
+    /// and the requests of the methods not included in its `METHODS` are rejected with
+    /// `405 Method Not Allowed`. If this method is called more than once, the last handler is used.
+    ///
+    /// By default, such requests are rejected with `404 Not Found`.
+    ///
+    /// # Errors
+    ///
+    /// If the handler has no methods, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn fallback_handler<H>(&mut self, handler: H) -> Result<&mut Self>
+    where
+        H: HandleRequest,
+        H::Decoder: Default,
+        H::Encoder: Default,
+    {
+        self.fallback_handler_with_options(handler, HandlerOptions::default())
+    }
+
+    /// Sets the fallback handler with the given options.
+    ///
+    /// See also `fallback_handler` method.
+    ///
+    /// # Errors
+    ///
+    /// If the handler has no methods, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn fallback_handler_with_options<H, D, E>(
+        &mut self,
+        handler: H,
+        options: HandlerOptions<H, D, E>,
+    ) -> Result<&mut Self>
+    where
+        H: HandleRequest,
+        D: Factory<Item = H::Decoder> + Send + Sync + 'static,
+        E: Factory<Item = H::Encoder> + Send + Sync + 'static,
+    {
+        track!(self.dispatcher.set_fallback_handler(handler, options))?;
         Ok(self)
     }
 
@@ -92,6 +356,29 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets whether the handlers added after this call are wrapped with `WithMetrics` automatically.
+    ///
+    /// The metrics of the handlers are registered with the `MetricBuilder` set by `metrics`
+    /// (so `metrics` should be called before adding handlers).
+    /// Note that the handlers that are already wrapped with `WithMetrics` should not be added
+    /// while this is enabled (otherwise, their metrics are registered twice).
+    ///
+    /// The default value is `false`.
+    pub fn handler_metrics(&mut self, enabled: bool) -> &mut Self {
+        self.handler_metrics = if enabled {
+            Some(BucketConfig::default())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Enables `handler_metrics` with the given buckets of the `request_duration_seconds` histograms.
+    pub fn handler_metrics_with_bucket_config(&mut self, bucket_config: BucketConfig) -> &mut Self {
+        self.handler_metrics = Some(bucket_config);
+        self
+    }
+
     /// Sets the application level read buffer size of the server in bytes.
     ///
     /// The default value is `8192`.
@@ -108,6 +395,98 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets the maximum size in bytes up to which the read buffer of a connection can grow.
+    ///
+    /// The buffer grows when it is filled up, and shrinks back to `read_buffer_size`
+    /// when the connection becomes idle.
+    /// If the value is less than `read_buffer_size`, the buffer never grows.
+    /// The times the buffer has been full at this limit are counted by `ServerMetrics::read_buffer_full_stalls`.
+    ///
+    /// The default value is `8192`.
+    pub fn max_read_buffer_size(&mut self, n: usize) -> &mut Self {
+        self.options.max_read_buffer_size = n;
+        self
+    }
+
+    /// Sets the maximum size in bytes up to which the write buffer of a connection can grow.
+    ///
+    /// The buffer grows when a response does not fit in it, and shrinks back to `write_buffer_size`
+    /// when the connection becomes idle.
+    /// If the value is less than `write_buffer_size`, the buffer never grows.
+    /// The times the buffer has been full at this limit are counted by `ServerMetrics::write_buffer_full_stalls`.
+    ///
+    /// The default value is `8192`.
+    pub fn max_write_buffer_size(&mut self, n: usize) -> &mut Self {
+        self.options.max_write_buffer_size = n;
+        self
+    }
+
+    /// Sets the minimum size in bytes of response bodies that are written to sockets directly.
+    ///
+    /// If the body of a response is a `Vec<u8>` (or `String`) encoded by
+    /// `BodyEncoder<BytesEncoder>` (or `BodyEncoder<Utf8Encoder>`) and its size is greater than
+    /// or equal to the value, the head and the body of the response are written by using
+    /// vectored I/O without being copied to the write buffer.
+    ///
+    /// The default value is `65536`.
+    pub fn vectored_write_threshold(&mut self, n: usize) -> &mut Self {
+        self.options.vectored_write_threshold = n;
+        self
+    }
+
+    /// Sets the maximum number of response bytes that a connection writes without yielding.
+    ///
+    /// Responses are encoded into the write buffer incrementally and flushed to the socket
+    /// chunk by chunk. Once a connection has written more bytes than this value in a single run,
+    /// it yields to the executor so that a huge response to a fast client
+    /// does not starve the other connections.
+    ///
+    /// The default value is `1048576`.
+    pub fn write_high_watermark(&mut self, n: usize) -> &mut Self {
+        self.options.write_high_watermark = n;
+        self
+    }
+
+    /// Enables the percent-decoding of the path segments of requests before dispatching them.
+    ///
+    /// See the documentation of `PathDecoding` for the details.
+    ///
+    /// By default, the (percent-encoded) path segments are compared with the paths of handlers as they are.
+    pub fn path_decoding(&mut self, decoding: PathDecoding) -> &mut Self {
+        self.options.path_decoding = Some(decoding);
+        self
+    }
+
+    /// Enables or disables the transparent decompression of request bodies.
+    ///
+    /// If `Some(max_size)` is specified, the bodies of requests that have the `Content-Encoding: gzip`
+    /// (or `deflate`) header are decompressed before being passed to the decoders of the handlers.
+    /// The compressed and decompressed bodies are limited to `max_size` bytes,
+    /// and the server responds with `413 Payload Too Large` if a body exceeds the limit
+    /// (or `400 Bad Request` if a body cannot be decompressed).
+    ///
+    /// Note that the header fields of the requests passed to the handlers are left unchanged.
+    ///
+    /// By default, request bodies are passed to the decoders as they are.
+    pub fn request_decompression(&mut self, max_size: Option<usize>) -> &mut Self {
+        self.options.max_decompressed_request_body_size = max_size;
+        self
+    }
+
+    /// Sets the maximum size of request bodies that are skipped in order to keep connections alive.
+    ///
+    /// If a handler responds without reading the request body
+    /// (via `HandleRequest::handle_request_head` or `HandleRequest::handle_decoding_error`),
+    /// the unread body is read and discarded before the next request on the connection is read.
+    /// The connection is closed instead if the body is larger than `max_size` bytes or
+    /// its size is unknown (e.g., `Transfer-Encoding: chunked`).
+    ///
+    /// The default value is `65536`.
+    pub fn max_drained_request_body_size(&mut self, max_size: usize) -> &mut Self {
+        self.options.max_drained_request_body_size = max_size;
+        self
+    }
+
     /// Sets the options of the request decoder of the server.
     ///
     /// The default value is `DecodeOptions::default()`.
@@ -116,24 +495,697 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets the maximum size of the request line of a request in bytes.
+    ///
+    /// If a request exceeds the limit, the `414 URI Too Long` response will be returned.
+    ///
+    /// The default value is `DecodeOptions::DEFAULT_MAX_START_LINE_SIZE`.
+    pub fn max_request_line_size(&mut self, size: usize) -> &mut Self {
+        self.options.values_mut().head_limits.max_request_line_size = size;
+        self
+    }
+
+    /// Sets the maximum length of the request target (e.g., `/foo?bar=baz`) of a request in bytes.
+    ///
+    /// Unlike `max_request_line_size`, this limit is checked when the request is dispatched,
+    /// so the rejection is passed to `dispatch_error_handler` as `DispatchError::UriTooLong`
+    /// (the `414 URI Too Long` response is returned by default).
+    ///
+    /// By default, request targets are limited only by `max_request_line_size`.
+    pub fn max_request_target_len(&mut self, len: usize) -> &mut Self {
+        self.dispatcher.set_max_target_len(Some(len));
+        self
+    }
+
+    /// Sets the maximum number of header fields of a request.
+    ///
+    /// If a request exceeds the limit, the `431 Request Header Fields Too Large` response will be returned.
+    ///
+    /// By default, the number of header fields is unlimited.
+    pub fn max_header_count(&mut self, n: usize) -> &mut Self {
+        self.options.values_mut().head_limits.max_header_count = n;
+        self
+    }
+
+    /// Sets the maximum size of each header field of a request in bytes.
+    ///
+    /// If a request exceeds the limit, the `431 Request Header Fields Too Large` response will be returned.
+    ///
+    /// The default value is `DecodeOptions::DEFAULT_MAX_HEADER_SIZE`.
+    pub fn max_header_field_size(&mut self, size: usize) -> &mut Self {
+        self.options.values_mut().head_limits.max_header_field_size = size;
+        self
+    }
+
+    /// Sets whether to reject request heads containing obsolete line foldings or bare CRs.
+    ///
+    /// Such requests are rejected with the `400 Bad Request` response.
+    ///
+    /// By default, the strict parsing mode is disabled.
+    pub fn strict_parsing(&mut self, enabled: bool) -> &mut Self {
+        self.options.values_mut().head_limits.strict = enabled;
+        self
+    }
+
+    /// Sets whether to reject requests whose framing may be interpreted differently by intermediaries
+    /// (i.e., HTTP request smuggling).
+    ///
+    /// If enabled, the following requests are rejected with the `400 Bad Request` response,
+    /// and the connections are closed:
+    /// - Requests having both `Content-Length` and `Transfer-Encoding` headers
+    /// - Requests having multiple conflicting (or malformed) `Content-Length` values
+    /// - Requests whose `Transfer-Encoding` does not end with `chunked`
+    /// - Chunked bodies containing bare LFs or malformed chunk extensions
+    ///
+    /// By default, the protection is disabled (i.e., such requests are handled as `httpcodec` does).
+    pub fn smuggling_protection(&mut self, enabled: bool) -> &mut Self {
+        self.options.smuggling_protection = enabled;
+        self
+    }
+
+    /// Sets how `TRACE` requests are handled.
+    ///
+    /// This applies only to the `TRACE` requests that cannot be dispatched to any handlers
+    /// (i.e., the handlers whose `HandleRequest::METHODS` contain `TRACE` take precedence).
+    ///
+    /// The default value is `TracePolicy::Reject` (i.e., `405 Method Not Allowed` is returned).
+    pub fn trace_policy(&mut self, policy: TracePolicy) -> &mut Self {
+        self.options.trace_policy = policy;
+        self
+    }
+
+    /// Sets the handler of `CONNECT` requests.
+    ///
+    /// If the handler opens a tunnel, `200 Connection Established` is returned to the client, and
+    /// then the bytes are relayed between the connection and the tunnel until either side closes it.
+    /// This applies to the `CONNECT` requests in the authority-form (e.g., `CONNECT example.com:443`)
+    /// and the ones that cannot be dispatched to any handlers added by `add_handler`.
+    ///
+    /// By default, `CONNECT` requests are rejected with `501 Not Implemented`.
+    pub fn connect_handler<H: HandleConnect>(&mut self, handler: H) -> &mut Self {
+        self.options.connect_handler = Some(ConnectHandler::new(handler));
+        self
+    }
+
+    /// Sets whether the `TCP_NODELAY` option is enabled on accepted sockets.
+    ///
+    /// The default value is `true`.
+    pub fn tcp_nodelay(&mut self, enabled: bool) -> &mut Self {
+        self.options.socket.nodelay = enabled;
+        self
+    }
+
+    /// Enables the `SO_KEEPALIVE` option on accepted sockets with the given probe interval.
+    ///
+    /// By default, the option is left to the OS default.
+    pub fn tcp_keepalive(&mut self, interval: Duration) -> &mut Self {
+        self.options.socket.keepalive = Some(interval);
+        self
+    }
+
+    /// Sets the `SO_LINGER` option on accepted sockets.
+    ///
+    /// By default, the option is left to the OS default.
+    pub fn tcp_linger(&mut self, linger: Duration) -> &mut Self {
+        self.options.socket.linger = Some(linger);
+        self
+    }
+
+    /// Sets the `SO_RCVBUF` option (i.e., the kernel level receive buffer size) on accepted sockets.
+    ///
+    /// By default, the option is left to the OS default.
+    pub fn tcp_recv_buffer_size(&mut self, n: usize) -> &mut Self {
+        self.options.socket.recv_buffer_size = Some(n);
+        self
+    }
+
+    /// Sets the `SO_SNDBUF` option (i.e., the kernel level send buffer size) on accepted sockets.
+    ///
+    /// By default, the option is left to the OS default.
+    pub fn tcp_send_buffer_size(&mut self, n: usize) -> &mut Self {
+        self.options.socket.send_buffer_size = Some(n);
+        self
+    }
+
+    /// Sets the threshold above which completed requests are regarded as slow.
+    ///
+    /// The duration is measured from the time the head part of a request has been received
+    /// to the time the response has been written.
+    /// Slow requests are logged at the `WARN` level and counted by `ServerMetrics::slow_requests`.
+    ///
+    /// By default, no threshold is set.
+    pub fn slow_request_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.options.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the timeout for receiving the head part of a request.
+    ///
+    /// The duration is measured from the time the connection becomes ready to read a new request.
+    /// If it expires, the `408 Request Timeout` response will be returned and the connection will be closed.
+    ///
+    /// By default, no timeout is set.
+    pub fn read_request_head_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.options.values_mut().read_request_head_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for receiving the body part of a request.
+    ///
+    /// The duration is measured from the time the head part of the request has been received.
+    /// If it expires, the `408 Request Timeout` response will be returned and the connection will be closed.
+    ///
+    /// By default, no timeout is set.
+    pub fn read_request_body_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.options.values_mut().read_request_body_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for writing a response to the client.
+    ///
+    /// The duration is measured from the time the response becomes ready to be written.
+    /// If it expires, the connection will be closed immediately.
+    ///
+    /// By default, no timeout is set.
+    pub fn write_response_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.options.values_mut().write_response_timeout = Some(timeout);
+        self
+    }
+
+    /// Enables the per-client rate limiting.
+    ///
+    /// Requests exceeding the limit are responded with `429 Too Many Requests`
+    /// and the `Retry-After` header.
+    ///
+    /// By default, the rate limiting is disabled.
+    pub fn rate_limit(&mut self, limit: RateLimit) -> &mut Self {
+        self.options.rate_limiter = Some(RateLimiter::new(limit));
+        self
+    }
+
+    /// Limits the bandwidth for reading requests from each connection.
+    ///
+    /// This can be overridden per handler (see `HandlerOptions::read_bandwidth_limit`).
+    ///
+    /// By default, the bandwidth is not limited.
+    pub fn read_bandwidth_limit(&mut self, limit: BandwidthLimit) -> &mut Self {
+        self.options.read_bandwidth_limit = Some(limit);
+        self
+    }
+
+    /// Limits the bandwidth for writing responses to each connection.
+    ///
+    /// This can be overridden per handler (see `HandlerOptions::write_bandwidth_limit`).
+    /// Note that responses are not written to sockets directly while the limit is in effect
+    /// (see `ServerBuilder::vectored_write_threshold`).
+    ///
+    /// By default, the bandwidth is not limited.
+    pub fn write_bandwidth_limit(&mut self, limit: BandwidthLimit) -> &mut Self {
+        self.options.write_bandwidth_limit = Some(limit);
+        self
+    }
+
+    /// Enables the load shedding.
+    ///
+    /// Requests shed by the server are responded with `503 Service Unavailable`.
+    /// See the documentation of `LoadShedding` for the details.
+    ///
+    /// By default, the load shedding is disabled.
+    pub fn load_shedding(&mut self, config: LoadShedding) -> &mut Self {
+        let active_connections = Arc::clone(&self.options.active_connections);
+        self.options.load_shedder = Some(LoadShedder::new(config, active_connections));
+        self
+    }
+
+    /// Sets whether to expose the live state of the server via `DebugHandler`.
+    ///
+    /// Note that `DebugHandler` has to be added by `add_handler` method separately.
+    /// Since the state is sensitive, the handler should not be reachable from untrusted clients.
+    ///
+    /// By default, the debug endpoint is disabled.
+    pub fn debug_endpoint(&mut self, enabled: bool) -> &mut Self {
+        self.options.debug_connections = if enabled { Some(Arc::default()) } else { None };
+        self
+    }
+
+    /// Enables the CSRF protection.
+    ///
+    /// Requests rejected by the protection are responded with `403 Forbidden`.
+    /// See the documentation of `CsrfProtection` for the details.
+    ///
+    /// By default, the CSRF protection is disabled.
+    pub fn csrf_protection(&mut self, protection: CsrfProtection) -> &mut Self {
+        self.options.csrf_protection = Some(Arc::new(protection));
+        self
+    }
+
+    /// Enables the validation of the `Host` header.
+    ///
+    /// Requests without a well-formed `Host` header are responded with `400 Bad Request`,
+    /// and the ones for hosts not in the allowlist are responded with `421 Misdirected Request`.
+    /// See the documentation of `HostValidation` for the details.
+    ///
+    /// By default, the `Host` header is not validated.
+    pub fn host_validation(&mut self, validation: HostValidation) -> &mut Self {
+        self.options.host_validation = Some(Arc::new(validation));
+        self
+    }
+
+    /// Adds the networks from which the server accepts connections.
+    ///
+    /// If one or more networks are allowed, connections from the other networks
+    /// are closed immediately after being accepted.
+    ///
+    /// By default, connections from any networks are accepted.
+    pub fn allow_cidrs<I>(&mut self, cidrs: I) -> &mut Self
+    where
+        I: IntoIterator<Item = Cidr>,
+    {
+        self.access_control.allow(cidrs);
+        self
+    }
+
+    /// Adds the networks from which the server rejects connections.
+    ///
+    /// Connections from the networks are closed immediately after being accepted.
+    /// This takes precedence over `allow_cidrs`.
+    pub fn deny_cidrs<I>(&mut self, cidrs: I) -> &mut Self
+    where
+        I: IntoIterator<Item = Cidr>,
+    {
+        self.access_control.deny(cidrs);
+        self
+    }
+
+    /// Sets the maximum number of accepted connections waiting to be spawned.
+    ///
+    /// If the number of such connections reaches the limit,
+    /// the server pauses accepting new connections until some of them are spawned.
+    ///
+    /// The default value is `1024`.
+    pub fn max_pending_connections(&mut self, n: usize) -> &mut Self {
+        self.max_pending_connections = n;
+        self
+    }
+
+    /// Sets the maximum number of connections that are being handled concurrently.
+    ///
+    /// If the number of such connections reaches the limit,
+    /// the server pauses accepting new connections until some of them are closed.
+    /// Clients exceeding the limit are left in the backlog of the listening socket.
+    ///
+    /// By default, the number of connections is unlimited.
+    pub fn max_active_connections(&mut self, n: usize) -> &mut Self {
+        self.max_active_connections = n;
+        self
+    }
+
+    /// Sets the policy of retrying to bind the listening socket (e.g., when the port is still in use).
+    ///
+    /// By default, the server fails if the first attempt to bind the socket fails.
+    pub fn bind_retry(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.bind_retry = Some(policy);
+        self
+    }
+
+    /// Adds an address to which the server is bound in addition to the one given to `ServerBuilder::new`.
+    ///
+    /// The connections accepted by any of the listening sockets are handled in the same manner,
+    /// and the settings of the listening socket (e.g., `bind_retry` and `listen_backlog`) are applied to each of them.
+    pub fn add_bind_addr(&mut self, addr: SocketAddr) -> &mut Self {
+        self.extra_bind_addrs.push(addr);
+        self
+    }
+
+    /// Sets whether the server starts if binding at least one of the addresses (see `add_bind_addr`) succeeds.
+    ///
+    /// If enabled, a failure of binding an address is logged, and the binding is retried in the background
+    /// while any of the other addresses is bound (or being bound).
+    /// The retries are made with the policy set by `bind_retry` (or `RetryPolicy::default()` if not set),
+    /// but their number is not limited.
+    /// The server fails only if binding all of the addresses fails.
+    ///
+    /// The default value is `false` (i.e., the server fails if binding any of the addresses fails).
+    pub fn partial_bind(&mut self, enabled: bool) -> &mut Self {
+        self.partial_bind = enabled;
+        self
+    }
+
+    /// Sets the policy of retrying to accept connections after an error (e.g., `EMFILE`).
+    ///
+    /// While backing off, the server does not accept new connections (the clients are left in the backlog).
+    /// The number of retries is reset whenever a connection is accepted.
+    /// If the retries are exhausted, the server fails.
+    ///
+    /// The default value is `RetryPolicy::default()` (i.e., the server never fails due to accept errors).
+    pub fn accept_retry(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.accept_retry = policy;
+        self
+    }
+
+    /// Sets whether to keep a spare file descriptor for the case the process runs out of them.
+    ///
+    /// If enabled, when accepting a connection fails with `EMFILE` or `ENFILE`, the server releases
+    /// the spare descriptor, accepts a pending connection and closes it immediately
+    /// (so that the client is notified of the overload rather than left waiting in the backlog),
+    /// and then reopens the spare descriptor.
+    /// In either case, the server backs off before accepting again (see `accept_retry`).
+    ///
+    /// This has an effect only on Unix platforms. The default value is `false`.
+    pub fn reserve_fd(&mut self, enabled: bool) -> &mut Self {
+        self.reserve_fd = enabled;
+        self
+    }
+
+    /// Sets the size of the backlog of the listening socket
+    /// (i.e., the maximum number of connections waiting to be accepted).
+    ///
+    /// Note that the size is silently capped by the OS (e.g., `net.core.somaxconn` on Linux).
+    ///
+    /// This has an effect only on Unix platforms. The default value is `1024`.
+    pub fn listen_backlog(&mut self, n: u32) -> &mut Self {
+        self.listen_backlog = Some(n.min(i32::MAX as u32) as i32);
+        self
+    }
+
+    /// Sets the periodic export of the gathered metrics (e.g., to a Prometheus Pushgateway).
+    ///
+    /// The export is executed by a task spawned via the `Spawn` given to `finish`,
+    /// and it stops when the server is dropped.
+    /// Failures of the export are logged, and the next export is made after the interval as usual.
+    ///
+    /// By default, the metrics are not exported.
+    pub fn metrics_push(&mut self, push: MetricsPush) -> &mut Self {
+        self.metrics_push = Some(push);
+        self
+    }
+
+    /// Sets the observer that is notified of the traffic of each request.
+    ///
+    /// By default, no observer is set.
+    pub fn connection_observer<O>(&mut self, observer: O) -> &mut Self
+    where
+        O: ConnectionObserver,
+    {
+        self.options.connection_observer = Some(SharedObserver::new(observer));
+        self
+    }
+
+    /// Sets the sink that receives the access log entry of each request (e.g., `AccessLogWriter`).
+    ///
+    /// By default, no sink is set.
+    pub fn access_log<S>(&mut self, sink: S) -> &mut Self
+    where
+        S: AccessLogSink,
+    {
+        self.options.access_log = Some(SharedAccessLog::new(sink));
+        self
+    }
+
+    /// Sets the sampler that captures a part of the requests and responses for debugging.
+    ///
+    /// By default, no sampler is set.
+    pub fn request_sampler(&mut self, sampler: RequestSampler) -> &mut Self {
+        self.options.request_sampler = Some(sampler);
+        self
+    }
+
+    /// Sets the function that is invoked when a request could not be dispatched to any handler.
+    ///
+    /// If the function returns `None`, the default error response will be returned to the client
+    /// (its status is `DispatchError::status`, e.g., `405 Method Not Allowed` with the `Allow` header
+    /// for the requests whose methods are not accepted by the handlers of the path).
+    ///
+    /// By default, no function is set.
+    pub fn dispatch_error_handler<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Req<()>, &DispatchError) -> Option<Res<Vec<u8>>> + Send + Sync + 'static,
+    {
+        self.options.dispatch_error_handler = Some(DispatchErrorHandler::new(f));
+        self
+    }
+
+    /// Sets the function that is invoked for each request before it is dispatched to a handler.
+    ///
+    /// The function can pass values to handlers via `Req::extensions_mut` method.
+    ///
+    /// By default, no function is set.
+    pub fn request_hook<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&mut Req<()>) + Send + Sync + 'static,
+    {
+        self.options.request_hook = Some(RequestHook(Arc::new(f)));
+        self
+    }
+
+    /// Sets the function that is invoked for each response from the handlers just before it is encoded.
+    ///
+    /// The function receives the route of the handler and the head part of the response,
+    /// whose status and header fields can be modified (e.g., for stripping internal headers or
+    /// adding `Cache-Control: no-store` to error responses).
+    /// The fields added by `default_header` and `HandlerOptions::header` are visible to the function,
+    /// while the `Connection` and framing headers (e.g., `Content-Length`) are set after it is invoked.
+    /// Note that the function is not invoked for the error responses made by the server itself
+    /// (e.g., `404 Not Found`).
+    ///
+    /// By default, no function is set.
+    pub fn response_hook<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(Route, &mut Res<()>) + Send + Sync + 'static,
+    {
+        self.options.response_hook = Some(ResponseHook(Arc::new(f)));
+        self
+    }
+
+    /// Sets the function that is invoked whenever a connection is terminated abnormally.
+    ///
+    /// The function receives the phase of the connection at which the error occurred,
+    /// the error itself, and the address of the peer.
+    /// Note that this is also invoked when the client closes (or resets) the connection.
+    ///
+    /// By default, no function is set.
+    pub fn on_connection_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(ConnectionPhase, &Error, SocketAddr) + Send + Sync + 'static,
+    {
+        self.options.connection_error_hook = Some(ConnectionErrorHook(Arc::new(f)));
+        self
+    }
+
+    /// Sets whether to honor the `X-HTTP-Method-Override` header of `POST` requests.
+    ///
+    /// If enabled, such requests are dispatched (and the request hook is invoked)
+    /// as if they had the method specified by the header.
+    /// This is useful for legacy clients that can only send `GET` and `POST` requests.
+    ///
+    /// By default, the header is ignored.
+    pub fn method_override(&mut self, enabled: bool) -> &mut Self {
+        self.options.method_override = enabled;
+        self
+    }
+
+    /// Adds the networks of the reverse proxies whose forwarding headers are trusted.
+    ///
+    /// For the requests received from such proxies, the scheme, host and port of `Req::url`
+    /// are reconstructed from the `Forwarded` header (RFC 7239) or the `X-Forwarded-Proto`,
+    /// `X-Forwarded-Host` and `X-Forwarded-Port` headers (the former takes precedence).
+    /// This is necessary for generating absolute URLs (e.g., redirects) behind TLS-terminating proxies.
+    /// Malformed headers are ignored.
+    ///
+    /// Note that the headers are ignored if the canonical base URL is set (see `ServerBuilder::base_url`).
+    ///
+    /// By default, no proxies are trusted (i.e., the forwarding headers are ignored).
+    pub fn trusted_proxies<I>(&mut self, cidrs: I) -> &mut Self
+    where
+        I: IntoIterator<Item = Cidr>,
+    {
+        self.options.trusted_proxies.extend(cidrs);
+        self
+    }
+
+    /// Sets the canonical external base URL of the server (e.g., `https://example.com/`).
+    ///
+    /// The scheme, host and port of the URL are used for `Req::url` (and hence the `Location`
+    /// headers made by `Req::redirect`) instead of the local address of the server socket.
+    /// The path and the other parts of the URL are ignored.
+    /// If this is set, the forwarding headers of trusted proxies are not used.
+    ///
+    /// By default, the base URL is derived from the local address (i.e., `http://{local_addr}/`).
+    pub fn base_url(&mut self, url: Url) -> &mut Self {
+        self.options.base_url = Some(url);
+        self
+    }
+
+    /// Adds a header field to every response from the handlers of the server
+    /// (e.g., `X-Environment: staging`).
+    ///
+    /// The field is not added if the response already has a field of the same name
+    /// (i.e., handlers can override it by setting the field themselves or by `HandlerOptions::header`).
+    /// Note that the error responses made by the server itself (e.g., `404 Not Found`) do not have the field.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a token, or `value` contains control characters (e.g., CR and LF) or
+    /// is longer than `header::MAX_FIELD_VALUE_LEN` bytes, an `ErrorKind::InvalidInput` error will be returned.
+    pub fn default_header(&mut self, name: &str, value: &str) -> Result<&mut Self> {
+        track!(header::validate_field(name, value))?;
+        Arc::make_mut(&mut self.options.default_headers).push((name.to_owned(), value.to_owned()));
+        Ok(self)
+    }
+
+    /// Registers a value shared by all handlers of the server.
+    ///
+    /// The value can be retrieved via `Req::state` method.
+    /// If a value of the same type has already been registered, it will be replaced.
+    pub fn state<T>(&mut self, value: T) -> &mut Self
+    where
+        T: Send + Sync + 'static,
+    {
+        Arc::get_mut(&mut self.options.state)
+            .expect("Never fails")
+            .insert(value);
+        self
+    }
+
+    /// Sets the clock used by the server.
+    ///
+    /// The clock is used for timeouts, rate limiting, bandwidth throttling and
+    /// measuring the duration of requests.
+    /// This is mainly intended for tests (see `ManualClock`).
+    ///
+    /// The default value is `SystemClock`.
+    pub fn clock<C: Clock>(&mut self, clock: C) -> &mut Self {
+        self.options.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Enables the warmup mode, and returns the gate that ends it.
+    ///
+    /// Until `ReadinessGate::set_ready` is called, the server accepts connections but responds to
+    /// all requests with `503 Service Unavailable` and the `Retry-After` header of `retry_after`.
+    ///
+    /// By default, the server is ready as soon as it starts.
+    pub fn warmup(&mut self, retry_after: Duration) -> ReadinessGate {
+        let gate = ReadinessGate::new(retry_after);
+        self.options.readiness_gate = Some(gate.clone());
+        gate
+    }
+
+    /// Returns a future that completes when the server built by this builder starts draining.
+    ///
+    /// See also `ServerHandle::drain`.
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        ShutdownSignal::new(Arc::clone(&self.options.drain))
+    }
+
     /// Builds a HTTP server with the given settings.
-    pub fn finish<S>(self, spawner: S) -> Server
+    pub fn finish<S>(mut self, spawner: S) -> Server
     where
         S: Spawn + Send + 'static,
     {
-        let logger = self.logger.new(o!("server" => self.bind_addr.to_string()));
+        let logger = self.options.reloadable.filter_logger(self.logger);
+        let logger = logger.new(o!("server" => self.bind_addr.to_string()));
 
-        info!(logger, "Starts HTTP server");
+        let options = EffectiveOptions::new(
+            &self.options,
+            self.max_pending_connections,
+            self.max_active_connections,
+        );
+        info!(logger, "Starts HTTP server"; "options" => ?options);
+        let dispatcher = self.dispatcher.finish();
+        self.options.install_debug_state(&dispatcher, options);
+        let drain = DrainState::watch(&self.options.drain);
+        let active_connections = ActiveConnections::new(
+            self.max_active_connections,
+            Arc::clone(&self.options.active_connections),
+            Arc::clone(&self.options.drain),
+        );
+        let is_server_alive = Arc::new(AtomicBool::new(true));
+        let spawner = spawner.boxed();
+        if let Some(push) = self.metrics_push {
+            spawner.spawn(push.into_task(
+                logger.clone(),
+                self.options.clock.clone(),
+                Arc::clone(&is_server_alive),
+            ));
+        }
         Server {
             logger,
-            metrics: ServerMetrics::new(self.metrics),
-            spawner: spawner.boxed(),
-            listener: Listener::Binding(TcpListener::bind(self.bind_addr)),
-            dispatcher: self.dispatcher.finish(),
-            is_server_alive: Arc::new(AtomicBool::new(true)),
-            options: self.options,
-            connected: Vec::new(),
-        }
+            metrics: ServerMetrics::new(self.metrics),
+            spawner,
+            listeners: Some(self.bind_addr)
+                .into_iter()
+                .chain(self.extra_bind_addrs)
+                .map(ListenerSlot::bind)
+                .collect(),
+            dispatcher,
+            access_control: self.access_control,
+            is_server_alive,
+            options: self.options,
+            connected: Vec::new(),
+            max_pending_connections: self.max_pending_connections,
+            active_connections,
+            is_accept_paused: false,
+            bind_retry: self.bind_retry,
+            partial_bind: self.partial_bind,
+            accept_retry: self.accept_retry,
+            listen_backlog: self.listen_backlog,
+            reserve_fd: if self.reserve_fd {
+                Some(ReserveFd::open())
+            } else {
+                None
+            },
+            drain,
+        }
+    }
+
+    /// Builds a `TestClient` that sends requests to an in-memory server with the given settings.
+    ///
+    /// Note that the settings about sockets, access control and the number of connections are ignored.
+    pub fn finish_test_client(mut self) -> TestClient {
+        let logger = self.options.reloadable.filter_logger(self.logger);
+        let logger = logger.new(o!("server" => self.bind_addr.to_string()));
+        let options = EffectiveOptions::new(
+            &self.options,
+            self.max_pending_connections,
+            self.max_active_connections,
+        );
+        let dispatcher = self.dispatcher.finish();
+        self.options.install_debug_state(&dispatcher, options);
+        TestClient::new(
+            logger,
+            ServerMetrics::new(self.metrics),
+            dispatcher,
+            self.bind_addr,
+            self.options,
+        )
+    }
+
+    /// Builds a `Simulation` that drives an in-memory server deterministically.
+    ///
+    /// `seed` determines the sizes of the partial I/O of the simulated connections.
+    /// Note that the clock of the server is replaced with a `ManualClock`.
+    pub fn finish_simulation(mut self, seed: u64) -> Simulation {
+        let clock = ManualClock::new();
+        self.options.clock = SharedClock::new(clock.clone());
+        let logger = self.options.reloadable.filter_logger(self.logger);
+        let logger = logger.new(o!("server" => self.bind_addr.to_string()));
+        let options = EffectiveOptions::new(
+            &self.options,
+            self.max_pending_connections,
+            self.max_active_connections,
+        );
+        let dispatcher = self.dispatcher.finish();
+        self.options.install_debug_state(&dispatcher, options);
+        Simulation::new(
+            logger,
+            ServerMetrics::new(self.metrics),
+            dispatcher,
+            self.bind_addr,
+            self.options,
+            clock,
+            seed,
+        )
     }
 }
 
@@ -146,60 +1198,395 @@ pub struct Server {
     logger: Logger,
     metrics: ServerMetrics,
     spawner: BoxSpawn,
-    listener: Listener,
+    listeners: Vec<ListenerSlot>,
     dispatcher: Dispatcher,
+    access_control: AccessControl,
     is_server_alive: Arc<AtomicBool>,
     options: ServerOptions,
     connected: Vec<(SocketAddr, Connected)>,
+    max_pending_connections: usize,
+    active_connections: ActiveConnections,
+    is_accept_paused: bool,
+    bind_retry: Option<RetryPolicy>,
+    partial_bind: bool,
+    accept_retry: RetryPolicy,
+    listen_backlog: Option<i32>,
+    reserve_fd: Option<ReserveFd>,
+    drain: DrainWatch,
 }
 impl Server {
     /// Returns a future that retrieves the address to which the server is bound.
+    ///
+    /// If the server is bound to multiple addresses (see `ServerBuilder::add_bind_addr`),
+    /// the address of the first listening socket (in the order the addresses were given) is retrieved.
+    ///
+    /// If the server is draining, the future will fail.
     pub fn local_addr(self) -> impl Future<Item = (Self, SocketAddr), Error = Error> {
-        match self.listener {
-            Listener::Listening { local_addr, .. } => Either::A(ok((self, local_addr))),
-            Listener::Binding(_) => {
-                let future = loop_fn(self, |mut this| {
-                    if fibers::fiber::with_current_context(|_| ()).is_none() {
-                        return Ok(Loop::Continue(this));
-                    }
+        if let Some(local_addr) = self.listening_addr() {
+            return Either::A(ok((self, local_addr)));
+        }
+        if self.listeners.iter().all(|l| l.listener.is_closed()) {
+            return Either::A(err(track!(Error::from(
+                ErrorKind::Other.cause("The server is draining")
+            ))));
+        }
+        let future = loop_fn(self, |mut this| {
+            if fibers::fiber::with_current_context(|_| ()).is_none() {
+                return Ok(Loop::Continue(this));
+            }
 
-                    track!(this.listener.poll())?;
-                    if let Listener::Listening { local_addr, .. } = this.listener {
-                        Ok(Loop::Break((this, local_addr)))
-                    } else {
-                        Ok(Loop::Continue(this))
-                    }
-                });
-                Either::B(future)
+            for i in 0..this.listeners.len() {
+                if this.listeners[i].local_addr().is_none() {
+                    track!(this.poll_listener(i))?;
+                }
             }
-        }
+            if let Some(local_addr) = this.listening_addr() {
+                Ok(Loop::Break((this, local_addr)))
+            } else {
+                Ok(Loop::Continue(this))
+            }
+        });
+        Either::B(future)
+    }
+
+    /// Returns the addresses to which the listening sockets of the server are bound.
+    ///
+    /// The sockets that are being bound (or waiting to retry binding) are not included.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|l| l.local_addr())
+            .collect()
     }
 
     /// Returns the metrics of the server.
     pub fn metrics(&self) -> &ServerMetrics {
         &self.metrics
     }
+
+    /// Returns the current effective options of the server.
+    pub fn options(&self) -> EffectiveOptions {
+        EffectiveOptions::new(
+            &self.options,
+            self.max_pending_connections,
+            self.active_connections.max,
+        )
+    }
+
+    /// Returns the `(method, path)` pairs of the handlers of the server.
+    ///
+    /// See also `ServerBuilder::routes`.
+    pub fn routes(&self) -> Vec<(&'static str, &str)> {
+        self.dispatcher.routes()
+    }
+
+    /// Returns a handle for updating the options of the server at runtime.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle::new(
+            self.logger.clone(),
+            Arc::clone(&self.options.reloadable),
+            Arc::clone(&self.options.drain),
+        )
+    }
+
+    /// Returns the raw file descriptor of the listening socket of the server.
+    ///
+    /// This returns `None` if the socket has not been bound yet (see `local_addr` method)
+    /// or the server is draining.
+    ///
+    /// The descriptor is owned by the server. To hand it over to another process
+    /// (e.g., for zero-downtime restarts by `exec`), duplicate it or clear its `FD_CLOEXEC` flag,
+    /// and then start draining the server via `ServerHandle::drain` method.
+    ///
+    /// Note that `Server` itself cannot be started from an inherited descriptor,
+    /// because the listening sockets of `fibers` can only be created by binding addresses.
+    /// For restarting a `Server` with another `Server`, start the new one with
+    /// `ServerBuilder::bind_retry`, and then drain the old one. The listening socket is closed
+    /// as soon as the draining starts, so the new server binds the address on its next retry
+    /// (the connections attempted in between are refused).
+    ///
+    /// If the server is bound to multiple addresses, the descriptor of the first listening socket is returned.
+    #[cfg(unix)]
+    pub fn listener_fd(&self) -> Option<RawFd> {
+        self.listeners.iter().find_map(|l| {
+            if let Listener::Listening { raw_fd, .. } = l.listener {
+                Some(raw_fd)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Handles the given request without any sockets, and returns a future that will result in the response.
+    ///
+    /// The request is encoded into bytes, and then decoded, dispatched and handled
+    /// in the same manner as the ones sent by real clients (the response is handled likewise).
+    /// So this is useful for testing the whole pipeline of the server (e.g., golden-file testing).
+    ///
+    /// The returned future has to be executed on fibers (e.g., by `fibers_global::execute`).
+    /// Note that the server does not need to be polled for handling the request.
+    pub fn call(&self, req: Request<Vec<u8>>) -> TestReply {
+        let local_addr = self.listening_addr().unwrap_or(self.listeners[0].bind_addr);
+        TestReply::new(req, |stream| {
+            track!(Connection::with_transport(
+                self.logger.clone(),
+                self.metrics.clone(),
+                stream,
+                ([127, 0, 0, 1], 0).into(),
+                local_addr,
+                self.dispatcher.clone(),
+                Arc::clone(&self.is_server_alive),
+                &self.options,
+            ))
+        })
+    }
 }
 impl Future for Server {
     type Item = ();
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.drain.is_draining() {
+            return Ok(self.poll_drain());
+        }
+        loop {
+            let is_paused = match track!(self.accept())? {
+                None => return Ok(Async::Ready(())),
+                Some(is_paused) => is_paused,
+            };
+            let spawned = track!(self.spawn_connected())?;
+            self.metrics
+                .pending_connections
+                .set(self.connected.len() as f64);
+            if !(is_paused && spawned > 0) {
+                break;
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+impl Server {
+    /// Returns the address of the first listening socket.
+    fn listening_addr(&self) -> Option<SocketAddr> {
+        self.listeners.iter().find_map(|l| l.local_addr())
+    }
+
+    /// Closes the listening sockets, and waits until all the connections are closed.
+    fn poll_drain(&mut self) -> Async<()> {
+        if self.listeners.iter().any(|l| !l.listener.is_closed()) {
+            info!(self.logger, "Starts draining";
+                  "active_connections" => self.active_connections.count(),
+                  "pending_connections" => self.connected.len());
+            for slot in &mut self.listeners {
+                slot.listener = Listener::Closed;
+                slot.backoff = None;
+            }
+            self.connected.clear();
+            self.metrics.pending_connections.set(0.0);
+        }
+        if self.active_connections.count() == 0 {
+            info!(self.logger, "Drained");
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        }
+    }
+
+    /// Accepts new connections until the listeners would block or the server becomes busy.
+    ///
+    /// Returns `Some(true)` if accepting was paused due to the limits of the server,
+    /// or `None` if all the listeners have been closed.
+    fn accept(&mut self) -> Result<Option<bool>> {
+        let mut closed = 0;
+        for i in 0..self.listeners.len() {
+            if track!(self.accept_from(i))? {
+                return Ok(Some(true));
+            }
+            if self.listeners[i].listener.is_closed() {
+                closed += 1;
+            }
+        }
+        if closed == self.listeners.len() {
+            warn!(self.logger, "The socket of the HTTP server has been closed");
+            return Ok(None);
+        }
+        Ok(Some(false))
+    }
+
+    /// Accepts new connections from the `i`-th listener.
+    ///
+    /// Returns `true` if accepting was paused due to the limits of the server.
+    fn accept_from(&mut self, i: usize) -> Result<bool> {
         loop {
-            match track!(self.listener.poll())? {
+            let is_busy = self.connected.len() >= self.max_pending_connections
+                || self.active_connections.is_saturated();
+            if is_busy {
+                if !self.is_accept_paused {
+                    debug!(self.logger, "Paused accepting new connections";
+                           "pending" => self.connected.len());
+                    self.metrics.accept_pauses.increment();
+                    self.is_accept_paused = true;
+                }
+                return Ok(true);
+            }
+            self.is_accept_paused = false;
+
+            match track!(self.poll_listener(i))? {
                 Async::NotReady => {
-                    break;
+                    return Ok(false);
                 }
                 Async::Ready(None) => {
-                    warn!(self.logger, "The socket of the HTTP server has been closed");
-                    return Ok(Async::Ready(()));
+                    self.listeners[i].listener = Listener::Closed;
+                    return Ok(false);
                 }
                 Async::Ready(Some((connected, addr))) => {
-                    self.connected.push((addr, connected));
+                    if self.access_control.is_allowed(addr.ip()) {
+                        self.connected.push((addr, connected));
+                    } else {
+                        debug!(self.logger, "Rejected a client"; "client" => addr.to_string());
+                        self.metrics.rejected_tcp_clients.increment();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Polls the `i`-th listener, retrying to bind it (see `ServerBuilder::bind_retry`) or
+    /// to accept connections (see `ServerBuilder::accept_retry`) after errors.
+    fn poll_listener(&mut self, i: usize) -> Result<Async<Option<(Connected, SocketAddr)>>> {
+        loop {
+            let slot = &mut self.listeners[i];
+            if let Some(ref mut backoff) = slot.backoff {
+                if let Ok(Async::NotReady) = backoff.poll() {
+                    return Ok(Async::NotReady);
+                }
+                slot.backoff = None;
+                if let Listener::Unbound = slot.listener {
+                    info!(self.logger, "Retries binding the listening socket";
+                          "bind_addr" => slot.bind_addr.to_string(), "retries" => slot.retries);
+                    slot.listener = Listener::Binding(TcpListener::bind(slot.bind_addr));
+                } else {
+                    info!(self.logger, "Resumes accepting new connections";
+                          "bind_addr" => slot.bind_addr.to_string(), "retries" => slot.retries);
+                }
+            }
+
+            let is_binding = matches!(slot.listener, Listener::Binding(_));
+            let result = slot.listener.poll();
+            if is_binding {
+                if let Listener::Listening { local_addr, .. } = slot.listener {
+                    info!(self.logger, "Bound the listening socket";
+                          "local_addr" => local_addr.to_string());
+                    slot.retries = 0;
+                    track!(self.configure_listener(i))?;
+                }
+            }
+            let e = match result {
+                Ok(Async::Ready(Some(connected))) => {
+                    self.listeners[i].retries = 0;
+                    return Ok(Async::Ready(Some(connected)));
                 }
+                Ok(polled) => return Ok(polled),
+                Err(e) => e,
+            };
+
+            let is_bind_error = matches!(self.listeners[i].listener, Listener::Binding(_));
+            let (policy, phase) = if is_bind_error {
+                self.metrics.bind_errors.increment();
+                self.listeners[i].listener = Listener::Unbound;
+                (self.bind_retry, "bind")
+            } else if is_fd_exhaustion(&e) {
+                self.metrics.fd_exhaustion_errors.increment();
+                self.shed_with_reserve_fd(i);
+                (Some(self.accept_retry), "accept")
+            } else {
+                self.metrics.accept_errors.increment();
+                (Some(self.accept_retry), "accept")
+            };
+            let bind_addr = self.listeners[i].bind_addr;
+            let retries = self.listeners[i].retries;
+            let backoff = match policy.and_then(|p| p.backoff(retries)) {
+                Some(backoff) => backoff,
+                None if is_bind_error && self.retries_in_background(i) => {
+                    let policy = self.bind_retry.unwrap_or_default().unlimited();
+                    policy.backoff(retries).expect("Never fails")
+                }
+                None => {
+                    error!(self.logger, "Gave up retrying"; "phase" => phase,
+                           "bind_addr" => bind_addr.to_string(),
+                           "retries" => retries, "error" => %e);
+                    return Err(track!(e));
+                }
+            };
+            warn!(self.logger, "Backs off after an error of the listening socket";
+                  "phase" => phase, "bind_addr" => bind_addr.to_string(), "retries" => retries,
+                  "backoff" => ?backoff, "error" => %e);
+            let slot = &mut self.listeners[i];
+            slot.retries += 1;
+            slot.backoff = Some(self.options.clock.sleep(backoff));
+        }
+    }
+
+    /// Returns `true` if the binding of the `i`-th listener should be retried in the background
+    /// (see `ServerBuilder::partial_bind`).
+    fn retries_in_background(&self, i: usize) -> bool {
+        self.partial_bind
+            && self.listeners.iter().enumerate().any(|(j, l)| {
+                j != i
+                    && matches!(
+                        l.listener,
+                        Listener::Binding(_) | Listener::Listening { .. }
+                    )
+            })
+    }
+
+    /// Applies the settings of the listening socket that are applied after it is bound
+    /// (see `ServerBuilder::listen_backlog`).
+    #[cfg(unix)]
+    fn configure_listener(&mut self, i: usize) -> Result<()> {
+        let raw_fd = match self.listeners[i].listener {
+            Listener::Listening { raw_fd, .. } => raw_fd,
+            _ => return Ok(()),
+        };
+        if let Some(backlog) = self.listen_backlog {
+            // The socket has already been listening with the default size (`1024`),
+            // but calling `listen(2)` again updates the size.
+            if unsafe { libc::listen(raw_fd, backlog) } != 0 {
+                let e = io::Error::last_os_error();
+                return Err(track!(Error::from(e); backlog));
             }
+            debug!(self.logger, "Set the backlog size"; "backlog" => backlog);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn configure_listener(&mut self, _i: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Releases the spare file descriptor (see `ServerBuilder::reserve_fd`) to accept
+    /// a pending connection and close it immediately.
+    fn shed_with_reserve_fd(&mut self, i: usize) {
+        let reserve = match self.reserve_fd {
+            None => return,
+            Some(ref mut reserve) => reserve,
+        };
+        if !reserve.release() {
+            return;
+        }
+        if let Ok(Async::Ready(Some((_connected, addr)))) = self.listeners[i].listener.poll() {
+            warn!(self.logger, "Closed a connection due to the exhaustion of file descriptors";
+                  "client" => addr.to_string());
         }
+        if !reserve.reopen() {
+            warn!(self.logger, "Cannot reopen the spare file descriptor");
+        }
+    }
 
+    /// Spawns the connections that have been registered to the poller, and returns the number of them.
+    fn spawn_connected(&mut self) -> Result<usize> {
+        let mut spawned = 0;
         let mut i = 0;
         while i < self.connected.len() {
             if let Async::Ready(stream) = track!(self.connected[i].1.poll().map_err(Error::from))? {
@@ -210,16 +1597,22 @@ impl Future for Server {
                     logger,
                     self.metrics.clone(),
                     stream,
+                    client_addr,
                     self.dispatcher.clone(),
                     Arc::clone(&self.is_server_alive),
                     &self.options,
                 ))?;
-                self.spawner.spawn(future);
+                let guard = self.active_connections.acquire();
+                self.spawner.spawn(future.then(move |result| {
+                    drop(guard);
+                    result
+                }));
+                spawned += 1;
             } else {
                 i += 1;
             }
         }
-        Ok(Async::NotReady)
+        Ok(spawned)
     }
 }
 impl Drop for Server {
@@ -228,13 +1621,52 @@ impl Drop for Server {
     }
 }
 
+/// A listening socket of the server, and the state of retrying to bind it or to accept connections.
+#[derive(Debug)]
+struct ListenerSlot {
+    listener: Listener,
+    bind_addr: SocketAddr,
+    // The number of consecutive retries of binding or accepting.
+    retries: usize,
+    backoff: Option<Sleep>,
+}
+impl ListenerSlot {
+    fn bind(bind_addr: SocketAddr) -> Self {
+        ListenerSlot {
+            listener: Listener::Binding(TcpListener::bind(bind_addr)),
+            bind_addr,
+            retries: 0,
+            backoff: None,
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        if let Listener::Listening { local_addr, .. } = self.listener {
+            Some(local_addr)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Listener {
     Binding(TcpListenerBind),
+
+    // Waiting to retry binding (see `ServerBuilder::bind_retry`).
+    Unbound,
     Listening {
         incoming: Incoming,
         local_addr: SocketAddr,
+        #[cfg(unix)]
+        raw_fd: RawFd,
     },
+    Closed,
+}
+impl Listener {
+    fn is_closed(&self) -> bool {
+        matches!(*self, Listener::Closed)
+    }
 }
 impl Stream for Listener {
     type Item = (Connected, SocketAddr);
@@ -246,10 +1678,14 @@ impl Stream for Listener {
                 Listener::Binding(ref mut f) => {
                     if let Async::Ready(listener) = track!(f.poll().map_err(Error::from))? {
                         let local_addr = track!(listener.local_addr().map_err(Error::from))?;
+                        #[cfg(unix)]
+                        let raw_fd = listener.with_inner(|l| l.as_raw_fd());
                         let incoming = listener.incoming();
                         Listener::Listening {
                             incoming,
                             local_addr,
+                            #[cfg(unix)]
+                            raw_fd,
                         }
                     } else {
                         return Ok(Async::NotReady);
@@ -260,15 +1696,683 @@ impl Stream for Listener {
                 } => {
                     return track!(incoming.poll().map_err(Error::from));
                 }
+                Listener::Unbound => return Ok(Async::NotReady),
+                Listener::Closed => return Ok(Async::Ready(None)),
             };
             *self = next;
         }
     }
 }
 
+/// Returns `true` if `e` is `EMFILE` or `ENFILE`.
+fn is_fd_exhaustion(e: &Error) -> bool {
+    e.concrete_cause::<io::Error>()
+        .and_then(|e| e.raw_os_error())
+        .map_or(false, |code| code == libc::EMFILE || code == libc::ENFILE)
+}
+
+/// A spare file descriptor that is released when the process runs out of descriptors.
+#[derive(Debug)]
+struct ReserveFd(Option<File>);
+impl ReserveFd {
+    fn open() -> Self {
+        let mut this = ReserveFd(None);
+        this.reopen();
+        this
+    }
+
+    /// Closes the descriptor, and returns `true` if it was open.
+    fn release(&mut self) -> bool {
+        self.0.take().is_some()
+    }
+
+    /// Opens the descriptor if it is not open, and returns `true` if it is open.
+    fn reopen(&mut self) -> bool {
+        #[cfg(unix)]
+        {
+            if self.0.is_none() {
+                self.0 = File::open("/dev/null").ok();
+            }
+        }
+        self.0.is_some()
+    }
+}
+
+/// Counter of the connections being handled.
+///
+/// If the counter is saturated or the server is draining,
+/// `Server` is notified via the channel when a connection is closed.
+#[derive(Debug)]
+struct ActiveConnections {
+    count: Arc<AtomicUsize>,
+    max: usize,
+    drain: Arc<DrainState>,
+    closed_tx: mpsc::Sender<()>,
+    closed_rx: mpsc::Receiver<()>,
+}
+impl ActiveConnections {
+    fn new(max: usize, count: Arc<AtomicUsize>, drain: Arc<DrainState>) -> Self {
+        let (closed_tx, closed_rx) = mpsc::channel();
+        ActiveConnections {
+            count,
+            max,
+            drain,
+            closed_tx,
+            closed_rx,
+        }
+    }
+
+    fn count(&mut self) -> usize {
+        // Drains the notifications so that the current fiber will be woken up by the next one.
+        while let Ok(Async::Ready(Some(()))) = self.closed_rx.poll() {}
+        self.count.load(Ordering::SeqCst)
+    }
+
+    fn is_saturated(&mut self) -> bool {
+        self.count() >= self.max
+    }
+
+    fn acquire(&self) -> ActiveConnectionGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        ActiveConnectionGuard {
+            count: Arc::clone(&self.count),
+            max: self.max,
+            drain: Arc::clone(&self.drain),
+            closed_tx: self.closed_tx.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ActiveConnectionGuard {
+    count: Arc<AtomicUsize>,
+    max: usize,
+    drain: Arc<DrainState>,
+    closed_tx: mpsc::Sender<()>,
+}
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        if self.count.fetch_sub(1, Ordering::SeqCst) >= self.max || self.drain.is_draining() {
+            let _ = self.closed_tx.send(());
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ServerOptions {
     pub read_buffer_size: usize,
     pub write_buffer_size: usize,
+    pub max_read_buffer_size: usize,
+    pub max_write_buffer_size: usize,
+    pub vectored_write_threshold: usize,
+    pub write_high_watermark: usize,
+    pub max_decompressed_request_body_size: Option<usize>,
+    pub max_drained_request_body_size: usize,
+    pub slow_request_threshold: Option<Duration>,
+    pub path_decoding: Option<PathDecoding>,
     pub decode_options: DecodeOptions,
+    pub dispatch_error_handler: Option<DispatchErrorHandler>,
+    pub rate_limiter: Option<RateLimiter>,
+    pub read_bandwidth_limit: Option<BandwidthLimit>,
+    pub write_bandwidth_limit: Option<BandwidthLimit>,
+    pub load_shedder: Option<LoadShedder>,
+    pub readiness_gate: Option<ReadinessGate>,
+    pub clock: SharedClock,
+    pub debug_connections: Option<Arc<ConnectionRegistry>>,
+    pub csrf_protection: Option<Arc<CsrfProtection>>,
+    pub host_validation: Option<Arc<HostValidation>>,
+    pub smuggling_protection: bool,
+    pub trace_policy: TracePolicy,
+    pub connect_handler: Option<ConnectHandler>,
+    pub connection_observer: Option<SharedObserver>,
+    pub access_log: Option<SharedAccessLog>,
+    pub request_sampler: Option<RequestSampler>,
+    pub request_hook: Option<RequestHook>,
+    pub response_hook: Option<ResponseHook>,
+    pub connection_error_hook: Option<ConnectionErrorHook>,
+    pub method_override: bool,
+    pub trusted_proxies: Vec<Cidr>,
+    pub base_url: Option<Url>,
+    pub default_headers: Arc<Vec<(String, String)>>,
+    pub state: Arc<Extensions>,
+    pub request_ids: Arc<AtomicU64>,
+    pub connection_ids: Arc<AtomicU64>,
+    pub reloadable: Arc<ReloadableOptions>,
+    pub drain: Arc<DrainState>,
+    pub active_connections: Arc<AtomicUsize>,
+    pub socket: SocketOptions,
+}
+impl ServerOptions {
+    fn values_mut(&mut self) -> &mut ReloadableValues {
+        Arc::get_mut(&mut self.reloadable)
+            .expect("Never fails")
+            .values_mut()
+    }
+
+    /// Registers the state exposed by `DebugHandler` as a shared value if the debug endpoint is enabled.
+    fn install_debug_state(&mut self, dispatcher: &Dispatcher, options: EffectiveOptions) {
+        if let Some(ref connections) = self.debug_connections {
+            let state = DebugState::new(
+                &dispatcher.routes(),
+                options,
+                Arc::clone(&self.reloadable),
+                Arc::clone(connections),
+                self.clock.clone(),
+            );
+            Arc::get_mut(&mut self.state)
+                .expect("Never fails")
+                .insert(state);
+        }
+    }
+}
+
+type RequestHookFn = dyn Fn(&mut Req<()>) + Send + Sync + 'static;
+
+#[derive(Clone)]
+pub struct RequestHook(Arc<RequestHookFn>);
+impl RequestHook {
+    pub fn call(&self, req: &mut Req<()>) {
+        (self.0)(req)
+    }
+}
+impl fmt::Debug for RequestHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RequestHook(_)")
+    }
+}
+
+type ResponseHookFn = dyn Fn(Route, &mut Res<()>) + Send + Sync + 'static;
+
+#[derive(Clone)]
+pub struct ResponseHook(Arc<ResponseHookFn>);
+impl ResponseHook {
+    pub fn call(&self, route: Route, res: &mut Res<()>) {
+        (self.0)(route, res)
+    }
+}
+impl fmt::Debug for ResponseHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ResponseHook(_)")
+    }
+}
+
+type ConnectionErrorHookFn = dyn Fn(ConnectionPhase, &Error, SocketAddr) + Send + Sync + 'static;
+
+#[derive(Clone)]
+pub struct ConnectionErrorHook(Arc<ConnectionErrorHookFn>);
+impl ConnectionErrorHook {
+    pub fn call(&self, phase: ConnectionPhase, error: &Error, peer: SocketAddr) {
+        (self.0)(phase, error, peer)
+    }
+}
+impl fmt::Debug for ConnectionErrorHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ConnectionErrorHook(_)")
+    }
+}
+
+/// The options applied to the accepted sockets (see `ServerBuilder::tcp_nodelay` and the like).
+///
+/// `SO_REUSEPORT` is deliberately not supported. It has to be set on the listening socket
+/// before the socket is bound, but `fibers` creates and binds the listening sockets by itself
+/// and provides no way to adopt a socket configured elsewhere.
+/// To run several processes on the same port, put a load balancer in front of them.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    pub linger: Option<Duration>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+}
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.with_inner(|s| {
+            s.set_nodelay(self.nodelay)?;
+            if let Some(interval) = self.keepalive {
+                s.set_keepalive(Some(interval))?;
+            }
+            if let Some(linger) = self.linger {
+                s.set_linger(Some(linger))?;
+            }
+            if let Some(n) = self.recv_buffer_size {
+                s.set_recv_buffer_size(n)?;
+            }
+            if let Some(n) = self.send_buffer_size {
+                s.set_send_buffer_size(n)?;
+            }
+            Ok(())
+        })
+    }
+}
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            linger: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{spawn_server, wait_until, Hello};
+    use crate::{Reply, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use httpcodec::{BodyDecoder, BodyEncoder};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    #[test]
+    fn is_fd_exhaustion_works() {
+        let e = Error::from(io::Error::from_raw_os_error(libc::EMFILE));
+        assert!(is_fd_exhaustion(&e));
+        let e = Error::from(io::Error::from_raw_os_error(libc::ENFILE));
+        assert!(is_fd_exhaustion(&e));
+        let e = Error::from(io::Error::from_raw_os_error(libc::ECONNABORTED));
+        assert!(!is_fd_exhaustion(&e));
+        let e = Error::from(io::Error::new(io::ErrorKind::Other, "foo"));
+        assert!(!is_fd_exhaustion(&e));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reserve_fd_works() {
+        let mut reserve = ReserveFd::open();
+        assert!(reserve.release());
+        assert!(!reserve.release());
+        assert!(reserve.reopen());
+        assert!(reserve.release());
+    }
+
+    #[test]
+    fn socket_options_work() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder
+            .tcp_nodelay(false)
+            .tcp_keepalive(Duration::from_secs(30))
+            .tcp_linger(Duration::from_secs(1))
+            .tcp_recv_buffer_size(16 * 1024)
+            .tcp_send_buffer_size(16 * 1024);
+        let addr = spawn_server(builder);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert!(buf.ends_with(b"hello"));
+    }
+
+    #[test]
+    fn max_active_connections_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.max_active_connections(1);
+        let server = builder.finish(fibers_global::handle());
+        let metrics = server.metrics().clone();
+        let (server, addr) = fibers_global::execute(server.local_addr()).unwrap();
+        thread::spawn(move || fibers_global::execute(server).unwrap());
+
+        let req = b"GET /hello HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let mut buf = [0; 1024];
+        let mut client0 = TcpStream::connect(addr).unwrap();
+        client0.write_all(req).unwrap();
+        assert!(client0.read(&mut buf).unwrap() > 0);
+
+        // The second client has to wait until the first one is closed.
+        let mut client1 = TcpStream::connect(addr).unwrap();
+        client1
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        client1.write_all(req).unwrap();
+        assert!(client1.read(&mut buf).is_err());
+        assert_eq!(metrics.accept_pauses(), 1);
+
+        std::mem::drop(client0);
+        client1.set_read_timeout(None).unwrap();
+        assert!(client1.read(&mut buf).unwrap() > 0);
+    }
+
+    #[test]
+    fn server_call_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        let server = builder.finish(fibers_global::handle());
+
+        let req = httpcodec::Request::new(
+            httpcodec::Method::new("GET").unwrap(),
+            httpcodec::RequestTarget::new("/hello").unwrap(),
+            httpcodec::HttpVersion::V1_1,
+            Vec::new(),
+        );
+        let res = fibers_global::execute(server.call(req)).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.header().get_field("Connection"), Some("keep-alive"));
+        assert_eq!(res.body(), b"hello");
+    }
+
+    #[test]
+    fn request_logger_works() {
+        use slog::{Drain, Logger, Record, Serializer, KV};
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+
+        struct Collect(Arc<Mutex<Vec<String>>>);
+        impl Drain for Collect {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                values: &slog::OwnedKVList,
+            ) -> std::result::Result<(), slog::Never> {
+                let mut line = Line(record.msg().to_string());
+                record.kv().serialize(record, &mut line).unwrap();
+                values.serialize(record, &mut line).unwrap();
+                self.0.lock().unwrap().push(line.0);
+                Ok(())
+            }
+        }
+
+        struct Line(String);
+        impl Serializer for Line {
+            fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+                self.0 += &format!(" {}={}", key, val);
+                Ok(())
+            }
+        }
+
+        struct LogHello;
+        impl HandleRequest for LogHello {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/hello";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                info!(req.logger(), "Hello");
+                Box::new(ok(Res::new(Status::Ok, "hello".to_owned())))
+            }
+        }
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(LogHello).unwrap();
+        builder.logger(Logger::root(Collect(Arc::clone(&lines)).fuse(), o!()));
+        let client = builder.finish_test_client();
+
+        let req = httpcodec::Request::new(
+            httpcodec::Method::new("GET").unwrap(),
+            httpcodec::RequestTarget::new("/hello").unwrap(),
+            httpcodec::HttpVersion::V1_1,
+            Vec::new(),
+        );
+        let res = fibers_global::execute(client.send(req)).unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        let mut req = httpcodec::Request::new(
+            httpcodec::Method::new("GET").unwrap(),
+            httpcodec::RequestTarget::new("/hello").unwrap(),
+            httpcodec::HttpVersion::V1_1,
+            Vec::new(),
+        );
+        req.header_mut()
+            .add_field(httpcodec::HeaderField::new("X-Request-Id", "foo").unwrap());
+        let res = fibers_global::execute(client.send(req)).unwrap();
+        assert_eq!(res.status_code(), 200);
+
+        let lines = lines.lock().unwrap();
+        let lines = lines
+            .iter()
+            .filter(|l| l.starts_with("Hello") || l.starts_with("Request completed"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Hello request_id=0 path=/hello method=GET"));
+        assert!(lines[1].starts_with("Request completed status=200 request_id=0"));
+        assert!(lines[2].starts_with("Hello request_id=foo path=/hello method=GET"));
+        assert!(lines[3].starts_with("Request completed status=200 request_id=foo"));
+    }
+
+    #[test]
+    fn default_headers_work() {
+        struct Version;
+        impl HandleRequest for Version {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/version";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                let mut res = Res::new(Status::Ok, "1.0".to_owned());
+                res.add_header("X-Version", "1.0").unwrap();
+                Box::new(ok(res))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Hello).unwrap();
+        let options = HandlerOptions::default().header("X-Environment", "canary");
+        builder.add_handler_with_options(Version, options).unwrap();
+        builder
+            .default_header("X-Environment", "staging")
+            .unwrap()
+            .default_header("X-Version", "0.0")
+            .unwrap();
+        assert!(builder.default_header("X Environment", "staging").is_err());
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.contains("X-Environment: staging\r\n"),
+            "{}",
+            received
+        );
+        assert!(received.contains("X-Version: 0.0\r\n"), "{}", received);
+
+        conn.write(b"GET /version HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.contains("X-Environment: canary\r\n"),
+            "{}",
+            received
+        );
+        assert!(received.contains("X-Version: 1.0\r\n"), "{}", received);
+        assert!(!received.contains("staging"), "{}", received);
+        assert!(!received.contains("X-Version: 0.0"), "{}", received);
+    }
+
+    #[test]
+    fn response_hook_works() {
+        struct Internal;
+        impl HandleRequest for Internal {
+            const METHOD: &'static str = "GET";
+            const PATH: &'static str = "/internal/*";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                let status = if req.wildcards()[0] == "fail" {
+                    Status::InternalServerError
+                } else {
+                    Status::Ok
+                };
+                let mut res = Res::new(status, "internal".to_owned());
+                res.add_header("X-Internal-Host", "db-1").unwrap();
+                Box::new(ok(res))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_handler(Internal).unwrap();
+        builder
+            .default_header("X-Internal-Version", "1.2.3")
+            .unwrap();
+        builder.response_hook(|route, res| {
+            assert_eq!(res.header().get_field("X-Internal-Version"), Some("1.2.3"));
+            res.remove_header("x-internal-version");
+            if route.path() == "/internal/*" {
+                assert_eq!(res.remove_header("X-Internal-Host"), 1);
+            }
+            if res.status_code() >= 500 {
+                res.add_header("Cache-Control", "no-store").unwrap();
+                res.set_status(Status::ServiceUnavailable);
+            }
+        });
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(b"GET /hello HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+        assert!(!received.contains("X-Internal"), "{}", received);
+        assert!(received.ends_with("hello"), "{}", received);
+
+        conn.write(b"GET /internal/foo HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+        assert!(!received.contains("X-Internal"), "{}", received);
+        assert!(!received.contains("Cache-Control"), "{}", received);
+
+        conn.write(b"GET /internal/fail HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            received
+        );
+        assert!(
+            received.contains("Cache-Control: no-store\r\n"),
+            "{}",
+            received
+        );
+        assert!(!received.contains("X-Internal"), "{}", received);
+        assert!(received.ends_with("internal"), "{}", received);
+    }
+
+    #[test]
+    fn bind_retry_works() {
+        let occupier = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = occupier.local_addr().unwrap();
+
+        let mut builder = ServerBuilder::new(addr);
+        builder.add_handler(Hello).unwrap();
+        builder.bind_retry(
+            RetryPolicy::new()
+                .initial_backoff(Duration::from_millis(50))
+                .max_backoff(Duration::from_millis(100)),
+        );
+        let server = builder.finish(fibers_global::handle());
+        let metrics = server.metrics().clone();
+        thread::spawn(move || {
+            // Releases the address once the server has failed to bind it
+            wait_until(|| metrics.bind_errors() > 0);
+            drop(occupier);
+        });
+        let (server, local_addr) = fibers_global::execute(server.local_addr()).unwrap();
+        assert_eq!(local_addr, addr);
+        assert!(server.metrics().bind_errors() > 0);
+
+        // Without retries, the server fails immediately
+        let occupier = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut builder = ServerBuilder::new(occupier.local_addr().unwrap());
+        builder.add_handler(Hello).unwrap();
+        let server = builder.finish(fibers_global::handle());
+        assert!(fibers_global::execute(server.local_addr()).is_err());
+    }
+
+    #[test]
+    fn listen_backlog_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.listen_backlog(16);
+        let addr = spawn_server(builder);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert!(buf.ends_with(b"hello"));
+    }
+
+    #[test]
+    fn partial_bind_works() {
+        let occupier = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let occupied_addr = occupier.local_addr().unwrap();
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_bind_addr(occupied_addr);
+        builder.partial_bind(true);
+        builder.bind_retry(
+            RetryPolicy::new()
+                .initial_backoff(Duration::from_millis(50))
+                .max_backoff(Duration::from_millis(100))
+                .max_retries(0),
+        );
+        let server = builder.finish(fibers_global::handle());
+        let (server, addr) = fibers_global::execute(server.local_addr()).unwrap();
+        assert_ne!(addr, occupied_addr);
+        assert_eq!(server.local_addrs(), vec![addr]);
+        let metrics = server.metrics().clone();
+        thread::spawn(move || {
+            fibers_global::execute(server).unwrap();
+        });
+        wait_until(|| metrics.bind_errors() > 0);
+
+        // The failed address is bound in the background
+        drop(occupier);
+        wait_until(|| TcpStream::connect(occupied_addr).is_ok());
+        for addr in vec![addr, occupied_addr] {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client
+                .write_all(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).unwrap();
+            assert!(buf.ends_with(b"hello"));
+        }
+
+        // Without the policy, the server fails if binding any of the addresses fails
+        let occupier = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_bind_addr(occupier.local_addr().unwrap());
+        let server = builder.finish(fibers_global::handle());
+        assert!(fibers_global::execute(server).is_err());
+    }
 }
@@ -0,0 +1,415 @@
+use bytecodec::io::{IoDecodeExt, ReadBuf};
+use bytecodec::{self, ByteCount, Decode, Eos};
+use httpcodec::Header;
+use std::fmt;
+
+/// A violation of the message framing rules detected by the request smuggling protection
+/// (see `ServerBuilder::smuggling_protection`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmugglingViolation {
+    ContentLengthWithTransferEncoding,
+    InvalidContentLength,
+    InvalidTransferEncoding,
+    InvalidChunk,
+}
+impl fmt::Display for SmugglingViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SmugglingViolation::ContentLengthWithTransferEncoding => {
+                write!(f, "Both Content-Length and Transfer-Encoding")
+            }
+            SmugglingViolation::InvalidContentLength => {
+                write!(f, "Conflicting or malformed Content-Length")
+            }
+            SmugglingViolation::InvalidTransferEncoding => {
+                write!(f, "Transfer-Encoding not ending with chunked")
+            }
+            SmugglingViolation::InvalidChunk => write!(f, "Malformed chunk"),
+        }
+    }
+}
+
+/// Checks the framing headers (i.e., `Content-Length` and `Transfer-Encoding`) of a request.
+///
+/// Returns `Ok(true)` if the body of the request is chunked.
+pub fn check_framing(header: &Header) -> Result<bool, SmugglingViolation> {
+    let mut last_coding = None;
+    let mut content_length = None;
+    for field in header.fields() {
+        if field.name().eq_ignore_ascii_case("Transfer-Encoding") {
+            for coding in field.value().split(',').map(|v| v.trim()) {
+                if !coding.is_empty() {
+                    last_coding = Some(coding);
+                }
+            }
+        } else if field.name().eq_ignore_ascii_case("Content-Length") {
+            for value in field.value().split(',').map(|v| v.trim()) {
+                if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(SmugglingViolation::InvalidContentLength);
+                }
+                match content_length {
+                    Some(len) if len != value => {
+                        return Err(SmugglingViolation::InvalidContentLength);
+                    }
+                    _ => content_length = Some(value),
+                }
+            }
+        }
+    }
+    match (last_coding, content_length) {
+        (None, _) => Ok(false),
+        (Some(_), Some(_)) => Err(SmugglingViolation::ContentLengthWithTransferEncoding),
+        (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => Ok(true),
+        (Some(_), None) => Err(SmugglingViolation::InvalidTransferEncoding),
+    }
+}
+
+/// A validator of the syntax of chunked bodies (RFC 7230, Section 4.1).
+///
+/// Unlike the body decoders, this rejects bare LFs and malformed chunk extensions.
+/// The bytes are validated before the handler consumes them, and the validation stops
+/// at the end of the body (i.e., the bytes of the next request are not scanned).
+#[derive(Debug, Default)]
+pub struct ChunkValidator {
+    // The number of bytes that have been scanned but not consumed by the handler yet.
+    scanned_ahead: usize,
+    state: ChunkState,
+    size: u64,
+    digits: usize,
+}
+impl ChunkValidator {
+    /// Scans the bytes in `buf` that have not been scanned yet (without consuming them).
+    pub fn scan_read_buf(&mut self, buf: &mut ReadBuf<Vec<u8>>) -> Result<(), SmugglingViolation> {
+        ScanOnly(self)
+            .decode_from_read_buf(buf)
+            .map_err(|_| SmugglingViolation::InvalidChunk)
+    }
+
+    fn scan(&mut self, buf: &[u8]) -> Result<(), SmugglingViolation> {
+        let mut i = self.scanned_ahead;
+        while i < buf.len() && self.state != ChunkState::Done {
+            if let ChunkState::Data(remaining) = self.state {
+                let n = remaining.min((buf.len() - i) as u64);
+                i += n as usize;
+                self.state = if n == remaining {
+                    ChunkState::DataCr
+                } else {
+                    ChunkState::Data(remaining - n)
+                };
+                continue;
+            }
+            if !self.feed(buf[i]) {
+                return Err(SmugglingViolation::InvalidChunk);
+            }
+            i += 1;
+        }
+        self.scanned_ahead = i;
+        Ok(())
+    }
+
+    pub fn consume(&mut self, size: usize) {
+        self.scanned_ahead = self.scanned_ahead.saturating_sub(size);
+    }
+
+    /// Feeds a byte to the state machine, and returns `false` if the byte is not allowed.
+    fn feed(&mut self, b: u8) -> bool {
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    if let Some(d) = (b as char).to_digit(16) {
+                        if self.size > u64::MAX >> 4 {
+                            return false;
+                        }
+                        self.size = (self.size << 4) | u64::from(d);
+                        self.digits += 1;
+                    } else if self.digits == 0 {
+                        return false;
+                    } else {
+                        self.state = ChunkState::Ext;
+                        continue;
+                    }
+                }
+                ChunkState::Ext => match b {
+                    b' ' | b'\t' => {}
+                    b';' => self.state = ChunkState::ExtName(false),
+                    b'\r' => self.state = ChunkState::SizeLf,
+                    _ => return false,
+                },
+                ChunkState::ExtName(started) => match b {
+                    b' ' | b'\t' if !started => {}
+                    _ if is_tchar(b) => self.state = ChunkState::ExtName(true),
+                    _ if started => {
+                        self.state = ChunkState::ExtEq;
+                        continue;
+                    }
+                    _ => return false,
+                },
+                ChunkState::ExtEq => match b {
+                    b' ' | b'\t' => {}
+                    b'=' => self.state = ChunkState::ExtValue,
+                    _ => {
+                        self.state = ChunkState::Ext;
+                        continue;
+                    }
+                },
+                ChunkState::ExtValue => match b {
+                    b' ' | b'\t' => {}
+                    b'"' => self.state = ChunkState::ExtQuoted,
+                    _ if is_tchar(b) => self.state = ChunkState::ExtToken,
+                    _ => return false,
+                },
+                ChunkState::ExtToken => {
+                    if !is_tchar(b) {
+                        self.state = ChunkState::Ext;
+                        continue;
+                    }
+                }
+                ChunkState::ExtQuoted => match b {
+                    b'"' => self.state = ChunkState::Ext,
+                    b'\\' => self.state = ChunkState::ExtQuotedPair,
+                    b'\t' | b' '..=b'~' | 0x80..=0xFF => {}
+                    _ => return false,
+                },
+                ChunkState::ExtQuotedPair => match b {
+                    b'\t' | b' '..=b'~' | 0x80..=0xFF => self.state = ChunkState::ExtQuoted,
+                    _ => return false,
+                },
+                ChunkState::SizeLf => {
+                    if b != b'\n' {
+                        return false;
+                    }
+                    self.state = if self.size == 0 {
+                        ChunkState::Trailer(false)
+                    } else {
+                        ChunkState::Data(self.size)
+                    };
+                }
+                ChunkState::Data(_) => unreachable!(),
+                ChunkState::DataCr => {
+                    if b != b'\r' {
+                        return false;
+                    }
+                    self.state = ChunkState::DataLf;
+                }
+                ChunkState::DataLf => {
+                    if b != b'\n' {
+                        return false;
+                    }
+                    self.state = ChunkState::Size;
+                    self.size = 0;
+                    self.digits = 0;
+                }
+                ChunkState::Trailer(started) => match b {
+                    b'\r' => self.state = ChunkState::TrailerLf(started),
+                    b'\n' => return false,
+                    _ => self.state = ChunkState::Trailer(true),
+                },
+                ChunkState::TrailerLf(started) => {
+                    if b != b'\n' {
+                        return false;
+                    }
+                    self.state = if started {
+                        ChunkState::Trailer(false)
+                    } else {
+                        ChunkState::Done
+                    };
+                }
+                ChunkState::Done => {}
+            }
+            return true;
+        }
+    }
+}
+
+/// A decoder that only scans the given bytes, so that the unconsumed bytes of a `ReadBuf` can be inspected.
+struct ScanOnly<'a>(&'a mut ChunkValidator);
+impl<'a> Decode for ScanOnly<'a> {
+    type Item = ();
+
+    fn decode(&mut self, buf: &[u8], _eos: Eos) -> bytecodec::Result<usize> {
+        if let Err(violation) = self.0.scan(buf) {
+            track_panic!(bytecodec::ErrorKind::InvalidInput, "{}", violation);
+        }
+        Ok(0)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.state == ChunkState::Done
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+    #[default]
+    Size,
+    Ext,
+    ExtName(bool),
+    ExtEq,
+    ExtValue,
+    ExtToken,
+    ExtQuoted,
+    ExtQuotedPair,
+    SizeLf,
+    Data(u64),
+    DataCr,
+    DataLf,
+    Trailer(bool),
+    TrailerLf(bool),
+    Done,
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+    use bytecodec::bytes::BytesEncoder;
+    use futures::future::ok;
+    use httpcodec::{
+        BodyDecoder, BodyEncoder, HeaderField, HttpVersion, Method, Request, RequestTarget,
+    };
+
+    fn check(fields: &[(&str, &str)]) -> Result<bool, SmugglingViolation> {
+        let mut req = Request::new(
+            Method::new("POST").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        for &(name, value) in fields {
+            req.header_mut()
+                .add_field(HeaderField::new(name, value).unwrap());
+        }
+        check_framing(&req.header())
+    }
+
+    fn validate(body: &[u8]) -> Result<usize, SmugglingViolation> {
+        let mut validator = ChunkValidator::default();
+        for chunk in body.chunks(3) {
+            validator.scan(chunk)?;
+            validator.consume(chunk.len());
+        }
+        let mut validator = ChunkValidator::default();
+        validator.scan(body)?;
+        Ok(validator.scanned_ahead)
+    }
+
+    #[test]
+    fn check_framing_works() {
+        assert_eq!(check(&[]), Ok(false));
+        assert_eq!(check(&[("Content-Length", "10")]), Ok(false));
+        assert_eq!(check(&[("Content-Length", "10, 10")]), Ok(false));
+        assert_eq!(check(&[("Transfer-Encoding", "gzip, chunked")]), Ok(true));
+        assert_eq!(
+            check(&[("Content-Length", "10"), ("Transfer-Encoding", "chunked")]),
+            Err(SmugglingViolation::ContentLengthWithTransferEncoding)
+        );
+        assert_eq!(
+            check(&[("Content-Length", "10"), ("Content-Length", "20")]),
+            Err(SmugglingViolation::InvalidContentLength)
+        );
+        assert_eq!(
+            check(&[("Content-Length", "+10")]),
+            Err(SmugglingViolation::InvalidContentLength)
+        );
+        assert_eq!(
+            check(&[("Transfer-Encoding", "chunked, identity")]),
+            Err(SmugglingViolation::InvalidTransferEncoding)
+        );
+    }
+
+    #[test]
+    fn chunk_validator_works() {
+        let body = b"5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(validate(body), Ok(15));
+        assert!(validate(b"5;a=b;c=\"d\\\"e\" ; f\r\nhello\r\n0\r\nX: 1\r\n\r\n").is_ok());
+
+        let invalid: &[&[u8]] = &[
+            b"5\nhello\r\n0\r\n\r\n",
+            b"5\r\nhello\n0\r\n\r\n",
+            b"5\r\nhello!\r\n0\r\n\r\n",
+            b"x\r\nhello\r\n0\r\n\r\n",
+            b"5;\r\nhello\r\n0\r\n\r\n",
+            b"5;a=\r\nhello\r\n0\r\n\r\n",
+            b"5;a=\"b\r\nhello\r\n0\r\n\r\n",
+            b"5 x\r\nhello\r\n0\r\n\r\n",
+            b"10000000000000000\r\n",
+            b"0\r\nX: 1\n\r\n",
+        ];
+        for body in invalid {
+            assert_eq!(
+                validate(body),
+                Err(SmugglingViolation::InvalidChunk),
+                "{:?}",
+                String::from_utf8_lossy(body)
+            );
+        }
+    }
+
+    #[test]
+    fn smuggling_protection_works() {
+        use bytecodec::bytes::RemainingBytesDecoder;
+
+        struct Echo;
+        impl HandleRequest for Echo {
+            const METHOD: &'static str = "POST";
+            const PATH: &'static str = "/echo";
+
+            type ReqBody = Vec<u8>;
+            type ResBody = Vec<u8>;
+            type Decoder = BodyDecoder<RemainingBytesDecoder>;
+            type Encoder = BodyEncoder<BytesEncoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, req.into_body())))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Hello).unwrap();
+        builder.add_handler(Echo).unwrap();
+        builder.smuggling_protection(true);
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n0\r\n\r\n",
+        );
+        conn.write(b"GET /hello HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert_eq!(received.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(received.ends_with("hello"), "{}", received);
+        assert!(!conn.is_closed());
+
+        for req in &[
+            &b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nfoo\r\n0\r\n\r\n"[..],
+            &b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nfoo"[..],
+            &b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;\r\nfoo\r\n0\r\n\r\n"[..],
+        ] {
+            let conn = sim.connect().unwrap();
+            conn.write(req);
+            sim.run().unwrap();
+            let received = String::from_utf8(conn.take_received()).unwrap();
+            assert!(received.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", received);
+            assert!(conn.is_closed());
+        }
+        let metrics = sim.metrics();
+        assert_eq!(metrics.content_length_with_transfer_encoding_errors(), 1);
+        assert_eq!(metrics.invalid_content_length_errors(), 1);
+        assert_eq!(metrics.invalid_chunk_errors(), 1);
+    }
+}
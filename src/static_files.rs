@@ -0,0 +1,953 @@
+use crate::file::{FileBody, FileBodyEncoder};
+use crate::header::{self, CacheControl, TypedHeader};
+use crate::path_decoding::percent_decode;
+use crate::{HandleRequest, Req, Res, Status};
+use bytecodec::bytes::BytesEncoder;
+use bytecodec::marker::Never;
+use bytecodec::null::NullDecoder;
+use bytecodec::{self, ByteCount, Encode, Eos};
+use futures::future::{finished, FutureResult};
+use httpcodec::{BodyDecoder, BodyEncode, Header, HeaderField, HeaderMut};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The mount point of `StaticFiles`.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::{ServerBuilder, StaticFiles, StaticMount};
+///
+/// struct Assets;
+/// impl StaticMount for Assets {
+///     const PATH: &'static str = "/assets/**";
+/// }
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder
+///     .add_handler(StaticFiles::<Assets>::new("public").auto_index(true))
+///     .unwrap();
+/// ```
+pub trait StaticMount: 'static {
+    /// The request path that the handler can handle.
+    ///
+    /// This must end with `/**`, and the remaining part of a request path is mapped
+    /// to the relative path from the root directory.
+    const PATH: &'static str;
+}
+
+type EtagCache = HashMap<PathBuf, (u64, Option<SystemTime>, String)>;
+
+/// A handler that serves the files under a directory.
+///
+/// If a request path points to a directory, the following rules are applied:
+/// - If the path does not end with `/`, the client is redirected to the path with `/`
+/// - If the directory has the index file (`index.html` by default), the file is served
+/// - If the auto-index mode is enabled, an HTML listing of the directory is served
+/// - Otherwise, `404 Not Found` is returned
+///
+/// The responses of files have the `ETag` header fields (see `StaticEtag`),
+/// and the conditional requests that have matching `If-None-Match` header fields get `304 Not Modified`.
+/// The `Cache-Control` header fields can be configured by `cache_control`, `default_cache_control` and
+/// `immutable_fingerprinted` methods.
+///
+/// If the precompressed mode is enabled, the sidecar files (e.g., `foo.js.br` and `foo.js.gz` for `foo.js`)
+/// are served instead of the requested files when they exist and are acceptable to the clients.
+///
+/// Files are read in the fibers that handle the requests.
+/// Wrap the handler with `WithThreadPool` if the file system is slow.
+pub struct StaticFiles<M> {
+    root: PathBuf,
+    index_file: Option<String>,
+    auto_index: bool,
+    cache_rules: Vec<(String, CacheControl)>,
+    default_cache_control: Option<CacheControl>,
+    immutable_fingerprinted: Option<CacheControl>,
+    etag: StaticEtag,
+    precompressed: bool,
+    etag_cache: Mutex<EtagCache>,
+    _mount: PhantomData<fn() -> M>,
+}
+impl<M: StaticMount> StaticFiles<M> {
+    /// Makes a new `StaticFiles` instance that serves the files under `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        StaticFiles {
+            root: root.as_ref().to_path_buf(),
+            index_file: Some("index.html".to_owned()),
+            auto_index: false,
+            cache_rules: Vec::new(),
+            default_cache_control: None,
+            immutable_fingerprinted: None,
+            etag: StaticEtag::Metadata,
+            precompressed: false,
+            etag_cache: Mutex::default(),
+            _mount: PhantomData,
+        }
+    }
+
+    /// Sets the name of the file served for directory requests.
+    ///
+    /// The default value is `Some("index.html")`.
+    pub fn index_file(mut self, name: Option<&str>) -> Self {
+        self.index_file = name.map(|n| n.to_owned());
+        self
+    }
+
+    /// Enables or disables the auto-index mode that renders HTML listings of directories.
+    ///
+    /// The default value is `false`.
+    pub fn auto_index(mut self, enabled: bool) -> Self {
+        self.auto_index = enabled;
+        self
+    }
+
+    /// Adds a `Cache-Control` rule for the files that have the given extension (e.g., `"css"`).
+    ///
+    /// Extensions are compared case-insensitively.
+    /// If multiple rules are added for an extension, the last one is used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fibers_http_server::header::CacheControl;
+    /// use fibers_http_server::{StaticFiles, StaticMount};
+    ///
+    /// struct Assets;
+    /// impl StaticMount for Assets {
+    ///     const PATH: &'static str = "/assets/**";
+    /// }
+    ///
+    /// # fn main() -> fibers_http_server::Result<()> {
+    /// let handler = StaticFiles::<Assets>::new("public")
+    ///     .cache_control("html", CacheControl::new().directive("no-cache", None)?)
+    ///     .default_cache_control(CacheControl::new().directive("max-age", Some("3600"))?)
+    ///     .immutable_fingerprinted(true);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn cache_control(mut self, extension: &str, value: CacheControl) -> Self {
+        self.cache_rules
+            .push((extension.to_ascii_lowercase(), value));
+        self
+    }
+
+    /// Sets the `Cache-Control` header field for the files that do not match any rules.
+    ///
+    /// By default, no `Cache-Control` header fields are added to such files.
+    pub fn default_cache_control(mut self, value: CacheControl) -> Self {
+        self.default_cache_control = Some(value);
+        self
+    }
+
+    /// Enables or disables `Cache-Control: public, max-age=31536000, immutable` for fingerprinted files.
+    ///
+    /// A file is regarded as fingerprinted if its name has a part (separated by `.` or `-`,
+    /// excluding the first part and the extension) that consists of at least eight hexadecimal digits
+    /// including a decimal digit (e.g., `app.3f2a9c1e.js` or `logo-8d7e6f5a40.png`).
+    /// This takes precedence over the other rules.
+    ///
+    /// The default value is `false`.
+    pub fn immutable_fingerprinted(mut self, enabled: bool) -> Self {
+        self.immutable_fingerprinted = if enabled {
+            let value = CacheControl::parse("public, max-age=31536000, immutable");
+            Some(value.expect("Never fails"))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Sets the way to generate the `ETag` header fields.
+    ///
+    /// The default value is `StaticEtag::Metadata`.
+    pub fn etag(mut self, etag: StaticEtag) -> Self {
+        self.etag = etag;
+        self
+    }
+
+    /// Enables or disables the precompressed mode.
+    ///
+    /// In this mode, if the `Accept-Encoding` header field of a request accepts `br` (or `gzip`) and
+    /// the requested file has the sidecar file with the `.br` (or `.gz`) extension,
+    /// the sidecar file is served with the `Content-Encoding` header field.
+    /// `br` takes precedence over `gzip`, and the plain file is served if no sidecar files are acceptable.
+    /// All the file responses have the `Vary: Accept-Encoding` header field.
+    ///
+    /// The default value is `false`.
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Returns the root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn handle(&self, url_path: &str, relative: Option<&str>, header: &Header) -> Res<StaticBody> {
+        let path = match relative.and_then(|relative| resolve_path(&self.root, relative)) {
+            None => return error_res(Status::NotFound),
+            Some(path) => path,
+        };
+        let metadata = match fs::metadata(&path) {
+            Err(e) => return io_error_res(&e),
+            Ok(metadata) => metadata,
+        };
+        if !metadata.is_dir() {
+            return self.serve_file(&path, header);
+        }
+
+        if !url_path.ends_with('/') {
+            let location = format!("{}/", url_path);
+            let mut res = Res::new(Status::MovedPermanently, StaticBody::Bytes(Vec::new()));
+            if res.add_header("Location", &location).is_err() {
+                return error_res(Status::NotFound);
+            }
+            return res;
+        }
+        if let Some(ref name) = self.index_file {
+            let index = path.join(name);
+            if index.is_file() {
+                return self.serve_file(&index, header);
+            }
+        }
+        if !self.auto_index {
+            return error_res(Status::NotFound);
+        }
+        match render_index(&path, url_path, relative != Some("")) {
+            Err(e) => io_error_res(&e),
+            Ok(html) => {
+                let mut res = Res::new(Status::Ok, StaticBody::Bytes(html.into_bytes()));
+                res.header_mut()
+                    .add_field(content_type_field("text/html; charset=utf-8"));
+                res
+            }
+        }
+    }
+
+    fn serve_file(&self, path: &Path, header: &Header) -> Res<StaticBody> {
+        let (served_path, encoding) =
+            self.select_encoding(path, header.get_field("Accept-Encoding"));
+        let file = match fs::File::open(&served_path) {
+            Err(e) => return io_error_res(&e),
+            Ok(file) => file,
+        };
+        let etag = match self.make_etag(&served_path, &file) {
+            Err(e) => return io_error_res(&e),
+            Ok(etag) => etag,
+        };
+        let cache_control = self.cache_control_of(path);
+
+        let not_modified = match (&etag, header.get_field("If-None-Match")) {
+            (Some(etag), Some(tags)) => etag_matches(tags, etag),
+            _ => false,
+        };
+        let mut res = if not_modified {
+            Res::new(Status::NotModified, StaticBody::Bytes(Vec::new()))
+        } else {
+            match FileBody::new(file) {
+                Err(_) => return error_res(Status::InternalServerError),
+                Ok(body) => {
+                    let mut res = Res::new(Status::Ok, StaticBody::File(body));
+                    res.header_mut()
+                        .add_field(content_type_field(content_type(path)));
+                    res
+                }
+            }
+        };
+        if let Some(etag) = etag {
+            let _ = res.add_header("ETag", &etag);
+        }
+        if let Some(cache_control) = cache_control {
+            let _ = res.add_typed_header(cache_control);
+        }
+        if let Some(encoding) = encoding {
+            res.header_mut()
+                .add_field(unsafe { HeaderField::new_unchecked("Content-Encoding", encoding) });
+        }
+        if self.precompressed {
+            res.header_mut()
+                .add_field(unsafe { HeaderField::new_unchecked("Vary", "Accept-Encoding") });
+        }
+        res
+    }
+
+    /// Returns the path of the file to be served and its content coding.
+    fn select_encoding(
+        &self,
+        path: &Path,
+        accept_encoding: Option<&str>,
+    ) -> (PathBuf, Option<&'static str>) {
+        if let (true, Some(accept_encoding)) = (self.precompressed, accept_encoding) {
+            for &(encoding, extension) in &[("br", "br"), ("gzip", "gz")] {
+                if header::encoding_quality(accept_encoding, encoding) <= 0.0 {
+                    continue;
+                }
+                let mut sidecar = path.as_os_str().to_owned();
+                sidecar.push(".");
+                sidecar.push(extension);
+                let sidecar = PathBuf::from(sidecar);
+                if sidecar.is_file() {
+                    return (sidecar, Some(encoding));
+                }
+            }
+        }
+        (path.to_path_buf(), None)
+    }
+
+    fn cache_control_of(&self, path: &Path) -> Option<&CacheControl> {
+        if let Some(ref immutable) = self.immutable_fingerprinted {
+            if is_fingerprinted(path) {
+                return Some(immutable);
+            }
+        }
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        self.cache_rules
+            .iter()
+            .rev()
+            .find(|r| Some(&r.0) == extension.as_ref())
+            .map(|r| &r.1)
+            .or(self.default_cache_control.as_ref())
+    }
+
+    fn make_etag(&self, path: &Path, file: &fs::File) -> io::Result<Option<String>> {
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        let mtime = metadata.modified().ok();
+        match self.etag {
+            StaticEtag::Disabled => Ok(None),
+            StaticEtag::Metadata => {
+                let nanos = mtime
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_nanos());
+                Ok(Some(format!("\"{:x}-{:x}\"", len, nanos)))
+            }
+            StaticEtag::ContentHash => {
+                let mut cache = self.etag_cache.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(&(cached_len, cached_mtime, ref etag)) = cache.get(path) {
+                    if cached_len == len && cached_mtime == mtime && mtime.is_some() {
+                        return Ok(Some(etag.clone()));
+                    }
+                }
+
+                let mut hasher = DefaultHasher::new();
+                let mut buf = [0; 16 * 1024];
+                let mut reader = file;
+                loop {
+                    let size = reader.read(&mut buf)?;
+                    if size == 0 {
+                        break;
+                    }
+                    hasher.write(&buf[..size]);
+                }
+                let etag = format!("\"{:x}-{:016x}\"", len, hasher.finish());
+                cache.insert(path.to_path_buf(), (len, mtime, etag.clone()));
+                Ok(Some(etag))
+            }
+        }
+    }
+}
+impl<M: StaticMount> HandleRequest for StaticFiles<M> {
+    const METHOD: &'static str = "GET";
+    const PATH: &'static str = M::PATH;
+
+    type ReqBody = ();
+    type ResBody = StaticBody;
+    type Decoder = BodyDecoder<NullDecoder>;
+    type Encoder = StaticBodyEncoder;
+    type Reply = FutureResult<Res<Self::ResBody>, Never>;
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        finished(self.handle(req.url().path(), req.rest_of_path(), &req.header()))
+    }
+}
+impl<M> fmt::Debug for StaticFiles<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "StaticFiles {{ root: {:?}, index_file: {:?}, auto_index: {:?}, etag: {:?}, .. }}",
+            self.root, self.index_file, self.auto_index, self.etag
+        )
+    }
+}
+
+/// The way to generate the `ETag` header fields of `StaticFiles`.
+///
+/// All the generated tags are strong validators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StaticEtag {
+    /// No `ETag` header fields are added.
+    Disabled,
+
+    /// Tags are generated from the size and the modification time of the files.
+    Metadata,
+
+    /// Tags are generated from the size and the hash of the content of the files.
+    ///
+    /// The hashes are cached while the size and the modification time of the files are unchanged.
+    ContentHash,
+}
+
+/// Response body of `StaticFiles`.
+#[derive(Debug)]
+pub enum StaticBody {
+    /// The content of a file.
+    File(FileBody),
+
+    /// In-memory bytes (e.g., directory listings and error messages).
+    Bytes(Vec<u8>),
+}
+
+/// Response body encoder for `StaticBody`.
+///
+/// Like `FileBodyEncoder`, the file bodies are written to sockets by using `sendfile(2)` (on Linux).
+#[derive(Debug, Default)]
+pub struct StaticBodyEncoder {
+    file: FileBodyEncoder,
+    bytes: BytesEncoder<Vec<u8>>,
+}
+impl StaticBodyEncoder {
+    /// Makes a new `StaticBodyEncoder` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl Encode for StaticBodyEncoder {
+    type Item = StaticBody;
+
+    fn encode(&mut self, buf: &mut [u8], eos: Eos) -> bytecodec::Result<usize> {
+        if self.file.is_idle() {
+            track!(self.bytes.encode(buf, eos))
+        } else {
+            track!(self.file.encode(buf, eos))
+        }
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        track_assert!(self.is_idle(), bytecodec::ErrorKind::EncoderFull);
+        match item {
+            StaticBody::File(x) => track!(self.file.start_encoding(x)),
+            StaticBody::Bytes(x) => track!(self.bytes.start_encoding(x)),
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.file.is_idle() && self.bytes.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.file.is_idle() {
+            self.bytes.requiring_bytes()
+        } else {
+            self.file.requiring_bytes()
+        }
+    }
+}
+impl BodyEncode for StaticBodyEncoder {
+    fn update_header(&self, header: &mut HeaderMut) -> bytecodec::Result<()> {
+        let value = self
+            .requiring_bytes()
+            .to_u64()
+            .expect("Never fails")
+            .to_string();
+        header.add_field(track!(HeaderField::new("Content-Length", &value))?);
+        Ok(())
+    }
+}
+
+/// Maps the (percent-encoded) relative request path to a file system path under `root`.
+///
+/// Returns `None` if the path contains segments that may escape from `root`.
+fn resolve_path(root: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in relative.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode(segment)?;
+        if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn render_index(dir: &Path, url_path: &str, has_parent: bool) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        let mut href = percent_encode(&name);
+        let size = if metadata.is_dir() {
+            name.push('/');
+            href.push('/');
+            "-".to_owned()
+        } else {
+            metadata.len().to_string()
+        };
+        let mtime = metadata.modified().map(format_time).unwrap_or_default();
+        entries.push((name, href, size, mtime));
+    }
+    entries.sort();
+
+    let title = escape_html(&percent_decode(url_path).unwrap_or_else(|| url_path.to_owned()));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {0}</title>\n</head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>\n",
+        title
+    );
+    if has_parent {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td>-</td><td></td></tr>\n");
+    }
+    for (name, href, size, mtime) in entries {
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            href,
+            escape_html(&name),
+            size,
+            mtime
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Formats `time` as `YYYY-MM-DD hh:mm:ss` (UTC).
+fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("txt") | Some("md") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("json") | Some("map") => "application/json",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+fn content_type_field(value: &'static str) -> HeaderField<'static, 'static> {
+    unsafe { HeaderField::new_unchecked("Content-Type", value) }
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|t| t.trim().trim_start_matches("W/") == etag)
+}
+
+fn is_fingerprinted(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        None => return false,
+        Some(name) => name,
+    };
+    let parts = name.split(['.', '-']).collect::<Vec<_>>();
+    parts.len() >= 3
+        && parts[1..parts.len() - 1].iter().any(|p| {
+            p.len() >= 8
+                && p.bytes().all(|b| b.is_ascii_hexdigit())
+                && p.bytes().any(|b| b.is_ascii_digit())
+        })
+}
+
+fn io_error_res(e: &io::Error) -> Res<StaticBody> {
+    let status = match e.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        io::ErrorKind::PermissionDenied => Status::Forbidden,
+        _ => Status::InternalServerError,
+    };
+    error_res(status)
+}
+
+fn error_res(status: Status) -> Res<StaticBody> {
+    let body = StaticBody::Bytes(status.reason_phrase().as_bytes().to_vec());
+    let mut res = Res::new(status, body);
+    res.header_mut()
+        .add_field(content_type_field("text/plain; charset=utf-8"));
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::PathCaptures;
+    use crate::ServerBuilder;
+    use futures::Future;
+    use httpcodec::{HttpVersion, Method, Request, RequestTarget};
+    use std::time::Duration;
+    use url::Url;
+
+    struct Mount;
+    impl StaticMount for Mount {
+        const PATH: &'static str = "/files/**";
+    }
+
+    fn get(handler: &StaticFiles<Mount>, path: &str) -> Res<StaticBody> {
+        get_with(handler, path, &[])
+    }
+
+    fn get_with(
+        handler: &StaticFiles<Mount>,
+        path: &str,
+        fields: &[(&str, &str)],
+    ) -> Res<StaticBody> {
+        let mut inner = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new(path).unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        for &(name, value) in fields {
+            let field = unsafe { HeaderField::new_unchecked(name, value) };
+            inner.header_mut().add_field(field);
+        }
+        let base_url = Url::parse("http://localhost/").unwrap();
+        let mut req = track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()));
+        let prefix_len = "/files/".len();
+        let captures = PathCaptures {
+            rest: Some(prefix_len..req.url().path().len()),
+            ..PathCaptures::default()
+        };
+        req.set_captures(captures);
+        handler.handle_request(req).wait().unwrap()
+    }
+
+    fn bytes(res: &Res<StaticBody>) -> &str {
+        match res.body() {
+            StaticBody::Bytes(x) => std::str::from_utf8(x).unwrap(),
+            StaticBody::File(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn static_files_works() {
+        let root = std::env::temp_dir().join(format!(
+            "fibers_http_server_static_files_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::create_dir_all(root.join("site")).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+        fs::write(root.join("<b>.css"), b"").unwrap();
+        fs::write(root.join("site/index.html"), b"<p>index</p>").unwrap();
+
+        let handler = StaticFiles::<Mount>::new(&root);
+        let res = get(&handler, "/files/a.txt");
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        match res.body() {
+            StaticBody::File(x) => assert_eq!(x.len(), 5),
+            StaticBody::Bytes(_) => panic!(),
+        }
+
+        assert_eq!(get(&handler, "/files/b.txt").status_code(), 404);
+        assert_eq!(get(&handler, "/files/%2E%2E/a.txt").status_code(), 404);
+        assert_eq!(get(&handler, "/files/..%2Fa.txt").status_code(), 404);
+        assert_eq!(get(&handler, "/files/").status_code(), 404);
+
+        // Index file resolution
+        let res = get(&handler, "/files/site");
+        assert_eq!(res.status_code(), 301);
+        assert_eq!(res.header().get_field("Location"), Some("/files/site/"));
+
+        let res = get(&handler, "/files/site/");
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        // Auto-index
+        let handler = StaticFiles::<Mount>::new(&root)
+            .index_file(None)
+            .auto_index(true);
+        let res = get(&handler, "/files/");
+        assert_eq!(res.status_code(), 200);
+        let html = bytes(&res);
+        assert!(html.contains("<title>Index of /files/</title>"));
+        assert!(!html.contains("href=\"../\""));
+        assert!(html.contains("<a href=\"a.txt\">a.txt</a></td><td>5</td>"));
+        assert!(html.contains("<a href=\"%3Cb%3E.css\">&lt;b&gt;.css</a>"));
+        assert!(html.contains("<a href=\"sub%20dir/\">sub dir/</a></td><td>-</td>"));
+
+        let res = get(&handler, "/files/sub%20dir/");
+        assert_eq!(res.status_code(), 200);
+        assert!(bytes(&res).contains("<title>Index of /files/sub dir/</title>"));
+        assert!(bytes(&res).contains("href=\"../\""));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn grouped_static_files_work() {
+        let root = std::env::temp_dir().join(format!(
+            "fibers_http_server_static_grouped_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder
+            .group("/v1")
+            .add_handler(StaticFiles::<Mount>::new(&root))
+            .unwrap();
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/v1/files/a.txt").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"hello");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn cache_headers_work() {
+        let root = std::env::temp_dir().join(format!(
+            "fibers_http_server_static_cache_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.html"), b"<p>hello</p>").unwrap();
+        fs::write(root.join("app.3f2a9c1e.js"), b"foo()").unwrap();
+        fs::write(root.join("style.css"), b"p {}").unwrap();
+
+        let handler = StaticFiles::<Mount>::new(&root)
+            .cache_control(
+                "HTML",
+                track_try_unwrap!(CacheControl::new().directive("no-cache", None)),
+            )
+            .default_cache_control(track_try_unwrap!(
+                CacheControl::new().directive("max-age", Some("60"))
+            ))
+            .immutable_fingerprinted(true);
+
+        let res = get(&handler, "/files/index.html");
+        assert_eq!(res.header().get_field("Cache-Control"), Some("no-cache"));
+        let etag = res.header().get_field("ETag").unwrap().to_owned();
+        assert!(etag.starts_with("\"c-"));
+
+        let res = get(&handler, "/files/style.css");
+        assert_eq!(res.header().get_field("Cache-Control"), Some("max-age=60"));
+
+        let res = get(&handler, "/files/app.3f2a9c1e.js");
+        assert_eq!(
+            res.header().get_field("Cache-Control"),
+            Some("public, max-age=31536000, immutable")
+        );
+
+        // Conditional requests
+        let res = get_with(&handler, "/files/index.html", &[("If-None-Match", &etag)]);
+        assert_eq!(res.status_code(), 304);
+        assert_eq!(res.header().get_field("ETag"), Some(etag.as_str()));
+        assert_eq!(res.header().get_field("Cache-Control"), Some("no-cache"));
+        assert_eq!(bytes(&res), "");
+
+        let tags = format!("\"foo\", W/{}", etag);
+        let res = get_with(&handler, "/files/index.html", &[("If-None-Match", &tags)]);
+        assert_eq!(res.status_code(), 304);
+
+        let res = get_with(
+            &handler,
+            "/files/index.html",
+            &[("If-None-Match", "\"foo\"")],
+        );
+        assert_eq!(res.status_code(), 200);
+
+        // Content hash
+        let handler = StaticFiles::<Mount>::new(&root).etag(StaticEtag::ContentHash);
+        let res = get(&handler, "/files/index.html");
+        let etag = res.header().get_field("ETag").unwrap().to_owned();
+        assert_eq!(etag.len(), 20);
+        let res = get(&handler, "/files/index.html");
+        assert_eq!(res.header().get_field("ETag"), Some(etag.as_str()));
+        let res = get(&handler, "/files/style.css");
+        assert_ne!(res.header().get_field("ETag"), Some(etag.as_str()));
+
+        let handler = StaticFiles::<Mount>::new(&root).etag(StaticEtag::Disabled);
+        let res = get(&handler, "/files/index.html");
+        assert_eq!(res.header().get_field("ETag"), None);
+        assert_eq!(res.header().get_field("Cache-Control"), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn precompressed_works() {
+        let root = std::env::temp_dir().join(format!(
+            "fibers_http_server_static_precompressed_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("app.js"), b"foo()").unwrap();
+        fs::write(root.join("app.js.gz"), b"gz").unwrap();
+        fs::write(root.join("app.js.br"), b"b").unwrap();
+        fs::write(root.join("style.css"), b"p {}").unwrap();
+        fs::write(root.join("style.css.gz"), b"gz").unwrap();
+
+        let file_len = |res: &Res<StaticBody>| match res.body() {
+            StaticBody::File(x) => x.len(),
+            StaticBody::Bytes(_) => panic!(),
+        };
+
+        let handler = StaticFiles::<Mount>::new(&root).precompressed(true);
+        let res = get_with(
+            &handler,
+            "/files/app.js",
+            &[("Accept-Encoding", "gzip, br")],
+        );
+        assert_eq!(res.header().get_field("Content-Encoding"), Some("br"));
+        assert_eq!(res.header().get_field("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(file_len(&res), 1);
+        let br_etag = res.header().get_field("ETag").unwrap().to_owned();
+
+        let res = get_with(
+            &handler,
+            "/files/app.js",
+            &[("Accept-Encoding", "gzip;q=0.5")],
+        );
+        assert_eq!(res.header().get_field("Content-Encoding"), Some("gzip"));
+        assert_eq!(file_len(&res), 2);
+        assert_ne!(res.header().get_field("ETag"), Some(br_etag.as_str()));
+
+        let res = get_with(
+            &handler,
+            "/files/style.css",
+            &[("Accept-Encoding", "br, *")],
+        );
+        assert_eq!(res.header().get_field("Content-Encoding"), Some("gzip"));
+
+        let res = get_with(
+            &handler,
+            "/files/app.js",
+            &[("Accept-Encoding", "br;q=0, gzip;q=0")],
+        );
+        assert_eq!(res.header().get_field("Content-Encoding"), None);
+        assert_eq!(res.header().get_field("Vary"), Some("Accept-Encoding"));
+        assert_eq!(file_len(&res), 5);
+
+        let res = get(&handler, "/files/app.js");
+        assert_eq!(res.header().get_field("Content-Encoding"), None);
+        assert_eq!(file_len(&res), 5);
+
+        let res = get_with(
+            &handler,
+            "/files/app.js",
+            &[("Accept-Encoding", "br"), ("If-None-Match", &br_etag)],
+        );
+        assert_eq!(res.status_code(), 304);
+        assert_eq!(res.header().get_field("Content-Encoding"), Some("br"));
+
+        // Disabled
+        let handler = StaticFiles::<Mount>::new(&root);
+        let res = get_with(&handler, "/files/app.js", &[("Accept-Encoding", "br")]);
+        assert_eq!(res.header().get_field("Content-Encoding"), None);
+        assert_eq!(res.header().get_field("Vary"), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn is_fingerprinted_works() {
+        assert!(is_fingerprinted(Path::new("app.3f2a9c1e.js")));
+        assert!(is_fingerprinted(Path::new("dir/logo-8d7e6f5a40.min.png")));
+        assert!(!is_fingerprinted(Path::new("3f2a9c1e.js")));
+        assert!(!is_fingerprinted(Path::new("app.js")));
+        assert!(!is_fingerprinted(Path::new("app.deadbeef.js")));
+        assert!(!is_fingerprinted(Path::new("app.3f2a.js")));
+    }
+
+    #[test]
+    fn format_time_works() {
+        assert_eq!(format_time(SystemTime::UNIX_EPOCH), "1970-01-01 00:00:00");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(format_time(time), "2000-02-29 12:34:56");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::{Error, Result};
+use httpcodec::{ReasonPhrase, StatusCode};
 use std::fmt;
 
 /// Response status.
@@ -9,7 +11,7 @@ pub enum Status {
     /// 101
     SwitchingProtocols,
 
-    /// 103
+    /// 102
     Processing,
 
     /// 200
@@ -118,8 +120,12 @@ pub enum Status {
     RangeNotSatisfiable,
 
     /// 417
+    #[deprecated(note = "Use `Status::ExpectationFailed` instead")]
     ExceptionFailed,
 
+    /// 417
+    ExpectationFailed,
+
     /// 418
     ImATeapot,
 
@@ -135,9 +141,18 @@ pub enum Status {
     /// 424
     FailedDependency,
 
+    /// 425
+    TooEarly,
+
     /// 426
     UpgradeRequired,
 
+    /// 429
+    TooManyRequests,
+
+    /// 431
+    RequestHeaderFieldsTooLarge,
+
     /// 451
     UnavailableForLegalReasons,
 
@@ -173,6 +188,14 @@ pub enum Status {
 
     /// 510
     NotExtended,
+
+    /// 511
+    NetworkAuthenticationRequired,
+
+    /// Non-standard (e.g., vendor-specific) status.
+    ///
+    /// Use `Status::custom` to make an instance of this variant.
+    Custom(CustomStatus),
 }
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -180,7 +203,20 @@ impl fmt::Display for Status {
     }
 }
 impl Status {
+    /// Makes a non-standard status which has the given code and reason phrase.
+    ///
+    /// # Errors
+    ///
+    /// If `code` is not a three digit number or `reason` contains invalid characters,
+    /// an `ErrorKind::InvalidInput` error will be returned.
+    pub fn custom(code: u16, reason: &'static str) -> Result<Self> {
+        track!(StatusCode::new(code).map_err(Error::from))?;
+        track!(ReasonPhrase::new(reason).map_err(Error::from))?;
+        Ok(Status::Custom(CustomStatus { code, reason }))
+    }
+
     /// Returns the code of the status.
+    #[allow(deprecated)]
     pub fn code(self) -> u16 {
         match self {
             Status::Continue => 100,
@@ -221,13 +257,16 @@ impl Status {
             Status::UriTooLong => 414,
             Status::UnsupportedMediaType => 415,
             Status::RangeNotSatisfiable => 416,
-            Status::ExceptionFailed => 417,
+            Status::ExceptionFailed | Status::ExpectationFailed => 417,
             Status::ImATeapot => 418,
             Status::MisdirectedRequest => 421,
             Status::UnprocessableEntity => 422,
             Status::Locked => 423,
             Status::FailedDependency => 424,
+            Status::TooEarly => 425,
             Status::UpgradeRequired => 426,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
             Status::UnavailableForLegalReasons => 451,
             Status::InternalServerError => 500,
             Status::NotImplemented => 501,
@@ -240,10 +279,13 @@ impl Status {
             Status::LoopDetected => 508,
             Status::BandwidthLimitExceeded => 509,
             Status::NotExtended => 510,
+            Status::NetworkAuthenticationRequired => 511,
+            Status::Custom(s) => s.code,
         }
     }
 
     /// Returns the typical reason phrase of the status.
+    #[allow(deprecated)]
     pub fn reason_phrase(self) -> &'static str {
         match self {
             Status::Continue => "Continue",
@@ -284,13 +326,16 @@ impl Status {
             Status::UriTooLong => "URI Too Long",
             Status::UnsupportedMediaType => "Unsupported Media Type",
             Status::RangeNotSatisfiable => "Range Not Satisfiable",
-            Status::ExceptionFailed => "Expectation Failed",
+            Status::ExceptionFailed | Status::ExpectationFailed => "Expectation Failed",
             Status::ImATeapot => "I'm a teapot",
             Status::MisdirectedRequest => "Misdirected Request",
-            Status::UnprocessableEntity => "Unporcessable Entity",
+            Status::UnprocessableEntity => "Unprocessable Entity",
             Status::Locked => "Locked",
             Status::FailedDependency => "Failed Dependency",
+            Status::TooEarly => "Too Early",
             Status::UpgradeRequired => "Upgrade Required",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             Status::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
             Status::InternalServerError => "Internal Server Error",
             Status::NotImplemented => "Not Implemented",
@@ -303,6 +348,59 @@ impl Status {
             Status::LoopDetected => "Loop Detected",
             Status::BandwidthLimitExceeded => "Bandwidth Limit Exceeded",
             Status::NotExtended => "Not Extended",
+            Status::NetworkAuthenticationRequired => "Network Authentication Required",
+            Status::Custom(s) => s.reason,
         }
     }
 }
+
+/// Non-standard status.
+///
+/// See `Status::custom`.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct CustomStatus {
+    code: u16,
+    reason: &'static str,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn standard_status_works() {
+        assert_eq!(Status::Processing.to_string(), "102 Processing");
+        assert_eq!(
+            Status::ExpectationFailed.to_string(),
+            "417 Expectation Failed"
+        );
+        assert_eq!(
+            Status::UnprocessableEntity.to_string(),
+            "422 Unprocessable Entity"
+        );
+        assert_eq!(Status::TooEarly.to_string(), "425 Too Early");
+        assert_eq!(Status::TooManyRequests.to_string(), "429 Too Many Requests");
+        assert_eq!(
+            Status::NetworkAuthenticationRequired.to_string(),
+            "511 Network Authentication Required"
+        );
+    }
+
+    #[test]
+    fn custom_status_works() {
+        let status = Status::custom(599, "Vendor Error").unwrap();
+        assert_eq!(status.code(), 599);
+        assert_eq!(status.reason_phrase(), "Vendor Error");
+        assert_eq!(status.to_string(), "599 Vendor Error");
+
+        assert_eq!(
+            Status::custom(1000, "Foo").err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert_eq!(
+            Status::custom(599, "Foo\r\n").err().map(|e| *e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use bytecodec::{self, ByteCount, Decode, Eos};
+use httpcodec::{BodyDecode, BodyDecoder, Header};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static TEMP_FILE_SEQNO: AtomicUsize = AtomicUsize::new(0);
+
+/// A request body decoder that writes large bodies to temporary files.
+///
+/// Bodies up to the threshold are held in memory, and the others are written to
+/// temporary files while they are being received, so that large uploads do not blow up the memory.
+///
+/// The default threshold is `1048576` bytes, and the temporary files are
+/// created in `std::env::temp_dir()`.
+#[derive(Debug)]
+pub struct TempFileDecoder {
+    threshold: usize,
+    inner: BodyDecoder<Spooler>,
+}
+impl TempFileDecoder {
+    /// Makes a new `TempFileDecoder` instance.
+    ///
+    /// Bodies larger than `threshold` bytes are written to temporary files.
+    pub fn new(threshold: usize) -> Self {
+        Self::with_dir(threshold, std::env::temp_dir())
+    }
+
+    /// Sets the directory in which temporary files are created.
+    pub fn dir<P: AsRef<Path>>(self, dir: P) -> Self {
+        Self::with_dir(self.threshold, dir.as_ref().to_path_buf())
+    }
+
+    fn with_dir(threshold: usize, dir: PathBuf) -> Self {
+        let spooler = Spooler {
+            threshold,
+            dir,
+            buf: Vec::new(),
+            file: None,
+            eos: false,
+        };
+        TempFileDecoder {
+            threshold,
+            inner: BodyDecoder::new(spooler),
+        }
+    }
+}
+impl Default for TempFileDecoder {
+    fn default() -> Self {
+        Self::new(1024 * 1024)
+    }
+}
+impl Decode for TempFileDecoder {
+    type Item = SpooledBody;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        track!(self.inner.decode(buf, eos))
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        track!(self.inner.finish_decoding())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        self.inner.requiring_bytes()
+    }
+}
+impl BodyDecode for TempFileDecoder {
+    fn initialize(&mut self, header: &Header) -> bytecodec::Result<()> {
+        track!(self.inner.initialize(header))
+    }
+}
+
+/// A request body decoded by `TempFileDecoder`.
+#[derive(Debug)]
+pub enum SpooledBody {
+    /// The body is held in memory.
+    Memory(Vec<u8>),
+
+    /// The body is written to a temporary file.
+    File(TempFile),
+}
+impl SpooledBody {
+    /// Returns the length of the body in bytes.
+    pub fn len(&self) -> u64 {
+        match *self {
+            SpooledBody::Memory(ref x) => x.len() as u64,
+            SpooledBody::File(ref x) => x.len(),
+        }
+    }
+
+    /// Returns `true` if the body is empty, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl Read for SpooledBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            SpooledBody::Memory(ref mut x) => {
+                let size = (&x[..]).read(buf)?;
+                x.drain(..size);
+                Ok(size)
+            }
+            SpooledBody::File(ref mut x) => x.file.read(buf),
+        }
+    }
+}
+
+/// A temporary file that holds a request body.
+///
+/// The file is removed when this is dropped (unless it has been persisted).
+#[derive(Debug)]
+pub struct TempFile {
+    file: File,
+    path: PathBuf,
+    len: u64,
+    persisted: bool,
+}
+impl TempFile {
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the length of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the file is empty, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference to the inner file.
+    ///
+    /// The cursor of the file is initially located at the beginning.
+    pub fn file_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a mutable reference to the inner file.
+    pub fn file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Moves the file to `path`, and returns the moved file.
+    ///
+    /// The moved file will not be removed automatically.
+    /// Note that the file cannot be moved across file systems.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> io::Result<File> {
+        fs::rename(&self.path, path)?;
+        self.persisted = true;
+        let file = self.file.try_clone()?;
+        Ok(file)
+    }
+}
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Spooler {
+    threshold: usize,
+    dir: PathBuf,
+    buf: Vec<u8>,
+    file: Option<TempFile>,
+    eos: bool,
+}
+impl Spooler {
+    fn create_temp_file(&self) -> io::Result<TempFile> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let name = format!(
+            "fibers_http_server-{}-{}-{}.body",
+            process::id(),
+            TEMP_FILE_SEQNO.fetch_add(1, Ordering::SeqCst),
+            nanos
+        );
+        let path = self.dir.join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(TempFile {
+            file,
+            path,
+            len: 0,
+            persisted: false,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.file.is_none() && self.buf.len() + buf.len() > self.threshold {
+            let mut file = self.create_temp_file()?;
+            file.file.write_all(&self.buf)?;
+            file.len = self.buf.len() as u64;
+            self.buf = Vec::new();
+            self.file = Some(file);
+        }
+        if let Some(ref mut file) = self.file {
+            file.file.write_all(buf)?;
+            file.len += buf.len() as u64;
+        } else {
+            self.buf.extend_from_slice(buf);
+        }
+        Ok(())
+    }
+}
+impl Decode for Spooler {
+    type Item = SpooledBody;
+
+    fn decode(&mut self, buf: &[u8], eos: Eos) -> bytecodec::Result<usize> {
+        if self.eos {
+            return Ok(0);
+        }
+        track!(self.write(buf).map_err(bytecodec::Error::from))?;
+        self.eos = eos.is_reached();
+        Ok(buf.len())
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        track_assert!(self.eos, bytecodec::ErrorKind::IncompleteDecoding);
+        self.eos = false;
+        if let Some(mut file) = self.file.take() {
+            track!(file
+                .file
+                .seek(SeekFrom::Start(0))
+                .map_err(bytecodec::Error::from))?;
+            Ok(SpooledBody::File(file))
+        } else {
+            Ok(SpooledBody::Memory(std::mem::take(&mut self.buf)))
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.eos
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        if self.eos {
+            ByteCount::Finite(0)
+        } else {
+            ByteCount::Infinite
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytecodec::DecodeExt;
+    use httpcodec::{HeaderField, HttpVersion, Method, Request, RequestTarget};
+
+    fn decode(decoder: &mut TempFileDecoder, body: &[u8]) -> SpooledBody {
+        let mut req = Request::new(
+            Method::new("POST").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        req.header_mut()
+            .add_field(HeaderField::new("Content-Length", &body.len().to_string()).unwrap());
+        track_try_unwrap!(decoder.initialize(&req.header()));
+        track_try_unwrap!(decoder.decode_from_bytes(body))
+    }
+
+    #[test]
+    fn temp_file_decoder_works() {
+        let mut decoder = TempFileDecoder::new(4);
+
+        let mut body = decode(&mut decoder, b"foo");
+        assert!(matches!(body, SpooledBody::Memory(_)));
+        let mut buf = Vec::new();
+        body.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"foo");
+
+        let mut body = decode(&mut decoder, b"foobar");
+        let path = match body {
+            SpooledBody::File(ref file) => file.path().to_path_buf(),
+            _ => panic!(),
+        };
+        assert_eq!(body.len(), 6);
+        assert!(path.exists());
+        let mut buf = Vec::new();
+        body.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"foobar");
+
+        drop(body);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,873 @@
+//! Utilities for testing handlers without binding real sockets.
+//!
+//! # Examples
+//!
+//! ```
+//! use bytecodec::bytes::Utf8Encoder;
+//! use bytecodec::null::NullDecoder;
+//! use fibers_http_server::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+//! use futures::future::ok;
+//! use httpcodec::{BodyDecoder, BodyEncoder};
+//!
+//! struct Hello;
+//! impl HandleRequest for Hello {
+//!     const METHOD: &'static str = "GET";
+//!     const PATH: &'static str = "/hello";
+//!
+//!     type ReqBody = ();
+//!     type ResBody = String;
+//!     type Decoder = BodyDecoder<NullDecoder>;
+//!     type Encoder = BodyEncoder<Utf8Encoder>;
+//!     type Reply = Reply<Self::ResBody>;
+//!
+//!     fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+//!         Box::new(ok(Res::new(Status::Ok, "hello".to_owned())))
+//!     }
+//! }
+//!
+//! let mut builder = ServerBuilder::new("127.0.0.1:80".parse().unwrap());
+//! builder.add_handler(Hello).unwrap();
+//! let client = builder.finish_test_client();
+//!
+//! let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+//! assert_eq!(res.status_code(), 200);
+//! assert_eq!(res.body(), b"hello");
+//! ```
+use crate::clock::ManualClock;
+use crate::connection::{Connection, Transport};
+use crate::dispatcher::Dispatcher;
+use crate::metrics::ServerMetrics;
+use crate::response;
+use crate::server::ServerOptions;
+use crate::{Error, ErrorKind, Res, Result};
+use bytecodec::bytes::{BytesEncoder, RemainingBytesDecoder};
+use bytecodec::io::{IoDecodeExt, ReadBuf};
+use bytecodec::{self, ByteCount, Decode, EncodeExt, Eos};
+use fibers::sync::mpsc;
+use futures::executor::{self, Notify, Spawn};
+use futures::{Async, Future, Poll, Stream};
+use httpcodec::{
+    BodyDecoder, BodyEncoder, HttpVersion, Method, NoBodyDecoder, Request, RequestEncoder,
+    RequestTarget, ResponseDecoder,
+};
+use slog::Logger;
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An in-memory duplex stream.
+///
+/// The bytes written to a stream can be read from the other stream of the same pair.
+/// If one of the pair is dropped, the other will reach EOS.
+///
+/// Note that reading would block until the peer writes some bytes,
+/// and such streams can only be used on fibers (i.e., not by `Future::wait`).
+#[derive(Debug)]
+pub struct MemoryStream {
+    rx: mpsc::Receiver<Vec<u8>>,
+    tx: mpsc::Sender<Vec<u8>>,
+    buf: Vec<u8>,
+    offset: usize,
+}
+impl MemoryStream {
+    /// Makes a pair of connected streams.
+    pub fn pair() -> (Self, Self) {
+        let (tx0, rx0) = mpsc::channel();
+        let (tx1, rx1) = mpsc::channel();
+        let a = MemoryStream {
+            rx: rx0,
+            tx: tx1,
+            buf: Vec::new(),
+            offset: 0,
+        };
+        let b = MemoryStream {
+            rx: rx1,
+            tx: tx0,
+            buf: Vec::new(),
+            offset: 0,
+        };
+        (a, b)
+    }
+}
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.buf.len() {
+            match self.rx.poll().expect("Never fails") {
+                Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
+                Async::Ready(None) => return Ok(0),
+                Async::Ready(Some(bytes)) => {
+                    self.buf = bytes;
+                    self.offset = 0;
+                }
+            }
+        }
+        let size = (&self.buf[self.offset..]).read(buf)?;
+        self.offset += size;
+        Ok(size)
+    }
+}
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() && self.tx.send(buf.to_owned()).is_err() {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Transport for MemoryStream {}
+
+/// A client that sends requests to an in-memory server.
+///
+/// Each request is sent via a new connection that is backed by `MemoryStream`,
+/// and handled in the same manner as the real connections of `Server`.
+///
+/// This is created via `ServerBuilder::finish_test_client`.
+#[derive(Debug)]
+pub struct TestClient {
+    logger: Logger,
+    metrics: ServerMetrics,
+    dispatcher: Dispatcher,
+    local_addr: SocketAddr,
+    options: ServerOptions,
+    is_server_alive: Arc<AtomicBool>,
+}
+impl TestClient {
+    pub(crate) fn new(
+        logger: Logger,
+        metrics: ServerMetrics,
+        dispatcher: Dispatcher,
+        local_addr: SocketAddr,
+        options: ServerOptions,
+    ) -> Self {
+        TestClient {
+            logger,
+            metrics,
+            dispatcher,
+            local_addr,
+            options,
+            is_server_alive: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Sends the given request, and returns a future that will result in the response.
+    ///
+    /// The returned future has to be executed on fibers (e.g., by `fibers_global::execute`).
+    pub fn send(&self, req: Request<Vec<u8>>) -> TestReply {
+        TestReply::new(req, |stream| {
+            track!(Connection::with_transport(
+                self.logger.clone(),
+                self.metrics.clone(),
+                stream,
+                ([127, 0, 0, 1], 0).into(),
+                self.local_addr,
+                self.dispatcher.clone(),
+                Arc::clone(&self.is_server_alive),
+                &self.options,
+            ))
+        })
+    }
+
+    /// Sends a `GET` request for `path`.
+    ///
+    /// This is equivalent to `self.send(req)` where `req` is a `GET` request that has an empty body.
+    pub fn get(&self, path: &str) -> Result<TestReply> {
+        let method = track!(Method::new("GET").map_err(Error::from))?;
+        let target = track!(RequestTarget::new(path).map_err(Error::from))?;
+        Ok(self.send(Request::new(method, target, HttpVersion::V1_1, Vec::new())))
+    }
+
+    /// Returns the metrics of the server.
+    pub fn metrics(&self) -> &ServerMetrics {
+        &self.metrics
+    }
+}
+
+/// `Future` that represents the response of a request sent by `TestClient` or `Server::call`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct TestReply(std::result::Result<TestReplyInner, Option<Error>>);
+impl TestReply {
+    pub(crate) fn new<F>(req: Request<Vec<u8>>, connect: F) -> Self
+    where
+        F: FnOnce(MemoryStream) -> Result<Connection<MemoryStream>>,
+    {
+        let is_head = req.method().as_str() == "HEAD";
+        let (mut client, server) = MemoryStream::pair();
+        let result = RequestEncoder::new(BodyEncoder::new(BytesEncoder::new()))
+            .encode_into_bytes(req)
+            .map_err(Error::from)
+            .and_then(|bytes| track!(client.write_all(&bytes).map_err(Error::from)))
+            .and_then(|()| track!(connect(server)));
+        let connection = match track!(result) {
+            Err(e) => return TestReply(Err(Some(e))),
+            Ok(connection) => connection,
+        };
+        let decoder = ResDecoder::Undecided { is_head };
+        TestReply(Ok(TestReplyInner {
+            connection: Some(connection),
+            client,
+            rbuf: ReadBuf::new(vec![0; 8192]),
+            decoder,
+        }))
+    }
+}
+impl Future for TestReply {
+    type Item = Res<Vec<u8>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Err(ref mut e) => Err(e.take().expect("Cannot poll TestReply twice")),
+            Ok(ref mut inner) => track!(inner.poll()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TestReplyInner {
+    connection: Option<Connection<MemoryStream>>,
+    client: MemoryStream,
+    rbuf: ReadBuf<Vec<u8>>,
+    decoder: ResDecoder,
+}
+impl TestReplyInner {
+    fn poll(&mut self) -> Poll<Res<Vec<u8>>, Error> {
+        let is_closed = match self.connection {
+            None => false,
+            Some(ref mut connection) => match connection.poll() {
+                Err(()) => track_panic!(ErrorKind::Other, "Connection aborted"),
+                Ok(Async::Ready(())) => true,
+                Ok(Async::NotReady) => false,
+            },
+        };
+        if is_closed {
+            self.connection = None;
+        }
+
+        track!(self.rbuf.fill(&mut self.client).map_err(Error::from))?;
+        if let ResDecoder::Undecided { is_head } = self.decoder {
+            let mut peek = PeekStatus(None);
+            track!(peek.decode_from_read_buf(&mut self.rbuf))?;
+            if let Some(status_code) = peek.0 {
+                self.decoder = if is_head || response::is_bodiless_status(status_code) {
+                    ResDecoder::NoBody(ResponseDecoder::default())
+                } else {
+                    ResDecoder::Body(ResponseDecoder::default())
+                };
+            }
+        }
+        let res = match self.decoder {
+            ResDecoder::Undecided { .. } => None,
+            ResDecoder::Body(ref mut d) => {
+                track!(d.decode_from_read_buf(&mut self.rbuf))?;
+                if !d.is_idle() {
+                    None
+                } else {
+                    Some(Res(track!(d.finish_decoding())?))
+                }
+            }
+            ResDecoder::NoBody(ref mut d) => {
+                track!(d.decode_from_read_buf(&mut self.rbuf))?;
+                if !d.is_idle() {
+                    None
+                } else {
+                    Some(Res(track!(d.finish_decoding())?.map_body(|()| Vec::new())))
+                }
+            }
+        };
+        if let Some(res) = res {
+            Ok(Async::Ready(res))
+        } else {
+            track_assert!(
+                !self.rbuf.stream_state().is_eos(),
+                ErrorKind::Other,
+                "Unexpected EOS"
+            );
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ResDecoder {
+    // Whether the response has a body is decided by the request method and the status code.
+    Undecided { is_head: bool },
+    Body(ResponseDecoder<BodyDecoder<RemainingBytesDecoder>>),
+    NoBody(ResponseDecoder<NoBodyDecoder>),
+}
+
+/// A decoder that peeks the status code of a response without consuming any bytes.
+struct PeekStatus(Option<u16>);
+impl Decode for PeekStatus {
+    type Item = ();
+
+    fn decode(&mut self, buf: &[u8], _eos: Eos) -> bytecodec::Result<usize> {
+        // e.g., "HTTP/1.1 200 OK\r\n"
+        if self.0.is_none() && buf.len() >= 12 {
+            let code = std::str::from_utf8(&buf[9..12])
+                .ok()
+                .and_then(|s| s.parse().ok());
+            self.0 = Some(track_assert_some!(code, bytecodec::ErrorKind::InvalidInput));
+        }
+        Ok(0)
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.0.is_some()
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+}
+
+/// A deterministic simulation of a server.
+///
+/// All the connections of a simulation are driven by `Simulation::run` on the calling thread
+/// (i.e., fibers are not used), and they use a `ManualClock` as the clock of the server.
+/// The clients of the connections are controlled via `SimConnection`, and the bytes are
+/// transferred through in-memory sockets.
+/// If partial I/O is enabled, each read and write of the server transfers a pseudo-random
+/// number of bytes that is derived from the seed of the simulation.
+///
+/// This is created via `ServerBuilder::finish_simulation`.
+///
+/// Note that handlers that depend on fibers (e.g., thread pools or fibers' timers) cannot be
+/// simulated deterministically.
+///
+/// # Examples
+///
+/// ```
+/// use bytecodec::bytes::Utf8Encoder;
+/// use bytecodec::null::NullDecoder;
+/// use fibers_http_server::{HandleRequest, Reply, Req, Res, ServerBuilder, Status};
+/// use futures::future::ok;
+/// use httpcodec::{BodyDecoder, BodyEncoder};
+/// use std::time::Duration;
+///
+/// struct Hello;
+/// impl HandleRequest for Hello {
+///     const METHOD: &'static str = "GET";
+///     const PATH: &'static str = "/hello";
+///
+///     type ReqBody = ();
+///     type ResBody = String;
+///     type Decoder = BodyDecoder<NullDecoder>;
+///     type Encoder = BodyEncoder<Utf8Encoder>;
+///     type Reply = Reply<Self::ResBody>;
+///
+///     fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+///         Box::new(ok(Res::new(Status::Ok, "hello".to_owned())))
+///     }
+/// }
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:80".parse().unwrap());
+/// builder.add_handler(Hello).unwrap();
+/// builder.read_request_head_timeout(Duration::from_secs(10));
+/// let mut sim = builder.finish_simulation(0);
+/// sim.partial_io(true);
+///
+/// // Pipelined requests
+/// let conn = sim.connect().unwrap();
+/// conn.write(b"GET /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\n");
+/// sim.run().unwrap();
+/// let received = String::from_utf8(conn.take_received()).unwrap();
+/// assert_eq!(received.matches("hello").count(), 2);
+///
+/// // Timeout
+/// conn.write(b"GET /hel");
+/// sim.run().unwrap();
+/// sim.advance(Duration::from_secs(10)).unwrap();
+/// assert!(conn.is_closed());
+/// assert!(String::from_utf8(conn.take_received()).unwrap().starts_with("HTTP/1.1 408"));
+/// ```
+#[derive(Debug)]
+pub struct Simulation {
+    logger: Logger,
+    metrics: ServerMetrics,
+    dispatcher: Dispatcher,
+    local_addr: SocketAddr,
+    options: ServerOptions,
+    is_server_alive: Arc<AtomicBool>,
+    clock: ManualClock,
+    seed: u64,
+    partial_io: bool,
+    notify: Arc<SimNotify>,
+    connections: Vec<Option<SimServerConnection>>,
+}
+impl Simulation {
+    const MAX_POLLS: usize = 1_000_000;
+
+    pub(crate) fn new(
+        logger: Logger,
+        metrics: ServerMetrics,
+        dispatcher: Dispatcher,
+        local_addr: SocketAddr,
+        options: ServerOptions,
+        clock: ManualClock,
+        seed: u64,
+    ) -> Self {
+        Simulation {
+            logger,
+            metrics,
+            dispatcher,
+            local_addr,
+            options,
+            is_server_alive: Arc::new(AtomicBool::new(true)),
+            clock,
+            seed,
+            partial_io: false,
+            notify: Arc::default(),
+            connections: Vec::new(),
+        }
+    }
+
+    /// Enables or disables the partial I/O of the connections created after this call.
+    ///
+    /// The default value is `false`.
+    pub fn partial_io(&mut self, enabled: bool) -> &mut Self {
+        self.partial_io = enabled;
+        self
+    }
+
+    /// Opens a new connection to the server.
+    pub fn connect(&mut self) -> Result<SimConnection> {
+        let id = self.connections.len();
+        let socket = Arc::new(Mutex::new(SimSocket::default()));
+        let stream = SimStream {
+            socket: Arc::clone(&socket),
+            rng: if self.partial_io {
+                Some(SimRng::new(self.seed, id as u64))
+            } else {
+                None
+            },
+        };
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], (id % 0xFFFF) as u16 + 1));
+        let connection = track!(Connection::with_transport(
+            self.logger.clone(),
+            self.metrics.clone(),
+            stream,
+            peer_addr,
+            self.local_addr,
+            self.dispatcher.clone(),
+            Arc::clone(&self.is_server_alive),
+            &self.options,
+        ))?;
+        self.connections.push(Some(SimServerConnection {
+            connection: executor::spawn(connection),
+            socket: Arc::clone(&socket),
+        }));
+        self.notify.mark(id);
+        Ok(SimConnection {
+            id,
+            peer_addr,
+            socket,
+            notify: Arc::clone(&self.notify),
+        })
+    }
+
+    /// Drives the connections until all of them are idle (i.e., waiting for I/O or time).
+    ///
+    /// # Errors
+    ///
+    /// If the connections do not become idle after a large number of polls,
+    /// an `ErrorKind::Other` error will be returned.
+    pub fn run(&mut self) -> Result<()> {
+        for _ in 0..Self::MAX_POLLS {
+            let id = match self.notify.pop() {
+                None => return Ok(()),
+                Some(id) => id,
+            };
+            let entry = match self.connections.get_mut(id) {
+                Some(Some(entry)) => entry,
+                _ => continue,
+            };
+            let before = entry.socket().transferred();
+            let result = entry
+                .connection
+                .poll_future_notify(&self.notify, id)
+                .map(|a| a.is_ready());
+            match result {
+                Ok(false) => {
+                    if entry.socket().transferred() != before {
+                        // The connection may have yielded (see `ServerBuilder::write_high_watermark`)
+                        self.notify.mark(id);
+                    }
+                }
+                Ok(true) | Err(()) => {
+                    entry.socket().is_server_closed = true;
+                    self.connections[id] = None;
+                }
+            }
+        }
+        track_panic!(
+            ErrorKind::Other,
+            "The simulation did not become idle after {} polls",
+            Self::MAX_POLLS
+        )
+    }
+
+    /// Advances the clock of the server by `duration`, and drives the connections.
+    pub fn advance(&mut self, duration: Duration) -> Result<()> {
+        self.clock.advance(duration);
+        track!(self.run())
+    }
+
+    /// Returns the clock of the server.
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// Returns the metrics of the server.
+    pub fn metrics(&self) -> &ServerMetrics {
+        &self.metrics
+    }
+}
+
+/// The client side of a connection of a `Simulation`.
+#[derive(Debug)]
+pub struct SimConnection {
+    id: usize,
+    peer_addr: SocketAddr,
+    socket: Arc<Mutex<SimSocket>>,
+    notify: Arc<SimNotify>,
+}
+impl SimConnection {
+    /// Returns the identifier of the connection.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the address of the client as seen by the server.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Sends `bytes` to the server.
+    ///
+    /// The bytes are delivered when the simulation runs next time.
+    pub fn write(&self, bytes: &[u8]) {
+        self.lock().to_server.extend(bytes);
+        self.notify.mark(self.id);
+    }
+
+    /// Closes the sending side of the connection (i.e., the server will reach EOS).
+    pub fn shutdown(&self) {
+        self.lock().is_client_closed = true;
+        self.notify.mark(self.id);
+    }
+
+    /// Aborts the connection (i.e., the server will get `ECONNRESET` errors).
+    pub fn reset(&self) {
+        self.lock().is_reset = true;
+        self.notify.mark(self.id);
+    }
+
+    /// Takes the bytes that the server has sent so far.
+    pub fn take_received(&self) -> Vec<u8> {
+        std::mem::take(&mut self.lock().to_client)
+    }
+
+    /// Returns `true` if the server has closed the connection, otherwise `false`.
+    pub fn is_closed(&self) -> bool {
+        self.lock().is_server_closed
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SimSocket> {
+        self.socket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug)]
+struct SimServerConnection {
+    connection: Spawn<Connection<SimStream>>,
+    socket: Arc<Mutex<SimSocket>>,
+}
+impl SimServerConnection {
+    fn socket(&self) -> std::sync::MutexGuard<'_, SimSocket> {
+        self.socket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimSocket {
+    to_server: VecDeque<u8>,
+    to_client: Vec<u8>,
+    read_bytes: u64,
+    written_bytes: u64,
+    is_client_closed: bool,
+    is_server_closed: bool,
+    is_reset: bool,
+}
+impl SimSocket {
+    fn transferred(&self) -> (u64, u64) {
+        (self.read_bytes, self.written_bytes)
+    }
+}
+
+#[derive(Debug)]
+struct SimStream {
+    socket: Arc<Mutex<SimSocket>>,
+    rng: Option<SimRng>,
+}
+impl SimStream {
+    fn io_size(&mut self, max: usize) -> usize {
+        match self.rng {
+            None => max,
+            Some(ref mut rng) => (rng.next_u64() % max as u64) as usize + 1,
+        }
+    }
+}
+impl Read for SimStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let socket = Arc::clone(&self.socket);
+        let mut socket = socket.lock().unwrap_or_else(|e| e.into_inner());
+        if socket.is_reset {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if socket.to_server.is_empty() {
+            if socket.is_client_closed {
+                return Ok(0);
+            }
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let size = self.io_size(buf.len().min(socket.to_server.len()));
+        for (b, x) in buf.iter_mut().zip(socket.to_server.drain(..size)) {
+            *b = x;
+        }
+        socket.read_bytes += size as u64;
+        Ok(size)
+    }
+}
+impl Write for SimStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let socket = Arc::clone(&self.socket);
+        let mut socket = socket.lock().unwrap_or_else(|e| e.into_inner());
+        if socket.is_reset {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let size = self.io_size(buf.len());
+        socket.to_client.extend_from_slice(&buf[..size]);
+        socket.written_bytes += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Transport for SimStream {}
+
+/// The set of the connections that need to be polled.
+#[derive(Debug, Default)]
+struct SimNotify {
+    ids: Mutex<BTreeSet<usize>>,
+}
+impl SimNotify {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<usize>> {
+        self.ids.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn mark(&self, id: usize) {
+        self.lock().insert(id);
+    }
+
+    fn pop(&self) -> Option<usize> {
+        let mut ids = self.lock();
+        let id = ids.iter().next().cloned()?;
+        ids.remove(&id);
+        Some(id)
+    }
+}
+impl Notify for SimNotify {
+    fn notify(&self, id: usize) {
+        self.mark(id);
+    }
+}
+
+/// A xorshift pseudo-random number generator.
+#[derive(Debug)]
+struct SimRng(u64);
+impl SimRng {
+    fn new(seed: u64, stream: u64) -> Self {
+        let state = seed ^ stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        SimRng(if state == 0 {
+            0x2545_F491_4F6C_DD1D
+        } else {
+            state
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HandleRequest, Reply, Req, ServerBuilder, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+
+    struct Hello;
+    impl HandleRequest for Hello {
+        const METHOD: &'static str = "GET";
+        const METHODS: &'static [&'static str] = &["GET", "HEAD"];
+        const PATH: &'static str = "/hello";
+
+        type ReqBody = ();
+        type ResBody = String;
+        type Decoder = BodyDecoder<NullDecoder>;
+        type Encoder = httpcodec::BodyEncoder<Utf8Encoder>;
+        type Reply = Reply<Self::ResBody>;
+
+        fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+            Box::new(ok(Res::new(Status::Ok, "hello".to_owned())))
+        }
+    }
+
+    #[test]
+    fn test_client_works() {
+        let mut builder = ServerBuilder::new("127.0.0.1:80".parse().unwrap());
+        track_try_unwrap!(builder.add_handler(Hello));
+        let client = builder.finish_test_client();
+
+        let res = track_try_unwrap!(fibers_global::execute(track_try_unwrap!(
+            client.get("/hello")
+        )));
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), b"hello");
+
+        let req = Request::new(
+            Method::new("HEAD").unwrap(),
+            RequestTarget::new("/hello").unwrap(),
+            HttpVersion::V1_1,
+            Vec::new(),
+        );
+        let res = track_try_unwrap!(fibers_global::execute(client.send(req)));
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.header().get_field("Content-Length"), Some("5"));
+        assert!(res.body().is_empty());
+
+        let res = track_try_unwrap!(fibers_global::execute(track_try_unwrap!(
+            client.get("/world")
+        )));
+        assert_eq!(res.status_code(), 404);
+        assert_eq!(client.metrics().connected_tcp_clients(), 3);
+    }
+
+    #[test]
+    fn memory_stream_works() {
+        let (mut a, mut b) = MemoryStream::pair();
+        a.write_all(b"foo").unwrap();
+        a.write_all(b"bar").unwrap();
+
+        let mut buf = [0; 4];
+        assert_eq!(b.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"foo");
+        assert_eq!(b.read(&mut buf[..2]).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ba");
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"r");
+
+        drop(a);
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+        assert_eq!(
+            b.write(b"foo").err().map(|e| e.kind()),
+            Some(io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    fn simulate(seed: u64) -> Vec<Vec<u8>> {
+        let mut builder = ServerBuilder::new("127.0.0.1:80".parse().unwrap());
+        track_try_unwrap!(builder.add_handler(Hello));
+        builder.read_request_head_timeout(Duration::from_secs(5));
+        let mut sim = builder.finish_simulation(seed);
+        sim.partial_io(true);
+
+        let mut rng = SimRng::new(seed, u64::MAX);
+        let mut connections = Vec::new();
+        for _ in 0..200 {
+            let conn = track_try_unwrap!(sim.connect());
+            let requests = rng.next_u64() % 4 + 1;
+            for _ in 0..requests {
+                conn.write(b"GET /hello HTTP/1.1\r\n\r\n");
+            }
+            let is_reset = match rng.next_u64() % 4 {
+                0 => {
+                    conn.reset();
+                    true
+                }
+                1 => {
+                    conn.write(b"GET /hel");
+                    false
+                }
+                _ => false,
+            };
+            connections.push((conn, requests, is_reset));
+        }
+        track_try_unwrap!(sim.run());
+        for (conn, requests, is_reset) in &connections {
+            assert!(!conn.is_closed() || *is_reset);
+            let received = String::from_utf8(conn.lock().to_client.clone()).unwrap();
+            if !is_reset {
+                assert_eq!(received.matches("hello").count() as u64, *requests);
+            }
+        }
+
+        // Both the idle and the partially received connections time out
+        track_try_unwrap!(sim.advance(Duration::from_secs(5)));
+
+        let mut transcripts = Vec::new();
+        for (conn, requests, is_reset) in connections {
+            assert!(conn.is_closed());
+            let received = conn.take_received();
+            let text = String::from_utf8(received.clone()).unwrap();
+            if is_reset {
+                assert!(text.is_empty());
+            } else {
+                assert_eq!(text.matches("hello").count() as u64, requests);
+                assert!(text.ends_with("Request Timeout"));
+            }
+            transcripts.push(received);
+        }
+        transcripts
+    }
+
+    #[test]
+    fn simulation_works() {
+        let transcripts = simulate(12345);
+        assert_eq!(transcripts.len(), 200);
+        assert!(transcripts
+            .iter()
+            .any(|t| t.starts_with(b"HTTP/1.1 200 OK")));
+        assert!(transcripts.iter().any(|t| t.ends_with(b"Request Timeout")));
+
+        // Deterministic
+        assert_eq!(simulate(12345), transcripts);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{Error, HandleRequest, Priority, Req, Res, Status};
+use bytecodec::marker::Never;
+use fibers::sync::oneshot;
+use fibers::Spawn;
+use futures::{self, Async, Future, Poll};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A handler that executes the inner handler `H` on the given executor (e.g., a dedicated thread pool).
+///
+/// This is useful for CPU-heavy handlers, because the fibers executing `Server` and connections
+/// would be blocked if such a handler processed requests on them.
+///
+/// Both the invocation of `H::handle_request` and the polling of the resulting future
+/// are done on the executor, and then the response is sent back via a oneshot channel.
+/// If the executor drops the task, the `500 Internal Server Error` response with
+/// the default body will be returned.
+///
+/// # Examples
+///
+/// ```
+/// use fibers::{Executor, ThreadPoolExecutor};
+/// use fibers_http_server::{ServerBuilder, WithThreadPool};
+/// use fibers_http_server::metrics::MetricsHandler;
+///
+/// let pool = ThreadPoolExecutor::with_thread_count(2).unwrap();
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.add_handler(WithThreadPool::new(MetricsHandler, pool.handle())).unwrap();
+/// ```
+pub struct WithThreadPool<H, S> {
+    inner: Arc<H>,
+    spawner: Mutex<S>,
+}
+impl<H, S> WithThreadPool<H, S>
+where
+    H: HandleRequest,
+    S: Spawn + Send + 'static,
+{
+    /// Makes a new `WithThreadPool` instance.
+    pub fn new(inner: H, spawner: S) -> Self {
+        WithThreadPool {
+            inner: Arc::new(inner),
+            spawner: Mutex::new(spawner),
+        }
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn inner_ref(&self) -> &H {
+        &self.inner
+    }
+}
+impl<H, S> HandleRequest for WithThreadPool<H, S>
+where
+    H: HandleRequest,
+    H::ResBody: Default,
+    S: Spawn + Send + 'static,
+{
+    const METHOD: &'static str = H::METHOD;
+    const METHODS: &'static [&'static str] = H::METHODS;
+    const PATH: &'static str = H::PATH;
+    const PRIORITY: Priority = H::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = H::QUERY;
+
+    type ReqBody = H::ReqBody;
+    type ResBody = H::ResBody;
+    type Decoder = H::Decoder;
+    type Encoder = H::Encoder;
+    type Reply = ThreadPoolReply<H>;
+
+    fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
+        self.inner.handle_request_head(req)
+    }
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let (tx, rx) = oneshot::channel();
+        let inner = Arc::clone(&self.inner);
+        let future = futures::lazy(move || inner.handle_request(req)).then(move |result| {
+            let _ = tx.send(result.expect("Never fails"));
+            Ok(())
+        });
+        let spawner = self.spawner.lock().unwrap_or_else(|e| e.into_inner());
+        spawner.spawn(future);
+        ThreadPoolReply(rx)
+    }
+
+    fn handle_decoding_error(&self, req: Req<()>, error: &Error) -> Option<Res<Self::ResBody>> {
+        self.inner.handle_decoding_error(req, error)
+    }
+}
+impl<H, S> fmt::Debug for WithThreadPool<H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WithThreadPool {{ .. }}")
+    }
+}
+
+/// `Future` that represents the reply from a handler executed by `WithThreadPool` or `BlockingHandler`.
+pub struct ThreadPoolReply<H: HandleRequest>(pub(crate) oneshot::Receiver<Res<H::ResBody>>);
+impl<H> Future for ThreadPoolReply<H>
+where
+    H: HandleRequest,
+    H::ResBody: Default,
+{
+    type Item = Res<H::ResBody>;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(res)) => Ok(Async::Ready(res)),
+            Err(_) => {
+                let res = Res::new(Status::InternalServerError, H::ResBody::default());
+                Ok(Async::Ready(res))
+            }
+        }
+    }
+}
+impl<H: HandleRequest> fmt::Debug for ThreadPoolReply<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ThreadPoolReply(_)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use httpcodec::{BodyDecoder, BodyEncoder, HttpVersion, Method, Request, RequestTarget};
+    use std::thread::{self, ThreadId};
+    use url::Url;
+
+    struct ThreadName;
+    impl HandleRequest for ThreadName {
+        const METHOD: &'static str = "GET";
+        const PATH: &'static str = "/";
+
+        type ReqBody = ();
+        type ResBody = String;
+        type Decoder = BodyDecoder<NullDecoder>;
+        type Encoder = BodyEncoder<Utf8Encoder>;
+        type Reply = futures::future::FutureResult<Res<String>, Never>;
+
+        fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+            let id: ThreadId = thread::current().id();
+            futures::finished(Res::new(Status::Ok, format!("{:?}", id)))
+        }
+    }
+
+    fn req() -> Req<()> {
+        let inner = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new("/").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        let base_url = Url::parse("http://localhost/").unwrap();
+        track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()))
+    }
+
+    #[test]
+    fn with_thread_pool_works() {
+        let handler = WithThreadPool::new(ThreadName, fibers_global::handle());
+        let res = fibers_global::execute(handler.handle_request(req())).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_ne!(*res.body(), format!("{:?}", thread::current().id()));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{Res, Status};
+use httpcodec::{HttpVersion, Request};
+
+/// How `TRACE` requests are handled (see `ServerBuilder::trace_policy`).
+///
+/// This applies only to the `TRACE` requests that cannot be dispatched to any handlers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TracePolicy {
+    /// The requests are rejected with `405 Method Not Allowed`.
+    #[default]
+    Reject,
+
+    /// The requests are echoed back as the `message/http` bodies of `200 OK` responses
+    /// (RFC 7231, Section 4.3.8).
+    ///
+    /// The header fields that may contain credentials (e.g., `Authorization` and `Cookie`) are excluded.
+    Echo,
+}
+
+const SENSITIVE_FIELDS: &[&str] = &[
+    "Authorization",
+    "Cookie",
+    "Proxy-Authorization",
+    "X-CSRF-Token",
+];
+
+/// Makes the response echoing the head of `req` back.
+pub fn echo(req: &Request<()>) -> Res<Vec<u8>> {
+    let version = match req.http_version() {
+        HttpVersion::V1_0 => "HTTP/1.0",
+        HttpVersion::V1_1 => "HTTP/1.1",
+    };
+    let mut body = format!(
+        "{} {} {}\r\n",
+        req.method().as_str(),
+        req.request_target().as_str(),
+        version
+    );
+    for field in req.header().fields() {
+        if SENSITIVE_FIELDS
+            .iter()
+            .any(|name| field.name().eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+        body.push_str(&format!("{}: {}\r\n", field.name(), field.value()));
+    }
+    body.push_str("\r\n");
+
+    let mut res = Res::new(Status::Ok, body.into_bytes());
+    res.add_header("Content-Type", "message/http")
+        .expect("Never fails");
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::{HandleRequest, Reply, Req, ServerBuilder};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::ok;
+    use httpcodec::{BodyDecoder, BodyEncoder, HeaderField, Method, RequestTarget};
+
+    #[test]
+    fn echo_works() {
+        let mut req = Request::new(
+            Method::new("TRACE").unwrap(),
+            RequestTarget::new("/foo?bar=baz").unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        req.header_mut()
+            .add_field(HeaderField::new("Host", "example.com").unwrap());
+        req.header_mut()
+            .add_field(HeaderField::new("Cookie", "session=secret").unwrap());
+
+        let res = echo(&req);
+        assert_eq!(res.header().get_field("Content-Type"), Some("message/http"));
+        assert_eq!(
+            res.body().as_slice(),
+            &b"TRACE /foo?bar=baz HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn trace_policy_works() {
+        let req = b"TRACE /hello HTTP/1.1\r\nHost: example.com\r\nCookie: a=b\r\n\r\n";
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Hello).unwrap();
+        let mut sim = builder.finish_simulation(0);
+        let conn = sim.connect().unwrap();
+        conn.write(req);
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "{}",
+            received
+        );
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Hello).unwrap();
+        builder.trace_policy(TracePolicy::Echo);
+        let mut sim = builder.finish_simulation(0);
+        let conn = sim.connect().unwrap();
+        conn.write(req);
+        conn.write(b"GET /hello HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+        assert!(received.contains("Content-Type: message/http\r\n"));
+        assert!(received.contains("\r\n\r\nTRACE /hello HTTP/1.1\r\nHost: example.com\r\n\r\n"));
+        assert!(!received.contains("Cookie"));
+        assert!(received.ends_with("hello"));
+    }
+
+    #[test]
+    fn trace_handler_works() {
+        struct Trace;
+        impl HandleRequest for Trace {
+            const METHOD: &'static str = "TRACE";
+            const PATH: &'static str = "/trace";
+
+            type ReqBody = ();
+            type ResBody = String;
+            type Decoder = BodyDecoder<NullDecoder>;
+            type Encoder = BodyEncoder<Utf8Encoder>;
+            type Reply = Reply<Self::ResBody>;
+
+            fn handle_request(&self, _req: Req<Self::ReqBody>) -> Self::Reply {
+                Box::new(ok(Res::new(Status::Ok, "traced".to_owned())))
+            }
+        }
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.add_handler(Trace).unwrap();
+        builder.trace_policy(TracePolicy::Echo);
+        let mut sim = builder.finish_simulation(0);
+        let conn = sim.connect().unwrap();
+        conn.write(b"TRACE /trace HTTP/1.1\r\n\r\n");
+        conn.write(b"TRACE /other HTTP/1.1\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{}", received);
+        assert!(received.contains("\r\n\r\ntraced"));
+        assert!(received.contains("\r\n\r\nTRACE /other HTTP/1.1\r\n\r\n"));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::problem::IntoProblem;
+use crate::reply::IntoRes;
+use crate::{Error, HandleRequest, Priority, Reply, Req, Res};
+use futures::Future;
+use httpcodec::{BodyDecode, BodyEncode};
+use std::fmt;
+use std::sync::Arc;
+
+/// `TryHandleRequest` allows for handling HTTP requests by using fallible futures.
+///
+/// Implementations are registered to a server via `TryHandler`,
+/// which converts the errors returned by the futures into responses.
+pub trait TryHandleRequest: Sized + Send + Sync + 'static {
+    /// The method that the handler can handle.
+    const METHOD: &'static str;
+
+    /// The methods that the handler can handle.
+    ///
+    /// See the documentation of `HandleRequest::METHODS` for the details.
+    const METHODS: &'static [&'static str] = &[Self::METHOD];
+
+    /// The request path that the handler can handle.
+    ///
+    /// See the documentation of `HandleRequest::PATH` for the syntax.
+    const PATH: &'static str;
+
+    /// The priority class of the requests handled by the handler.
+    ///
+    /// See the documentation of `HandleRequest::PRIORITY` for the details.
+    const PRIORITY: Priority = Priority::Normal;
+
+    /// The query parameters that the requests handled by the handler must have.
+    ///
+    /// See the documentation of `HandleRequest::QUERY` for the details.
+    const QUERY: &'static [(&'static str, &'static str)] = &[];
+
+    /// The type of the request bodies.
+    type ReqBody: Send + 'static;
+
+    /// The type of the response bodies.
+    type ResBody: Send + 'static;
+
+    /// Request body decoder.
+    type Decoder: BodyDecode<Item = Self::ReqBody> + Send + 'static;
+
+    /// Response body encoder.
+    type Encoder: BodyEncode<Item = Self::ResBody> + Send + 'static;
+
+    /// The type of the errors that may be returned by the handler.
+    type Error: Send + 'static;
+
+    /// `Future` that represents reply to a request.
+    type Reply: Future<Item = Res<Self::ResBody>, Error = Self::Error> + Send + 'static;
+
+    /// Handles the head part of a request.
+    ///
+    /// If a `Some(..)` value is returned, the invocation of `handle_request` method will be skipped.
+    ///
+    /// The default implementation always returns `None`.
+    #[allow(unused_variables)]
+    fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
+        None
+    }
+
+    /// Handles a request.
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply;
+
+    /// Handles an error occurred while decoding the body of a request.
+    ///
+    /// The default implementation always returns `None`
+    /// (i.e., the default error response will be returned to the HTTP client).
+    #[allow(unused_variables)]
+    fn handle_decoding_error(&self, req: Req<()>, error: &Error) -> Option<Res<Self::ResBody>> {
+        None
+    }
+}
+
+type ErrorMapper<E, T> = dyn Fn(E) -> Res<T> + Send + Sync + 'static;
+
+/// A `HandleRequest` implementation that drives the inner `TryHandleRequest`.
+///
+/// If a future returned by the inner handler fails, the error is converted into
+/// a response by the error mapper of this handler.
+///
+/// # Examples
+///
+/// ```
+/// use bytecodec::bytes::Utf8Encoder;
+/// use bytecodec::null::NullDecoder;
+/// use fibers_http_server::{Error, ErrorKind, Req, Res, ServerBuilder, Status};
+/// use fibers_http_server::{TryHandleRequest, TryHandler};
+/// use futures::future::{failed, finished, Either, FutureResult};
+/// use httpcodec::{BodyDecoder, BodyEncoder};
+///
+/// struct Hello;
+/// impl TryHandleRequest for Hello {
+///     const METHOD: &'static str = "GET";
+///     const PATH: &'static str = "/hello/*";
+///
+///     type ReqBody = ();
+///     type ResBody = String;
+///     type Decoder = BodyDecoder<NullDecoder>;
+///     type Encoder = BodyEncoder<Utf8Encoder>;
+///     type Error = Error;
+///     type Reply = FutureResult<Res<Self::ResBody>, Self::Error>;
+///
+///     fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+///         let name = req.url().path_segments().and_then(|mut s| s.nth(1));
+///         match name {
+///             Some("") | None => failed(ErrorKind::InvalidInput.into()),
+///             Some(name) => finished(Res::new(Status::Ok, format!("hello {}", name))),
+///         }
+///     }
+/// }
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.add_handler(TryHandler::new(Hello)).unwrap();
+/// ```
+pub struct TryHandler<H: TryHandleRequest> {
+    inner: Arc<H>,
+    error_mapper: Arc<ErrorMapper<H::Error, H::ResBody>>,
+}
+impl<H: TryHandleRequest> TryHandler<H> {
+    /// Makes a new `TryHandler` instance.
+    ///
+    /// The errors are converted into responses by `IntoRes::into_res`.
+    pub fn new(inner: H) -> Self
+    where
+        H::Error: IntoRes<H::ResBody>,
+    {
+        Self::with_error_mapper(inner, IntoRes::into_res)
+    }
+
+    /// Makes a new `TryHandler` instance that uses `error_mapper` to convert the errors into responses.
+    pub fn with_error_mapper<F>(inner: H, error_mapper: F) -> Self
+    where
+        F: Fn(H::Error) -> Res<H::ResBody> + Send + Sync + 'static,
+    {
+        TryHandler {
+            inner: Arc::new(inner),
+            error_mapper: Arc::new(error_mapper),
+        }
+    }
+
+    /// Makes a new `TryHandler` instance that renders the errors as problem documents ([RFC 7807]).
+    ///
+    /// The errors are converted into `application/problem+json` responses
+    /// by `IntoProblem::into_problem` and `Res::problem`.
+    ///
+    /// [RFC 7807]: https://tools.ietf.org/html/rfc7807
+    pub fn with_problem_details(inner: H) -> Self
+    where
+        H: TryHandleRequest<ResBody = String>,
+        H::Error: IntoProblem,
+    {
+        Self::with_error_mapper(inner, |e: H::Error| Res::problem(&e.into_problem()))
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn inner_ref(&self) -> &H {
+        &self.inner
+    }
+}
+impl<H: TryHandleRequest> HandleRequest for TryHandler<H> {
+    const METHOD: &'static str = H::METHOD;
+    const METHODS: &'static [&'static str] = H::METHODS;
+    const PATH: &'static str = H::PATH;
+    const PRIORITY: Priority = H::PRIORITY;
+    const QUERY: &'static [(&'static str, &'static str)] = H::QUERY;
+
+    type ReqBody = H::ReqBody;
+    type ResBody = H::ResBody;
+    type Decoder = H::Decoder;
+    type Encoder = H::Encoder;
+    type Reply = Reply<Self::ResBody>;
+
+    fn handle_request_head(&self, req: &Req<()>) -> Option<Res<Self::ResBody>> {
+        self.inner.handle_request_head(req)
+    }
+
+    fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+        let error_mapper = Arc::clone(&self.error_mapper);
+        let future = self.inner.handle_request(req);
+        Box::new(future.or_else(move |e| Ok(error_mapper(e))))
+    }
+
+    fn handle_decoding_error(&self, req: Req<()>, error: &Error) -> Option<Res<Self::ResBody>> {
+        self.inner.handle_decoding_error(req, error)
+    }
+}
+impl<H: TryHandleRequest + fmt::Debug> fmt::Debug for TryHandler<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TryHandler {{ inner: {:?}, .. }}", self.inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ErrorKind, Status};
+    use bytecodec::bytes::Utf8Encoder;
+    use bytecodec::null::NullDecoder;
+    use futures::future::{failed, finished, FutureResult};
+    use httpcodec::{BodyDecoder, BodyEncoder, HttpVersion, Method, Request, RequestTarget};
+    use url::Url;
+
+    struct Hello;
+    impl TryHandleRequest for Hello {
+        const METHOD: &'static str = "GET";
+        const PATH: &'static str = "/hello/*";
+
+        type ReqBody = ();
+        type ResBody = String;
+        type Decoder = BodyDecoder<NullDecoder>;
+        type Encoder = BodyEncoder<Utf8Encoder>;
+        type Error = Error;
+        type Reply = FutureResult<Res<Self::ResBody>, Self::Error>;
+
+        fn handle_request(&self, req: Req<Self::ReqBody>) -> Self::Reply {
+            match req.url().path_segments().and_then(|mut s| s.nth(1)) {
+                Some("") | None => failed(ErrorKind::InvalidInput.into()),
+                Some(name) => finished(Res::new(Status::Ok, format!("hello {}", name))),
+            }
+        }
+    }
+
+    fn req(path: &str) -> Req<()> {
+        let inner = Request::new(
+            Method::new("GET").unwrap(),
+            RequestTarget::new(path).unwrap(),
+            HttpVersion::V1_1,
+            (),
+        );
+        let base_url = Url::parse("http://localhost/").unwrap();
+        track_try_unwrap!(Req::new(inner, &base_url, ([127, 0, 0, 1], 80).into()))
+    }
+
+    #[test]
+    fn try_handler_works() {
+        let handler = TryHandler::new(Hello);
+        let res = handler.handle_request(req("/hello/foo")).wait().unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.body(), "hello foo");
+
+        let res = handler.handle_request(req("/hello/")).wait().unwrap();
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(res.body(), "Bad Request");
+
+        let handler = TryHandler::with_error_mapper(Hello, |e: Error| {
+            Res::new(Status::UnprocessableEntity, e.to_string())
+        });
+        let res = handler.handle_request(req("/hello/")).wait().unwrap();
+        assert_eq!(res.status_code(), 422);
+
+        let handler = TryHandler::with_problem_details(Hello);
+        let res = handler.handle_request(req("/hello/")).wait().unwrap();
+        assert_eq!(res.status_code(), 400);
+        assert_eq!(
+            res.header().get_field("Content-Type"),
+            Some("application/problem+json")
+        );
+        assert_eq!(
+            res.body(),
+            r#"{"type":"about:blank","title":"Bad Request","status":400}"#
+        );
+    }
+}
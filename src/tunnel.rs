@@ -0,0 +1,387 @@
+use crate::{Res, Result};
+use bytecodec::io::{IoDecodeExt, IoEncodeExt, ReadBuf, WriteBuf};
+use bytecodec::marker::Never;
+use bytecodec::{self, ByteCount, Decode, Encode, Eos};
+use futures::{Future, Poll};
+use httpcodec::Header;
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use trackable::error::ErrorKindExt;
+
+/// A handler of `CONNECT` requests (see `ServerBuilder::connect_handler`).
+///
+/// # Examples
+///
+/// ```
+/// use fibers::net::TcpStream;
+/// use fibers_http_server::{ConnectReply, HandleConnect, Res, ServerBuilder, Status};
+/// use futures::Future;
+/// use httpcodec::Header;
+/// use std::net::SocketAddr;
+///
+/// struct Proxy;
+/// impl HandleConnect for Proxy {
+///     type Tunnel = TcpStream;
+///
+///     fn handle_connect(
+///         &self,
+///         authority: &str,
+///         _header: &Header,
+///         _peer_addr: SocketAddr,
+///     ) -> ConnectReply<Self::Tunnel> {
+///         let addr = match authority.parse() {
+///             Ok(addr) => addr,
+///             Err(_) => return Box::new(futures::future::err(Res::new(Status::BadRequest, Vec::new()))),
+///         };
+///         Box::new(
+///             TcpStream::connect(addr)
+///                 .map_err(|_| Res::new(Status::BadGateway, Vec::new())),
+///         )
+///     }
+/// }
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// builder.connect_handler(Proxy);
+/// ```
+pub trait HandleConnect: Send + Sync + 'static {
+    /// The stream connected to the destination of a tunnel (e.g., `fibers::net::TcpStream`).
+    ///
+    /// The stream has to be non-blocking, and has to notify the current task when it becomes
+    /// readable or writable (as the streams of `fibers` do).
+    type Tunnel: Read + Write + Send + 'static;
+
+    /// Opens a tunnel to `authority` (i.e., the `host:port` in the request line).
+    ///
+    /// If the returned future fails, the error response is returned to the client.
+    fn handle_connect(
+        &self,
+        authority: &str,
+        header: &Header,
+        peer_addr: SocketAddr,
+    ) -> ConnectReply<Self::Tunnel>;
+}
+
+/// The future returned by `HandleConnect::handle_connect`.
+pub type ConnectReply<T> = Box<dyn Future<Item = T, Error = Res<Vec<u8>>> + Send + 'static>;
+
+pub trait TunnelStream: Read + Write + Send + 'static {}
+impl<T: Read + Write + Send + 'static> TunnelStream for T {}
+
+type BoxTunnel = Box<dyn TunnelStream>;
+
+trait OpenTunnel: Send + Sync + 'static {
+    fn open(&self, authority: &str, header: &Header, peer_addr: SocketAddr) -> PendingTunnel;
+}
+impl<H: HandleConnect> OpenTunnel for H {
+    fn open(&self, authority: &str, header: &Header, peer_addr: SocketAddr) -> PendingTunnel {
+        let future = self
+            .handle_connect(authority, header, peer_addr)
+            .map(|tunnel| Box::new(tunnel) as BoxTunnel);
+        PendingTunnel(Box::new(future))
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectHandler(Arc<dyn OpenTunnel>);
+impl ConnectHandler {
+    pub fn new<H: HandleConnect>(handler: H) -> Self {
+        ConnectHandler(Arc::new(handler))
+    }
+
+    pub fn call(&self, authority: &str, header: &Header, peer_addr: SocketAddr) -> PendingTunnel {
+        self.0.open(authority, header, peer_addr)
+    }
+}
+impl fmt::Debug for ConnectHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ConnectHandler(_)")
+    }
+}
+
+pub struct PendingTunnel(ConnectReply<BoxTunnel>);
+impl Future for PendingTunnel {
+    type Item = BoxTunnel;
+    type Error = Res<Vec<u8>>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+impl fmt::Debug for PendingTunnel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PendingTunnel(_)")
+    }
+}
+
+/// Relays the bytes between a client and the destination of a tunnel.
+pub struct Relay {
+    tunnel: BoxTunnel,
+    // The unsent part of the response to the `CONNECT` request.
+    established: &'static [u8],
+    is_tunnel_eos: bool,
+    progressed: bool,
+}
+impl Relay {
+    pub fn new(tunnel: BoxTunnel) -> Self {
+        Relay {
+            tunnel,
+            established: b"HTTP/1.1 200 Connection Established\r\n\r\n",
+            is_tunnel_eos: false,
+            progressed: false,
+        }
+    }
+
+    /// Moves the bytes read from the client to the tunnel, and the ones read from the tunnel to the client.
+    pub fn relay(
+        &mut self,
+        rbuf: &mut ReadBuf<Vec<u8>>,
+        wbuf: &mut WriteBuf<Vec<u8>>,
+    ) -> Result<()> {
+        self.progressed = false;
+        track!(ToTunnel(self).decode_from_read_buf(rbuf))?;
+        track!(FromTunnel(self).encode_to_write_buf(wbuf))?;
+        Ok(())
+    }
+
+    /// Returns `true` if the tunnel has been closed by the destination.
+    pub fn is_tunnel_eos(&self) -> bool {
+        self.is_tunnel_eos
+    }
+
+    /// Returns `true` if the last `relay` call moved no bytes.
+    pub fn is_blocked(&self) -> bool {
+        !self.progressed
+    }
+}
+impl fmt::Debug for Relay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Relay {{ is_tunnel_eos: {}, progressed: {} }}",
+            self.is_tunnel_eos, self.progressed
+        )
+    }
+}
+
+/// A decoder that writes the given bytes to the tunnel.
+struct ToTunnel<'a>(&'a mut Relay);
+impl<'a> Decode for ToTunnel<'a> {
+    type Item = ();
+
+    fn decode(&mut self, buf: &[u8], _eos: Eos) -> bytecodec::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.0.tunnel.write(buf) {
+            Ok(size) => {
+                self.0.progressed |= size > 0;
+                Ok(size)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(track!(bytecodec::ErrorKind::Other.cause(e)).into()),
+        }
+    }
+
+    fn finish_decoding(&mut self) -> bytecodec::Result<Self::Item> {
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        true
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+}
+
+/// An encoder that fills the given buffer with the bytes read from the tunnel.
+struct FromTunnel<'a>(&'a mut Relay);
+impl<'a> Encode for FromTunnel<'a> {
+    type Item = Never;
+
+    fn encode(&mut self, buf: &mut [u8], _eos: Eos) -> bytecodec::Result<usize> {
+        let relay = &mut *self.0;
+        if !relay.established.is_empty() {
+            let size = cmp::min(buf.len(), relay.established.len());
+            buf[..size].copy_from_slice(&relay.established[..size]);
+            relay.established = &relay.established[size..];
+            relay.progressed |= size > 0;
+            return Ok(size);
+        }
+        if buf.is_empty() || relay.is_tunnel_eos {
+            return Ok(0);
+        }
+        match relay.tunnel.read(buf) {
+            Ok(size) => {
+                relay.is_tunnel_eos = size == 0;
+                relay.progressed = true;
+                Ok(size)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(track!(bytecodec::ErrorKind::Other.cause(e)).into()),
+        }
+    }
+
+    fn start_encoding(&mut self, item: Self::Item) -> bytecodec::Result<()> {
+        match item {}
+    }
+
+    fn is_idle(&self) -> bool {
+        true
+    }
+
+    fn requiring_bytes(&self) -> ByteCount {
+        ByteCount::Unknown
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ServerBuilder, Status};
+    use futures::future::ok;
+
+    #[derive(Default)]
+    struct Loopback(Vec<u8>);
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let size = cmp::min(buf.len(), self.0.len());
+            buf[..size].copy_from_slice(&self.0[..size]);
+            self.0.drain(..size);
+            Ok(size)
+        }
+    }
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn relay_works() {
+        let mut relay = Relay::new(Box::new(Loopback::default()));
+        let mut from_tunnel = vec![0; 64];
+
+        let size =
+            track_try_unwrap!(FromTunnel(&mut relay).encode(&mut from_tunnel, Eos::new(false)));
+        assert_eq!(
+            &from_tunnel[..size],
+            &b"HTTP/1.1 200 Connection Established\r\n\r\n"[..]
+        );
+        let size =
+            track_try_unwrap!(FromTunnel(&mut relay).encode(&mut from_tunnel, Eos::new(false)));
+        assert_eq!(size, 0);
+
+        let size = track_try_unwrap!(ToTunnel(&mut relay).decode(b"ping", Eos::new(false)));
+        assert_eq!(size, 4);
+        let size =
+            track_try_unwrap!(FromTunnel(&mut relay).encode(&mut from_tunnel, Eos::new(false)));
+        assert_eq!(&from_tunnel[..size], b"ping");
+        assert!(!relay.is_tunnel_eos());
+    }
+
+    #[test]
+    fn connect_handler_works() {
+        use std::io;
+
+        #[derive(Default)]
+        struct Loopback(Vec<u8>);
+        impl io::Read for Loopback {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                let size = std::cmp::min(buf.len(), self.0.len());
+                buf[..size].copy_from_slice(&self.0[..size]);
+                self.0.drain(..size);
+                Ok(size)
+            }
+        }
+        impl io::Write for Loopback {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        struct Connect;
+        impl HandleConnect for Connect {
+            type Tunnel = Loopback;
+
+            fn handle_connect(
+                &self,
+                authority: &str,
+                _header: &httpcodec::Header,
+                _peer_addr: std::net::SocketAddr,
+            ) -> ConnectReply<Self::Tunnel> {
+                if authority == "example.com:443" {
+                    Box::new(ok(Loopback::default()))
+                } else {
+                    Box::new(futures::future::err(Res::new(
+                        Status::Forbidden,
+                        Vec::new(),
+                    )))
+                }
+            }
+        }
+
+        let req = b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n";
+
+        // Not supported
+        let builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        let mut sim = builder.finish_simulation(0);
+        let conn = sim.connect().unwrap();
+        conn.write(req);
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 501 Not Implemented\r\n"),
+            "{}",
+            received
+        );
+
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 80).into());
+        builder.connect_handler(Connect);
+        let mut sim = builder.finish_simulation(0);
+
+        let conn = sim.connect().unwrap();
+        conn.write(req);
+        conn.write(b"ping");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert_eq!(received, "HTTP/1.1 200 Connection Established\r\n\r\nping");
+
+        conn.write(b"pong");
+        sim.run().unwrap();
+        assert_eq!(conn.take_received(), b"pong");
+        assert!(!conn.is_closed());
+
+        // Rejected by the handler
+        let conn = sim.connect().unwrap();
+        conn.write(b"CONNECT example.com:22 HTTP/1.1\r\nHost: example.com:22\r\n\r\n");
+        sim.run().unwrap();
+        let received = String::from_utf8(conn.take_received()).unwrap();
+        assert!(
+            received.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{}",
+            received
+        );
+        assert!(conn.is_closed());
+    }
+}
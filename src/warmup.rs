@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A gate that keeps a server answering `503 Service Unavailable` until the application is ready.
+///
+/// While the gate is closed, the server accepts connections as usual
+/// (so that load balancers do not observe connection refusals),
+/// but every request is responded with `503 Service Unavailable` and the `Retry-After` header.
+///
+/// This is created via `ServerBuilder::warmup`.
+///
+/// # Examples
+///
+/// ```
+/// use fibers_http_server::ServerBuilder;
+/// use std::time::Duration;
+///
+/// let mut builder = ServerBuilder::new("127.0.0.1:0".parse().unwrap());
+/// let gate = builder.warmup(Duration::from_secs(5));
+/// assert!(!gate.is_ready());
+///
+/// // ...load caches, connect to databases, etc...
+///
+/// gate.set_ready();
+/// assert!(gate.is_ready());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReadinessGate {
+    is_ready: Arc<AtomicBool>,
+    retry_after: Duration,
+}
+impl ReadinessGate {
+    pub(crate) fn new(retry_after: Duration) -> Self {
+        ReadinessGate {
+            is_ready: Arc::new(AtomicBool::new(false)),
+            retry_after,
+        }
+    }
+
+    /// Opens the gate (i.e., the server starts handling requests).
+    pub fn set_ready(&self) {
+        self.is_ready.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the gate has been opened, otherwise `false`.
+    pub fn is_ready(&self) -> bool {
+        self.is_ready.load(Ordering::SeqCst)
+    }
+
+    /// Returns the value of the `Retry-After` header of the responses returned while warming up.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::Hello;
+    use crate::ServerBuilder;
+
+    #[test]
+    fn warmup_works() {
+        let mut builder = ServerBuilder::new(([127, 0, 0, 1], 0).into());
+        builder.add_handler(Hello).unwrap();
+        let gate = builder.warmup(Duration::from_secs(5));
+        let client = builder.finish_test_client();
+
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 503);
+        assert_eq!(res.header().get_field("Retry-After"), Some("5"));
+        assert_eq!(client.metrics().warmup_rejected_requests(), 1);
+
+        gate.set_ready();
+        let res = fibers_global::execute(client.get("/hello").unwrap()).unwrap();
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(client.metrics().warmup_rejected_requests(), 1);
+    }
+}